@@ -0,0 +1,33 @@
+//! Feeds arbitrary bytes through the config pipeline, as a hand-edited
+//! config file. The daemon is a binary, so the modules that pipeline needs
+//! are included by path.
+#![no_main]
+#![allow(dead_code)]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/config.rs"]
+mod config;
+#[path = "../../src/curve.rs"]
+mod curve;
+#[path = "../../src/expr.rs"]
+mod expr;
+#[path = "../../src/fuzz.rs"]
+mod fuzz;
+#[path = "../../src/gpu.rs"]
+mod gpu;
+#[path = "../../src/history.rs"]
+mod history;
+#[path = "../../src/lock.rs"]
+mod lock;
+#[path = "../../src/ring.rs"]
+mod ring;
+#[path = "../../src/shutdown.rs"]
+mod shutdown;
+#[path = "../../src/units.rs"]
+mod units;
+
+fuzz_target!(|data: &[u8]| {
+    // Errors are fine; panics and stack overflows are what the fuzzer reports
+    let _ = fuzz::run_config_on_small_stack(data);
+});
@@ -0,0 +1,539 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::config::{AlertAction, AlertCondition, AlertRule, DeviceId, NotifySettings};
+use crate::history::TemperatureHistory;
+use crate::rpm_loop::STALL_RPM;
+use crate::sensors::{SourceState, SourceTransition};
+use crate::units::DisplayUnits;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub name: &'static str,
+    pub message: String,
+    pub temperature: Option<f64>,
+    pub device: Option<DeviceId>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FiredAlert {
+    pub alert: Alert,
+    pub actions: Vec<AlertAction>,
+}
+
+/// A driven channel's RPM as read this tick, for fan_stall rules.
+#[derive(Clone, Debug)]
+pub struct ChannelRpm<'a> {
+    pub device_id: &'a DeviceId,
+    pub channel: usize,
+    /// What the channel was last set to.
+    pub speed_percent: Option<u8>,
+    /// None without a reading or without fans on the channel.
+    pub rpm: Option<u16>,
+}
+
+/// Turns observations from the control loop into alerts. Each alert fires
+/// once when its condition starts holding and re-arms when it clears.
+pub struct AlertManager {
+    rules: Vec<AlertRule>,
+    // Whether each temperature rule's condition held on the last tick
+    active: Vec<bool>,
+    lost_devices: HashSet<DeviceId>,
+    // When each stalled channel was first seen stalled, in observe_rpms'
+    // seconds, and when observe_rpms was last called
+    stalled_since: HashMap<(DeviceId, usize), f64>,
+    last_rpm_seconds: Option<f64>,
+    notify: NotifySettings,
+    units: DisplayUnits,
+    debug: bool,
+}
+
+impl AlertManager {
+    pub fn new(
+        rules: Vec<AlertRule>,
+        notify: NotifySettings,
+        units: DisplayUnits,
+        debug: bool,
+    ) -> Self {
+        #[cfg(not(feature = "notify"))]
+        if rules.iter().any(|rule| {
+            rule.actions
+                .iter()
+                .any(|a| matches!(a, AlertAction::Notify { .. }))
+        }) {
+            eprintln!(
+                "Alert rules use the notify action, but this build lacks the `notify` feature; \
+                 those notifications will be skipped."
+            );
+        }
+
+        Self {
+            active: vec![false; rules.len()],
+            rules,
+            lost_devices: HashSet::new(),
+            stalled_since: HashMap::new(),
+            last_rpm_seconds: None,
+            notify,
+            units,
+            debug,
+        }
+    }
+
+    /// `source` names where the temperature came from, for the message.
+    pub fn observe_temperature(&mut self, source: &str, temperature: f64) -> Vec<FiredAlert> {
+        let mut fired = Vec::new();
+        for (rule, overheated) in self.rules.iter().zip(self.active.iter_mut()) {
+            let AlertCondition::Overheat { above_celsius } = rule.condition else {
+                continue;
+            };
+            let above = temperature > above_celsius;
+            if above && !*overheated {
+                fired.push(FiredAlert {
+                    alert: Alert {
+                        name: "overheat",
+                        message: format!(
+                            "{} temperature {} is above {}",
+                            source,
+                            self.units.temperature(temperature),
+                            self.units.temperature(above_celsius)
+                        ),
+                        temperature: Some(temperature),
+                        device: None,
+                    },
+                    actions: rule.actions.clone(),
+                });
+            }
+            *overheated = above;
+        }
+        fired
+    }
+
+    /// Fires when the temperature has been climbing faster than a
+    /// runaway_temperature rule allows, averaged over its window.
+    pub fn observe_rate(&mut self, source: &str, history: &TemperatureHistory) -> Vec<FiredAlert> {
+        let mut fired = Vec::new();
+        for (rule, runaway) in self.rules.iter().zip(self.active.iter_mut()) {
+            let AlertCondition::RunawayTemperature {
+                above_celsius_per_second,
+                over_seconds,
+            } = rule.condition
+            else {
+                continue;
+            };
+            let rate = history.rate_over(over_seconds);
+            let above = rate.is_some_and(|rate| rate > above_celsius_per_second);
+            if let (true, false, Some(rate)) = (above, *runaway, rate) {
+                fired.push(FiredAlert {
+                    alert: Alert {
+                        name: "runaway_temperature",
+                        message: format!(
+                            "{} temperature climbed {}/s over the last {:.0}s, faster than {}/s",
+                            source,
+                            self.units.delta(rate),
+                            over_seconds,
+                            self.units.delta(above_celsius_per_second)
+                        ),
+                        temperature: None,
+                        device: None,
+                    },
+                    actions: rule.actions.clone(),
+                });
+            }
+            *runaway = above;
+        }
+        fired
+    }
+
+    /// Whether a fan_stall rule needs every driven channel's RPM.
+    pub fn watches_rpms(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| matches!(rule.condition, AlertCondition::FanStall { .. }))
+    }
+
+    /// Fires for each channel that has read under STALL_RPM while set above
+    /// 0% for as long as a fan_stall rule allows. `seconds` is the time of
+    /// the readings, counted from any fixed point.
+    pub fn observe_rpms(&mut self, seconds: f64, channels: &[ChannelRpm]) -> Vec<FiredAlert> {
+        let last = self.last_rpm_seconds.replace(seconds);
+        let mut fired = Vec::new();
+        for reading in channels {
+            let key = (reading.device_id.clone(), reading.channel);
+            let stalled = reading.rpm.is_some_and(|rpm| rpm < STALL_RPM)
+                && reading.speed_percent.is_some_and(|speed| speed > 0);
+            if !stalled {
+                self.stalled_since.remove(&key);
+                continue;
+            }
+            let since = *self.stalled_since.entry(key).or_insert(seconds);
+            let stalled_for = seconds - since;
+            let stalled_before = last.map_or(f64::NEG_INFINITY, |last| last - since);
+            for rule in &self.rules {
+                let AlertCondition::FanStall { for_seconds } = rule.condition else {
+                    continue;
+                };
+                if stalled_for >= for_seconds && stalled_before < for_seconds {
+                    fired.push(FiredAlert {
+                        alert: Alert {
+                            name: "fan_stall",
+                            message: format!(
+                                "Channel {} of device {} read {} RPM at {}% for {:.0}s",
+                                reading.channel,
+                                reading.device_id,
+                                reading.rpm.unwrap_or_default(),
+                                reading.speed_percent.unwrap_or_default(),
+                                stalled_for
+                            ),
+                            temperature: None,
+                            device: Some(reading.device_id.clone()),
+                        },
+                        actions: rule.actions.clone(),
+                    });
+                }
+            }
+        }
+        fired
+    }
+
+    /// How far back observe_rate needs the temperature history to go.
+    pub fn history_seconds(&self) -> f64 {
+        self.rules
+            .iter()
+            .filter_map(|rule| match rule.condition {
+                AlertCondition::RunawayTemperature { over_seconds, .. } => Some(over_seconds),
+                _ => None,
+            })
+            .fold(0.0, f64::max)
+    }
+
+    /// Whether a rule with the full_speed action currently holds.
+    pub fn full_speed(&self) -> bool {
+        self.rules
+            .iter()
+            .zip(&self.active)
+            .any(|(rule, active)| *active && rule.actions.contains(&AlertAction::FullSpeed))
+    }
+
+    pub fn observe_device(&mut self, device_id: &DeviceId, write_ok: bool) -> Vec<FiredAlert> {
+        if write_ok {
+            self.lost_devices.remove(device_id);
+            return Vec::new();
+        }
+        if !self.lost_devices.insert(device_id.clone()) {
+            return Vec::new();
+        }
+
+        self.rules
+            .iter()
+            .filter(|rule| rule.condition == AlertCondition::DeviceLost)
+            .map(|rule| FiredAlert {
+                alert: Alert {
+                    name: "device_lost",
+                    message: format!("Device {} stopped accepting commands", device_id),
+                    temperature: None,
+                    device: Some(device_id.clone()),
+                },
+                actions: rule.actions.clone(),
+            })
+            .collect()
+    }
+
+    /// Fires when the temperature source becomes lost. The state machine
+    /// only reports a loss once, so there is nothing to re-arm here.
+    pub fn observe_source(&self, name: &str, transition: SourceTransition) -> Vec<FiredAlert> {
+        if transition.to != SourceState::Lost {
+            return Vec::new();
+        }
+        self.rules
+            .iter()
+            .filter(|rule| rule.condition == AlertCondition::SourceLost)
+            .map(|rule| FiredAlert {
+                alert: Alert {
+                    name: "source_lost",
+                    message: format!(
+                        "Temperature source {} lost after {} failed reads",
+                        name, transition.reads
+                    ),
+                    temperature: None,
+                    device: None,
+                },
+                actions: rule.actions.clone(),
+            })
+            .collect()
+    }
+
+    pub fn dispatch(&self, fired: &FiredAlert) {
+        for action in &fired.actions {
+            match action {
+                AlertAction::Log => {
+                    eprintln!("ALERT {}: {}", fired.alert.name, fired.alert.message)
+                }
+                // Applied by the control loop through full_speed
+                AlertAction::FullSpeed => {}
+                AlertAction::Notify {
+                    urgency,
+                    summary,
+                    body,
+                } => {
+                    let summary = render_template(summary, &fired.alert, self.units);
+                    let body = render_template(body, &fired.alert, self.units);
+                    #[cfg(feature = "notify")]
+                    crate::notify::send(&self.notify, *urgency, &summary, &body, self.debug);
+                    #[cfg(not(feature = "notify"))]
+                    if self.debug {
+                        let _ = (urgency, &self.notify);
+                        println!(
+                            "Skipping notification (no notify feature): {}: {}",
+                            summary, body
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Fills in a notification template. `{temperature}` is the bare number in
+/// `units`, so templates add the unit themselves.
+pub fn render_template(template: &str, alert: &Alert, units: DisplayUnits) -> String {
+    template
+        .replace("{alert}", alert.name)
+        .replace("{message}", &alert.message)
+        .replace(
+            "{temperature}",
+            &alert
+                .temperature
+                .map(|t| format!("{:.1}", units.value(t)))
+                .unwrap_or_default(),
+        )
+        .replace(
+            "{device}",
+            &alert
+                .device
+                .as_ref()
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Urgency;
+
+    fn manager() -> AlertManager {
+        let rules = vec![
+            AlertRule {
+                condition: AlertCondition::Overheat {
+                    above_celsius: 80.0,
+                },
+                actions: vec![AlertAction::Log],
+            },
+            AlertRule {
+                condition: AlertCondition::DeviceLost,
+                actions: vec![AlertAction::Notify {
+                    urgency: Urgency::Critical,
+                    summary: "{alert}".to_string(),
+                    body: "{message}".to_string(),
+                }],
+            },
+        ];
+        AlertManager::new(
+            rules,
+            NotifySettings::default(),
+            DisplayUnits::Celsius,
+            false,
+        )
+    }
+
+    #[test]
+    fn test_overheat_fires_once_until_cleared() {
+        let mut alerts = manager();
+
+        assert!(alerts.observe_temperature("cpu", 70.0).is_empty());
+        let fired = alerts.observe_temperature("gpu-hottest-edge", 85.0);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].alert.name, "overheat");
+        assert_eq!(
+            fired[0].alert.message,
+            "gpu-hottest-edge temperature 85.0°C is above 80.0°C"
+        );
+        assert!(alerts.observe_temperature("cpu", 90.0).is_empty());
+        assert!(alerts.observe_temperature("cpu", 75.0).is_empty());
+        assert_eq!(alerts.observe_temperature("cpu", 81.0).len(), 1);
+    }
+
+    #[test]
+    fn test_device_lost_fires_once_until_recovered() {
+        let mut alerts = manager();
+        let device_id = DeviceId(0x0cf2, 0xa102, "TEST123".to_string());
+
+        let fired = alerts.observe_device(&device_id, false);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].alert.device, Some(device_id.clone()));
+        assert!(alerts.observe_device(&device_id, false).is_empty());
+        assert!(alerts.observe_device(&device_id, true).is_empty());
+        assert_eq!(alerts.observe_device(&device_id, false).len(), 1);
+    }
+
+    #[test]
+    fn test_source_lost() {
+        let mut rules = manager().rules;
+        rules.push(AlertRule {
+            condition: AlertCondition::SourceLost,
+            actions: vec![AlertAction::Log],
+        });
+        let alerts = AlertManager::new(
+            rules,
+            NotifySettings::default(),
+            DisplayUnits::Celsius,
+            false,
+        );
+        let transition = |from, to| SourceTransition { from, to, reads: 5 };
+
+        assert!(alerts
+            .observe_source(
+                "gpu",
+                transition(SourceState::Available, SourceState::Degraded)
+            )
+            .is_empty());
+        let fired =
+            alerts.observe_source("gpu", transition(SourceState::Degraded, SourceState::Lost));
+        assert_eq!(fired.len(), 1);
+        assert_eq!(
+            fired[0].alert.message,
+            "Temperature source gpu lost after 5 failed reads"
+        );
+    }
+
+    #[test]
+    fn test_fan_stall() {
+        let mut rules = manager().rules;
+        rules.push(AlertRule {
+            condition: AlertCondition::FanStall { for_seconds: 10.0 },
+            actions: vec![AlertAction::Log],
+        });
+        let mut alerts = AlertManager::new(
+            rules,
+            NotifySettings::default(),
+            DisplayUnits::Celsius,
+            false,
+        );
+        assert!(alerts.watches_rpms());
+        assert!(!manager().watches_rpms());
+        let device_id = DeviceId(0x0cf2, 0xa102, "TEST123".to_string());
+        let reading = |channel, speed_percent, rpm| ChannelRpm {
+            device_id: &device_id,
+            channel,
+            speed_percent: Some(speed_percent),
+            rpm,
+        };
+
+        let mut fired_at = Vec::new();
+        for second in 0..30 {
+            // Channel 1 stops at 5s and spins up again at 20s; channel 2 is
+            // set to 0% and channel 3 has no fans
+            let rpm = if (5..20).contains(&second) { 0 } else { 900 };
+            let fired = alerts.observe_rpms(
+                second as f64,
+                &[
+                    reading(1, 40, Some(rpm)),
+                    reading(2, 0, Some(0)),
+                    reading(3, 40, None),
+                ],
+            );
+            fired_at.extend(fired.into_iter().map(|fired| (second, fired.alert.message)));
+        }
+        assert_eq!(
+            fired_at,
+            [(
+                15,
+                "Channel 1 of device (0cf2, a102, TEST123) read 0 RPM at 40% for 10s".to_string()
+            )]
+        );
+
+        // It fires again once the channel has recovered and stalls anew
+        for second in 30..45 {
+            let fired = alerts.observe_rpms(second as f64, &[reading(1, 40, Some(50))]);
+            assert_eq!(fired.len(), usize::from(second == 40), "{}", second);
+        }
+    }
+
+    #[test]
+    fn test_runaway_fires_before_overheat() {
+        let rules = vec![
+            AlertRule {
+                condition: AlertCondition::Overheat {
+                    above_celsius: 90.0,
+                },
+                actions: vec![AlertAction::Log, AlertAction::FullSpeed],
+            },
+            AlertRule {
+                condition: AlertCondition::RunawayTemperature {
+                    above_celsius_per_second: 1.5,
+                    over_seconds: 10.0,
+                },
+                actions: vec![AlertAction::Log, AlertAction::FullSpeed],
+            },
+        ];
+        let mut alerts = AlertManager::new(
+            rules,
+            NotifySettings::default(),
+            DisplayUnits::Celsius,
+            false,
+        );
+        let mut history = TemperatureHistory::default();
+        history.keep_for(alerts.history_seconds(), 1);
+
+        // Idle at 45°C with a brief 5°C spike at 10s; the pump fails at 30s
+        // and the temperature climbs 2°C/s until 60s
+        let trace = |second: u32| match second {
+            10 => 50.0,
+            ..=30 => 45.0,
+            31..=60 => 45.0 + 2.0 * (second - 30) as f64,
+            _ => 105.0,
+        };
+        let mut first_fired = std::collections::HashMap::new();
+        for second in 0..70 {
+            let temperature = trace(second);
+            history.push(second as f64, temperature);
+            let fired = [
+                alerts.observe_temperature("cpu", temperature),
+                alerts.observe_rate("cpu", &history),
+            ]
+            .concat();
+            for fired in fired {
+                first_fired.entry(fired.alert.name).or_insert(second);
+            }
+            if second < 30 {
+                assert!(!alerts.full_speed());
+            }
+        }
+
+        // 1.6°C/s averaged over 10s by 38s, while 90°C takes until 53s
+        assert_eq!(first_fired["runaway_temperature"], 38);
+        assert_eq!(first_fired["overheat"], 53);
+        assert!(alerts.full_speed());
+    }
+
+    #[test]
+    fn test_render_template() {
+        let alert = Alert {
+            name: "overheat",
+            message: "too hot".to_string(),
+            temperature: Some(91.25),
+            device: None,
+        };
+        assert_eq!(
+            render_template(
+                "{alert} at {temperature}°C: {message}{device}",
+                &alert,
+                DisplayUnits::Celsius
+            ),
+            "overheat at 91.2°C: too hot"
+        );
+        assert_eq!(
+            render_template("{temperature}°F", &alert, DisplayUnits::Fahrenheit),
+            "196.2°F"
+        );
+    }
+}
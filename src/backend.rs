@@ -0,0 +1,218 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+use crate::config::{ChannelMode, DeviceId, SpeedPercent};
+
+/// A single operation on a fan controller, independent of how a backend
+/// encodes it on the wire.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    SyncRgb(bool),
+    SetMode {
+        channel: usize,
+        mode: ChannelMode,
+    },
+    SetSpeed {
+        channel: usize,
+        speed_percent: SpeedPercent,
+    },
+}
+
+/// A report a hub can be asked for. The backend builds the request packet
+/// from its own protocol table, so no caller ever picks the bytes written.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Report {
+    FanCounts,
+    ChannelStates,
+    Rpms,
+}
+
+impl Report {
+    /// The report's name, as HardwareError::Unsupported gives it.
+    pub fn operation(self) -> &'static str {
+        match self {
+            Report::FanCounts => "fan count reports",
+            Report::ChannelStates => "channel state reports",
+            Report::Rpms => "RPM reports",
+        }
+    }
+}
+
+/// What a device reports about itself at discovery, to tell firmware
+/// revisions apart in bug reports. Devices may leave the strings out.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    /// The USB bcdDevice, e.g. 0x0102 for firmware 1.02.
+    pub release_number: u16,
+    /// The USB port the device is plugged into, e.g. "1-3.2". Only known on
+    /// Linux.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port_path: Option<String>,
+}
+
+impl DeviceMetadata {
+    pub fn firmware(&self) -> String {
+        format!(
+            "{:x}.{:02x}",
+            self.release_number >> 8,
+            self.release_number & 0xff
+        )
+    }
+}
+
+impl fmt::Display for DeviceMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}, firmware {}",
+            self.manufacturer
+                .as_deref()
+                .unwrap_or("unknown manufacturer"),
+            self.product.as_deref().unwrap_or("unknown product"),
+            self.firmware()
+        )?;
+        if let Some(port_path) = &self.port_path {
+            write!(f, ", on USB port {}", port_path)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum HardwareError {
+    /// The device has no way of doing this, e.g. its hub model lacks the feature.
+    Unsupported {
+        device_id: DeviceId,
+        operation: &'static str,
+    },
+    /// The device exists but can't be opened, usually because the daemon
+    /// lacks permission (or udev hasn't applied its rules yet).
+    PermissionDenied {
+        device_id: DeviceId,
+    },
+    /// A write or report read on the device's I/O thread didn't finish in time.
+    Timeout {
+        device_id: DeviceId,
+        timeout: Duration,
+    },
+    Other(anyhow::Error),
+}
+
+impl HardwareError {
+    /// Whether `error` is, or wraps, a PermissionDenied.
+    pub fn is_permission_denied(error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<HardwareError>(),
+            Some(HardwareError::PermissionDenied { .. })
+        )
+    }
+
+    /// Whether `error` is, or wraps, a Timeout.
+    pub fn is_timeout(error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<HardwareError>(),
+            Some(HardwareError::Timeout { .. })
+        )
+    }
+}
+
+impl fmt::Display for HardwareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HardwareError::Unsupported {
+                device_id,
+                operation,
+            } => write!(f, "Device {} does not support {}", device_id, operation),
+            HardwareError::PermissionDenied { device_id } => write!(
+                f,
+                "Could not open device {}; please run uni-sync with elevated permissions",
+                device_id
+            ),
+            HardwareError::Timeout { device_id, timeout } => write!(
+                f,
+                "I/O on device {} did not finish within {:?}",
+                device_id, timeout
+            ),
+            HardwareError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for HardwareError {}
+
+impl From<anyhow::Error> for HardwareError {
+    fn from(e: anyhow::Error) -> Self {
+        HardwareError::Other(e)
+    }
+}
+
+/// Talks to one device with a handle of its own, on that device's I/O
+/// thread; see FanBackend::writer.
+pub trait DeviceWriter: Send {
+    fn send(&mut self, command: &Command) -> Result<()>;
+
+    /// Asks the device for `report`, as FanBackend::read_report does.
+    fn read_report(&mut self, report: Report) -> Result<Vec<u8>, HardwareError>;
+}
+
+/// Something that can drive fan channels: Lian Li UNI hubs over HID, or
+/// motherboard headers through hwmon sysfs.
+pub trait FanBackend: Send {
+    /// Devices this backend found and can drive.
+    fn devices(&self) -> Vec<DeviceId>;
+
+    /// What `device_id` reported about itself, if the backend can tell.
+    fn metadata(&self, _device_id: &DeviceId) -> Option<DeviceMetadata> {
+        None
+    }
+
+    fn send(&mut self, device_id: &DeviceId, command: &Command) -> Result<()>;
+
+    /// A writer for `device_id` to hand to an I/O thread, or None to keep
+    /// writing through `send` and reading through `read_report`. It opens
+    /// the device on that thread, so this must not block. Wrappers that act
+    /// on each command keep the default, so their commands never bypass them.
+    fn writer(&mut self, _device_id: &DeviceId) -> Result<Option<Box<dyn DeviceWriter>>> {
+        Ok(None)
+    }
+
+    /// Looks for devices again, so `devices` reflects what is plugged in now.
+    fn rescan(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Closes and re-opens `device_id`, as the first step of a reset.
+    fn reopen(&mut self, _device_id: &DeviceId) -> Result<()> {
+        Ok(())
+    }
+
+    /// How long the device needs after `command` before it accepts the next one.
+    fn settle_time(&self, _command: &Command) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Asks the device for `report` and returns its raw reply. Only
+    /// packet-based (HID) backends can do this.
+    fn read_report(
+        &mut self,
+        device_id: &DeviceId,
+        report: Report,
+    ) -> Result<Vec<u8>, HardwareError> {
+        Err(HardwareError::Unsupported {
+            device_id: device_id.clone(),
+            operation: report.operation(),
+        })
+    }
+
+    /// Hands any channels this backend took over back to their previous
+    /// (usually automatic) control. Called once on shutdown.
+    fn release(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
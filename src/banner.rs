@@ -0,0 +1,215 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::backend::DeviceMetadata;
+use crate::config::{
+    AboveMax, BelowMin, ConfigOrigin, CurveConfig, DaemonMode, DeviceId, FanCurve,
+};
+use crate::hardware;
+use crate::style::{Color, Column, Style, Table};
+
+/// The startup summary: where the config came from, the effective global
+/// settings, every discovered device and every curve that will run.
+pub fn format_banner(
+    config_path: &Path,
+    origin: ConfigOrigin,
+    config: &CurveConfig,
+    devices: &[(DeviceId, bool, Option<DeviceMetadata>)],
+    fan_curves: &[FanCurve],
+    style: Style,
+) -> String {
+    let mut text = String::new();
+
+    let _ = write!(text, "Config: {}", config_path.display());
+    match origin {
+        ConfigOrigin::File => {}
+        ConfigOrigin::CreatedDefault => {
+            let _ = write!(text, " (created with defaults)");
+        }
+        ConfigOrigin::Defaults => {
+            let _ = write!(text, " (missing; running defaults, nothing written)");
+        }
+        ConfigOrigin::SafeMode => {
+            let _ = write!(text, " (could not be loaded; SAFE MODE, built-in curves)");
+        }
+    }
+    let _ = writeln!(text);
+    let _ = writeln!(
+        text,
+        "Interval: {}s, strict: {}, skip empty channels: {}, alert rules: {}",
+        config.interval_seconds,
+        config.strict,
+        config.skip_empty_channels,
+        config.alerts.len()
+    );
+    if config.mode == DaemonMode::Monitor {
+        let _ = writeln!(
+            text,
+            "Monitor mode: speeds are computed and reported, never written"
+        );
+    }
+
+    let _ = writeln!(text);
+    let mut table = Table::new(vec![
+        Column::left(18, 0),
+        Column::left(24, 1),
+        Column::right(8, 1),
+        Column::left(7, 2),
+        Column::left(0, 2),
+    ]);
+    table.row(
+        ["MODEL", "SERIAL", "CHANNELS", "MANAGED", "FIRMWARE"]
+            .map(String::from)
+            .to_vec(),
+        Some(Color::Bold),
+    );
+    for (device_id, managed, metadata) in devices {
+        let model = hardware::device_model(device_id);
+        table.row(
+            vec![
+                model.name.to_string(),
+                device_id.2.clone(),
+                model.channels.to_string(),
+                if *managed { "yes" } else { "no" }.to_string(),
+                metadata
+                    .as_ref()
+                    .map_or("-".to_string(), DeviceMetadata::firmware),
+            ],
+            (!managed).then_some(Color::Dim),
+        );
+    }
+    for line in table.lines(style) {
+        let _ = writeln!(text, "{}", line);
+    }
+
+    let _ = writeln!(text);
+    let mut table = Table::new(vec![
+        Column::left(24, 0),
+        Column::left(6, 1),
+        Column::right(6, 1),
+        Column::left(6, 2),
+        Column::right(8, 1),
+        Column::left(6, 2),
+        Column::left(0, 1),
+    ]);
+    table.row(
+        [
+            "CURVE", "SOURCE", "POINTS", "MODE", "SPEEDS", "BELOW", "ABOVE",
+        ]
+        .map(String::from)
+        .to_vec(),
+        Some(Color::Bold),
+    );
+    for fan_curve in fan_curves {
+        let speeds = fan_curve.curve_points.iter().map(|p| p.fan_speed_percent);
+        let range = match (speeds.clone().min(), speeds.max()) {
+            (Some(min), Some(max)) => format!("{}-{}%", min, max),
+            _ => "-".to_string(),
+        };
+        table.row(
+            vec![
+                fan_curve.label(),
+                config.temperature_source.name().to_string(),
+                fan_curve.curve_points.len().to_string(),
+                format!("{:?}", fan_curve.mode),
+                range,
+                below_min_name(fan_curve.below_min).to_string(),
+                above_max_name(fan_curve.above_max).to_string(),
+            ],
+            None,
+        );
+    }
+    for line in table.lines(style) {
+        let _ = writeln!(text, "{}", line);
+    }
+
+    text
+}
+
+fn below_min_name(below_min: BelowMin) -> &'static str {
+    match below_min {
+        BelowMin::Clamp => "clamp",
+        BelowMin::Zero => "zero",
+    }
+}
+
+fn above_max_name(above_max: AboveMax) -> &'static str {
+    match above_max {
+        AboveMax::Clamp => "clamp",
+        AboveMax::Extrapolate => "extrapolate",
+        AboveMax::FullSpeed => "full_speed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ChannelMode, CurvePoint, SpeedPercent};
+
+    #[test]
+    fn test_format_banner() {
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let header = DeviceId(0, 0, "hwmon3/pwm2".to_string());
+        let config = CurveConfig {
+            interval_seconds: 5,
+            strict: true,
+            ..Default::default()
+        };
+        let fan_curves = vec![
+            FanCurve {
+                name: Some("front".to_string()),
+                device_id: hub.clone(),
+                channel: 1,
+                curve_points: vec![
+                    CurvePoint {
+                        temperature_celsius: 30.0,
+                        fan_speed_percent: SpeedPercent::new(25).unwrap(),
+                    },
+                    CurvePoint {
+                        temperature_celsius: 80.0,
+                        fan_speed_percent: SpeedPercent::new(100).unwrap(),
+                    },
+                ],
+                above_max: AboveMax::FullSpeed,
+                ..Default::default()
+            },
+            FanCurve {
+                device_id: hub.clone(),
+                channel: 2,
+                mode: ChannelMode::PWM,
+                ..Default::default()
+            },
+        ];
+
+        assert_eq!(
+            format_banner(
+                Path::new("/etc/uni-sync-curve.json"),
+                ConfigOrigin::CreatedDefault,
+                &config,
+                &[
+                    (
+                        hub,
+                        true,
+                        Some(DeviceMetadata {
+                            release_number: 0x0102,
+                            ..Default::default()
+                        })
+                    ),
+                    (header, false, None)
+                ],
+                &fan_curves,
+                Style::plain(),
+            ),
+            "Config: /etc/uni-sync-curve.json (created with defaults)\n\
+             Interval: 5s, strict: true, skip empty channels: false, alert rules: 0\n\
+             \n\
+             MODEL              SERIAL                   CHANNELS  MANAGED  FIRMWARE\n\
+             UNI SL v2          ABC                             4  yes      1.02\n\
+             hwmon PWM header   hwmon3/pwm2                     1  no       -\n\
+             \n\
+             CURVE                    SOURCE POINTS  MODE     SPEEDS  BELOW  ABOVE\n\
+             front                    cpu         2  Manual  25-100%  clamp  full_speed\n\
+             (0cf2, a103, ABC)/2      cpu         0  PWM           -  clamp  clamp\n"
+        );
+    }
+}
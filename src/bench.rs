@@ -0,0 +1,192 @@
+use serde::Serialize;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::config::{ChannelMode, DeviceId, SpeedPercent};
+use crate::hardware::FanController;
+
+/// Command delays tried for every channel, in milliseconds.
+pub const BENCH_DELAYS_MS: [u64; 5] = [0, 25, 50, 100, 200];
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct BenchResult {
+    pub channel: usize,
+    pub delay_ms: u64,
+    pub runs: usize,
+    pub errors: usize,
+    /// Wall time of the full mode + speed write sequence.
+    pub mean_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BenchReport {
+    pub device_id: DeviceId,
+    pub results: Vec<BenchResult>,
+    pub recommended_delay_ms: Option<u64>,
+}
+
+/// Writes `speeds[channel]` to every channel `runs` times at each delay,
+/// timing each write sequence. Writing the speed a channel already runs at
+/// keeps the fans steady while benchmarking.
+pub async fn bench(
+    fan_controller: &mut FanController,
+    device_id: &DeviceId,
+    speeds: &[SpeedPercent],
+    delays_ms: &[u64],
+    runs: usize,
+) -> Vec<BenchResult> {
+    let mut results = Vec::new();
+
+    for (channel, &speed) in speeds.iter().enumerate() {
+        for &delay_ms in delays_ms {
+            fan_controller.set_command_delay(Some(Duration::from_millis(delay_ms)));
+            let mut times = Vec::new();
+            let mut errors = 0;
+            for _ in 0..runs {
+                // Time the whole mode + speed sequence every run
+                fan_controller.forget_modes();
+                let started = Instant::now();
+                match fan_controller
+                    .set_fan_speed(device_id, channel, &ChannelMode::Manual, speed)
+                    .await
+                {
+                    Ok(()) => times.push(started.elapsed().as_secs_f64() * 1000.0),
+                    Err(_) => errors += 1,
+                }
+            }
+
+            results.push(BenchResult {
+                channel,
+                delay_ms,
+                runs,
+                errors,
+                mean_ms: if times.is_empty() {
+                    0.0
+                } else {
+                    times.iter().sum::<f64>() / times.len() as f64
+                },
+                max_ms: times.iter().copied().fold(0.0, f64::max),
+            });
+        }
+    }
+
+    fan_controller.set_command_delay(None);
+    results
+}
+
+/// The smallest delay no channel failed at, or the slowest write sequence
+/// at that delay if the hub is slower than that, plus half again as margin,
+/// rounded up to 10 ms. None if every delay failed somewhere.
+pub fn recommend_delay_ms(results: &[BenchResult]) -> Option<u64> {
+    let mut delays: Vec<u64> = results.iter().map(|r| r.delay_ms).collect();
+    delays.sort_unstable();
+    delays.dedup();
+
+    let safe_delay = delays.into_iter().find(|&delay_ms| {
+        results
+            .iter()
+            .filter(|r| r.delay_ms == delay_ms)
+            .all(|r| r.errors == 0)
+    })?;
+    let slowest = results
+        .iter()
+        .filter(|r| r.delay_ms == safe_delay)
+        .map(|r| r.max_ms)
+        .fold(safe_delay as f64, f64::max);
+
+    let with_margin = (slowest * 1.5).ceil() as u64;
+    Some(with_margin.div_ceil(10).max(1) * 10)
+}
+
+pub fn format_report(report: &BenchReport) -> String {
+    let mut text = String::new();
+
+    let _ = writeln!(text, "Device {}", report.device_id);
+    let _ = writeln!(
+        text,
+        "{:>7} {:>8} {:>6} {:>9} {:>9}",
+        "CHANNEL", "DELAY", "ERRORS", "MEAN", "MAX"
+    );
+    for result in &report.results {
+        let _ = writeln!(
+            text,
+            "{:>7} {:>6}ms {:>3}/{:<2} {:>7.1}ms {:>7.1}ms",
+            result.channel,
+            result.delay_ms,
+            result.errors,
+            result.runs,
+            result.mean_ms,
+            result.max_ms
+        );
+    }
+    match report.recommended_delay_ms {
+        Some(delay_ms) => {
+            let _ = writeln!(text, "Recommended command_delay_ms: {}", delay_ms);
+        }
+        None => {
+            let _ = writeln!(text, "Every delay failed at least once; no recommendation");
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockBackend;
+
+    fn result(channel: usize, delay_ms: u64, errors: usize, max_ms: f64) -> BenchResult {
+        BenchResult {
+            channel,
+            delay_ms,
+            runs: 5,
+            errors,
+            mean_ms: max_ms,
+            max_ms,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bench() {
+        let device_id = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let backend = MockBackend::new(vec![device_id.clone()]);
+        let packets = backend.packets();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+
+        let results = bench(
+            &mut controller,
+            &device_id,
+            &[
+                SpeedPercent::new(40).unwrap(),
+                SpeedPercent::new(60).unwrap(),
+            ],
+            &[0, 5],
+            3,
+        )
+        .await;
+
+        assert_eq!(results.len(), 4);
+        assert!(results.iter().all(|r| r.runs == 3 && r.errors == 0));
+        assert!(results[1].mean_ms >= 10.0);
+        // Sync once, then mode + speed for each of 2 channels × 2 delays × 3 runs
+        assert_eq!(packets.lock().unwrap().len(), 1 + 2 * 2 * 2 * 3);
+    }
+
+    #[test]
+    fn test_recommend_delay() {
+        assert_eq!(
+            recommend_delay_ms(&[
+                result(0, 0, 1, 1.0),
+                result(1, 0, 0, 1.0),
+                result(0, 25, 0, 52.0),
+                result(1, 25, 0, 54.0),
+            ]),
+            Some(90)
+        );
+        assert_eq!(recommend_delay_ms(&[result(0, 0, 0, 0.2)]), Some(10));
+        assert_eq!(recommend_delay_ms(&[result(0, 50, 0, 20.0)]), Some(80));
+        assert_eq!(recommend_delay_ms(&[result(0, 0, 5, 0.0)]), None);
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use tokio::time::Instant;
+
+use crate::config::{DeviceId, WriteBudgetSettings};
+
+/// What a slew substep spends of a channel's budget, in writes.
+pub const SUBSTEP_WEIGHT: f64 = 0.25;
+
+/// What to do with a speed write, given the channel's budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Admission {
+    Write,
+    /// Out of budget; write the latest speed once budget is back.
+    Defer,
+    /// Out of budget, and the channel already runs at this speed.
+    Skip,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+    last_speed: Option<u8>,
+}
+
+/// A token bucket of speed writes per channel. Each holds an hour's worth
+/// of writes and refills at that rate, so a burst after startup or a reload
+/// is fine but a steady stream of writes is capped.
+#[derive(Debug)]
+pub struct WriteBudget {
+    settings: WriteBudgetSettings,
+    buckets: HashMap<(DeviceId, usize), Bucket>,
+}
+
+impl WriteBudget {
+    pub fn new(settings: WriteBudgetSettings) -> Self {
+        Self {
+            settings,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Applies new limits; budget already spent stays spent.
+    pub fn set_settings(&mut self, settings: WriteBudgetSettings) {
+        self.settings = settings;
+        let capacity = settings.max_writes_per_hour as f64;
+        for bucket in self.buckets.values_mut() {
+            bucket.tokens = bucket.tokens.min(capacity);
+        }
+    }
+
+    /// Decides on writing `speed` to a channel at `now`, spending a write
+    /// when the answer is Write.
+    pub fn admit(
+        &mut self,
+        device_id: &DeviceId,
+        channel: usize,
+        speed: u8,
+        now: Instant,
+    ) -> Admission {
+        if self.settings.max_writes_per_hour == 0 {
+            return Admission::Write;
+        }
+        let urgent_delta_percent = self.settings.urgent_delta_percent;
+        let bucket = self.refilled(device_id, channel, now);
+        let urgent = bucket
+            .last_speed
+            .is_none_or(|last| last.abs_diff(speed) > urgent_delta_percent);
+        if bucket.tokens < 1.0 && !urgent {
+            return if bucket.last_speed == Some(speed) {
+                Admission::Skip
+            } else {
+                Admission::Defer
+            };
+        }
+        bucket.tokens = (bucket.tokens - 1.0).max(0.0);
+        bucket.last_speed = Some(speed);
+        Admission::Write
+    }
+
+    /// Decides on writing a slew substep of `speed` to a channel at `now`,
+    /// spending SUBSTEP_WEIGHT when it may. Substeps are never deferred, and
+    /// only go out while a full write is left for the speed they lead to.
+    pub fn admit_substep(
+        &mut self,
+        device_id: &DeviceId,
+        channel: usize,
+        speed: u8,
+        now: Instant,
+    ) -> bool {
+        if self.settings.max_writes_per_hour == 0 {
+            return true;
+        }
+        let bucket = self.refilled(device_id, channel, now);
+        if bucket.tokens < 1.0 + SUBSTEP_WEIGHT {
+            return false;
+        }
+        bucket.tokens -= SUBSTEP_WEIGHT;
+        bucket.last_speed = Some(speed);
+        true
+    }
+
+    fn refilled(&mut self, device_id: &DeviceId, channel: usize, now: Instant) -> &mut Bucket {
+        let capacity = self.settings.max_writes_per_hour as f64;
+        let bucket = self
+            .buckets
+            .entry((device_id.clone(), channel))
+            .or_insert(Bucket {
+                tokens: capacity,
+                refilled_at: now,
+                last_speed: None,
+            });
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * capacity / 3600.0).min(capacity);
+        bucket.refilled_at = now;
+        bucket
+    }
+
+    /// Whole writes left for a channel as of its last admission, or None
+    /// without a cap or before its first write.
+    pub fn remaining(&self, device_id: &DeviceId, channel: usize) -> Option<u32> {
+        if self.settings.max_writes_per_hour == 0 {
+            return None;
+        }
+        self.buckets
+            .get(&(device_id.clone(), channel))
+            .map(|bucket| bucket.tokens as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_write_budget() {
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let mut budget = WriteBudget::new(WriteBudgetSettings {
+            max_writes_per_hour: 4,
+            urgent_delta_percent: 20,
+        });
+
+        // The first hour's budget may go at once
+        for (second, speed) in [(0, 40), (1, 42), (2, 44), (3, 46)] {
+            assert_eq!(budget.admit(&hub, 0, speed, at(second)), Admission::Write);
+        }
+        assert_eq!(budget.remaining(&hub, 0), Some(0));
+        assert_eq!(budget.admit(&hub, 0, 48, at(4)), Admission::Defer);
+        assert_eq!(budget.admit(&hub, 0, 46, at(5)), Admission::Skip);
+        // Other channels have their own budget
+        assert_eq!(budget.admit(&hub, 1, 48, at(5)), Admission::Write);
+
+        // A big change goes through anyway, without going into debt
+        assert_eq!(budget.admit(&hub, 0, 70, at(6)), Admission::Write);
+        assert_eq!(budget.remaining(&hub, 0), Some(0));
+        assert_eq!(budget.admit(&hub, 0, 72, at(7)), Admission::Defer);
+
+        // One write refills every 15 minutes
+        assert_eq!(budget.admit(&hub, 0, 72, at(6 + 899)), Admission::Defer);
+        assert_eq!(budget.admit(&hub, 0, 72, at(6 + 901)), Admission::Write);
+        assert_eq!(budget.admit(&hub, 0, 74, at(6 + 902)), Admission::Defer);
+
+        // Refills stop at an hour's worth
+        assert_eq!(budget.admit(&hub, 0, 74, at(10 * 3600)), Admission::Write);
+        assert_eq!(budget.remaining(&hub, 0), Some(3));
+
+        budget.set_settings(WriteBudgetSettings {
+            max_writes_per_hour: 0,
+            urgent_delta_percent: 20,
+        });
+        assert_eq!(budget.admit(&hub, 0, 74, at(10 * 3600)), Admission::Write);
+        assert_eq!(budget.remaining(&hub, 0), None);
+    }
+
+    #[test]
+    fn test_substeps_spend_less() {
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let now = Instant::now();
+        let mut budget = WriteBudget::new(WriteBudgetSettings {
+            max_writes_per_hour: 4,
+            urgent_delta_percent: 20,
+        });
+
+        // Twelve substeps cost three writes, and the fourth is kept for the
+        // speed they lead to
+        let admitted = (0..20)
+            .filter(|step| budget.admit_substep(&hub, 0, 40 + step, now))
+            .count();
+        assert_eq!(admitted, 12);
+        assert_eq!(budget.admit(&hub, 0, 60, now), Admission::Write);
+        assert_eq!(budget.remaining(&hub, 0), Some(0));
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::config::{self, CurveConfig, DeviceId, FanCurve};
+use crate::hardware;
+
+/// Bundles written by a newer version may hold settings this one can't read.
+pub const BUNDLE_VERSION: u32 = 1;
+
+// What ties a curve to one machine's hardware, or is chosen on import
+const LOCAL_FIELDS: [&str; 3] = ["name", "device_id", "channel"];
+
+/// Curves to share with another machine: every setting of each curve,
+/// without the device and channel it drives.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Bundle {
+    pub version: u32,
+    pub curves: Vec<BundleCurve>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BundleCurve {
+    pub name: String,
+    /// The curve's fields as in the config file, minus LOCAL_FIELDS.
+    #[serde(flatten)]
+    pub settings: Map<String, Value>,
+}
+
+/// `name`, or `name-2`, `name-3`, ... if that is taken.
+pub fn unique_name(name: &str, taken: &[String]) -> String {
+    (1..)
+        .map(|n| match n {
+            1 => name.to_string(),
+            n => format!("{}-{}", name, n),
+        })
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap()
+}
+
+/// Bundles `fan_curves`. Unnamed curves are labelled by their device, so
+/// they are called curve-1, curve-2, ... by their place instead.
+pub fn export(fan_curves: &[FanCurve]) -> Result<Bundle> {
+    let mut names: Vec<String> = fan_curves
+        .iter()
+        .filter_map(|fan_curve| fan_curve.name.clone())
+        .collect();
+    let mut curves = Vec::new();
+    for (index, fan_curve) in fan_curves.iter().enumerate() {
+        let name = match &fan_curve.name {
+            Some(name) => name.clone(),
+            None => {
+                let name = unique_name(&format!("curve-{}", index + 1), &names);
+                names.push(name.clone());
+                name
+            }
+        };
+        let Value::Object(mut settings) = serde_json::to_value(fan_curve)? else {
+            bail!("Curve {} did not serialize to an object", fan_curve.label());
+        };
+        for field in LOCAL_FIELDS {
+            settings.remove(field);
+        }
+        curves.push(BundleCurve { name, settings });
+    }
+    Ok(Bundle {
+        version: BUNDLE_VERSION,
+        curves,
+    })
+}
+
+/// Parses where an imported curve goes, as "vid:pid:serial/channel".
+pub fn parse_target(s: &str) -> Result<(DeviceId, usize)> {
+    let Some((device, channel)) = s.rsplit_once('/') else {
+        bail!("Expected vid:pid:serial/channel, got {:?}", s);
+    };
+    let device_id: DeviceId = device.parse()?;
+    let channel: usize = channel
+        .parse()
+        .with_context(|| format!("Invalid channel {:?} in {:?}", channel, s))?;
+    let channels = hardware::device_model(&device_id).channels;
+    if channel >= channels {
+        bail!(
+            "Device {} has channels 0-{}, not {}",
+            device_id,
+            channels - 1,
+            channel
+        );
+    }
+    Ok((device_id, channel))
+}
+
+/// Adds the bundle's curves to `config`, each on the channel `targets` gives
+/// for its name; curves without one are skipped. An imported curve replaces
+/// the one already on its channel, and gets a suffix if its name is taken.
+/// Returns a note for each curve.
+pub fn import(
+    config: &mut CurveConfig,
+    bundle: &Bundle,
+    targets: &BTreeMap<String, (DeviceId, usize)>,
+) -> Result<Vec<String>> {
+    let mut notes = Vec::new();
+    for curve in &bundle.curves {
+        let Some((device_id, channel)) = targets.get(&curve.name) else {
+            notes.push(format!("Skipped curve {}", curve.name));
+            continue;
+        };
+
+        if let Some(index) = config.fan_curves.iter().position(|fan_curve| {
+            fan_curve.device_id == *device_id && fan_curve.channel == *channel
+        }) {
+            let replaced = config.fan_curves.remove(index);
+            notes.push(format!(
+                "Curve {} replaces curve {} on {}/{}",
+                curve.name,
+                replaced.label(),
+                device_id,
+                channel
+            ));
+        }
+        let labels: Vec<String> = config.fan_curves.iter().map(FanCurve::label).collect();
+        let name = unique_name(&curve.name, &labels);
+
+        let mut fields = curve.settings.clone();
+        fields.insert("name".to_string(), Value::String(name.clone()));
+        fields.insert("device_id".to_string(), serde_json::to_value(device_id)?);
+        fields.insert("channel".to_string(), Value::from(*channel));
+        let fan_curve: FanCurve = serde_json::from_value(Value::Object(fields))
+            .with_context(|| format!("Invalid curve {} in the bundle", curve.name))?;
+        config.fan_curves.push(fan_curve);
+        if name == curve.name {
+            notes.push(format!(
+                "Imported curve {} on {}/{}",
+                name, device_id, channel
+            ));
+        } else {
+            notes.push(format!(
+                "Imported curve {} as {} on {}/{}, the name was taken",
+                curve.name, name, device_id, channel
+            ));
+        }
+    }
+    config::validate_config(config)?;
+    Ok(notes)
+}
+
+pub fn read_bundle(path: &Path) -> Result<Bundle> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Could not read {:?}", path))?;
+    let bundle: Bundle =
+        serde_json::from_str(&content).with_context(|| format!("Invalid bundle {:?}", path))?;
+    if bundle.version > BUNDLE_VERSION {
+        bail!(
+            "Bundle {:?} is version {}; this version of uni-sync-curve reads up to {}",
+            path,
+            bundle.version,
+            BUNDLE_VERSION
+        );
+    }
+    Ok(bundle)
+}
+
+pub fn write_bundle(path: &Path, bundle: &Bundle) -> Result<()> {
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, serde_json::to_string_pretty(bundle)?)?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Anticipation, ChannelMode, CurvePoint, SpeedPercent};
+
+    fn curve(name: Option<&str>, device_id: &DeviceId, channel: usize) -> FanCurve {
+        FanCurve {
+            name: name.map(str::to_string),
+            device_id: device_id.clone(),
+            channel,
+            curve_points: vec![
+                CurvePoint {
+                    temperature_celsius: 30.0,
+                    fan_speed_percent: SpeedPercent::new(20).unwrap(),
+                },
+                CurvePoint {
+                    temperature_celsius: 80.0,
+                    fan_speed_percent: SpeedPercent::new(100).unwrap(),
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let hub = DeviceId(0x0cf2, 0xa103, "6243A4A3".to_string());
+        let front = FanCurve {
+            group: Some("intake".to_string()),
+            anticipation: Some(Anticipation {
+                degrees_per_second_gain: 2.0,
+                max_boost_percent: 15,
+            }),
+            speed_multiplier: 0.9,
+            never_stop: true,
+            ..curve(Some("front"), &hub, 0)
+        };
+        let rear = FanCurve {
+            mode: ChannelMode::PWM,
+            ..curve(None, &hub, 1)
+        };
+        let original = CurveConfig {
+            fan_curves: vec![front, rear],
+            ..Default::default()
+        };
+
+        let bundle = export(&original.fan_curves).unwrap();
+        let text = serde_json::to_string(&bundle).unwrap();
+        assert!(!text.contains("6243A4A3"));
+        assert_eq!(bundle.curves[1].name, "curve-2");
+
+        let targets = BTreeMap::from([
+            ("front".to_string(), (hub.clone(), 0)),
+            ("curve-2".to_string(), (hub.clone(), 1)),
+        ]);
+        let mut imported = CurveConfig::default();
+        import(&mut imported, &bundle, &targets).unwrap();
+
+        let mut expected = original.fan_curves.clone();
+        expected[1].name = Some("curve-2".to_string());
+        assert_eq!(
+            serde_json::to_value(&imported.fan_curves).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_import_merges() {
+        let hub = DeviceId(0x0cf2, 0xa103, "LOCAL".to_string());
+        let mut config = CurveConfig {
+            fan_curves: vec![curve(Some("front"), &hub, 0), curve(Some("top"), &hub, 2)],
+            ..Default::default()
+        };
+        let bundle = export(&[
+            curve(Some("front"), &hub, 0),
+            curve(Some("top"), &hub, 1),
+            curve(Some("rear"), &hub, 3),
+        ])
+        .unwrap();
+        let targets = BTreeMap::from([
+            ("front".to_string(), (hub.clone(), 1)),
+            ("top".to_string(), (hub.clone(), 2)),
+        ]);
+
+        let notes = import(&mut config, &bundle, &targets).unwrap();
+        let labels: Vec<String> = config.fan_curves.iter().map(FanCurve::label).collect();
+        // The local top is replaced, so the imported one keeps its name
+        assert_eq!(labels, vec!["front", "front-2", "top"]);
+        assert_eq!(notes.len(), 4);
+        assert!(notes.contains(&"Skipped curve rear".to_string()));
+
+        assert!(parse_target("0cf2:a103:LOCAL/3").is_ok());
+        assert!(parse_target("0cf2:a103:LOCAL/4").is_err());
+        assert!(parse_target("0cf2:a103:LOCAL").is_err());
+    }
+}
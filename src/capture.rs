@@ -0,0 +1,214 @@
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::backend::{Command, DeviceMetadata, FanBackend, HardwareError, Report};
+use crate::config::DeviceId;
+use crate::hardware::{encode_packet, report_request};
+
+pub type CaptureSink = Arc<Mutex<dyn Write + Send>>;
+
+/// Opens a capture file for appending, one line per packet.
+pub fn open(path: &Path) -> Result<CaptureSink> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Could not open capture file {:?}", path))?;
+    Ok(Arc::new(Mutex::new(LineWriter::new(file))))
+}
+
+/// Records every packet written to and read from the wrapped backend's
+/// devices, as lines of
+///
+/// ```text
+/// <unix time> <vid:pid:serial> <W|R> <hex bytes> [! error]
+/// ```
+///
+/// Writes are recorded as the HID backend encodes them. Commands to hwmon
+/// headers have no packet and are recorded as the command instead.
+pub struct CaptureBackend {
+    inner: Box<dyn FanBackend>,
+    sink: CaptureSink,
+}
+
+impl CaptureBackend {
+    pub fn new(inner: Box<dyn FanBackend>, sink: CaptureSink) -> Self {
+        Self { inner, sink }
+    }
+
+    fn record(&self, device_id: &DeviceId, direction: char, data: &str, error: Option<String>) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format_line(time, device_id, direction, data, error.as_deref());
+        // A capture that can't be written must not break fan control
+        let _ = writeln!(self.sink.lock().unwrap(), "{}", line);
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len() * 3);
+    for (i, byte) in bytes.iter().enumerate() {
+        if i > 0 {
+            text.push(' ');
+        }
+        let _ = write!(text, "{:02x}", byte);
+    }
+    text
+}
+
+fn format_line(
+    time: Duration,
+    device_id: &DeviceId,
+    direction: char,
+    data: &str,
+    error: Option<&str>,
+) -> String {
+    let mut line = format!(
+        "{}.{:06} {:04x}:{:04x}:{} {} {}",
+        time.as_secs(),
+        time.subsec_micros(),
+        device_id.0,
+        device_id.1,
+        device_id.2,
+        direction,
+        data
+    );
+    if let Some(error) = error {
+        let _ = write!(line, " ! {}", error);
+    }
+    line
+}
+
+impl FanBackend for CaptureBackend {
+    fn devices(&self) -> Vec<DeviceId> {
+        self.inner.devices()
+    }
+
+    fn metadata(&self, device_id: &DeviceId) -> Option<DeviceMetadata> {
+        self.inner.metadata(device_id)
+    }
+
+    fn send(&mut self, device_id: &DeviceId, command: &Command) -> Result<()> {
+        let result = self.inner.send(device_id, command);
+        let packet = if device_id.is_sysfs_pwm() {
+            None
+        } else {
+            encode_packet(device_id.1, command).ok()
+        };
+        let data = match packet {
+            Some(packet) => hex(&packet),
+            None => format!("{:?}", command),
+        };
+        self.record(
+            device_id,
+            'W',
+            &data,
+            result.as_ref().err().map(|e| e.to_string()),
+        );
+        result
+    }
+
+    fn rescan(&mut self) -> Result<()> {
+        self.inner.rescan()
+    }
+
+    fn reopen(&mut self, device_id: &DeviceId) -> Result<()> {
+        self.inner.reopen(device_id)
+    }
+
+    fn settle_time(&self, command: &Command) -> Duration {
+        self.inner.settle_time(command)
+    }
+
+    fn read_report(
+        &mut self,
+        device_id: &DeviceId,
+        report: Report,
+    ) -> Result<Vec<u8>, HardwareError> {
+        let result = self.inner.read_report(device_id, report);
+        let request = match report_request(device_id.1, report) {
+            Some(request) => hex(&request),
+            None => format!("{:?}", report),
+        };
+        match &result {
+            Ok(reply) => {
+                self.record(device_id, 'W', &request, None);
+                self.record(device_id, 'R', &hex(reply), None);
+            }
+            // Unsupported requests never reach the device
+            Err(HardwareError::Unsupported { .. }) => {}
+            Err(e) => self.record(device_id, 'W', &request, Some(e.to_string())),
+        }
+        result
+    }
+
+    fn release(&mut self) -> Result<()> {
+        self.inner.release()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ChannelMode;
+    use crate::mock::MockBackend;
+
+    #[test]
+    fn test_capture() {
+        let device_id = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let backend = MockBackend::new(vec![device_id.clone()])
+            .with_reply(vec![224, 80, 0, 0], vec![224, 80, 1, 2, 3, 4]);
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let mut capture = CaptureBackend::new(Box::new(backend), buffer.clone());
+
+        capture
+            .send(
+                &device_id,
+                &Command::SetMode {
+                    channel: 0,
+                    mode: ChannelMode::Manual,
+                },
+            )
+            .unwrap();
+        capture.read_report(&device_id, Report::FanCounts).unwrap();
+        assert!(capture
+            .read_report(&device_id, Report::ChannelStates)
+            .is_err());
+
+        let text = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = text
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0cf2:a103:ABC W e0 10 62 10",
+                "0cf2:a103:ABC W e0 50 00 00",
+                "0cf2:a103:ABC R e0 50 01 02 03 04",
+                "0cf2:a103:ABC W e0 51 00 00 ! No reply from (0cf2, a103, ABC)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_line() {
+        let device_id = DeviceId(0, 0, "hwmon3/pwm2".to_string());
+        assert_eq!(
+            format_line(
+                Duration::new(1700000000, 1_500_000),
+                &device_id,
+                'W',
+                "SetSpeed { channel: 0, speed_percent: 40 }",
+                None
+            ),
+            "1700000000.001500 0000:0000:hwmon3/pwm2 W SetSpeed { channel: 0, speed_percent: 40 }"
+        );
+    }
+}
@@ -1,11 +1,124 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
+use crate::expr::Transform;
+use crate::gpu::{Gpu, GpuSelector, GpuSensorKind};
+use crate::lock::{ConfigLock, CONFIG_LOCK_TIMEOUT};
+use crate::units::{self, DisplayUnits, TemperatureUnit};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 // (vendor_id, product_id, serial_number)
+//
+// Serials are compared normalized, so "A02B 1C3D" from one tool matches
+// "a02b1c3d" from another; the original text is kept for display.
 pub struct DeviceId(pub u16, pub u16, pub String);
 
+impl DeviceId {
+    /// Motherboard headers found through hwmon use vendor and product id 0.
+    pub fn is_sysfs_pwm(&self) -> bool {
+        self.0 == 0 && self.1 == 0
+    }
+
+    pub fn normalized_serial(&self) -> String {
+        normalize_serial(&self.2)
+    }
+
+    /// A candidate with the same vendor and product id whose serial is one
+    /// character off, for "did you mean" hints.
+    pub fn closest_match<'a>(&self, candidates: &'a [DeviceId]) -> Option<&'a DeviceId> {
+        let serial = self.normalized_serial();
+        candidates.iter().find(|candidate| {
+            candidate.0 == self.0
+                && candidate.1 == self.1
+                && within_one_edit(&serial, &candidate.normalized_serial())
+        })
+    }
+}
+
+impl std::str::FromStr for DeviceId {
+    type Err = anyhow::Error;
+
+    /// Parses "vid:pid:serial" with hexadecimal ids, e.g. "0cf2:a103:A02B1C3D".
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(vid), Some(pid), Some(serial)) => Ok(DeviceId(
+                u16::from_str_radix(vid, 16)?,
+                u16::from_str_radix(pid, 16)?,
+                serial.to_string(),
+            )),
+            _ => anyhow::bail!("Expected a device as vid:pid:serial, got {:?}", s),
+        }
+    }
+}
+
+impl PartialEq for DeviceId {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+            && self.1 == other.1
+            && self.normalized_serial() == other.normalized_serial()
+    }
+}
+
+impl Eq for DeviceId {}
+
+/// Devices sort by vendor id, product id and then normalized serial, so
+/// everything listing them does so in the same order on every run.
+impl Ord for DeviceId {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.0, self.1, self.normalized_serial()).cmp(&(
+            other.0,
+            other.1,
+            other.normalized_serial(),
+        ))
+    }
+}
+
+impl PartialOrd for DeviceId {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::hash::Hash for DeviceId {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+        self.1.hash(state);
+        self.normalized_serial().hash(state);
+    }
+}
+
+/// Drops all whitespace and uppercases, e.g. " a02b 1c3d" -> "A02B1C3D".
+pub fn normalize_serial(serial: &str) -> String {
+    serial
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+/// Whether `a` becomes `b` with at most one inserted, removed or replaced character.
+fn within_one_edit(a: &str, b: &str) -> bool {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if long.len() - short.len() > 1 {
+        return false;
+    }
+
+    let prefix = short.iter().zip(&long).take_while(|(x, y)| x == y).count();
+    if prefix == short.len() {
+        return true;
+    }
+    if short.len() == long.len() {
+        short[prefix + 1..] == long[prefix + 1..]
+    } else {
+        short[prefix..] == long[prefix + 1..]
+    }
+}
+
 impl std::fmt::Display for DeviceId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "({:04x}, {:04x}, {})", self.0, self.1, self.2)
@@ -16,76 +129,2814 @@ impl std::fmt::Display for DeviceId {
 pub struct CurveConfig {
     pub interval_seconds: u64,
     pub fan_curves: Vec<FanCurve>,
+    #[serde(default)]
+    pub devices: Vec<DeviceSettings>,
+    #[serde(default)]
+    pub alerts: Vec<AlertRule>,
+    #[serde(default)]
+    pub notify: NotifySettings,
+    /// Devices this instance may drive. Unset means every discovered device;
+    /// use disjoint lists to run several instances side by side.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub managed_devices: Option<Vec<DeviceId>>,
+    /// Verify every curve evaluation against the curve contract and abort on
+    /// a violation. Debug builds always verify, but only log.
+    #[serde(default)]
+    pub strict: bool,
+    /// Ignore curves for channels the hub reports as having no fans.
+    #[serde(default)]
+    pub skip_empty_channels: bool,
+    /// Ask v2 hubs (SL v2, AL v2) how many fans each channel has, what each
+    /// channel was doing at startup, and how fast the fans spin. The report
+    /// packets are guessed, not taken from a capture, so this is
+    /// experimental and off until captured bytes replace them.
+    #[serde(default)]
+    pub experimental_hub_reports: bool,
+    /// Wait between commands to a device, instead of each model's default.
+    /// `uni-sync-curve bench` recommends a value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_delay_ms: Option<u64>,
+    /// Open, write to and read from each hub on an OS thread of its own,
+    /// and give up on a write or report read that takes longer than this,
+    /// quarantining the hub. Unset does all of it from the daemon's loop.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io_timeout_ms: Option<u64>,
+    /// When the first ticks all take longer than `interval_seconds`, tick
+    /// as often as the hardware sustains instead of overrunning every tick.
+    #[serde(default)]
+    pub auto_stretch_interval: bool,
+    /// For this long after startup, speeds ramp linearly from
+    /// `startup_speed_percent` to the curve targets instead of jumping there.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub startup_ramp_seconds: Option<u64>,
+    #[serde(default)]
+    pub startup_speed_percent: SpeedPercent,
+    /// On exit, put channels back into the mode and speed read from the hub
+    /// at startup. Only hubs that report their channel state, with
+    /// `experimental_hub_reports` on, can be restored, and none are until
+    /// the report's layout is confirmed from a capture; until then channels
+    /// keep the daemon's last write, as without this.
+    #[serde(default)]
+    pub restore_on_exit: bool,
+    /// How long stopping may take, restoring channels included, before the
+    /// daemon gives up on what is left and exits anyway.
+    #[serde(default = "default_shutdown_timeout_seconds")]
+    pub shutdown_timeout_seconds: u64,
+    #[serde(default)]
+    pub on_permission_error: PermissionPolicy,
+    #[serde(default)]
+    pub temperature_source: TemperatureSource,
+    /// Sources tried in order while `temperature_source` has no plausible
+    /// reading. The source only counts as failed once all of them fail.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_sources: Vec<TemperatureSource>,
+    /// Most a source's `reading_latency_seconds` may move its reading, up or
+    /// down, in °C.
+    #[serde(default = "default_max_extrapolation_celsius")]
+    pub max_extrapolation_celsius: f64,
+    /// Speed used where a curve can't give one. Curves without points are
+    /// rejected, so this only matters if curve evaluation has a bug.
+    #[serde(default = "default_fallback_speed_percent")]
+    pub fallback_speed_percent: SpeedPercent,
+    /// Read fan RPMs back a few seconds after each speed write and re-send
+    /// writes the hub seems to have ignored. v2 hubs with `experimental_hub_reports` only.
+    #[serde(default)]
+    pub verify_writes: bool,
+    /// Unit temperatures are shown in: logs, status and command output.
+    /// Curve points stay in Celsius.
+    #[serde(default)]
+    pub display_units: DisplayUnits,
+    /// Every this many seconds, re-send every channel's mode and speed, one
+    /// device per tick, in case a hub reset unnoticed. 0 disables it.
+    #[serde(default = "default_reconcile_every_seconds")]
+    pub reconcile_every_seconds: u64,
+    /// Most the reconciliation period may vary at random, in percent of it,
+    /// so machines started together don't reconcile in step. The tick
+    /// interval is never jittered.
+    #[serde(default)]
+    pub timer_jitter_percent: u8,
+    /// How many of the last speed commands the daemon keeps for
+    /// `uni-sync-curve journal`.
+    #[serde(default = "default_journal_entries")]
+    pub journal_entries: usize,
+    /// The lowest speed of curves marked `never_stop`.
+    #[serde(default = "default_never_stop_speed_percent")]
+    pub never_stop_speed_percent: SpeedPercent,
+    /// When the temperature source counts as degraded, lost and recovered.
+    #[serde(default)]
+    pub source_health: SourceHealthSettings,
+    /// How often each channel's speed may be written, to spare the hub's flash.
+    #[serde(default)]
+    pub write_budget: WriteBudgetSettings,
+    /// The check a channel must pass before a curve may stop its fans.
+    #[serde(default)]
+    pub zero_rpm_check: ZeroRpmCheckSettings,
+    /// Whether the daemon drives fans at all. Takes a restart to change.
+    #[serde(default)]
+    pub mode: DaemonMode,
+    /// How each power state changes the speeds, e.g. quieter on battery.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub power_profiles: BTreeMap<PowerState, PowerProfile>,
+    /// Left by `uni-sync-curve init` to say why it picked the
+    /// `temperature_source` it did. Never read.
+    #[serde(rename = "_note", default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl Default for CurveConfig {
+    fn default() -> Self {
+        Self {
+            interval_seconds: 0,
+            fan_curves: Vec::new(),
+            devices: Vec::new(),
+            alerts: Vec::new(),
+            notify: NotifySettings::default(),
+            managed_devices: None,
+            strict: false,
+            skip_empty_channels: false,
+            experimental_hub_reports: false,
+            command_delay_ms: None,
+            io_timeout_ms: None,
+            auto_stretch_interval: false,
+            startup_ramp_seconds: None,
+            startup_speed_percent: SpeedPercent::ZERO,
+            restore_on_exit: false,
+            shutdown_timeout_seconds: default_shutdown_timeout_seconds(),
+            on_permission_error: PermissionPolicy::default(),
+            temperature_source: TemperatureSource::default(),
+            fallback_sources: Vec::new(),
+            max_extrapolation_celsius: default_max_extrapolation_celsius(),
+            fallback_speed_percent: default_fallback_speed_percent(),
+            verify_writes: false,
+            display_units: DisplayUnits::default(),
+            reconcile_every_seconds: default_reconcile_every_seconds(),
+            timer_jitter_percent: 0,
+            journal_entries: default_journal_entries(),
+            never_stop_speed_percent: default_never_stop_speed_percent(),
+            source_health: SourceHealthSettings::default(),
+            write_budget: WriteBudgetSettings::default(),
+            zero_rpm_check: ZeroRpmCheckSettings::default(),
+            mode: DaemonMode::default(),
+            power_profiles: BTreeMap::new(),
+            note: None,
+        }
+    }
+}
+
+impl CurveConfig {
+    /// `temperature_source`, then each of `fallback_sources`.
+    pub fn source_chain(&self) -> Vec<TemperatureSource> {
+        std::iter::once(self.temperature_source.clone())
+            .chain(self.fallback_sources.iter().cloned())
+            .collect()
+    }
+}
+
+/// Where the temperature every curve follows comes from. Every source may
+/// `transform` its raw reading, e.g. `"x * 0.001 - 273.15"` for one in
+/// milli-Kelvin. A source whose readings lag can give its
+/// `reading_latency_seconds`; the curves then follow the reading carried
+/// forward along its trend by that long.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TemperatureSource {
+    /// The hottest CPU sensor.
+    Cpu {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        transform: Option<Transform>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reading_latency_seconds: Option<f64>,
+    },
+    /// The hottest SoC sensor, such as the package of a mini PC's SoC with
+    /// its iGPU, or the chipset, which the CPU's sensors miss.
+    Soc {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        transform: Option<Transform>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reading_latency_seconds: Option<f64>,
+    },
+    /// A GPU sensor. Without `gpu`, the hottest such sensor over all GPUs;
+    /// `uni-sync-curve list-sensors` shows the index and PCI bus id to pick one.
+    Gpu {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        gpu: Option<GpuSelector>,
+        #[serde(default)]
+        sensor: GpuSensorKind,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        transform: Option<Transform>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reading_latency_seconds: Option<f64>,
+    },
+}
+
+impl Default for TemperatureSource {
+    fn default() -> Self {
+        TemperatureSource::Cpu {
+            transform: None,
+            reading_latency_seconds: None,
+        }
+    }
+}
+
+impl TemperatureSource {
+    pub fn transform(&self) -> Option<&Transform> {
+        match self {
+            TemperatureSource::Cpu { transform, .. }
+            | TemperatureSource::Soc { transform, .. }
+            | TemperatureSource::Gpu { transform, .. } => transform.as_ref(),
+        }
+    }
+
+    /// How old the source's readings already are when they are read, such
+    /// as a controller's own sampling period. 0 unless configured.
+    pub fn reading_latency_seconds(&self) -> f64 {
+        match self {
+            TemperatureSource::Cpu {
+                reading_latency_seconds,
+                ..
+            }
+            | TemperatureSource::Soc {
+                reading_latency_seconds,
+                ..
+            }
+            | TemperatureSource::Gpu {
+                reading_latency_seconds,
+                ..
+            } => reading_latency_seconds.unwrap_or(0.0),
+        }
+    }
+
+    /// The source's value for a raw reading of its sensor.
+    pub fn apply(&self, raw: f64) -> f64 {
+        self.transform()
+            .map_or(raw, |transform| transform.eval(raw))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TemperatureSource::Cpu { .. } => "cpu",
+            TemperatureSource::Soc { .. } => "soc",
+            TemperatureSource::Gpu { .. } => "gpu",
+        }
+    }
+
+    /// Identifies what the source reads, so sources written differently in
+    /// the config but reading the same sensor share one read per tick. The
+    /// transform isn't part of it; each source applies its own.
+    pub fn key(&self) -> String {
+        match self {
+            TemperatureSource::Cpu { .. } => "cpu".to_string(),
+            TemperatureSource::Soc { .. } => "soc".to_string(),
+            TemperatureSource::Gpu { gpu, sensor, .. } => {
+                let gpu = match gpu {
+                    None => "hottest".to_string(),
+                    Some(GpuSelector::Index(index)) => index.to_string(),
+                    Some(GpuSelector::BusId(bus_id)) => bus_id.trim().to_ascii_lowercase(),
+                };
+                format!("gpu {} {:?}", gpu, sensor).to_ascii_lowercase()
+            }
+        }
+    }
+}
+
+/// Thresholds of the temperature source's availability, in consecutive
+/// reads. While the source is lost, every channel runs at the fallback speed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceHealthSettings {
+    #[serde(default = "default_degraded_after_failures")]
+    pub degraded_after_failures: u32,
+    #[serde(default = "default_lost_after_failures")]
+    pub lost_after_failures: u32,
+    #[serde(default = "default_recovered_after_successes")]
+    pub recovered_after_successes: u32,
+}
+
+impl Default for SourceHealthSettings {
+    fn default() -> Self {
+        Self {
+            degraded_after_failures: default_degraded_after_failures(),
+            lost_after_failures: default_lost_after_failures(),
+            recovered_after_successes: default_recovered_after_successes(),
+        }
+    }
+}
+
+fn default_degraded_after_failures() -> u32 {
+    2
+}
+
+fn default_lost_after_failures() -> u32 {
+    5
+}
+
+fn default_recovered_after_successes() -> u32 {
+    3
+}
+
+/// Caps speed writes per channel. Past the cap, changes wait for budget with
+/// only the latest kept, unless they move the speed by more than
+/// `urgent_delta_percent` from the last write.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteBudgetSettings {
+    /// 0 removes the cap.
+    #[serde(default = "default_max_writes_per_hour")]
+    pub max_writes_per_hour: u32,
+    #[serde(default = "default_urgent_delta_percent")]
+    pub urgent_delta_percent: u8,
+}
+
+impl Default for WriteBudgetSettings {
+    fn default() -> Self {
+        Self {
+            max_writes_per_hour: default_max_writes_per_hour(),
+            urgent_delta_percent: default_urgent_delta_percent(),
+        }
+    }
+}
+
+fn default_max_writes_per_hour() -> u32 {
+    720
+}
+
+fn default_urgent_delta_percent() -> u8 {
+    20
+}
+
+/// Before a curve with `below_min: "zero"` may stop its channel, the daemon
+/// runs the channel at `test_speed_percent` once and checks that its RPM
+/// looks like fans. A pump mislabeled as fans spins much faster, and must
+/// never be stopped. Results are kept, so each channel is checked once.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ZeroRpmCheckSettings {
+    /// Without it, curves stop their channels unchecked.
+    #[serde(default = "default_zero_rpm_check_required")]
+    pub required: bool,
+    #[serde(default = "default_zero_rpm_test_speed_percent")]
+    pub test_speed_percent: SpeedPercent,
+    /// How long the channel runs at the test speed before its RPM is read.
+    #[serde(default = "default_zero_rpm_settle_seconds")]
+    pub settle_seconds: u64,
+    /// Below this, nothing on the channel is spinning.
+    #[serde(default = "default_zero_rpm_min_fan_rpm")]
+    pub min_fan_rpm: u16,
+    /// Above this, the channel looks like a pump.
+    #[serde(default = "default_zero_rpm_max_fan_rpm")]
+    pub max_fan_rpm: u16,
+}
+
+impl Default for ZeroRpmCheckSettings {
+    fn default() -> Self {
+        Self {
+            required: default_zero_rpm_check_required(),
+            test_speed_percent: default_zero_rpm_test_speed_percent(),
+            settle_seconds: default_zero_rpm_settle_seconds(),
+            min_fan_rpm: default_zero_rpm_min_fan_rpm(),
+            max_fan_rpm: default_zero_rpm_max_fan_rpm(),
+        }
+    }
+}
+
+fn default_zero_rpm_check_required() -> bool {
+    true
+}
+
+fn default_zero_rpm_test_speed_percent() -> SpeedPercent {
+    SpeedPercent(25)
+}
+
+fn default_zero_rpm_settle_seconds() -> u64 {
+    3
+}
+
+fn default_zero_rpm_min_fan_rpm() -> u16 {
+    100
+}
+
+fn default_zero_rpm_max_fan_rpm() -> u16 {
+    1800
+}
+
+/// What the daemon does with the speeds its curves compute.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DaemonMode {
+    /// Write them to the fans.
+    #[default]
+    Control,
+    /// Only report them, as if every curve were `manage: "monitor_only"`.
+    /// Devices are still read, but never written.
+    Monitor,
+}
+
+impl std::fmt::Display for DaemonMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DaemonMode::Control => write!(f, "control"),
+            DaemonMode::Monitor => write!(f, "monitor"),
+        }
+    }
+}
+
+/// In monitor mode, marks every curve monitor-only so nothing is written
+/// and status shows each speed as one that would apply.
+pub fn apply_daemon_mode(config: &mut CurveConfig) {
+    if config.mode == DaemonMode::Monitor {
+        for fan_curve in &mut config.fan_curves {
+            fan_curve.manage = Manage::MonitorOnly;
+        }
+    }
+}
+
+/// Where the machine's power comes from.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerState {
+    /// Mains power, or a UPS that is on line power.
+    Ac,
+    /// A battery, or a UPS running off its battery.
+    Battery,
+}
+
+impl std::fmt::Display for PowerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PowerState::Ac => write!(f, "AC"),
+            PowerState::Battery => write!(f, "battery"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PowerProfile {
+    /// Added to every channel's speed, like the interactive override, which
+    /// replaces it while set.
+    pub offset_percent: i8,
+}
+
+/// How much of a channel the daemon may touch.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Manage {
+    /// RGB sync, channel mode and speed.
+    #[default]
+    Full,
+    /// Only the speed; the channel is assumed to be in manual mode already
+    /// (see `uni-sync-curve apply-defaults`).
+    #[serde(alias = "speed-only")]
+    SpeedOnly,
+    /// Evaluate and report, but never write.
+    #[serde(alias = "monitor-only")]
+    MonitorOnly,
+}
+
+/// What to do when a device can't be opened for lack of permission.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionPolicy {
+    /// Stop the daemon with exit code 77.
+    #[default]
+    Exit,
+    /// Wait and try again, e.g. for udev rules that apply just after the
+    /// device shows up.
+    Retry,
+    /// Quarantine the device and keep driving the others.
+    #[serde(alias = "skip-device")]
+    SkipDevice,
+}
+
+/// Per-device options that apply to every channel of a device.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeviceSettings {
+    pub device_id: DeviceId,
+    #[serde(default)]
+    pub lighting_control: LightingControl,
+    /// Pins `device_id` to the hub in this USB port, e.g. "1-3.2", whatever
+    /// number its serial got. Only hubs of the same model and serial match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port_path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LightingControl {
+    /// The daemon disables RGB sync once when it first drives the device.
+    #[default]
+    Daemon,
+    /// Lighting belongs to another tool (e.g. OpenRGB); never send any
+    /// lighting or sync packet to the device.
+    External,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlertRule {
+    #[serde(flatten)]
+    pub condition: AlertCondition,
+    #[serde(default = "default_alert_actions")]
+    pub actions: Vec<AlertAction>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// The temperature rose above the threshold.
+    Overheat { above_celsius: f64 },
+    /// Writing to a configured device failed.
+    DeviceLost,
+    /// The temperature source failed enough reads in a row to count as lost.
+    SourceLost,
+    /// The temperature climbed faster than `above_celsius_per_second` on
+    /// average over the last `over_seconds`, as it does when a pump fails,
+    /// well before an absolute threshold is reached.
+    RunawayTemperature {
+        above_celsius_per_second: f64,
+        #[serde(default = "default_runaway_over_seconds")]
+        over_seconds: f64,
+    },
+    /// A driven channel with fans on it read almost no RPM while set above
+    /// 0% for `for_seconds` in a row.
+    FanStall {
+        #[serde(default = "default_fan_stall_seconds")]
+        for_seconds: f64,
+    },
+}
+
+fn default_runaway_over_seconds() -> f64 {
+    10.0
+}
+
+fn default_fan_stall_seconds() -> f64 {
+    10.0
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AlertAction {
+    Log,
+    /// Desktop notification; needs the `notify` cargo feature. `summary` and
+    /// `body` may use {alert}, {message}, {temperature} and {device}.
+    Notify {
+        #[serde(default)]
+        urgency: Urgency,
+        #[serde(default = "default_notify_summary")]
+        summary: String,
+        #[serde(default = "default_notify_body")]
+        body: String,
+    },
+    /// Run every channel at 100% for as long as the condition holds. Only
+    /// for `overheat` and `runaway_temperature`.
+    FullSpeed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
+fn default_alert_actions() -> Vec<AlertAction> {
+    vec![AlertAction::Log]
+}
+
+fn default_notify_summary() -> String {
+    "uni-sync-curve: {alert}".to_string()
+}
+
+fn default_notify_body() -> String {
+    "{message}".to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct NotifySettings {
+    /// D-Bus address of a user session bus (e.g. "unix:path=/run/user/1000/bus")
+    /// to deliver notifications to when the daemon itself has no session bus,
+    /// as is the case when it runs as a root service.
+    #[serde(default)]
+    pub user_bus: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(remote = "Self")]
 pub struct FanCurve {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     pub device_id: DeviceId,
     pub channel: usize,
     pub mode: ChannelMode,
+    #[serde(alias = "curve_points_rising")]
     pub curve_points: Vec<CurvePoint>,
+    /// Points followed instead of `curve_points` while the temperature is
+    /// falling, e.g. to let fans spin down gently after a load.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub curve_points_falling: Option<Vec<CurvePoint>>,
+    /// Curves sharing a group all run at the highest speed any of them asks for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub above_max: AboveMax,
+    #[serde(default)]
+    pub below_min: BelowMin,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anticipation: Option<Anticipation>,
+    /// Speeds for the CPUs' throttle pressure, the percentage of CPUs the
+    /// kernel throttled for heat since the last tick. The channel runs at
+    /// the higher of these and its curve.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub throttle_points: Vec<ThrottlePoint>,
+    /// Holds the fans at an RPM for the temperature instead of a duty. The
+    /// curve's points then give the duty to start from, and to fall back on
+    /// without RPM readings or while the fans are stalled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpm_control: Option<RpmControl>,
+    /// Splits each change of the curve's speed into this many writes,
+    /// spread over part of the interval, so big fans don't jump audibly.
+    /// Only Manual channels are eased in like this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slew_substeps: Option<u8>,
+    /// What the curve follows instead of the latest reading, e.g. an
+    /// average for a water loop whose thermal mass makes single readings
+    /// meaningless.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<TemperatureFilter>,
+    /// Per-channel trim on top of the curve: the curve's speed is scaled by
+    /// `speed_multiplier`, then `speed_offset_percent` is added.
+    #[serde(default = "default_speed_multiplier")]
+    pub speed_multiplier: f64,
+    #[serde(default)]
+    pub speed_offset_percent: i8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_speed_percent: Option<SpeedPercent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_speed_percent: Option<SpeedPercent>,
+    #[serde(default)]
+    pub manage: Manage,
+    /// Disabled curves stay in the file but their channels aren't driven.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// The channel drives something that must never stop, such as an AIO
+    /// pump: whatever the curve, override or startup ramp say, it runs at
+    /// `never_stop_speed_percent` at least.
+    #[serde(default)]
+    pub never_stop: bool,
+    /// Log every stage of this curve's decision each tick, as `explain`
+    /// shows it, whether or not `--debug` is given.
+    #[serde(default)]
+    pub debug: bool,
+    /// Drive every channel of the device with this curve, ignoring
+    /// `channel`. It must then be the device's only curve.
+    #[serde(default)]
+    pub broadcast: bool,
+    /// The unit the points' temperatures are written in, e.g. for curves
+    /// pasted from a tool that uses Fahrenheit. They are converted to
+    /// Celsius on load. A curve in Fahrenheit can't also have points with
+    /// `temperature_fahrenheit`, which would be converted twice.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature_unit: Option<TemperatureUnit>,
+}
+
+impl Serialize for FanCurve {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FanCurve::serialize(self, serializer)
+    }
+}
+
+/// Points lose how their temperature was written once read, so a curve is
+/// read through its JSON to reject `temperature_fahrenheit` points in a
+/// curve tagged Fahrenheit.
+impl<'de> Deserialize<'de> for FanCurve {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let fan_curve = FanCurve::deserialize(&value).map_err(serde::de::Error::custom)?;
+        let fahrenheit_points = [
+            "curve_points",
+            "curve_points_rising",
+            "curve_points_falling",
+        ]
+        .iter()
+        .filter_map(|key| value.get(key)?.as_array())
+        .flatten()
+        .any(|point| point.get("temperature_fahrenheit").is_some());
+        if fahrenheit_points && fan_curve.temperature_unit == Some(TemperatureUnit::Fahrenheit) {
+            return Err(serde::de::Error::custom(format!(
+                "Curve {}: its points are already in Fahrenheit with \"temperature_unit\": \"fahrenheit\", so they must give temperature_celsius, not temperature_fahrenheit",
+                fan_curve.label()
+            )));
+        }
+        Ok(fan_curve)
+    }
+}
+
+fn default_speed_multiplier() -> f64 {
+    1.0
+}
+
+pub const DEFAULT_FALLBACK_SPEED_PERCENT: SpeedPercent = SpeedPercent(50);
+
+/// Most writes `slew_substeps` may split a change into.
+pub const MAX_SLEW_SUBSTEPS: u8 = 10;
+
+fn default_fallback_speed_percent() -> SpeedPercent {
+    DEFAULT_FALLBACK_SPEED_PERCENT
+}
+
+pub const DEFAULT_NEVER_STOP_SPEED_PERCENT: SpeedPercent = SpeedPercent(20);
+
+fn default_never_stop_speed_percent() -> SpeedPercent {
+    DEFAULT_NEVER_STOP_SPEED_PERCENT
+}
+
+fn default_max_extrapolation_celsius() -> f64 {
+    5.0
+}
+
+fn default_shutdown_timeout_seconds() -> u64 {
+    crate::shutdown::DEFAULT_SHUTDOWN_TIMEOUT_SECONDS
+}
+
+fn default_reconcile_every_seconds() -> u64 {
+    300
+}
+
+/// Longest any of the durations in seconds may be, a week, so adding one to
+/// the current time can't overflow.
+const MAX_DURATION_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+/// Keeps the journal to a few hundred kilobytes at the default and a few
+/// tens of megabytes at most.
+const MAX_JOURNAL_ENTRIES: usize = 100_000;
+
+fn default_journal_entries() -> usize {
+    1000
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Default for FanCurve {
+    fn default() -> Self {
+        Self {
+            name: None,
+            device_id: DeviceId::default(),
+            channel: 0,
+            mode: ChannelMode::default(),
+            curve_points: Vec::new(),
+            curve_points_falling: None,
+            group: None,
+            above_max: AboveMax::default(),
+            below_min: BelowMin::default(),
+            anticipation: None,
+            throttle_points: Vec::new(),
+            rpm_control: None,
+            slew_substeps: None,
+            filter: None,
+            speed_multiplier: default_speed_multiplier(),
+            speed_offset_percent: 0,
+            min_speed_percent: None,
+            max_speed_percent: None,
+            manage: Manage::default(),
+            enabled: default_enabled(),
+            never_stop: false,
+            broadcast: false,
+            debug: false,
+            temperature_unit: None,
+        }
+    }
+}
+
+pub const SPEED_MULTIPLIER_RANGE: std::ops::RangeInclusive<f64> = 0.1..=2.0;
+
+/// How a curve smooths the temperature it follows.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TemperatureFilter {
+    /// The average over the last `seconds`, each reading weighted by the
+    /// time it covers, so missed and stretched ticks count for what they
+    /// were. Until the daemon has run that long, the readings so far.
+    WindowAvg { seconds: f64 },
+}
+
+impl TemperatureFilter {
+    /// How far back the filter looks, in seconds.
+    pub fn seconds(&self) -> f64 {
+        match self {
+            TemperatureFilter::WindowAvg { seconds } => *seconds,
+        }
+    }
+}
+
+/// Longest window a filter may average over.
+pub const MAX_FILTER_SECONDS: f64 = 3600.0;
+
+/// How far back the temperature history must go for every curve's filter.
+pub fn filter_seconds(fan_curves: &[FanCurve]) -> f64 {
+    fan_curves
+        .iter()
+        .filter_map(|curve| curve.filter.map(|filter| filter.seconds()))
+        .fold(0.0, f64::max)
+}
+
+/// Ramps fans early while the temperature is rising, by feeding the curve
+/// `degrees_per_second_gain` × the rate of change on top of the reading.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Anticipation {
+    pub degrees_per_second_gain: f64,
+    /// Most the anticipated speed may exceed the plain curve output by.
+    pub max_boost_percent: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ThrottlePoint {
+    pub pressure_percent: f64,
+    pub fan_speed_percent: SpeedPercent,
+}
+
+/// Closed-loop control of a channel's RPM, on hubs that report RPMs. Each
+/// tick the duty moves toward the RPM `points` give for the temperature, by
+/// `gain_percent_per_100_rpm` for every 100 RPM it is off, unless it is
+/// within `deadband_rpm`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RpmControl {
+    pub points: Vec<RpmPoint>,
+    #[serde(default = "default_rpm_gain")]
+    pub gain_percent_per_100_rpm: f64,
+    #[serde(default = "default_rpm_deadband")]
+    pub deadband_rpm: u16,
+    /// The most duty the loop asks for, however far off the RPM is.
+    #[serde(default = "default_rpm_max_speed_percent")]
+    pub max_speed_percent: SpeedPercent,
+    /// How long the fans may report no RPM at a duty that should spin them
+    /// before the curve's duty takes over from the loop.
+    #[serde(default = "default_rpm_stall_seconds")]
+    pub stall_seconds: u64,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+impl Default for RpmControl {
+    fn default() -> Self {
+        Self {
+            points: Vec::new(),
+            gain_percent_per_100_rpm: default_rpm_gain(),
+            deadband_rpm: default_rpm_deadband(),
+            max_speed_percent: default_rpm_max_speed_percent(),
+            stall_seconds: default_rpm_stall_seconds(),
+        }
+    }
+}
+
+fn default_rpm_gain() -> f64 {
+    2.0
+}
+
+fn default_rpm_deadband() -> u16 {
+    50
+}
+
+fn default_rpm_max_speed_percent() -> SpeedPercent {
+    SpeedPercent(100)
+}
+
+fn default_rpm_stall_seconds() -> u64 {
+    30
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RpmPoint {
+    pub temperature_celsius: f64,
+    pub fan_rpm: u16,
+}
+
+/// What happens above the hottest curve point.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AboveMax {
+    /// Stay at the last point's speed.
+    #[default]
+    Clamp,
+    /// Continue the slope of the last segment, up to 100%.
+    Extrapolate,
+    FullSpeed,
+}
+
+/// What happens below the coolest curve point.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BelowMin {
+    /// Stay at the first point's speed.
+    #[default]
+    Clamp,
+    /// Stop the fans.
+    Zero,
+}
+
+impl FanCurve {
+    /// The curve's name, or its device and channel when it has none.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{}/{}", self.device_id, self.channel),
+        }
+    }
+
+    /// The curve with its falling points as `curve_points`, if it has any.
+    pub fn falling(&self) -> Option<FanCurve> {
+        let points = self.curve_points_falling.clone()?;
+        Some(FanCurve {
+            curve_points: points,
+            curve_points_falling: None,
+            ..self.clone()
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ChannelMode {
+    #[default]
     Manual,
     PWM,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "CurvePointFields")]
 pub struct CurvePoint {
     pub temperature_celsius: f64,
-    pub fan_speed_percent: u8,
+    pub fan_speed_percent: SpeedPercent,
+}
+
+/// A curve point as written in the file, which may give the temperature in
+/// Fahrenheit instead; it is converted to Celsius on load.
+#[derive(Deserialize)]
+struct CurvePointFields {
+    temperature_celsius: Option<f64>,
+    temperature_fahrenheit: Option<f64>,
+    fan_speed_percent: SpeedPercent,
+}
+
+impl TryFrom<CurvePointFields> for CurvePoint {
+    type Error = String;
+
+    fn try_from(fields: CurvePointFields) -> Result<Self, Self::Error> {
+        let temperature_celsius = match (fields.temperature_celsius, fields.temperature_fahrenheit)
+        {
+            (Some(celsius), None) => celsius,
+            (None, Some(fahrenheit)) => units::celsius_from_fahrenheit(fahrenheit),
+            (Some(_), Some(_)) => {
+                return Err(
+                    "curve point has both temperature_celsius and temperature_fahrenheit"
+                        .to_string(),
+                )
+            }
+            (None, None) => return Err("curve point needs temperature_celsius".to_string()),
+        };
+        Ok(CurvePoint {
+            temperature_celsius,
+            fan_speed_percent: fields.fan_speed_percent,
+        })
+    }
+}
+
+/// A fan speed from 0 to 100%. Speeds above 100 can't be constructed, and
+/// are rejected when a config is read.
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct SpeedPercent(u8);
+
+impl SpeedPercent {
+    pub const ZERO: SpeedPercent = SpeedPercent(0);
+    pub const FULL: SpeedPercent = SpeedPercent(100);
+
+    pub fn new(percent: u8) -> Result<Self> {
+        if percent > 100 {
+            anyhow::bail!("speed {}% is above 100%", percent);
+        }
+        Ok(SpeedPercent(percent))
+    }
+
+    /// For speeds computed with clamped arithmetic; anything above 100
+    /// becomes 100.
+    pub fn saturating(percent: u8) -> Self {
+        SpeedPercent(percent.min(100))
+    }
+
+    pub fn get(self) -> u8 {
+        self.0
+    }
+}
+
+impl TryFrom<u8> for SpeedPercent {
+    type Error = String;
+
+    fn try_from(percent: u8) -> Result<Self, Self::Error> {
+        SpeedPercent::new(percent).map_err(|e| e.to_string())
+    }
+}
+
+impl From<SpeedPercent> for u8 {
+    fn from(speed: SpeedPercent) -> u8 {
+        speed.0
+    }
+}
+
+impl PartialEq<u8> for SpeedPercent {
+    fn eq(&self, other: &u8) -> bool {
+        self.0 == *other
+    }
+}
+
+impl std::fmt::Display for SpeedPercent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn default_curve_points() -> Vec<CurvePoint> {
+    vec![
+        CurvePoint {
+            temperature_celsius: 30.0,
+            fan_speed_percent: SpeedPercent(25),
+        },
+        CurvePoint {
+            temperature_celsius: 50.0,
+            fan_speed_percent: SpeedPercent(50),
+        },
+        CurvePoint {
+            temperature_celsius: 65.0,
+            fan_speed_percent: SpeedPercent(75),
+        },
+        CurvePoint {
+            temperature_celsius: 80.0,
+            fan_speed_percent: SpeedPercent(100),
+        },
+    ]
+}
+
+/// The source a generated config follows, asked for with `init --source`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum SourceRequest {
+    /// The CPU, or the hottest sensor there is without a CPU sensor.
+    #[default]
+    Auto,
+    Cpu,
+    Gpu(Option<GpuSelector>),
+}
+
+impl std::str::FromStr for SourceRequest {
+    type Err = anyhow::Error;
+
+    /// Parses "cpu", "gpu", or "gpu:" and a GPU's index or PCI bus id, e.g.
+    /// "gpu:0000:03:00.0".
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (kind, selector) = match s.split_once(':') {
+            Some((kind, selector)) => (kind, Some(selector.trim())),
+            None => (s, None),
+        };
+        match (kind.to_ascii_lowercase().as_str(), selector) {
+            ("cpu", None) => Ok(SourceRequest::Cpu),
+            ("gpu", None) => Ok(SourceRequest::Gpu(None)),
+            ("gpu", Some(selector)) if !selector.is_empty() => {
+                Ok(SourceRequest::Gpu(Some(match selector.parse() {
+                    Ok(index) => GpuSelector::Index(index),
+                    Err(_) => GpuSelector::BusId(selector.to_string()),
+                })))
+            }
+            ("hwmon", _) => anyhow::bail!(
+                "There is no hwmon temperature source; use cpu or gpu, \
+                 or a GPU's hwmon sensor as gpu:<index or PCI bus id> (see list-sensors)"
+            ),
+            _ => anyhow::bail!(
+                "Expected cpu, gpu or gpu:<index or PCI bus id>, got {:?}",
+                s
+            ),
+        }
+    }
+}
+
+/// The temperature sensors found, as `uni-sync-curve list-sensors` lists
+/// them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DetectedSensors {
+    pub cpu_celsius: Option<f64>,
+    pub gpus: Vec<Gpu>,
+}
+
+/// The source a generated config follows, with a note on why when it isn't
+/// the one asked for or reads nothing.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DefaultSource {
+    pub source: TemperatureSource,
+    pub note: Option<String>,
+}
+
+/// Picks the source for a generated config. Asked for nothing, it is the
+/// CPU; without a CPU sensor, the hottest sensor found, the first in
+/// `list-sensors` order among equally hot ones.
+pub fn choose_default_source(request: &SourceRequest, sensors: &DetectedSensors) -> DefaultSource {
+    let gpu_source = |gpu, sensor| TemperatureSource::Gpu {
+        gpu,
+        sensor,
+        transform: None,
+        reading_latency_seconds: None,
+    };
+    match request {
+        SourceRequest::Auto if sensors.cpu_celsius.is_some() => DefaultSource::default(),
+        SourceRequest::Auto => {
+            let mut hottest: Option<(&Gpu, GpuSensorKind, f64)> = None;
+            for gpu in &sensors.gpus {
+                for &(kind, temperature) in &gpu.temperatures {
+                    if hottest.is_none_or(|(_, _, hottest)| temperature > hottest) {
+                        hottest = Some((gpu, kind, temperature));
+                    }
+                }
+            }
+            match hottest {
+                Some((gpu, kind, temperature)) => DefaultSource {
+                    source: gpu_source(Some(GpuSelector::BusId(gpu.pci_bus_id.clone())), kind),
+                    note: Some(format!(
+                        "No CPU temperature sensor was found, so temperature_source is the \
+                         hottest sensor there was: gpu {} {:?} at {:.1}°C. \
+                         Change it if the fans should follow something else.",
+                        gpu.pci_bus_id, kind, temperature
+                    )),
+                },
+                None => DefaultSource {
+                    source: TemperatureSource::default(),
+                    note: Some(
+                        "No temperature sensor was found, so temperature_source is the CPU, \
+                         which reads nothing until a CPU sensor appears."
+                            .to_string(),
+                    ),
+                },
+            }
+        }
+        SourceRequest::Cpu => DefaultSource {
+            source: TemperatureSource::default(),
+            note: sensors.cpu_celsius.is_none().then(|| {
+                "No CPU temperature sensor was found; temperature_source reads nothing \
+                 until one appears."
+                    .to_string()
+            }),
+        },
+        SourceRequest::Gpu(selector) => {
+            let found = sensors.gpus.iter().any(|gpu| {
+                !gpu.temperatures.is_empty() && selector.as_ref().is_none_or(|s| gpu.matches(s))
+            });
+            DefaultSource {
+                source: gpu_source(selector.clone(), GpuSensorKind::default()),
+                note: (!found).then(|| {
+                    "No such GPU temperature sensor was found; temperature_source reads \
+                     nothing until one appears."
+                        .to_string()
+                }),
+            }
+        }
+    }
+}
+
+fn get_default_config(device_ids: Vec<DeviceId>, source: DefaultSource) -> CurveConfig {
+    get_default_config_for_channels(
+        device_ids
+            .into_iter()
+            .map(|device_id| (device_id, vec![0]))
+            .collect(),
+        source,
+    )
 }
 
-fn get_default_config(device_ids: Vec<DeviceId>) -> CurveConfig {
+/// Defaults with a curve on each of the given channels of each device.
+pub fn get_default_config_for_channels(
+    mut channels: Vec<(DeviceId, Vec<usize>)>,
+    source: DefaultSource,
+) -> CurveConfig {
+    channels.sort();
     CurveConfig {
         interval_seconds: 10,
-        fan_curves: device_ids
+        temperature_source: source.source,
+        note: source.note,
+        fan_curves: channels
             .into_iter()
-            .map(|device_id| FanCurve {
-                device_id,
-                channel: 0,
-                mode: ChannelMode::Manual,
-                curve_points: vec![
-                    CurvePoint {
-                        temperature_celsius: 30.0,
-                        fan_speed_percent: 25,
-                    },
-                    CurvePoint {
-                        temperature_celsius: 50.0,
-                        fan_speed_percent: 50,
-                    },
-                    CurvePoint {
-                        temperature_celsius: 65.0,
-                        fan_speed_percent: 75,
-                    },
-                    CurvePoint {
-                        temperature_celsius: 80.0,
-                        fan_speed_percent: 100,
-                    },
-                ],
+            .filter(|(device_id, _)| !device_id.is_sysfs_pwm())
+            .flat_map(|(device_id, channels)| {
+                channels.into_iter().map(move |channel| FanCurve {
+                    device_id: device_id.clone(),
+                    channel,
+                    mode: ChannelMode::Manual,
+                    curve_points: default_curve_points(),
+                    ..Default::default()
+                })
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// What the daemon runs when the config can't be loaded and
+/// `--safe-mode-on-config-error` is given: the default curve on every
+/// channel of every hub, given as (device, channel count).
+pub fn safe_mode_config(devices: &[(DeviceId, usize)]) -> CurveConfig {
+    CurveConfig {
+        interval_seconds: 10,
+        fan_curves: devices
+            .iter()
+            .filter(|(device_id, _)| !device_id.is_sysfs_pwm())
+            .flat_map(|(device_id, channels)| {
+                (0..*channels).map(|channel| FanCurve {
+                    device_id: device_id.clone(),
+                    channel,
+                    mode: ChannelMode::Manual,
+                    curve_points: default_curve_points(),
+                    ..Default::default()
+                })
             })
             .collect(),
+        ..Default::default()
     }
 }
 
-pub fn load_config(config_path: &Path, available_devices: Vec<DeviceId>) -> Result<CurveConfig> {
+/// Settings that don't stop the daemon from running but are probably not
+/// what the user meant.
+pub fn validation_warnings(config: &CurveConfig) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if config.experimental_hub_reports {
+        warnings.push(
+            "experimental_hub_reports sends report requests whose bytes are guessed, not \
+             captured from a hub; a hub may ignore or misread them"
+                .to_string(),
+        );
+    }
+    // Until hardware::CHANNEL_STATES_CONFIRMED is set
+    if config.restore_on_exit {
+        warnings.push(
+            "restore_on_exit does nothing until the channel state report is confirmed from a \
+             capture; channels keep the last speed written"
+                .to_string(),
+        );
+    }
+
+    let mut group_modes: HashMap<&str, (&FanCurve, &ChannelMode)> = HashMap::new();
+    for curve in &config.fan_curves {
+        let Some(group) = &curve.group else {
+            continue;
+        };
+        match group_modes.get(group.as_str()) {
+            Some((first, mode)) if *mode != &curve.mode => warnings.push(format!(
+                "Group \"{}\" mixes modes: {} is {:?} but {} is {:?}",
+                group,
+                first.label(),
+                mode,
+                curve.label(),
+                curve.mode
+            )),
+            Some(_) => {}
+            None => {
+                group_modes.insert(group, (curve, &curve.mode));
+            }
+        }
+    }
+
+    warnings
+}
+
+/// The configuration as the daemon runs it: curves it can't drive are
+/// dropped, `managed_devices` lists the concrete devices and every managed
+/// device has its settings. Returns the resolved config and a note for each
+/// curve that was dropped.
+pub fn resolve_config(
+    config: &CurveConfig,
+    available_devices: &[DeviceId],
+    is_managed: impl Fn(&DeviceId) -> bool,
+    fan_count: impl Fn(&DeviceId, usize) -> Option<u8>,
+    channels: impl Fn(&DeviceId) -> usize,
+) -> (CurveConfig, Vec<String>) {
+    let mut resolved = config.clone();
+    convert_temperature_units(&mut resolved);
+    let mut notes: Vec<String> = resolved
+        .fan_curves
+        .iter()
+        .filter(|fan_curve| fan_curve.temperature_unit.is_none() && looks_like_fahrenheit(fan_curve))
+        .map(|fan_curve| {
+            format!(
+                "Warning: curve {} only has points above {}°C, which looks like Fahrenheit; if it is, add \"temperature_unit\": \"fahrenheit\" to it (or \"celsius\" to keep it as it is)",
+                fan_curve.label(),
+                FAHRENHEIT_SUSPECT_CELSIUS
+            )
+        })
+        .collect();
+
+    // A broadcast curve becomes one curve per channel, all with its label so
+    // they are disabled and shown together
+    resolved.fan_curves = std::mem::take(&mut resolved.fan_curves)
+        .into_iter()
+        .flat_map(|fan_curve| {
+            if !fan_curve.broadcast {
+                return vec![fan_curve];
+            }
+            let name = Some(fan_curve.label());
+            (0..channels(&fan_curve.device_id))
+                .map(|channel| FanCurve {
+                    name: name.clone(),
+                    channel,
+                    broadcast: false,
+                    ..fan_curve.clone()
+                })
+                .collect()
+        })
+        .collect();
+
+    resolved.fan_curves.retain(|fan_curve| {
+        if !fan_curve.enabled {
+            notes.push(format!("Ignoring curve {}: disabled", fan_curve.label()));
+            return false;
+        }
+        if !is_managed(&fan_curve.device_id) {
+            notes.push(format!(
+                "Ignoring curve for device {} channel {}: device is not in managed_devices",
+                fan_curve.device_id, fan_curve.channel
+            ));
+            return false;
+        }
+        if config.skip_empty_channels
+            && fan_count(&fan_curve.device_id, fan_curve.channel) == Some(0)
+        {
+            notes.push(format!(
+                "Ignoring curve {}: the hub reports no fans on device {} channel {}",
+                fan_curve.label(),
+                fan_curve.device_id,
+                fan_curve.channel
+            ));
+            return false;
+        }
+        true
+    });
+
+    let managed: Vec<DeviceId> = available_devices
+        .iter()
+        .filter(|device_id| is_managed(device_id))
+        .cloned()
+        .collect();
+    for device_id in &managed {
+        if !resolved.devices.iter().any(|d| d.device_id == *device_id) {
+            resolved.devices.push(DeviceSettings {
+                device_id: device_id.clone(),
+                lighting_control: LightingControl::default(),
+                port_path: None,
+            });
+        }
+    }
+    resolved.managed_devices = Some(managed);
+
+    (resolved, notes)
+}
+
+/// Converts the points of curves tagged `"temperature_unit": "fahrenheit"`
+/// to Celsius and tags them Celsius, so converting again changes nothing.
+pub fn convert_temperature_units(config: &mut CurveConfig) {
+    for fan_curve in &mut config.fan_curves {
+        if fan_curve.temperature_unit != Some(TemperatureUnit::Fahrenheit) {
+            continue;
+        }
+        let points = fan_curve
+            .curve_points
+            .iter_mut()
+            .chain(fan_curve.curve_points_falling.iter_mut().flatten());
+        for point in points {
+            point.temperature_celsius = units::celsius_from_fahrenheit(point.temperature_celsius);
+        }
+        for point in fan_curve
+            .rpm_control
+            .iter_mut()
+            .flat_map(|control| &mut control.points)
+        {
+            point.temperature_celsius = units::celsius_from_fahrenheit(point.temperature_celsius);
+        }
+        fan_curve.temperature_unit = Some(TemperatureUnit::Celsius);
+    }
+}
+
+/// A curve whose points are all above this may be in Fahrenheit: 95°F is
+/// 35°C, where most curves start, while few parts run hotter than 95°C.
+const FAHRENHEIT_SUSPECT_CELSIUS: f64 = 95.0;
+
+/// How widely the points of a curve that looks like Fahrenheit are spread.
+/// Hot Celsius curves, such as one for VRMs, span a few degrees below where
+/// the part throttles; a Fahrenheit curve spans 1.8 times its Celsius range.
+const FAHRENHEIT_SUSPECT_SPREAD: f64 = 20.0;
+
+/// No sensor a fan follows reads this hot in Celsius.
+const FAHRENHEIT_CERTAIN_CELSIUS: f64 = 125.0;
+
+fn looks_like_fahrenheit(fan_curve: &FanCurve) -> bool {
+    let temperatures: Vec<f64> = fan_curve
+        .curve_points
+        .iter()
+        .chain(fan_curve.curve_points_falling.iter().flatten())
+        .map(|point| point.temperature_celsius)
+        .collect();
+    let lowest = temperatures.iter().copied().fold(f64::INFINITY, f64::min);
+    let highest = temperatures
+        .iter()
+        .copied()
+        .fold(f64::NEG_INFINITY, f64::max);
+    lowest > FAHRENHEIT_SUSPECT_CELSIUS
+        && (highest - lowest >= FAHRENHEIT_SUSPECT_SPREAD || highest > FAHRENHEIT_CERTAIN_CELSIUS)
+}
+
+/// Moves each device pinned with `port_path` to the device now found on that
+/// port, given the port of each discovered device: its curves, settings and
+/// `managed_devices` entry follow. Returns a note for each pin that matched
+/// no device, which then keeps its `device_id`.
+pub fn pin_port_paths(config: &mut CurveConfig, ports: &[(DeviceId, String)]) -> Vec<String> {
+    // Suffixed serials of hubs sharing one, such as "ABC#2", match "ABC"
+    let base = |device_id: &DeviceId| {
+        let serial = device_id.2.split('#').next().unwrap_or_default();
+        DeviceId(device_id.0, device_id.1, serial.to_string())
+    };
+    let mut notes = Vec::new();
+    let mut moves: HashMap<DeviceId, DeviceId> = HashMap::new();
+    for settings in &config.devices {
+        let Some(port_path) = &settings.port_path else {
+            continue;
+        };
+        match ports.iter().find(|(device_id, port)| {
+            port == port_path && base(device_id) == base(&settings.device_id)
+        }) {
+            Some((device_id, _)) => {
+                if *device_id != settings.device_id {
+                    moves.insert(settings.device_id.clone(), device_id.clone());
+                }
+            }
+            None => notes.push(format!(
+                "Device {} is pinned to USB port {}, but no such hub is on it; using the device_id",
+                settings.device_id, port_path
+            )),
+        }
+    }
+    if moves.is_empty() {
+        return notes;
+    }
+
+    let pin = |device_id: &mut DeviceId| {
+        if let Some(moved) = moves.get(device_id) {
+            *device_id = moved.clone();
+        }
+    };
+    for fan_curve in &mut config.fan_curves {
+        pin(&mut fan_curve.device_id);
+    }
+    for settings in &mut config.devices {
+        pin(&mut settings.device_id);
+    }
+    for device_id in config.managed_devices.iter_mut().flatten() {
+        pin(device_id);
+    }
+    notes
+}
+
+/// Lists what changed between two JSON documents, one line per changed
+/// value, addressed by path (e.g. `fan_curves[0].above_max`).
+pub fn diff_json(before: &serde_json::Value, after: &serde_json::Value) -> Vec<String> {
+    let mut changes = Vec::new();
+    diff_json_at("", before, after, &mut changes);
+    changes
+}
+
+fn diff_json_at(
+    path: &str,
+    before: &serde_json::Value,
+    after: &serde_json::Value,
+    changes: &mut Vec<String>,
+) {
+    use serde_json::Value;
+
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            for (key, value) in before {
+                let path = join_path(path, key);
+                match after.get(key) {
+                    Some(after_value) => diff_json_at(&path, value, after_value, changes),
+                    None => changes.push(format!("- {}: {}", path, value)),
+                }
+            }
+            for (key, value) in after {
+                if !before.contains_key(key) {
+                    changes.push(format!("+ {}: {}", join_path(path, key), value));
+                }
+            }
+        }
+        (Value::Array(before), Value::Array(after)) if before.len() == after.len() => {
+            for (index, (b, a)) in before.iter().zip(after).enumerate() {
+                diff_json_at(&format!("{}[{}]", path, index), b, a, changes);
+            }
+        }
+        (Value::Array(before), Value::Array(after)) => {
+            // Entries were dropped or added; indices no longer line up, so
+            // report whole entries instead
+            for (index, value) in before.iter().enumerate() {
+                if !after.contains(value) {
+                    changes.push(format!("- {}[{}]: {}", path, index, value));
+                }
+            }
+            for (index, value) in after.iter().enumerate() {
+                if !before.contains(value) {
+                    changes.push(format!("+ {}[{}]: {}", path, index, value));
+                }
+            }
+        }
+        _ if before != after => changes.push(format!("~ {}: {} -> {}", path, before, after)),
+        _ => {}
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Where the daemon's configuration came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigOrigin {
+    File,
+    /// No file existed, so defaults for the detected devices were written.
+    CreatedDefault,
+    /// The file couldn't be loaded; running the built-in safe mode curves.
+    SafeMode,
+    /// No file existed; running defaults for the detected devices without
+    /// writing them.
+    Defaults,
+}
+
+/// What `load_config` does when the config file doesn't exist.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CreateIfMissing {
+    /// Write defaults for the detected devices to the path, then use them.
+    Write,
+    /// Use defaults for the detected devices without writing anything.
+    Defaults,
+    /// Fail, pointing at `uni-sync-curve init`.
+    Error,
+}
+
+pub fn load_config(
+    config_path: &Path,
+    available_devices: Vec<DeviceId>,
+    create: CreateIfMissing,
+    create_dirs: bool,
+    default_source: impl FnOnce() -> DefaultSource,
+) -> Result<(CurveConfig, ConfigOrigin)> {
     if !config_path.exists() {
-        if let Some(parent) = config_path.parent() {
-            std::fs::create_dir_all(parent)?;
+        return match create {
+            CreateIfMissing::Write => Ok((
+                create_default_config(
+                    config_path,
+                    available_devices,
+                    create_dirs,
+                    default_source(),
+                )?,
+                ConfigOrigin::CreatedDefault,
+            )),
+            CreateIfMissing::Defaults => Ok((
+                get_default_config(available_devices, default_source()),
+                ConfigOrigin::Defaults,
+            )),
+            CreateIfMissing::Error => anyhow::bail!(
+                "{} does not exist; create it with `uni-sync-curve init`",
+                config_path.display()
+            ),
+        };
+    }
+
+    Ok((read_config(config_path)?, ConfigOrigin::File))
+}
+
+/// Where a missing config directory is created without `create_dirs`: a
+/// directory of its own directly in `/etc` or the XDG config home, like the
+/// default `/etc/uni-sync-curve`.
+fn config_roots() -> Vec<PathBuf> {
+    let mut roots = vec![PathBuf::from("/etc")];
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(home) if !home.is_empty() => roots.push(PathBuf::from(home)),
+        _ => {
+            if let Some(home) = std::env::var_os("HOME") {
+                roots.push(PathBuf::from(home).join(".config"));
+            }
+        }
+    }
+    roots
+}
+
+/// Makes sure `config_path`'s directory exists and can be written. A typo
+/// like `/etx/uni-sync-curve.json` is an error rather than a new `/etx`,
+/// unless `create_dirs` says to create whatever is missing.
+fn prepare_config_dir(config_path: &Path, create_dirs: bool, roots: &[PathBuf]) -> Result<()> {
+    let Some(parent) = config_path.parent().filter(|p| !p.as_os_str().is_empty()) else {
+        return Ok(());
+    };
+    if !parent.exists() {
+        let under_root = parent
+            .parent()
+            .is_some_and(|grandparent| roots.iter().any(|root| root == grandparent));
+        if !(create_dirs || under_root && parent.parent().is_some_and(Path::is_dir)) {
+            anyhow::bail!(
+                "parent directory does not exist: {} (pass --create-config-dirs to create it)",
+                std::path::absolute(parent)
+                    .unwrap_or_else(|_| parent.to_path_buf())
+                    .display()
+            );
+        }
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::metadata(parent)?.permissions().readonly() {
+        anyhow::bail!(
+            "{} is not writable; can't create {}",
+            parent.display(),
+            config_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Writes defaults for `available_devices`, following `source`, to
+/// `config_path`.
+pub fn create_default_config(
+    config_path: &Path,
+    available_devices: Vec<DeviceId>,
+    create_dirs: bool,
+    source: DefaultSource,
+) -> Result<CurveConfig> {
+    let default_config = get_default_config(available_devices, source);
+    write_default_config(config_path, &default_config, create_dirs)?;
+    Ok(default_config)
+}
+
+/// Writes a generated config to `config_path`, which shouldn't exist yet.
+pub fn write_default_config(
+    config_path: &Path,
+    default_config: &CurveConfig,
+    create_dirs: bool,
+) -> Result<()> {
+    prepare_config_dir(config_path, create_dirs, &config_roots())?;
+    let _lock = ConfigLock::acquire(config_path, CONFIG_LOCK_TIMEOUT)?;
+
+    let config_json = serde_json::to_string_pretty(default_config)?;
+    std::fs::write(config_path, config_json).map_err(|e| match e.kind() {
+        // A read-only mount doesn't show in the directory's permissions
+        std::io::ErrorKind::ReadOnlyFilesystem | std::io::ErrorKind::PermissionDenied => {
+            anyhow::anyhow!(
+                "{} is on a read-only file system or not writable; can't create it: {}",
+                config_path.display(),
+                e
+            )
         }
+        _ => e.into(),
+    })?;
+    Ok(())
+}
 
-        let default_config = get_default_config(available_devices);
-        let config_json = serde_json::to_string_pretty(&default_config)?;
-        std::fs::write(config_path, config_json)?;
-        println!("Created default configuration at: {:?}", config_path);
-        return Ok(default_config);
+/// Replaces the config file, writing then renaming so the daemon never reads
+/// a half-written file. The previous file is kept next to it as `.bak`. A
+/// config that fails validation isn't written at all.
+pub fn write_config(config_path: &Path, config: &CurveConfig) -> Result<()> {
+    validate_config(config)?;
+    if config_path.exists() {
+        std::fs::copy(config_path, config_path.with_extension("json.bak"))?;
     }
+    let temp_path = config_path.with_extension("json.tmp");
+    std::fs::write(&temp_path, serde_json::to_string_pretty(config)?)?;
+    std::fs::rename(&temp_path, config_path)?;
+    Ok(())
+}
+
+/// Reads the config, lets `edit` change it and writes it back, holding the
+/// config lock throughout so concurrent writers take turns. If something
+/// that doesn't take the lock, such as an editor, changed the file in the
+/// meantime, the file is left alone and this fails.
+pub fn modify_config<T>(
+    config_path: &Path,
+    edit: impl FnOnce(&mut CurveConfig) -> Result<T>,
+) -> Result<T> {
+    let _lock = ConfigLock::acquire(config_path, CONFIG_LOCK_TIMEOUT)?;
+    let original = std::fs::read_to_string(config_path)?;
+    let mut config: CurveConfig = serde_json::from_str(&original)?;
+    validate_config(&config)?;
+    let result = edit(&mut config)?;
+    if std::fs::read_to_string(config_path)? != original {
+        anyhow::bail!(
+            "{} changed on disk while it was being edited; not overwriting it",
+            config_path.display()
+        );
+    }
+    write_config(config_path, &config)?;
+    Ok(result)
+}
 
-    let config_content = std::fs::read_to_string(config_path)?;
-    let config: CurveConfig = serde_json::from_str(&config_content)?;
+pub fn read_config(config_path: &Path) -> Result<CurveConfig> {
+    parse_config(&std::fs::read(config_path)?)
+}
+
+/// A config file's content as the daemon runs it, in Celsius, or why it
+/// can't.
+pub fn parse_config(content: &[u8]) -> Result<CurveConfig> {
+    let mut config: CurveConfig = serde_json::from_slice(content)?;
+    convert_temperature_units(&mut config);
+    validate_config(&config)?;
     Ok(config)
 }
 
+/// Rejects settings the daemon can't run with.
+pub fn validate_config(config: &CurveConfig) -> Result<()> {
+    for curve in &config.fan_curves {
+        if curve.curve_points.is_empty() {
+            anyhow::bail!("Curve {}: curve_points is empty", curve.label());
+        }
+        if curve
+            .curve_points_falling
+            .as_ref()
+            .is_some_and(|points| points.is_empty())
+        {
+            anyhow::bail!("Curve {}: curve_points_falling is empty", curve.label());
+        }
+        if let Some(point) = curve
+            .curve_points
+            .iter()
+            .chain(curve.curve_points_falling.iter().flatten())
+            .find(|point| !point.temperature_celsius.is_finite())
+        {
+            anyhow::bail!(
+                "Curve {}: temperature {} is out of range",
+                curve.label(),
+                point.temperature_celsius
+            );
+        }
+        if !SPEED_MULTIPLIER_RANGE.contains(&curve.speed_multiplier) {
+            anyhow::bail!(
+                "Curve {}: speed_multiplier {} is outside {}-{}",
+                curve.label(),
+                curve.speed_multiplier,
+                SPEED_MULTIPLIER_RANGE.start(),
+                SPEED_MULTIPLIER_RANGE.end()
+            );
+        }
+        if let (Some(min), Some(max)) = (curve.min_speed_percent, curve.max_speed_percent) {
+            if min > max {
+                anyhow::bail!(
+                    "Curve {}: min_speed_percent {} is above max_speed_percent {}",
+                    curve.label(),
+                    min,
+                    max
+                );
+            }
+        }
+        if let Some(point) = curve
+            .throttle_points
+            .iter()
+            .find(|point| !(0.0..=100.0).contains(&point.pressure_percent))
+        {
+            anyhow::bail!(
+                "Curve {}: throttle pressure {} is outside 0-100%",
+                curve.label(),
+                point.pressure_percent
+            );
+        }
+        if let Some(control) = &curve.rpm_control {
+            if control.points.is_empty() {
+                anyhow::bail!("Curve {}: rpm_control.points is empty", curve.label());
+            }
+            if let Some(point) = control
+                .points
+                .iter()
+                .find(|point| !point.temperature_celsius.is_finite())
+            {
+                anyhow::bail!(
+                    "Curve {}: rpm_control temperature {} is out of range",
+                    curve.label(),
+                    point.temperature_celsius
+                );
+            }
+            if !(control.gain_percent_per_100_rpm > 0.0 && control.gain_percent_per_100_rpm <= 50.0)
+            {
+                anyhow::bail!(
+                    "Curve {}: rpm_control.gain_percent_per_100_rpm must be above 0 and at most 50",
+                    curve.label()
+                );
+            }
+            if control.stall_seconds == 0 || control.stall_seconds > MAX_DURATION_SECONDS {
+                anyhow::bail!(
+                    "Curve {}: rpm_control.stall_seconds must be between 1 and {}",
+                    curve.label(),
+                    MAX_DURATION_SECONDS
+                );
+            }
+            if curve.anticipation.is_some() {
+                anyhow::bail!(
+                    "Curve {}: anticipation can't be combined with rpm_control",
+                    curve.label()
+                );
+            }
+        }
+        if let Some(filter) = curve.filter {
+            let seconds = filter.seconds();
+            if !(seconds > 0.0 && seconds <= MAX_FILTER_SECONDS) {
+                anyhow::bail!(
+                    "Curve {}: filter.seconds must be above 0 and at most {}",
+                    curve.label(),
+                    MAX_FILTER_SECONDS
+                );
+            }
+        }
+        if let Some(substeps) = curve.slew_substeps {
+            if !(2..=MAX_SLEW_SUBSTEPS).contains(&substeps) {
+                anyhow::bail!(
+                    "Curve {}: slew_substeps must be between 2 and {}",
+                    curve.label(),
+                    MAX_SLEW_SUBSTEPS
+                );
+            }
+        }
+        if curve.never_stop && curve.below_min == BelowMin::Zero {
+            anyhow::bail!(
+                "Curve {}: never_stop can't be combined with below_min \"zero\"",
+                curve.label()
+            );
+        }
+        if curve.broadcast {
+            if let Some(other) = config
+                .fan_curves
+                .iter()
+                .find(|other| other.device_id == curve.device_id && !std::ptr::eq(*other, curve))
+            {
+                anyhow::bail!(
+                    "Curve {} broadcasts to every channel of device {}, so it must be the device's only curve, but curve {} drives channel {}",
+                    curve.label(),
+                    curve.device_id,
+                    other.label(),
+                    other.channel
+                );
+            }
+        }
+    }
+    for rule in &config.alerts {
+        let temperature_rule = match rule.condition {
+            AlertCondition::Overheat { .. } => true,
+            AlertCondition::RunawayTemperature {
+                above_celsius_per_second,
+                over_seconds,
+            } => {
+                if above_celsius_per_second <= 0.0 || over_seconds <= 0.0 {
+                    anyhow::bail!(
+                        "runaway_temperature alerts need a positive above_celsius_per_second and over_seconds"
+                    );
+                }
+                true
+            }
+            AlertCondition::FanStall { for_seconds } => {
+                if for_seconds <= 0.0 {
+                    anyhow::bail!("fan_stall alerts need a positive for_seconds");
+                }
+                false
+            }
+            AlertCondition::DeviceLost | AlertCondition::SourceLost => false,
+        };
+        if !temperature_rule && rule.actions.contains(&AlertAction::FullSpeed) {
+            anyhow::bail!(
+                "The full_speed alert action only works on overheat and runaway_temperature alerts"
+            );
+        }
+    }
+    for (index, settings) in config.devices.iter().enumerate() {
+        let Some(port_path) = &settings.port_path else {
+            continue;
+        };
+        if port_path.trim().is_empty() {
+            anyhow::bail!("Device {}: port_path is empty", settings.device_id);
+        }
+        if let Some(other) = config.devices[..index]
+            .iter()
+            .find(|other| other.port_path.as_ref() == Some(port_path))
+        {
+            anyhow::bail!(
+                "Devices {} and {} are both pinned to USB port {}",
+                other.device_id,
+                settings.device_id,
+                port_path
+            );
+        }
+    }
+    for (path, source) in
+        std::iter::once(("temperature_source".to_string(), &config.temperature_source)).chain(
+            config
+                .fallback_sources
+                .iter()
+                .enumerate()
+                .map(|(index, source)| (format!("fallback_sources[{}]", index), source)),
+        )
+    {
+        let latency = source.reading_latency_seconds();
+        if !(latency.is_finite() && latency >= 0.0) {
+            anyhow::bail!("{}.reading_latency_seconds must be at least 0", path);
+        }
+        let Some(transform) = source.transform() else {
+            continue;
+        };
+        if let Some(error) = transform.error() {
+            anyhow::bail!("{}.transform {:?}: {}", path, transform.to_string(), error);
+        }
+    }
+    if !(config.max_extrapolation_celsius.is_finite() && config.max_extrapolation_celsius >= 0.0) {
+        anyhow::bail!("max_extrapolation_celsius must be at least 0");
+    }
+    if config.timer_jitter_percent > 50 {
+        anyhow::bail!("timer_jitter_percent must be at most 50");
+    }
+    if !(1..=MAX_JOURNAL_ENTRIES).contains(&config.journal_entries) {
+        anyhow::bail!(
+            "journal_entries must be between 1 and {}",
+            MAX_JOURNAL_ENTRIES
+        );
+    }
+    for (name, seconds) in [
+        ("interval_seconds", Some(config.interval_seconds)),
+        ("startup_ramp_seconds", config.startup_ramp_seconds),
+        (
+            "shutdown_timeout_seconds",
+            Some(config.shutdown_timeout_seconds),
+        ),
+        (
+            "reconcile_every_seconds",
+            Some(config.reconcile_every_seconds),
+        ),
+        (
+            "zero_rpm_check.settle_seconds",
+            Some(config.zero_rpm_check.settle_seconds),
+        ),
+    ] {
+        if seconds.is_some_and(|seconds| seconds > MAX_DURATION_SECONDS) {
+            anyhow::bail!("{} must be at most {}", name, MAX_DURATION_SECONDS);
+        }
+    }
+    if config.shutdown_timeout_seconds == 0 {
+        anyhow::bail!("shutdown_timeout_seconds must be at least 1");
+    }
+    if config.io_timeout_ms == Some(0) {
+        anyhow::bail!("io_timeout_ms must be at least 1");
+    }
+    let health = &config.source_health;
+    if health.degraded_after_failures == 0 || health.recovered_after_successes == 0 {
+        anyhow::bail!("source_health thresholds must be at least 1");
+    }
+    if health.lost_after_failures < health.degraded_after_failures {
+        anyhow::bail!(
+            "source_health: lost_after_failures {} is below degraded_after_failures {}",
+            health.lost_after_failures,
+            health.degraded_after_failures
+        );
+    }
+    let check = &config.zero_rpm_check;
+    if check.min_fan_rpm >= check.max_fan_rpm {
+        anyhow::bail!(
+            "zero_rpm_check: min_fan_rpm {} must be below max_fan_rpm {}",
+            check.min_fan_rpm,
+            check.max_fan_rpm
+        );
+    }
+    for (state, profile) in &config.power_profiles {
+        if !(-100..=100).contains(&profile.offset_percent) {
+            anyhow::bail!(
+                "power_profiles: offset_percent {} for {} is outside -100-100",
+                profile.offset_percent,
+                state
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_serials_match_normalized() {
+        let discovered = DeviceId(0x0cf2, 0xa103, "a02b1c3d".to_string());
+        let configured: DeviceId = serde_json::from_str(r#"[3314, 41219, " A02B 1C3D "]"#).unwrap();
+
+        assert_eq!(configured, discovered);
+        assert!(HashSet::from([discovered.clone()]).contains(&configured));
+        assert_eq!(configured.to_string(), "(0cf2, a103,  A02B 1C3D )");
+        assert_ne!(DeviceId(0x0cf2, 0xa104, "a02b1c3d".to_string()), discovered);
+    }
+
+    #[test]
+    fn test_monitor_mode() {
+        let mut config: CurveConfig = serde_json::from_str(
+            r#"{
+                "interval_seconds": 5,
+                "mode": "monitor",
+                "fan_curves": [
+                    { "device_id": [3314, 41219, "ABC"], "channel": 0, "mode": "Manual", "curve_points": [] },
+                    { "device_id": [3314, 41219, "ABC"], "channel": 1, "mode": "Manual", "curve_points": [], "manage": "speed_only" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        apply_daemon_mode(&mut config);
+
+        assert!(config
+            .fan_curves
+            .iter()
+            .all(|fan_curve| fan_curve.manage == Manage::MonitorOnly));
+        assert_eq!(CurveConfig::default().mode, DaemonMode::Control);
+    }
+
+    #[test]
+    fn test_default_config_is_stable() {
+        let devices = vec![
+            DeviceId(0x0cf2, 0xa103, "b2".to_string()),
+            DeviceId(0x0cf2, 0xa101, "C3".to_string()),
+            DeviceId(0x0cf2, 0xa103, "A1".to_string()),
+        ];
+        let generate = |devices: Vec<DeviceId>| {
+            serde_json::to_string_pretty(&get_default_config(devices, DefaultSource::default()))
+                .unwrap()
+        };
+        let mut reversed = devices.clone();
+        reversed.reverse();
+
+        assert_eq!(generate(devices.clone()), generate(reversed));
+        let config = get_default_config(devices, DefaultSource::default());
+        let serials: Vec<&str> = config
+            .fan_curves
+            .iter()
+            .map(|curve| curve.device_id.2.as_str())
+            .collect();
+        assert_eq!(serials, vec!["C3", "A1", "b2"]);
+    }
+
+    #[test]
+    fn test_default_source() {
+        assert_eq!("cpu".parse::<SourceRequest>().unwrap(), SourceRequest::Cpu);
+        assert_eq!(
+            "gpu:1".parse::<SourceRequest>().unwrap(),
+            SourceRequest::Gpu(Some(GpuSelector::Index(1)))
+        );
+        assert_eq!(
+            "gpu:0000:03:00.0".parse::<SourceRequest>().unwrap(),
+            SourceRequest::Gpu(Some(GpuSelector::BusId("0000:03:00.0".to_string())))
+        );
+        let error = "hwmon:nct6798".parse::<SourceRequest>().unwrap_err();
+        assert!(error.to_string().contains("no hwmon"), "{}", error);
+        assert!("gpu:".parse::<SourceRequest>().is_err());
+
+        let gpu = |index: usize, bus_id: &str, temperatures: Vec<(GpuSensorKind, f64)>| Gpu {
+            index,
+            pci_bus_id: bus_id.to_string(),
+            driver: "amdgpu".to_string(),
+            temperatures,
+        };
+        let mut sensors = DetectedSensors {
+            cpu_celsius: Some(45.0),
+            gpus: vec![
+                gpu(0, "0000:03:00.0", vec![(GpuSensorKind::Edge, 50.0)]),
+                gpu(
+                    1,
+                    "0000:04:00.0",
+                    vec![(GpuSensorKind::Edge, 55.0), (GpuSensorKind::Junction, 70.0)],
+                ),
+                gpu(2, "0000:05:00.0", vec![(GpuSensorKind::Junction, 70.0)]),
+            ],
+        };
+        assert_eq!(
+            choose_default_source(&SourceRequest::Auto, &sensors),
+            DefaultSource::default()
+        );
+
+        // Without a CPU sensor, the hottest sensor; the first of equally hot
+        sensors.cpu_celsius = None;
+        let chosen = choose_default_source(&SourceRequest::Auto, &sensors);
+        assert_eq!(
+            chosen.source,
+            TemperatureSource::Gpu {
+                gpu: Some(GpuSelector::BusId("0000:04:00.0".to_string())),
+                sensor: GpuSensorKind::Junction,
+                transform: None,
+                reading_latency_seconds: None,
+            }
+        );
+        let config = get_default_config(Vec::new(), chosen);
+        let json = serde_json::to_value(&config).unwrap();
+        assert!(json["_note"].as_str().unwrap().contains("0000:04:00.0"));
+        let read: CurveConfig = serde_json::from_value(json).unwrap();
+        assert_eq!(read.note, config.note);
+        assert!(validate_config(&read).is_ok());
+
+        // Asked-for sources are kept, with a note when they read nothing
+        let chosen = choose_default_source(&SourceRequest::Cpu, &sensors);
+        assert_eq!(chosen.source, TemperatureSource::default());
+        assert!(chosen.note.is_some());
+        let request = SourceRequest::Gpu(Some(GpuSelector::Index(0)));
+        assert!(choose_default_source(&request, &sensors).note.is_none());
+        let request = SourceRequest::Gpu(Some(GpuSelector::Index(3)));
+        assert!(choose_default_source(&request, &sensors).note.is_some());
+
+        sensors.gpus.clear();
+        let chosen = choose_default_source(&SourceRequest::Auto, &sensors);
+        assert_eq!(chosen.source, TemperatureSource::default());
+        assert!(chosen.note.is_some());
+    }
+
+    #[test]
+    fn test_resolve_config() {
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let other = DeviceId(0x0cf2, 0xa103, "DEF".to_string());
+        let config: CurveConfig = serde_json::from_str(
+            r#"{
+                "interval_seconds": 5,
+                "skip_empty_channels": true,
+                "managed_devices": [[3314, 41219, "ABC"]],
+                "fan_curves": [
+                    { "device_id": [3314, 41219, "ABC"], "channel": 0, "mode": "Manual", "curve_points": [] },
+                    { "device_id": [3314, 41219, "ABC"], "channel": 1, "mode": "Manual", "curve_points": [] },
+                    { "device_id": [3314, 41219, "DEF"], "channel": 0, "mode": "Manual", "curve_points": [] }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let (resolved, notes) = resolve_config(
+            &config,
+            &[hub.clone(), other.clone()],
+            |device_id| *device_id == hub,
+            |_, channel| Some(if channel == 1 { 0 } else { 3 }),
+            |_| 4,
+        );
+
+        assert_eq!(resolved.fan_curves.len(), 1);
+        assert_eq!(resolved.fan_curves[0].channel, 0);
+        assert_eq!(notes.len(), 2);
+        assert_eq!(resolved.managed_devices, Some(vec![hub.clone()]));
+        assert_eq!(resolved.devices.len(), 1);
+    }
+
+    #[test]
+    fn test_temperature_units() {
+        let config = |points: &[f64], unit: &str| -> CurveConfig {
+            let points: Vec<String> = points
+                .iter()
+                .map(|t| {
+                    format!(
+                        r#"{{"temperature_celsius": {}, "fan_speed_percent": 50}}"#,
+                        t
+                    )
+                })
+                .collect();
+            serde_json::from_str(&format!(
+                r#"{{ "interval_seconds": 5, "fan_curves": [{{ "name": "vrm", "device_id": [3314, 41219, "ABC"],
+                    "channel": 0, "mode": "Manual", "curve_points": [{}]{} }}] }}"#,
+                points.join(", "),
+                unit
+            ))
+            .unwrap()
+        };
+        let resolve =
+            |config: &CurveConfig| resolve_config(config, &[], |_| true, |_, _| None, |_| 4);
+        let temperatures = |config: &CurveConfig| -> Vec<f64> {
+            config.fan_curves[0]
+                .curve_points
+                .iter()
+                .map(|point| point.temperature_celsius.round())
+                .collect()
+        };
+
+        // Typical Fahrenheit curves warn until they are tagged
+        for points in [&[104.0, 140.0, 176.0][..], &[122.0, 131.0, 140.0], &[140.0]] {
+            let (resolved, notes) = resolve(&config(points, ""));
+            assert_eq!(temperatures(&resolved), points.to_vec());
+            assert_eq!(notes.len(), 1, "{:?}", points);
+            assert!(notes[0].contains(r#""temperature_unit": "fahrenheit""#));
+        }
+        let tagged = config(
+            &[104.0, 140.0, 176.0],
+            r#", "temperature_unit": "fahrenheit""#,
+        );
+        let (resolved, notes) = resolve(&tagged);
+        assert_eq!(temperatures(&resolved), vec![40.0, 60.0, 80.0]);
+        assert!(notes.is_empty());
+        // Converting what is already converted changes nothing
+        let (again, _) = resolve(&resolved);
+        assert_eq!(temperatures(&again), vec![40.0, 60.0, 80.0]);
+
+        // Hot Celsius curves don't
+        for points in [&[95.0, 100.0, 105.0][..], &[96.0, 105.0], &[40.0, 110.0]] {
+            let (_, notes) = resolve(&config(points, ""));
+            assert!(notes.is_empty(), "{:?}", points);
+        }
+        let (_, notes) = resolve(&config(
+            &[104.0, 140.0],
+            r#", "temperature_unit": "celsius""#,
+        ));
+        assert!(notes.is_empty());
+    }
+
+    #[test]
+    fn test_broadcast_curves() {
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let config = |curves: &str| -> CurveConfig {
+            serde_json::from_str(&format!(
+                r#"{{ "interval_seconds": 5, "fan_curves": [{}] }}"#,
+                curves
+            ))
+            .unwrap()
+        };
+        let broadcast = r#"{ "device_id": [3314, 41219, "ABC"], "channel": 0, "mode": "Manual",
+            "curve_points": [{"temperature_celsius": 40, "fan_speed_percent": 30}], "broadcast": true }"#;
+        let other = r#"{ "name": "pump", "device_id": [3314, 41219, "ABC"], "channel": 2, "mode": "Manual",
+            "curve_points": [{"temperature_celsius": 40, "fan_speed_percent": 30}] }"#;
+        let elsewhere = r#"{ "device_id": [3314, 41219, "DEF"], "channel": 0, "mode": "Manual",
+            "curve_points": [{"temperature_celsius": 40, "fan_speed_percent": 30}] }"#;
+
+        let alone = config(&format!("{}, {}", broadcast, elsewhere));
+        validate_config(&alone).unwrap();
+        let (resolved, _) = resolve_config(
+            &alone,
+            std::slice::from_ref(&hub),
+            |_| true,
+            |_, _| None,
+            |_| 3,
+        );
+        let channels: Vec<(String, usize, bool)> = resolved
+            .fan_curves
+            .iter()
+            .filter(|curve| curve.device_id == hub)
+            .map(|curve| (curve.label(), curve.channel, curve.broadcast))
+            .collect();
+        let label = format!("{}/0", hub);
+        assert_eq!(
+            channels,
+            vec![
+                (label.clone(), 0, false),
+                (label.clone(), 1, false),
+                (label.clone(), 2, false)
+            ]
+        );
+
+        assert_eq!(
+            validate_config(&config(&format!("{}, {}", broadcast, other)))
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Curve {} broadcasts to every channel of device {}, so it must be the device's only curve, but curve pump drives channel 2",
+                label, hub
+            )
+        );
+        // Either way round
+        assert!(validate_config(&config(&format!("{}, {}", other, broadcast))).is_err());
+    }
+
+    #[test]
+    fn test_pin_port_paths() {
+        let hub = |serial: &str| DeviceId(0x0cf2, 0xa103, serial.to_string());
+        let mut config: CurveConfig = serde_json::from_str(
+            r#"{
+                "interval_seconds": 5,
+                "managed_devices": [[3314, 41219, "ABC#1"], [3314, 41219, "ABC#2"]],
+                "devices": [
+                    { "device_id": [3314, 41219, "ABC#1"], "port_path": "1-3.2" },
+                    { "device_id": [3314, 41219, "ABC#2"], "port_path": "1-3.1" },
+                    { "device_id": [3314, 41219, "DEF"], "port_path": "1-4" }
+                ],
+                "fan_curves": [
+                    { "name": "front", "device_id": [3314, 41219, "ABC#1"], "channel": 0, "mode": "Manual", "curve_points": [] },
+                    { "name": "top", "device_id": [3314, 41219, "ABC#2"], "channel": 0, "mode": "Manual", "curve_points": [] },
+                    { "name": "pump", "device_id": [3314, 41219, "DEF"], "channel": 0, "mode": "Manual", "curve_points": [] }
+                ]
+            }"#,
+        )
+        .unwrap();
+        // The hubs were numbered the other way round this boot, and DEF is
+        // on another port
+        let ports = [
+            (hub("ABC#1"), "1-3.1".to_string()),
+            (hub("ABC#2"), "1-3.2".to_string()),
+            (hub("DEF"), "1-5".to_string()),
+        ];
+
+        let notes = pin_port_paths(&mut config, &ports);
+        let targets: Vec<&str> = config
+            .fan_curves
+            .iter()
+            .map(|fan_curve| fan_curve.device_id.2.as_str())
+            .collect();
+        assert_eq!(targets, vec!["ABC#2", "ABC#1", "DEF"]);
+        assert_eq!(config.devices[0].device_id, hub("ABC#2"));
+        assert_eq!(
+            config.managed_devices,
+            Some(vec![hub("ABC#2"), hub("ABC#1")])
+        );
+        assert_eq!(notes.len(), 1);
+        assert!(notes[0].contains("1-4"), "{}", notes[0]);
 
+        let pinned = |serial: &str, port_path: &str| DeviceSettings {
+            device_id: hub(serial),
+            lighting_control: LightingControl::default(),
+            port_path: Some(port_path.to_string()),
+        };
+        let mut config = CurveConfig {
+            devices: vec![pinned("ABC#1", "1-3.2"), pinned("ABC#2", "1-3.1")],
+            ..Default::default()
+        };
+        assert!(validate_config(&config).is_ok());
+        config.devices[1].port_path = Some("1-3.2".to_string());
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_source_transforms() {
+        let config: CurveConfig = serde_json::from_str(
+            r#"{
+                "interval_seconds": 5,
+                "fan_curves": [],
+                "temperature_source": {"type": "cpu", "transform": "x * 0.001 - 273.15"},
+                "fallback_sources": [{"type": "gpu"}, {"type": "gpu", "transform": "clamp(x, 0, "}]
+            }"#,
+        )
+        .unwrap();
+        assert!((config.temperature_source.apply(323_150.0) - 50.0).abs() < 1e-9);
+        assert_eq!(config.fallback_sources[0].apply(61.0), 61.0);
+        // Sources differing only in their transform read one sensor
+        assert_eq!(
+            config.fallback_sources[0].key(),
+            config.fallback_sources[1].key()
+        );
+        assert_eq!(
+            validate_config(&config).unwrap_err().to_string(),
+            "fallback_sources[1].transform \"clamp(x, 0, \": unexpected end"
+        );
+
+        let config: CurveConfig = serde_json::from_str(
+            r#"{
+                "interval_seconds": 5,
+                "fan_curves": [],
+                "temperature_source": {"type": "cpu", "reading_latency_seconds": -5}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            validate_config(&config).unwrap_err().to_string(),
+            "temperature_source.reading_latency_seconds must be at least 0"
+        );
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let config = |curve: FanCurve| CurveConfig {
+            fan_curves: vec![FanCurve {
+                curve_points: vec![CurvePoint {
+                    temperature_celsius: 40.0,
+                    fan_speed_percent: SpeedPercent(40),
+                }],
+                ..curve
+            }],
+            ..Default::default()
+        };
+
+        assert!(validate_config(&config(FanCurve::default())).is_ok());
+        for speed_multiplier in [0.05, 2.5] {
+            assert!(validate_config(&config(FanCurve {
+                speed_multiplier,
+                ..Default::default()
+            }))
+            .is_err());
+        }
+        assert!(validate_config(&config(FanCurve {
+            min_speed_percent: Some(SpeedPercent(60)),
+            max_speed_percent: Some(SpeedPercent(40)),
+            ..Default::default()
+        }))
+        .is_err());
+        assert!(validate_config(&config(FanCurve {
+            never_stop: true,
+            below_min: BelowMin::Zero,
+            ..Default::default()
+        }))
+        .is_err());
+        assert!(validate_config(&config(FanCurve {
+            never_stop: true,
+            ..Default::default()
+        }))
+        .is_ok());
+        for seconds in [0.0, -5.0, MAX_FILTER_SECONDS + 1.0, f64::NAN] {
+            assert!(validate_config(&config(FanCurve {
+                filter: Some(TemperatureFilter::WindowAvg { seconds }),
+                ..Default::default()
+            }))
+            .is_err());
+        }
+        let averaged: FanCurve = serde_json::from_str(
+            r#"{"device_id": [3314, 41219, "ABC"], "channel": 0, "mode": "Manual",
+                "curve_points": [{"temperature_celsius": 40.0, "fan_speed_percent": 40}],
+                "filter": {"type": "window_avg", "seconds": 120}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            averaged.filter,
+            Some(TemperatureFilter::WindowAvg { seconds: 120.0 })
+        );
+        assert!(validate_config(&config(averaged)).is_ok());
+
+        let empty = CurveConfig {
+            fan_curves: vec![FanCurve::default()],
+            ..Default::default()
+        };
+        assert!(validate_config(&empty).is_err());
+
+        let mut flaky = config(FanCurve::default());
+        flaky.source_health.lost_after_failures = 1;
+        assert!(validate_config(&flaky).is_err());
+        flaky.source_health = SourceHealthSettings {
+            degraded_after_failures: 1,
+            lost_after_failures: 1,
+            recovered_after_successes: 0,
+        };
+        assert!(validate_config(&flaky).is_err());
+    }
+
+    #[test]
+    fn test_speed_percent() {
+        assert_eq!(SpeedPercent::new(100).unwrap(), 100);
+        assert!(SpeedPercent::new(101).is_err());
+        assert_eq!(SpeedPercent::saturating(200), SpeedPercent::FULL);
+
+        let error = serde_json::from_str::<CurvePoint>(
+            r#"{"temperature_celsius": 60.0, "fan_speed_percent": 150}"#,
+        )
+        .unwrap_err();
+        assert!(
+            error.to_string().contains("speed 150% is above 100%"),
+            "{}",
+            error
+        );
+        assert!(serde_json::from_str::<CurveConfig>(
+            r#"{"interval_seconds": 5, "fan_curves": [], "fallback_speed_percent": 101}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_fahrenheit_curve_points() {
+        let point: CurvePoint =
+            serde_json::from_str(r#"{"temperature_fahrenheit": 212.0, "fan_speed_percent": 80}"#)
+                .unwrap();
+        assert_eq!(point.temperature_celsius, 100.0);
+        // Stored back in Celsius
+        assert!(serde_json::to_string(&point)
+            .unwrap()
+            .contains(r#""temperature_celsius":100.0"#));
+
+        for invalid in [
+            r#"{"temperature_celsius": 40.0, "temperature_fahrenheit": 104.0, "fan_speed_percent": 80}"#,
+            r#"{"fan_speed_percent": 80}"#,
+        ] {
+            assert!(serde_json::from_str::<CurvePoint>(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_fahrenheit_points_in_tagged_curves() {
+        let config = |unit: &str| {
+            format!(
+                r#"{{ "interval_seconds": 5, "fan_curves": [{{ "name": "vrm", "device_id": [3314, 41219, "ABC"],
+                    "channel": 0, "mode": "Manual", "curve_points": [
+                        {{"temperature_celsius": 40.0, "fan_speed_percent": 30}},
+                        {{"temperature_fahrenheit": 176.0, "fan_speed_percent": 80}}
+                    ]{} }}] }}"#,
+                unit
+            )
+        };
+
+        // An untagged curve may mix the two; each point says its own unit
+        let mixed = parse_config(config("").as_bytes()).unwrap();
+        let temperatures: Vec<f64> = mixed.fan_curves[0]
+            .curve_points
+            .iter()
+            .map(|point| point.temperature_celsius)
+            .collect();
+        assert_eq!(temperatures, vec![40.0, 80.0]);
+
+        // In a curve tagged Fahrenheit the second point would be converted twice
+        let error = parse_config(config(r#", "temperature_unit": "fahrenheit""#).as_bytes())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Curve vrm"), "{}", error);
+        assert!(error.contains("temperature_fahrenheit"), "{}", error);
+        let falling = config(r#", "temperature_unit": "fahrenheit""#).replace(
+            r#""curve_points": ["#,
+            r#""curve_points": [{"temperature_celsius": 104.0, "fan_speed_percent": 30}], "curve_points_falling": ["#,
+        );
+        assert!(parse_config(falling.as_bytes()).is_err());
+        assert!(parse_config(config(r#", "temperature_unit": "celsius""#).as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_safe_mode_config() {
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let header = DeviceId(0, 0, "hwmon3/pwm2".to_string());
+        let config = safe_mode_config(&[(hub.clone(), 4), (header, 1)]);
+
+        let channels: Vec<_> = config
+            .fan_curves
+            .iter()
+            .map(|curve| (curve.device_id.clone(), curve.channel))
+            .collect();
+        assert_eq!(
+            channels,
+            (0..4)
+                .map(|channel| (hub.clone(), channel))
+                .collect::<Vec<_>>()
+        );
+        assert!(validate_config(&config).is_ok());
+    }
+
+    #[test]
+    fn test_diff_json() {
+        let before = serde_json::json!({
+            "interval_seconds": 5,
+            "strict": false,
+            "fan_curves": [{ "channel": 0 }, { "channel": 1 }],
+            "devices": [{ "lighting_control": "daemon" }]
+        });
+        let after = serde_json::json!({
+            "interval_seconds": 10,
+            "fan_curves": [{ "channel": 0 }],
+            "devices": [{ "lighting_control": "external" }],
+            "managed_devices": []
+        });
+
+        assert_eq!(
+            diff_json(&before, &after),
+            vec![
+                r#"~ devices[0].lighting_control: "daemon" -> "external""#,
+                r#"- fan_curves[1]: {"channel":1}"#,
+                "~ interval_seconds: 5 -> 10",
+                "- strict: false",
+                "+ managed_devices: []",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_closest_match() {
+        let available = vec![
+            DeviceId(0x0cf2, 0xa102, "A02B1C3E".to_string()),
+            DeviceId(0x0cf2, 0xa103, "A02B1C3E".to_string()),
+        ];
+        let typo = |serial: &str| DeviceId(0x0cf2, 0xa103, serial.to_string());
+
+        assert_eq!(
+            typo("a02b 1c3d").closest_match(&available),
+            Some(&available[1])
+        );
+        assert_eq!(
+            typo("A02B1C3").closest_match(&available),
+            Some(&available[1])
+        );
+        assert_eq!(
+            typo("A02B1C3EF").closest_match(&available),
+            Some(&available[1])
+        );
+        assert_eq!(typo("A02B1C").closest_match(&available), None);
+        assert_eq!(typo("B02B1C3D").closest_match(&available), None);
+    }
+
+    #[test]
+    fn test_group_mode_mismatch_warns() {
+        let curve = |channel, mode| FanCurve {
+            name: Some(format!("front-{}", channel)),
+            channel,
+            mode,
+            group: Some("front".to_string()),
+            ..Default::default()
+        };
+        let mut config = CurveConfig {
+            fan_curves: vec![curve(0, ChannelMode::Manual), curve(1, ChannelMode::Manual)],
+            ..Default::default()
+        };
+        assert!(validation_warnings(&config).is_empty());
+
+        config.fan_curves.push(curve(2, ChannelMode::PWM));
+        let warnings = validation_warnings(&config);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("front-0") && warnings[0].contains("front-2"));
+
+        config.experimental_hub_reports = true;
+        assert_eq!(validation_warnings(&config).len(), 2);
+        config.restore_on_exit = true;
+        assert_eq!(validation_warnings(&config).len(), 3);
+    }
+
+    #[test]
+    fn test_missing_config() {
+        let dir =
+            std::env::temp_dir().join(format!("uni-sync-curve-config-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("uni-sync-curve.json");
+        let devices = vec![DeviceId(0x0cf2, 0xa103, "ABC".to_string())];
+
+        let error = load_config(
+            &path,
+            devices.clone(),
+            CreateIfMissing::Error,
+            false,
+            DefaultSource::default,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("uni-sync-curve init"), "{}", error);
+        assert!(!path.exists());
+
+        let (config, origin) = load_config(
+            &path,
+            devices.clone(),
+            CreateIfMissing::Defaults,
+            false,
+            DefaultSource::default,
+        )
+        .unwrap();
+        assert_eq!(origin, ConfigOrigin::Defaults);
+        assert_eq!(config.fan_curves.len(), 1);
+        assert!(!path.exists());
+
+        let (written, origin) = load_config(
+            &path,
+            devices.clone(),
+            CreateIfMissing::Write,
+            true,
+            DefaultSource::default,
+        )
+        .unwrap();
+        assert_eq!(origin, ConfigOrigin::CreatedDefault);
+        assert!(path.exists());
+        // Once the file exists, every option just reads it
+        for create in [CreateIfMissing::Error, CreateIfMissing::Write] {
+            let (read, origin) =
+                load_config(&path, Vec::new(), create, false, DefaultSource::default).unwrap();
+            assert_eq!(origin, ConfigOrigin::File);
+            assert_eq!(read.fan_curves.len(), written.fan_curves.len());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prepare_config_dir() {
+        let dir = std::env::temp_dir().join(format!("uni-sync-curve-dirs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let roots = vec![dir.clone()];
+        let devices = vec![DeviceId(0x0cf2, 0xa103, "ABC".to_string())];
+
+        // A typo'd directory isn't created
+        let typo = dir.join("etx").join("uni-sync-curve.json");
+        let error = create_default_config(&typo, devices.clone(), false, DefaultSource::default())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with("parent directory does not exist"),
+            "{}",
+            error
+        );
+        assert!(
+            error.contains(&dir.join("etx").display().to_string()),
+            "{}",
+            error
+        );
+        assert!(!typo.parent().unwrap().exists());
+        // ...unless asked to
+        create_default_config(&typo, devices.clone(), true, DefaultSource::default()).unwrap();
+        assert!(typo.exists());
+
+        // A directory of its own in a known root is fine
+        let own = dir.join("uni-sync-curve").join("uni-sync-curve.json");
+        prepare_config_dir(&own, false, &roots).unwrap();
+        assert!(own.parent().unwrap().is_dir());
+        // But not one nested deeper
+        let nested = dir.join("a").join("b").join("uni-sync-curve.json");
+        assert!(prepare_config_dir(&nested, false, &roots).is_err());
+        // An existing directory needs nothing
+        prepare_config_dir(&dir.join("c.json"), false, &[]).unwrap();
+
+        // A directory nobody can write to
+        use std::os::unix::fs::PermissionsExt;
+        let locked = dir.join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o555)).unwrap();
+        let error = create_default_config(
+            &locked.join("c.json"),
+            devices,
+            false,
+            DefaultSource::default(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("is not writable"), "{}", error);
+        assert!(!locked.join("c.json").exists());
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_modify_config() {
+        let dir = std::env::temp_dir().join(format!(
+            "uni-sync-curve-modify-config-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("uni-sync-curve.json");
+        let mut config = get_default_config(
+            vec![DeviceId(0x0cf2, 0xa103, "ABC".to_string())],
+            DefaultSource::default(),
+        );
+        config.interval_seconds = 1;
+        write_config(&path, &config).unwrap();
+
+        // Competing writers, each bumping the interval: none is lost
+        let writers: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..5 {
+                        modify_config(&path, |config| {
+                            config.interval_seconds += 1;
+                            Ok(())
+                        })
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(read_config(&path).unwrap().interval_seconds, 41);
+
+        // A change made without the lock isn't clobbered
+        let error = modify_config(&path, |config| {
+            let mut edited = config.clone();
+            edited.interval_seconds = 99;
+            std::fs::write(&path, serde_json::to_string_pretty(&edited)?)?;
+            config.interval_seconds = 2;
+            Ok(())
+        })
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("changed on disk"), "{}", error);
+        assert_eq!(read_config(&path).unwrap().interval_seconds, 99);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_config_keeps_backup() {
+        let dir = std::env::temp_dir().join(format!(
+            "uni-sync-curve-write-config-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("uni-sync-curve.json");
+        let original = get_default_config(
+            vec![DeviceId(0x0cf2, 0xa103, "ABC".to_string())],
+            DefaultSource::default(),
+        );
+        write_config(&path, &original).unwrap();
+        assert!(!path.with_extension("json.bak").exists());
+
+        let mut edited = original.clone();
+        edited.interval_seconds = 3;
+        write_config(&path, &edited).unwrap();
+        assert_eq!(read_config(&path).unwrap().interval_seconds, 3);
+        let backup = read_config(&path.with_extension("json.bak")).unwrap();
+        assert_eq!(backup.interval_seconds, original.interval_seconds);
+
+        // An invalid config leaves both files alone
+        edited.fan_curves[0].curve_points.clear();
+        assert!(write_config(&path, &edited).is_err());
+        assert_eq!(read_config(&path).unwrap().interval_seconds, 3);
+        assert_eq!(
+            read_config(&path.with_extension("json.bak"))
+                .unwrap()
+                .interval_seconds,
+            original.interval_seconds
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
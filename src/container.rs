@@ -0,0 +1,183 @@
+use std::fs;
+use std::path::Path;
+
+/// What the daemon can see of the machine it runs on, to tell a container
+/// or VM that wasn't given the hardware apart from a machine without it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Environment {
+    /// /.dockerenv or /run/.containerenv exists.
+    pub container: bool,
+    /// /proc/cpuinfo has the hypervisor flag.
+    pub virtual_machine: bool,
+    pub hwmon_entries: usize,
+    pub hidraw_nodes: usize,
+}
+
+impl Environment {
+    /// Looks under `root`, "/" outside tests.
+    pub fn detect(root: &Path) -> Self {
+        let count = |dir: &str, prefix: &str| {
+            fs::read_dir(root.join(dir)).map_or(0, |entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.file_name().to_string_lossy().starts_with(prefix))
+                    .count()
+            })
+        };
+        Self {
+            container: root.join(".dockerenv").exists() || root.join("run/.containerenv").exists(),
+            virtual_machine: fs::read_to_string(root.join("proc/cpuinfo")).is_ok_and(|cpuinfo| {
+                cpuinfo
+                    .lines()
+                    .filter(|line| line.starts_with("flags"))
+                    .any(|line| line.split_whitespace().any(|flag| flag == "hypervisor"))
+            }),
+            hwmon_entries: count("sys/class/hwmon", "hwmon"),
+            hidraw_nodes: count("dev", "hidraw"),
+        }
+    }
+
+    fn isolated(&self) -> bool {
+        self.container || self.virtual_machine
+    }
+}
+
+/// Hints for why no hub or no temperature was found, when the cause looks
+/// like the container or VM rather than the hardware. Empty on a machine
+/// that simply has neither.
+pub fn diagnostics(
+    environment: &Environment,
+    devices_found: bool,
+    sensors_found: bool,
+) -> Vec<String> {
+    let mut hints = Vec::new();
+    let missing = !devices_found || !sensors_found || environment.hwmon_entries == 0;
+    let suspect = environment.isolated() || environment.hwmon_entries == 0;
+    if !(missing && suspect) {
+        return hints;
+    }
+    if environment.container {
+        hints.push("Running in a container.".to_string());
+    } else if environment.virtual_machine {
+        hints.push("Running in a virtual machine.".to_string());
+    }
+    if !devices_found {
+        if environment.hidraw_nodes == 0 {
+            hints.push(
+                "No /dev/hidraw* nodes are visible. Pass the hub's node through, e.g. \
+                 --device /dev/hidraw3 for docker, or the USB device to the VM."
+                    .to_string(),
+            );
+        } else {
+            hints.push(format!(
+                "{} /dev/hidraw* nodes are visible but none is a hub; check the container \
+                 may open them (--device, not just the mount) and that the hub's is among them.",
+                environment.hidraw_nodes
+            ));
+        }
+    }
+    if !sensors_found || environment.hwmon_entries == 0 {
+        if environment.hwmon_entries == 0 {
+            hints.push(
+                "/sys/class/hwmon is empty, so no temperature can be read and no PWM header \
+                 driven. Mount the host's /sys read-only, e.g. -v /sys:/sys:ro."
+                    .to_string(),
+            );
+        }
+        if environment.virtual_machine && !environment.container {
+            hints.push(
+                "A VM sees no host sensors. Run the daemon on the host, where the CPU and GPU \
+                 sources can read them."
+                    .to_string(),
+            );
+        }
+    }
+    hints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_root(name: &str) -> std::path::PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "uni-sync-curve-container-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sys/class/hwmon")).unwrap();
+        fs::create_dir_all(root.join("dev")).unwrap();
+        fs::create_dir_all(root.join("proc")).unwrap();
+        root
+    }
+
+    #[test]
+    fn test_detect() {
+        let root = fake_root("detect");
+        assert_eq!(Environment::detect(&root), Environment::default());
+
+        fs::write(root.join(".dockerenv"), "").unwrap();
+        fs::write(
+            root.join("proc/cpuinfo"),
+            "flags\t\t: fpu vme hypervisor lahf_lm\n",
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("sys/class/hwmon/hwmon0")).unwrap();
+        fs::write(root.join("dev/hidraw0"), "").unwrap();
+        fs::write(root.join("dev/hidraw1"), "").unwrap();
+        fs::write(root.join("dev/null"), "").unwrap();
+        assert_eq!(
+            Environment::detect(&root),
+            Environment {
+                container: true,
+                virtual_machine: true,
+                hwmon_entries: 1,
+                hidraw_nodes: 2,
+            }
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let host = Environment {
+            hwmon_entries: 4,
+            hidraw_nodes: 2,
+            ..Environment::default()
+        };
+        // Nothing to blame on isolation
+        assert!(diagnostics(&host, false, false).is_empty());
+
+        let bare_container = Environment {
+            container: true,
+            ..Environment::default()
+        };
+        let hints = diagnostics(&bare_container, false, false);
+        assert_eq!(hints.len(), 3);
+        assert!(hints[1].contains("--device /dev/hidraw"));
+        assert!(hints[2].contains("-v /sys:/sys:ro"));
+
+        // Devices passed through, /sys not mounted
+        let hints = diagnostics(
+            &Environment {
+                hidraw_nodes: 1,
+                ..bare_container.clone()
+            },
+            true,
+            false,
+        );
+        assert_eq!(hints.len(), 2);
+        assert!(hints[1].contains("/sys/class/hwmon is empty"));
+
+        let vm = Environment {
+            virtual_machine: true,
+            hwmon_entries: 1,
+            ..Environment::default()
+        };
+        let hints = diagnostics(&vm, true, false);
+        assert_eq!(hints.len(), 2);
+        assert!(hints[1].contains("Run the daemon on the host"));
+        assert!(diagnostics(&vm, true, true).is_empty());
+    }
+}
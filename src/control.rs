@@ -0,0 +1,149 @@
+use std::fmt::Write;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc::Sender;
+
+use crate::shutdown::ShutdownToken;
+use crate::status::Status;
+use crate::units::DisplayUnits;
+
+/// Keys not yet handled by the daemon's loop; typing ahead of it waits.
+pub const CONTROL_QUEUE_DEPTH: usize = 16;
+
+/// How far one `+` or `-` moves the override.
+pub const NUDGE_STEP_PERCENT: i8 = 5;
+
+/// Requests to the running daemon's loop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlCommand {
+    /// Stop writing fan speeds, or start again.
+    TogglePause,
+    /// Move the global override, which is added to every channel's speed.
+    Nudge(i8),
+    Reload,
+    PrintStatus,
+    Quit,
+}
+
+pub fn parse_key(key: char) -> Option<ControlCommand> {
+    match key {
+        'p' => Some(ControlCommand::TogglePause),
+        '+' => Some(ControlCommand::Nudge(NUDGE_STEP_PERCENT)),
+        '-' => Some(ControlCommand::Nudge(-NUDGE_STEP_PERCENT)),
+        'r' => Some(ControlCommand::Reload),
+        's' => Some(ControlCommand::PrintStatus),
+        'q' => Some(ControlCommand::Quit),
+        _ => None,
+    }
+}
+
+/// Reads keyboard commands from stdin until `token` is cancelled. The
+/// terminal stays in line mode, so every key takes effect once Enter is
+/// pressed; `++` nudges twice.
+pub fn spawn_stdin_reader(commands: Sender<ControlCommand>, token: ShutdownToken) {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        loop {
+            let line = tokio::select! {
+                _ = token.cancelled() => return,
+                line = lines.next_line() => line,
+            };
+            let Ok(Some(line)) = line else {
+                return;
+            };
+            for key in line.chars().filter(|c| !c.is_whitespace()) {
+                match parse_key(key) {
+                    Some(command) => {
+                        if commands.send(command).await.is_err() {
+                            return;
+                        }
+                    }
+                    None => eprintln!(
+                        "Unknown key {:?}: p pause/resume, +/- override, r reload, s status, q quit",
+                        key
+                    ),
+                }
+            }
+        }
+    });
+}
+
+/// Adds the global override to a duty.
+pub fn apply_override(duty: f64, override_percent: i8) -> f64 {
+    (duty + override_percent as f64).clamp(0.0, 100.0)
+}
+
+/// One line summarizing the daemon's state, reprinted after each keyboard
+/// command so it stands out between log lines.
+pub fn status_bar(
+    paused: bool,
+    override_percent: i8,
+    status: Option<&Status>,
+    units: DisplayUnits,
+) -> String {
+    let mut text = String::from(if paused { "[paused]" } else { "[running]" });
+    if override_percent != 0 {
+        let _ = write!(text, " override {:+}%", override_percent);
+    }
+    if let Some(status) = status {
+        if let Some(temperature) = status.temperature_celsius {
+            let _ = write!(text, " CPU {}", units.temperature(temperature));
+        }
+        for channel in &status.channels {
+            let _ = write!(text, " | {} {}%", channel.curve, channel.speed_percent);
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ChannelMode, DeviceId};
+    use crate::engine::SpeedReason;
+    use crate::status::ChannelStatus;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key('p'), Some(ControlCommand::TogglePause));
+        assert_eq!(parse_key('-'), Some(ControlCommand::Nudge(-5)));
+        assert_eq!(parse_key('q'), Some(ControlCommand::Quit));
+        assert_eq!(parse_key('x'), None);
+    }
+
+    #[test]
+    fn test_override_and_status_bar() {
+        assert_eq!(apply_override(50.0, 10), 60.0);
+        assert_eq!(apply_override(95.0, 10), 100.0);
+        assert_eq!(apply_override(5.0, -10), 0.0);
+        assert_eq!(apply_override(42.5, -2), 40.5);
+
+        let status = Status {
+            updated_at: 0,
+            temperature_celsius: Some(61.0),
+            channels: vec![ChannelStatus {
+                curve: "front".to_string(),
+                id: "front".to_string(),
+                device_id: DeviceId::default(),
+                channel: 0,
+                mode: ChannelMode::Manual,
+                speed_percent: 70,
+                fan_count: None,
+                group: None,
+                group_winner: None,
+                reason: SpeedReason::Curve,
+                previous: None,
+                quarantined: false,
+                manage: Default::default(),
+                never_stop: false,
+                duty: None,
+                write_budget: None,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            status_bar(false, 10, Some(&status), DisplayUnits::Celsius),
+            "[running] override +10% CPU 61.0°C | front 70%"
+        );
+        assert_eq!(status_bar(true, 0, None, DisplayUnits::Celsius), "[paused]");
+    }
+}
@@ -1,29 +1,65 @@
-use crate::config::FanCurve;
+use std::collections::HashMap;
 
-pub fn calculate_fan_speed(curve: &FanCurve, temperature: f64) -> u8 {
+use crate::config::{AboveMax, BelowMin, CurvePoint, FanCurve, SpeedPercent};
+
+/// The curve's speed at `temperature`. `fallback_speed` is only used for a
+/// curve without points, which validation rejects, or if no segment matches.
+pub fn calculate_fan_speed(curve: &FanCurve, temperature: f64, fallback_speed: SpeedPercent) -> u8 {
+    quantize(calculate_fan_duty(curve, temperature, fallback_speed))
+}
+
+/// Rounds a duty to the whole percent a channel is set to. The engine
+/// carries fractional duties through every stage and only quantizes the
+/// result, so rounding never compounds from stage to stage.
+pub fn quantize(duty: f64) -> u8 {
+    duty.round().clamp(0.0, 100.0) as u8
+}
+
+/// calculate_fan_speed, before rounding to a whole percent.
+pub fn calculate_fan_duty(curve: &FanCurve, temperature: f64, fallback_speed: SpeedPercent) -> f64 {
     let points = &curve.curve_points;
 
     if points.is_empty() {
-        return 50;
+        return fallback_speed.get() as f64;
     }
 
-    if points.len() == 1 {
-        return points[0].fan_speed_percent;
+    let mut sorted_points = points.clone();
+    sorted_points.sort_by(|a, b| a.temperature_celsius.total_cmp(&b.temperature_celsius));
+
+    let first = &sorted_points[0];
+    if temperature < first.temperature_celsius && curve.below_min == BelowMin::Zero {
+        return 0.0;
     }
 
-    let mut sorted_points = points.clone();
-    sorted_points.sort_by(|a, b| {
-        a.temperature_celsius
-            .partial_cmp(&b.temperature_celsius)
-            .unwrap()
-    });
+    let last = sorted_points.last().unwrap();
+    if temperature > last.temperature_celsius {
+        match curve.above_max {
+            AboveMax::Clamp => {}
+            AboveMax::FullSpeed => return 100.0,
+            AboveMax::Extrapolate if sorted_points.len() >= 2 => {
+                let previous = &sorted_points[sorted_points.len() - 2];
+                return interpolate(
+                    previous.temperature_celsius,
+                    previous.fan_speed_percent.get(),
+                    last.temperature_celsius,
+                    last.fan_speed_percent.get(),
+                    temperature,
+                );
+            }
+            AboveMax::Extrapolate => {}
+        }
+    }
+
+    if points.len() == 1 {
+        return points[0].fan_speed_percent.get() as f64;
+    }
 
     if temperature <= sorted_points[0].temperature_celsius {
-        return sorted_points[0].fan_speed_percent;
+        return sorted_points[0].fan_speed_percent.get() as f64;
     }
 
     if temperature >= sorted_points.last().unwrap().temperature_celsius {
-        return sorted_points.last().unwrap().fan_speed_percent;
+        return sorted_points.last().unwrap().fan_speed_percent.get() as f64;
     }
 
     for i in 0..sorted_points.len() - 1 {
@@ -33,33 +69,350 @@ pub fn calculate_fan_speed(curve: &FanCurve, temperature: f64) -> u8 {
         if temperature >= point1.temperature_celsius && temperature <= point2.temperature_celsius {
             return interpolate(
                 point1.temperature_celsius,
-                point1.fan_speed_percent,
+                point1.fan_speed_percent.get(),
                 point2.temperature_celsius,
-                point2.fan_speed_percent,
+                point2.fan_speed_percent.get(),
+                temperature,
+            );
+        }
+    }
+
+    // Only a bug (or a NaN temperature) gets here
+    eprintln!(
+        "Warning: curve {} has no segment for {:.2}°C, using {}%",
+        curve.label(),
+        temperature,
+        fallback_speed
+    );
+    fallback_speed.get() as f64
+}
+
+/// A curve the evaluation contract holds for: distinct finite temperatures
+/// and speeds that never decrease as temperature rises.
+pub fn is_monotone_curve(points: &[CurvePoint]) -> bool {
+    let mut sorted_points = points.to_vec();
+    sorted_points.sort_by(|a, b| a.temperature_celsius.total_cmp(&b.temperature_celsius));
+
+    sorted_points
+        .iter()
+        .all(|p| p.temperature_celsius.is_finite())
+        && sorted_points.windows(2).all(|w| {
+            w[0].temperature_celsius < w[1].temperature_celsius
+                && w[0].fan_speed_percent <= w[1].fan_speed_percent
+        })
+}
+
+/// Describes how `speed` at `temperature` breaks the evaluation contract
+/// for a monotone curve, if it does.
+pub fn check_invariants(
+    curve: &FanCurve,
+    temperature: f64,
+    speed: u8,
+    fallback_speed: SpeedPercent,
+) -> Option<String> {
+    let points = &curve.curve_points;
+    if points.len() < 2 || !is_monotone_curve(points) || !temperature.is_finite() {
+        return None;
+    }
+
+    let min = points
+        .iter()
+        .map(|p| p.fan_speed_percent.get())
+        .min()
+        .unwrap();
+    let max = points
+        .iter()
+        .map(|p| p.fan_speed_percent.get())
+        .max()
+        .unwrap();
+    let first = points
+        .iter()
+        .map(|p| p.temperature_celsius)
+        .fold(f64::INFINITY, f64::min);
+    let last = points
+        .iter()
+        .map(|p| p.temperature_celsius)
+        .fold(f64::NEG_INFINITY, f64::max);
+    // above_max and below_min may deliberately leave the point range
+    let bounded = !(temperature > last && curve.above_max != AboveMax::Clamp
+        || temperature < first && curve.below_min != BelowMin::Clamp);
+    if bounded && (speed < min || speed > max) {
+        return Some(format!(
+            "speed {}% at {:.2}°C is outside the curve's range {}-{}%",
+            speed, temperature, min, max
+        ));
+    }
+
+    if let Some(point) = points.iter().find(|p| p.temperature_celsius == temperature) {
+        if speed != point.fan_speed_percent.get() {
+            return Some(format!(
+                "speed {}% at point {:.2}°C differs from the point's {}%",
+                speed,
                 temperature,
+                point.fan_speed_percent.get()
+            ));
+        }
+    }
+
+    let below = calculate_fan_speed(curve, temperature - 0.5, fallback_speed);
+    let above = calculate_fan_speed(curve, temperature + 0.5, fallback_speed);
+    if below > speed || speed > above {
+        return Some(format!(
+            "curve is not monotone around {:.2}°C ({}% / {}% / {}%)",
+            temperature, below, speed, above
+        ));
+    }
+
+    None
+}
+
+/// calculate_fan_duty, verifying the evaluation contract in debug builds or
+/// when `strict` is set. Violations are logged, and abort in strict mode.
+pub fn calculate_fan_duty_checked(
+    curve: &FanCurve,
+    temperature: f64,
+    strict: bool,
+    fallback_speed: SpeedPercent,
+) -> f64 {
+    let duty = calculate_fan_duty(curve, temperature, fallback_speed);
+
+    if cfg!(debug_assertions) || strict {
+        if let Some(violation) =
+            check_invariants(curve, temperature, quantize(duty), fallback_speed)
+        {
+            eprintln!(
+                "Curve invariant violated for device {} channel {}: {}",
+                curve.device_id, curve.channel, violation
             );
+            if strict {
+                panic!("curve invariant violated: {}", violation);
+            }
+        }
+    }
+
+    duty
+}
+
+/// The curve's duty for `temperature`, boosted while the temperature rises
+/// at `rate` °C/s if the curve has anticipation. A falling temperature never
+/// takes the duty below the plain curve output.
+pub fn calculate_anticipated_duty(
+    curve: &FanCurve,
+    temperature: f64,
+    rate: Option<f64>,
+    strict: bool,
+    fallback_speed: SpeedPercent,
+) -> f64 {
+    let duty = calculate_fan_duty_checked(curve, temperature, strict, fallback_speed);
+
+    match (curve.anticipation, rate) {
+        (Some(anticipation), Some(rate)) if rate > 0.0 => {
+            let anticipated = calculate_fan_duty(
+                curve,
+                temperature + anticipation.degrees_per_second_gain * rate,
+                fallback_speed,
+            );
+            duty + (anticipated - duty).clamp(0.0, anticipation.max_boost_percent as f64)
+        }
+        _ => duty,
+    }
+}
+
+/// The duty the curve's `throttle_points` give for a throttle pressure of
+/// `pressure` percent, interpolated between them and held beyond the first
+/// and last; None for a curve without them.
+pub fn calculate_throttle_duty(curve: &FanCurve, pressure: f64) -> Option<f64> {
+    if curve.throttle_points.is_empty() {
+        return None;
+    }
+    let pressure_curve = FanCurve {
+        curve_points: curve
+            .throttle_points
+            .iter()
+            .map(|point| CurvePoint {
+                temperature_celsius: point.pressure_percent,
+                fan_speed_percent: point.fan_speed_percent,
+            })
+            .collect(),
+        ..FanCurve::default()
+    };
+    Some(calculate_fan_duty(
+        &pressure_curve,
+        pressure,
+        SpeedPercent::ZERO,
+    ))
+}
+
+/// Applies a channel's trim to the speed its curve computed: multiplier,
+/// then offset, rounded to a whole percent, then clamped to the curve's
+/// min/max speed and finally to 0-100.
+pub fn adjust_speed(curve: &FanCurve, speed: u8) -> u8 {
+    quantize(adjust_duty(curve, speed as f64))
+}
+
+/// adjust_speed, before rounding to a whole percent.
+pub fn adjust_duty(curve: &FanCurve, duty: f64) -> f64 {
+    let mut adjusted =
+        (duty * curve.speed_multiplier + curve.speed_offset_percent as f64).clamp(0.0, 100.0);
+    if let Some(min) = curve.min_speed_percent {
+        adjusted = adjusted.max(min.get() as f64);
+    }
+    if let Some(max) = curve.max_speed_percent {
+        adjusted = adjusted.min(max.get() as f64);
+    }
+    adjusted
+}
+
+/// Whether the curve has any trim for adjust_speed to apply.
+pub fn has_trim(curve: &FanCurve) -> bool {
+    curve.speed_multiplier != 1.0
+        || curve.speed_offset_percent != 0
+        || curve.min_speed_percent.is_some()
+        || curve.max_speed_percent.is_some()
+}
+
+/// The curve's trim, e.g. "x1.20, +5%, min 30%".
+pub fn describe_trim(curve: &FanCurve) -> String {
+    let mut parts = Vec::new();
+    if curve.speed_multiplier != 1.0 {
+        parts.push(format!("x{:.2}", curve.speed_multiplier));
+    }
+    if curve.speed_offset_percent != 0 {
+        parts.push(format!("{:+}%", curve.speed_offset_percent));
+    }
+    if let Some(min) = curve.min_speed_percent {
+        parts.push(format!("min {}%", min));
+    }
+    if let Some(max) = curve.max_speed_percent {
+        parts.push(format!("max {}%", max));
+    }
+    parts.join(", ")
+}
+
+/// Which points calculate_fan_speed uses at `temperature`, e.g.
+/// "between 50.0°C 40% and 70.0°C 80%".
+pub fn describe_lookup(curve: &FanCurve, temperature: f64) -> String {
+    let mut points = curve.curve_points.clone();
+    points.sort_by(|a, b| a.temperature_celsius.total_cmp(&b.temperature_celsius));
+    let point = |p: &CurvePoint| format!("{:.1}°C {}%", p.temperature_celsius, p.fan_speed_percent);
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return "no points, fallback speed".to_string();
+    };
+
+    if let Some(exact) = points.iter().find(|p| p.temperature_celsius == temperature) {
+        return format!("at point {}", point(exact));
+    }
+    if temperature < first.temperature_celsius {
+        return match curve.below_min {
+            BelowMin::Clamp => format!("below the first point {}, clamped", point(first)),
+            BelowMin::Zero => format!("below the first point {}, stopped", point(first)),
+        };
+    }
+    if temperature > last.temperature_celsius {
+        let handling = match curve.above_max {
+            AboveMax::Clamp => "clamped",
+            AboveMax::Extrapolate if points.len() >= 2 => "extrapolated",
+            AboveMax::Extrapolate => "clamped",
+            AboveMax::FullSpeed => "full speed",
+        };
+        return format!("above the last point {}, {}", point(last), handling);
+    }
+    points
+        .windows(2)
+        .find(|pair| {
+            pair[0].temperature_celsius <= temperature && temperature <= pair[1].temperature_celsius
+        })
+        .map(|pair| format!("between {} and {}", point(&pair[0]), point(&pair[1])))
+        .unwrap_or_else(|| "no matching segment, fallback speed".to_string())
+}
+
+/// Holds a `never_stop` curve's channel at `minimum` at least. Nothing may
+/// run after this: it is the last word on a pump channel's speed.
+pub fn never_stop_floor(curve: &FanCurve, speed: u8, minimum: SpeedPercent) -> u8 {
+    if curve.never_stop {
+        speed.max(minimum.get())
+    } else {
+        speed
+    }
+}
+
+/// Raises every curve in a group to the highest speed any member computed.
+/// Returns, per curve, the index of the group member whose speed won.
+pub fn synchronize_groups(curves: &[FanCurve], speeds: &mut [f64]) -> Vec<Option<usize>> {
+    let mut winners: HashMap<&str, usize> = HashMap::new();
+    for (index, curve) in curves.iter().enumerate() {
+        if let Some(group) = &curve.group {
+            let winner = winners.entry(group.as_str()).or_insert(index);
+            if speeds[index] > speeds[*winner] {
+                *winner = index;
+            }
         }
     }
 
-    50
+    curves
+        .iter()
+        .enumerate()
+        .map(|(index, curve)| {
+            let winner = winners[curve.group.as_deref()?];
+            speeds[index] = speeds[winner];
+            Some(winner)
+        })
+        .collect()
 }
 
-fn interpolate(temp1: f64, speed1: u8, temp2: f64, speed2: u8, current_temp: f64) -> u8 {
+fn interpolate(temp1: f64, speed1: u8, temp2: f64, speed2: u8, current_temp: f64) -> f64 {
     let temp_range = temp2 - temp1;
+    // Two points at one temperature: the speed steps there
+    if temp_range == 0.0 {
+        return speed2 as f64;
+    }
     let speed_range = speed2 as f64 - speed1 as f64;
     let temp_offset = current_temp - temp1;
 
     let interpolated_speed = speed1 as f64 + (temp_offset / temp_range) * speed_range;
 
-    interpolated_speed.round().clamp(0.0, 100.0) as u8
+    interpolated_speed.clamp(0.0, 100.0)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::ChannelMode;
-    use crate::config::CurvePoint;
-    use crate::config::DeviceId;
+    use crate::config::DEFAULT_FALLBACK_SPEED_PERCENT as FALLBACK;
+    use crate::config::{Anticipation, DeviceId};
+    use crate::history::TemperatureHistory;
+    use proptest::prelude::*;
+
+    /// Monotone curves of 2 to 8 points with distinct temperatures.
+    fn monotone_curve() -> impl Strategy<Value = FanCurve> {
+        (
+            -10.0..60.0f64,
+            0u8..60,
+            prop::collection::vec((0.1..20.0f64, 0u8..25), 1..8),
+        )
+            .prop_map(|(mut temperature, mut speed, steps)| {
+                let mut curve_points = vec![CurvePoint {
+                    temperature_celsius: temperature,
+                    fan_speed_percent: SpeedPercent::new(speed).unwrap(),
+                }];
+                for (temperature_step, speed_step) in steps {
+                    temperature += temperature_step;
+                    speed = (speed + speed_step).min(100);
+                    curve_points.push(CurvePoint {
+                        temperature_celsius: temperature,
+                        fan_speed_percent: SpeedPercent::new(speed).unwrap(),
+                    });
+                }
+
+                FanCurve {
+                    device_id: DeviceId(0x0cf2, 0x7750, "TEST123".to_string()),
+                    channel: 0,
+                    mode: ChannelMode::Manual,
+                    curve_points,
+                    ..Default::default()
+                }
+            })
+    }
 
     #[test]
     fn test_fan_curve_calculation() {
@@ -70,29 +423,254 @@ mod tests {
             curve_points: vec![
                 CurvePoint {
                     temperature_celsius: 30.0,
-                    fan_speed_percent: 20,
+                    fan_speed_percent: SpeedPercent::new(20).unwrap(),
                 },
                 CurvePoint {
                     temperature_celsius: 50.0,
-                    fan_speed_percent: 40,
+                    fan_speed_percent: SpeedPercent::new(40).unwrap(),
                 },
                 CurvePoint {
                     temperature_celsius: 70.0,
-                    fan_speed_percent: 70,
+                    fan_speed_percent: SpeedPercent::new(70).unwrap(),
                 },
                 CurvePoint {
                     temperature_celsius: 85.0,
-                    fan_speed_percent: 100,
+                    fan_speed_percent: SpeedPercent::new(100).unwrap(),
+                },
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(calculate_fan_speed(&curve, 25.0, FALLBACK), 20);
+        assert_eq!(calculate_fan_speed(&curve, 30.0, FALLBACK), 20);
+        assert_eq!(calculate_fan_speed(&curve, 40.0, FALLBACK), 30);
+        assert_eq!(calculate_fan_speed(&curve, 50.0, FALLBACK), 40);
+        assert_eq!(calculate_fan_speed(&curve, 60.0, FALLBACK), 55);
+        assert_eq!(calculate_fan_speed(&curve, 70.0, FALLBACK), 70);
+        assert_eq!(calculate_fan_speed(&curve, 90.0, FALLBACK), 100);
+    }
+
+    proptest! {
+        #[test]
+        fn test_curve_properties(
+            curve in monotone_curve(),
+            positions in prop::collection::vec(0.0..1.0f64, 50),
+        ) {
+            prop_assert!(is_monotone_curve(&curve.curve_points));
+
+            let first = curve.curve_points.first().unwrap().temperature_celsius;
+            let last = curve.curve_points.last().unwrap().temperature_celsius;
+
+            for point in &curve.curve_points {
+                prop_assert_eq!(
+                    calculate_fan_speed(&curve, point.temperature_celsius, FALLBACK),
+                    point.fan_speed_percent.get()
+                );
+            }
+
+            // Spread from 20°C below the first point to 20°C above the last
+            let mut temperatures: Vec<f64> = positions
+                .iter()
+                .map(|position| first - 20.0 + position * (last - first + 40.0))
+                .collect();
+            temperatures.sort_by(f64::total_cmp);
+
+            let mut previous = 0;
+            for temperature in temperatures {
+                let speed = calculate_fan_speed(&curve, temperature, FALLBACK);
+                prop_assert!(speed >= previous, "{:?} at {}", curve.curve_points, temperature);
+                prop_assert_eq!(check_invariants(&curve, temperature, speed, FALLBACK), None);
+                previous = speed;
+            }
+        }
+
+        #[test]
+        fn test_check_invariants_reports_violations(curve in monotone_curve()) {
+            let point = &curve.curve_points[0];
+            let max = curve.curve_points.last().unwrap().fan_speed_percent.get();
+
+            prop_assert!(check_invariants(
+                &curve,
+                point.temperature_celsius,
+                max.saturating_add(1),
+                FALLBACK
+            )
+            .is_some());
+            if point.fan_speed_percent.get() != max {
+                prop_assert!(
+                    check_invariants(&curve, point.temperature_celsius, max, FALLBACK).is_some()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_boundary_modes() {
+        let mut curve = FanCurve {
+            curve_points: vec![
+                CurvePoint {
+                    temperature_celsius: 30.0,
+                    fan_speed_percent: SpeedPercent::new(20).unwrap(),
+                },
+                CurvePoint {
+                    temperature_celsius: 50.0,
+                    fan_speed_percent: SpeedPercent::new(40).unwrap(),
+                },
+                CurvePoint {
+                    temperature_celsius: 70.0,
+                    fan_speed_percent: SpeedPercent::new(80).unwrap(),
+                },
+            ],
+            ..Default::default()
+        };
+
+        // clamp is the default at both ends
+        assert_eq!(calculate_fan_speed(&curve, 20.0, FALLBACK), 20);
+        assert_eq!(calculate_fan_speed(&curve, 70.0, FALLBACK), 80);
+        assert_eq!(calculate_fan_speed(&curve, 75.0, FALLBACK), 80);
+
+        curve.above_max = AboveMax::Extrapolate;
+        assert_eq!(calculate_fan_speed(&curve, 70.0, FALLBACK), 80);
+        assert_eq!(calculate_fan_speed(&curve, 75.0, FALLBACK), 90);
+        assert_eq!(calculate_fan_speed(&curve, 90.0, FALLBACK), 100);
+
+        curve.above_max = AboveMax::FullSpeed;
+        assert_eq!(calculate_fan_speed(&curve, 70.0, FALLBACK), 80);
+        assert_eq!(calculate_fan_speed(&curve, 70.1, FALLBACK), 100);
+
+        curve.below_min = BelowMin::Zero;
+        assert_eq!(calculate_fan_speed(&curve, 30.0, FALLBACK), 20);
+        assert_eq!(calculate_fan_speed(&curve, 29.9, FALLBACK), 0);
+        assert_eq!(calculate_fan_speed(&curve, 40.0, FALLBACK), 30);
+
+        for temperature in [10.0, 29.9, 30.0, 70.0, 70.1, 95.0] {
+            let speed = calculate_fan_speed(&curve, temperature, FALLBACK);
+            assert_eq!(check_invariants(&curve, temperature, speed, FALLBACK), None);
+        }
+    }
+
+    #[test]
+    fn test_anticipation_ramps_earlier() {
+        let plain = FanCurve {
+            curve_points: vec![
+                CurvePoint {
+                    temperature_celsius: 40.0,
+                    fan_speed_percent: SpeedPercent::new(20).unwrap(),
+                },
+                CurvePoint {
+                    temperature_celsius: 80.0,
+                    fan_speed_percent: SpeedPercent::new(100).unwrap(),
                 },
             ],
+            ..Default::default()
+        };
+        let anticipating = FanCurve {
+            anticipation: Some(Anticipation {
+                degrees_per_second_gain: 5.0,
+                max_boost_percent: 15,
+            }),
+            ..plain.clone()
+        };
+
+        // Idle at 40°C, a load step heats up at 2°C/s to 76°C, then cools down
+        let trace: Vec<f64> = (0..40)
+            .map(|second| match second {
+                0..=4 => 40.0,
+                5..=22 => 40.0 + 2.0 * (second - 4) as f64,
+                _ => (76.0 - 2.0 * (second - 22) as f64).max(40.0),
+            })
+            .collect();
+
+        let mut history = TemperatureHistory::default();
+        let mut first_plain = None;
+        let mut first_anticipated = None;
+        for (second, &temperature) in trace.iter().enumerate() {
+            history.push(second as f64, temperature);
+            let rate = history.rate();
+            let plain_speed = quantize(calculate_anticipated_duty(
+                &plain,
+                temperature,
+                rate,
+                false,
+                FALLBACK,
+            ));
+            let speed = quantize(calculate_anticipated_duty(
+                &anticipating,
+                temperature,
+                rate,
+                false,
+                FALLBACK,
+            ));
+
+            assert_eq!(
+                plain_speed,
+                calculate_fan_speed(&plain, temperature, FALLBACK)
+            );
+            assert!(speed >= plain_speed);
+            assert!(speed - plain_speed <= 15);
+            if rate.is_none_or(|rate| rate <= 0.0) {
+                assert_eq!(speed, plain_speed);
+            }
+
+            if plain_speed >= 60 && first_plain.is_none() {
+                first_plain = Some(second);
+            }
+            if speed >= 60 && first_anticipated.is_none() {
+                first_anticipated = Some(second);
+            }
+        }
+
+        assert!(first_anticipated.unwrap() < first_plain.unwrap());
+    }
+
+    #[test]
+    fn test_adjust_speed() {
+        let trimmed = |speed_multiplier, speed_offset_percent| FanCurve {
+            speed_multiplier,
+            speed_offset_percent,
+            ..Default::default()
         };
 
-        assert_eq!(calculate_fan_speed(&curve, 25.0), 20);
-        assert_eq!(calculate_fan_speed(&curve, 30.0), 20);
-        assert_eq!(calculate_fan_speed(&curve, 40.0), 30);
-        assert_eq!(calculate_fan_speed(&curve, 50.0), 40);
-        assert_eq!(calculate_fan_speed(&curve, 60.0), 55);
-        assert_eq!(calculate_fan_speed(&curve, 70.0), 70);
-        assert_eq!(calculate_fan_speed(&curve, 90.0), 100);
+        assert_eq!(adjust_speed(&FanCurve::default(), 47), 47);
+        // Offset is added after scaling: 40 × 1.5 + 10, not (40 + 10) × 1.5
+        assert_eq!(adjust_speed(&trimmed(1.5, 10), 40), 70);
+        assert_eq!(adjust_speed(&trimmed(1.0, -10), 5), 0);
+        assert_eq!(adjust_speed(&trimmed(2.0, 10), 60), 100);
+        // Rounded once, at the end: 33 × 1.1 = 36.3, 35 × 1.1 = 38.5
+        assert_eq!(adjust_speed(&trimmed(1.1, 0), 33), 36);
+        assert_eq!(adjust_speed(&trimmed(1.1, 0), 35), 39);
+
+        // min/max apply to the adjusted speed
+        let bounded = FanCurve {
+            min_speed_percent: SpeedPercent::new(30).ok(),
+            max_speed_percent: SpeedPercent::new(80).ok(),
+            ..trimmed(1.0, 10)
+        };
+        assert_eq!(adjust_speed(&bounded, 0), 30);
+        assert_eq!(adjust_speed(&bounded, 50), 60);
+        assert_eq!(adjust_speed(&bounded, 75), 80);
+    }
+
+    #[test]
+    fn test_synchronize_groups() {
+        let curve = |channel, group: Option<&str>| FanCurve {
+            device_id: DeviceId(0x0cf2, 0xa102, "TEST123".to_string()),
+            channel,
+            group: group.map(str::to_string),
+            ..Default::default()
+        };
+        let curves = vec![
+            curve(0, Some("front")),
+            curve(1, Some("front")),
+            curve(2, None),
+            curve(3, Some("front")),
+            curve(0, Some("top")),
+        ];
+        let mut speeds = vec![40.0, 70.5, 90.0, 70.0, 30.0];
+
+        let winners = synchronize_groups(&curves, &mut speeds);
+
+        assert_eq!(speeds, vec![70.5, 70.5, 90.0, 70.5, 30.0]);
+        assert_eq!(winners, vec![Some(1), Some(1), None, Some(1), Some(4)]);
     }
 }
@@ -0,0 +1,1048 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+use crate::backend::{self, DeviceMetadata};
+use crate::config::{self, ChannelMode, CurveConfig, DeviceId, FanCurve};
+use crate::shutdown::{self, ShutdownToken};
+use crate::{
+    alerts, curve, disabled, duty, engine, explain, hardware, history, journal, names, power,
+    sensors, slew, status, throttle, ticklog, timing,
+};
+
+/// Everything the daemon keeps between ticks. It is built from a loaded
+/// config, a FanController and the temperature readers, so tests can run it
+/// on mock hardware and a paused clock; run_daemon owns the signals and the
+/// interactive controls around it.
+pub struct Daemon {
+    pub config: CurveConfig,
+    pub fan_controller: hardware::FanController,
+    pub engine: engine::Engine,
+    /// Every resolved curve, including runtime-disabled ones.
+    pub all_fan_curves: Vec<FanCurve>,
+    /// Identifiers of every resolved curve's channel.
+    pub channel_names: names::ChannelNames,
+    pub runtime_disabled: BTreeSet<String>,
+    pub runtime_debug: BTreeSet<String>,
+    pub alerts: alerts::AlertManager,
+    pub tick_summarizer: ticklog::TickSummarizer,
+    pub temperature_history: history::TemperatureHistory,
+    pub full_speed: bool,
+    // Outlives config reloads, but not restarts
+    pub duty_history: duty::DutyHistory,
+    /// Shared with the panic hook, which dumps it.
+    pub journal: Arc<Mutex<journal::Journal>>,
+    pub started: Instant,
+    pub source_readers: sensors::SourceReaders,
+    pub source_health: sensors::SourceHealth,
+    pub source_chain: sensors::SourceChain,
+    pub paused: bool,
+    pub override_percent: i8,
+    pub power_monitor: power::PowerMonitor,
+    /// Only while a curve has `throttle_points` and the CPUs count
+    /// throttling.
+    pub throttle_monitor: Option<throttle::ThrottleMonitor>,
+    /// Hubs whose RPMs a curve with `rpm_control` couldn't read, logged
+    /// once until they can again.
+    pub rpm_read_failed: HashSet<DeviceId>,
+    pub last_status: Option<status::Status>,
+    pub status_error_logged: bool,
+    pub schedule: timing::TickSchedule,
+    pub reconcile: timing::ReconcileSchedule,
+    pub interval_check: timing::IntervalCheck,
+    /// Cancelled when shutdown starts, for every task that must stop then.
+    pub token: ShutdownToken,
+    /// The interval ticks run at instead of the configured one, once the
+    /// hardware turned out too slow for it.
+    pub stretched_interval: Option<u64>,
+    pub safe_mode: Option<String>,
+    pub device_metadata: Vec<(DeviceId, DeviceMetadata)>,
+    pub debug: bool,
+    pub status_path: PathBuf,
+    pub disabled_path: PathBuf,
+    pub debug_path: PathBuf,
+    pub journal_path: PathBuf,
+    pub journal_request_path: PathBuf,
+}
+
+impl Daemon {
+    /// Runtime-disabled and -debugged curves are read from next to
+    /// `status_path`, as on every tick.
+    pub fn new(
+        config: CurveConfig,
+        fan_controller: hardware::FanController,
+        all_fan_curves: Vec<FanCurve>,
+        source_readers: sensors::SourceReaders,
+        status_path: &Path,
+        debug: bool,
+    ) -> Self {
+        let disabled_path = disabled::disabled_path(status_path);
+        let runtime_disabled = disabled::read_labels(&disabled_path).unwrap_or_else(|e| {
+            eprintln!("Ignoring runtime-disabled curves: {}", e);
+            BTreeSet::new()
+        });
+        let debug_path = disabled::debug_path(status_path);
+        let runtime_debug = disabled::read_labels(&debug_path).unwrap_or_else(|e| {
+            eprintln!("Ignoring runtime-debugged curves: {}", e);
+            BTreeSet::new()
+        });
+
+        let alerts = alerts::AlertManager::new(
+            config.alerts.clone(),
+            config.notify.clone(),
+            config.display_units,
+            debug,
+        );
+        let mut tick_summarizer = ticklog::TickSummarizer::default();
+        tick_summarizer.set_units(config.display_units);
+        let mut temperature_history = history::TemperatureHistory::default();
+        temperature_history.keep_for(
+            alerts
+                .history_seconds()
+                .max(config::filter_seconds(&all_fan_curves)),
+            config.interval_seconds,
+        );
+        let startup_ramp = config
+            .startup_ramp_seconds
+            .map(|seconds| engine::StartupRamp {
+                duration: Duration::from_secs(seconds),
+                start_speed: config.startup_speed_percent,
+            });
+        let mut engine = engine::Engine::new(
+            disabled::active_curves(&all_fan_curves, &runtime_disabled),
+            config.strict,
+            config.fallback_speed_percent,
+            config.never_stop_speed_percent,
+            startup_ramp,
+        );
+        engine.set_debug_curves(runtime_debug.clone());
+        let now = Instant::now();
+
+        Self {
+            engine,
+            channel_names: names::ChannelNames::new(&all_fan_curves),
+            throttle_monitor: throttle_monitor(&all_fan_curves),
+            rpm_read_failed: HashSet::new(),
+            all_fan_curves,
+            runtime_disabled,
+            runtime_debug,
+            alerts,
+            tick_summarizer,
+            temperature_history,
+            full_speed: false,
+            duty_history: duty::DutyHistory::new(config.interval_seconds),
+            journal: Arc::new(Mutex::new(journal::Journal::new(config.journal_entries))),
+            started: now,
+            source_readers,
+            source_health: sensors::SourceHealth::new(config.source_health),
+            source_chain: sensors::SourceChain::new(config.source_chain()),
+            paused: false,
+            override_percent: 0,
+            power_monitor: power::PowerMonitor::new(Path::new(power::POWER_SUPPLY_ROOT)),
+            last_status: None,
+            status_error_logged: false,
+            schedule: timing::TickSchedule::new(now, Duration::from_secs(config.interval_seconds)),
+            reconcile: timing::ReconcileSchedule::new(
+                now,
+                timing::JitteredInterval::new(
+                    Duration::from_secs(config.reconcile_every_seconds),
+                    config.timer_jitter_percent,
+                ),
+            ),
+            interval_check: timing::IntervalCheck::new(Duration::from_secs(
+                config.interval_seconds,
+            )),
+            stretched_interval: None,
+            token: ShutdownToken::default(),
+            safe_mode: None,
+            device_metadata: Vec::new(),
+            debug,
+            status_path: status_path.to_path_buf(),
+            disabled_path,
+            debug_path,
+            journal_path: journal::journal_path(status_path),
+            journal_request_path: journal::request_path(status_path),
+            config,
+            fan_controller,
+        }
+    }
+
+    /// When the next tick is due.
+    pub fn next_deadline(&mut self) -> Instant {
+        self.schedule.next_deadline(Instant::now())
+    }
+
+    /// Switches to a reloaded config and its resolved curves.
+    pub async fn apply_reload(&mut self, new_config: CurveConfig, new_fan_curves: Vec<FanCurve>) {
+        if new_config.interval_seconds != self.config.interval_seconds
+            || self.stretched_interval.is_some()
+        {
+            self.schedule
+                .set_period(Duration::from_secs(new_config.interval_seconds));
+        }
+        // The new config may change how long a tick takes, so measure again
+        self.interval_check =
+            timing::IntervalCheck::new(Duration::from_secs(new_config.interval_seconds));
+        self.stretched_interval = None;
+        self.reconcile.set_interval(
+            Instant::now(),
+            timing::JitteredInterval::new(
+                Duration::from_secs(new_config.reconcile_every_seconds),
+                new_config.timer_jitter_percent,
+            ),
+        );
+        self.alerts = alerts::AlertManager::new(
+            new_config.alerts.clone(),
+            new_config.notify.clone(),
+            new_config.display_units,
+            self.debug,
+        );
+        self.temperature_history.keep_for(
+            self.alerts
+                .history_seconds()
+                .max(config::filter_seconds(&new_fan_curves)),
+            new_config.interval_seconds,
+        );
+        self.duty_history.set_interval(new_config.interval_seconds);
+        journal::lock(&self.journal).set_capacity(new_config.journal_entries);
+        self.tick_summarizer.set_units(new_config.display_units);
+        let keys = |config: &CurveConfig| -> Vec<String> {
+            config
+                .source_chain()
+                .iter()
+                .map(|source| source.key())
+                .collect()
+        };
+        if keys(&new_config) != keys(&self.config) {
+            self.source_health = sensors::SourceHealth::new(new_config.source_health);
+            self.source_chain = sensors::SourceChain::new(new_config.source_chain());
+        } else {
+            self.source_health.set_settings(new_config.source_health);
+        }
+        self.channel_names = names::ChannelNames::new(&new_fan_curves);
+        let wants_throttle = |fan_curves: &[FanCurve]| {
+            fan_curves
+                .iter()
+                .any(|fan_curve| !fan_curve.throttle_points.is_empty())
+        };
+        if wants_throttle(&new_fan_curves) != wants_throttle(&self.all_fan_curves) {
+            self.throttle_monitor = throttle_monitor(&new_fan_curves);
+        }
+        self.all_fan_curves = new_fan_curves;
+        self.engine.set_curves(
+            disabled::active_curves(&self.all_fan_curves, &self.runtime_disabled),
+            new_config.strict,
+            new_config.fallback_speed_percent,
+            new_config.never_stop_speed_percent,
+        );
+        let temperature = self
+            .last_status
+            .as_ref()
+            .and_then(|status| status.temperature_celsius);
+        let flushed = apply_mode_changes(
+            &mut self.fan_controller,
+            self.engine.curves(),
+            temperature,
+            new_config.fallback_speed_percent,
+            new_config.never_stop_speed_percent,
+        )
+        .await;
+        self.record_writes(&flushed, temperature);
+        self.config = new_config;
+        if self.safe_mode.take().is_some() {
+            println!("Left safe mode");
+        }
+        println!(
+            "Reloaded configuration with {} fan curves",
+            self.engine.curves().len()
+        );
+    }
+
+    pub fn memory(&self) -> status::MemoryStatus {
+        status::MemoryStatus {
+            temperature_samples: self.temperature_history.usage(),
+            duty_transitions: self.duty_history.usage(),
+            write_queues: self.fan_controller.queue_usage(),
+            speed_journal: journal::lock(&self.journal).usage(),
+        }
+    }
+
+    /// Journals the commands of one flush, written while following
+    /// `temperature`.
+    fn record_writes(
+        &self,
+        flushed: &[(hardware::SpeedCommand, anyhow::Result<()>)],
+        temperature: Option<f64>,
+    ) {
+        journal::lock(&self.journal).record(
+            timing::unix_seconds(Instant::now()),
+            flushed,
+            &self.channel_names,
+            &names::source_id(self.source_chain.active()),
+            temperature,
+        );
+    }
+
+    /// The RPM of each curve's channel, in curve order, for curves with
+    /// `rpm_control`, or for every curve while a fan_stall alert watches
+    /// them. Each hub they are on is asked once.
+    async fn read_loop_rpms(&mut self) -> Vec<Option<u16>> {
+        let watch_all = self.alerts.watches_rpms();
+        let channels: Vec<Option<(DeviceId, usize)>> = self
+            .engine
+            .curves()
+            .iter()
+            .map(|fan_curve| {
+                (watch_all || fan_curve.rpm_control.is_some())
+                    .then(|| (fan_curve.device_id.clone(), fan_curve.channel))
+            })
+            .collect();
+        let mut by_device: HashMap<DeviceId, Option<Vec<u16>>> = HashMap::new();
+        let mut rpms = Vec::with_capacity(channels.len());
+        for channel in channels {
+            let Some((device_id, channel)) = channel else {
+                rpms.push(None);
+                continue;
+            };
+            // A channel without fans reads 0 RPM, which isn't a stall
+            if self.fan_controller.fan_count(&device_id, channel) == Some(0) {
+                rpms.push(None);
+                continue;
+            }
+            if !by_device.contains_key(&device_id) {
+                let reading = match self.fan_controller.query_rpms(&device_id).await {
+                    Ok(reading) => {
+                        self.rpm_read_failed.remove(&device_id);
+                        Some(reading)
+                    }
+                    Err(e) => {
+                        if self.rpm_read_failed.insert(device_id.clone()) {
+                            eprintln!(
+                                "Could not read RPMs of {}, following curve duties: {}",
+                                device_id, e
+                            );
+                        }
+                        None
+                    }
+                };
+                by_device.insert(device_id.clone(), reading);
+            }
+            rpms.push(
+                by_device[&device_id]
+                    .as_ref()
+                    .and_then(|reading| reading.get(channel).copied()),
+            );
+        }
+        rpms
+    }
+
+    /// Writes the journal next to the status file, replacing the last dump.
+    pub fn dump_journal(&self, trigger: journal::DumpTrigger) {
+        let dump = journal::lock(&self.journal).dump(timing::unix_seconds(Instant::now()), trigger);
+        if let Err(e) = journal::write_dump(&self.journal_path, &dump) {
+            eprintln!("Could not dump the speed journal: {}", e);
+        }
+    }
+
+    /// Stops the daemon in the order of `shutdown::STEPS`, abandoning the
+    /// steps left once `timeout` has passed. The devices are released
+    /// either way.
+    pub async fn shutdown(&mut self, timeout: Duration) -> shutdown::Report {
+        let deadline = Instant::now() + timeout;
+        let mut report = shutdown::Report::default();
+        for step in shutdown::STEPS {
+            if time::timeout_at(deadline, self.shutdown_step(step))
+                .await
+                .is_err()
+            {
+                report.abandoned = Some(step);
+                break;
+            }
+            report.completed.push(step);
+        }
+        self.fan_controller.release();
+        report
+    }
+
+    async fn shutdown_step(&mut self, step: shutdown::Step) {
+        match step {
+            shutdown::Step::StopControls => self.token.cancel(),
+            shutdown::Step::FinishWrites => {
+                let flushed = self.fan_controller.flush().await;
+                let temperature = self
+                    .last_status
+                    .as_ref()
+                    .and_then(|status| status.temperature_celsius);
+                self.record_writes(&flushed, temperature);
+                for (command, result) in &flushed {
+                    if let Err(e) = result {
+                        eprintln!(
+                            "Could not finish writing {}% to device {} channel {}: {}",
+                            command.speed_percent, command.device_id, command.channel, e
+                        );
+                    }
+                }
+            }
+            shutdown::Step::ExitPolicy => {
+                if self.config.restore_on_exit {
+                    self.fan_controller.restore_previous_states().await;
+                }
+            }
+            shutdown::Step::FlushState => {
+                let memory = self.memory();
+                if let Some(status) = self.last_status.as_mut() {
+                    status.updated_at = status::now();
+                    status.memory = Some(memory);
+                    if let Err(e) = status::write_status(&self.status_path, status) {
+                        eprintln!("Could not write status file: {}", e);
+                    }
+                }
+                self.dump_journal(journal::DumpTrigger::Shutdown);
+            }
+        }
+    }
+
+    /// Runs one tick: reads the temperature, decides every channel's speed,
+    /// writes them and the status file. Breaks with an exit code when the
+    /// daemon has to stop.
+    pub async fn tick(&mut self) -> ControlFlow<i32> {
+        let scheduled = self.schedule.start_tick();
+        self.run_tick(scheduled).await
+    }
+
+    /// Moves the global override and applies it at once with an extra tick
+    /// off the schedule, rather than on the next one. Like every command of
+    /// the loop it runs between ticks, so its writes never land in the
+    /// middle of another tick's.
+    pub async fn nudge(&mut self, step: i8) -> ControlFlow<i32> {
+        self.override_percent = self.override_percent.saturating_add(step).clamp(-100, 100);
+        self.run_tick(Instant::now()).await
+    }
+
+    async fn run_tick(&mut self, scheduled: Instant) -> ControlFlow<i32> {
+        if journal::take_request(&self.journal_request_path) {
+            self.dump_journal(journal::DumpTrigger::Request);
+        }
+        if self.paused || self.token.is_cancelled() {
+            return ControlFlow::Continue(());
+        }
+
+        let managed_devices = self.fan_controller.get_managed_devices();
+        if self
+            .reconcile
+            .start_pass_if_due(Instant::now(), &managed_devices)
+        {
+            println!(
+                "Reconciling the full state of {} devices, one per tick",
+                managed_devices.len()
+            );
+        }
+        if let Some(device_id) = self.reconcile.next_device() {
+            self.fan_controller.reconcile(&device_id);
+        }
+
+        match disabled::read_labels(&self.disabled_path) {
+            Ok(now_disabled) if now_disabled != self.runtime_disabled => {
+                for label in now_disabled.difference(&self.runtime_disabled) {
+                    println!("Curve {} disabled", label);
+                }
+                for label in self.runtime_disabled.difference(&now_disabled) {
+                    println!("Curve {} enabled", label);
+                }
+                self.runtime_disabled = now_disabled;
+                self.engine.set_curves(
+                    disabled::active_curves(&self.all_fan_curves, &self.runtime_disabled),
+                    self.config.strict,
+                    self.config.fallback_speed_percent,
+                    self.config.never_stop_speed_percent,
+                );
+                if self.engine.curves().is_empty() {
+                    eprintln!("Warning: every curve is disabled; no channel is being driven");
+                }
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Could not read runtime-disabled curves: {}", e),
+        }
+        match disabled::read_labels(&self.debug_path) {
+            Ok(now_debug) if now_debug != self.runtime_debug => {
+                for label in now_debug.difference(&self.runtime_debug) {
+                    println!("Debugging curve {}", label);
+                }
+                for label in self.runtime_debug.difference(&now_debug) {
+                    println!("Stopped debugging curve {}", label);
+                }
+                self.runtime_debug = now_debug;
+                self.engine.set_debug_curves(self.runtime_debug.clone());
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Could not read runtime-debugged curves: {}", e),
+        }
+        // Only read the power state when it changes anything
+        let power_state = if self.config.power_profiles.is_empty() {
+            None
+        } else {
+            let (power_state, changed) = self.power_monitor.poll();
+            if changed {
+                match self.config.power_profiles.get(&power_state) {
+                    Some(profile) => println!(
+                        "On {} power: speeds {:+}%",
+                        power_state, profile.offset_percent
+                    ),
+                    None => println!("On {} power: speeds unchanged", power_state),
+                }
+            }
+            Some(power_state)
+        };
+        let power_override = power::effective_override(
+            self.override_percent,
+            power_state.and_then(|power_state| self.config.power_profiles.get(&power_state)),
+        );
+
+        let tick_started = Instant::now();
+        // Every source of the chain is read, so a fallback has a recent
+        // reading when it is needed
+        let readings = self
+            .source_readers
+            .snapshot(self.source_chain.sources())
+            .await;
+        let chosen = readings.first_plausible(self.source_chain.sources());
+        let reading = chosen.map(|(_, temperature)| temperature);
+        let sensor_time = tick_started.elapsed();
+        if let Some(line) = self.source_chain.observe(chosen.map(|(index, _)| index)) {
+            println!("{}", line);
+        }
+        let source_name = self.source_chain.active().name();
+        let source_id = names::source_id(self.source_chain.active());
+        let mut source_lost = false;
+        if let Some(transition) = self.source_health.observe(reading.is_some()) {
+            match transition.to {
+                sensors::SourceState::Available => println!(
+                    "Temperature source {} recovered after {} good reads",
+                    source_name, transition.reads
+                ),
+                sensors::SourceState::Degraded => eprintln!(
+                    "Temperature source {} degraded: {} failed reads in a row. Continuing with previous settings.",
+                    source_name, transition.reads
+                ),
+                sensors::SourceState::Lost => {
+                    eprintln!(
+                        "Temperature source {} lost after {} failed reads; running every channel at {}%",
+                        source_name, transition.reads, self.config.fallback_speed_percent
+                    );
+                    source_lost = true;
+                }
+            }
+            for fired in self.alerts.observe_source(source_name, transition) {
+                self.alerts.dispatch(&fired);
+            }
+        }
+        match reading {
+            Some(cpu_temp) => {
+                for fired in self.alerts.observe_temperature(&source_id, cpu_temp) {
+                    self.alerts.dispatch(&fired);
+                }
+                let sampled_at = readings
+                    .reading(self.source_chain.active())
+                    .map_or_else(Instant::now, |reading| reading.read_at);
+                self.temperature_history.push(
+                    sampled_at
+                        .saturating_duration_since(self.started)
+                        .as_secs_f64(),
+                    cpu_temp,
+                );
+                for fired in self
+                    .alerts
+                    .observe_rate(&source_id, &self.temperature_history)
+                {
+                    self.alerts.dispatch(&fired);
+                }
+                if self.alerts.full_speed() != self.full_speed {
+                    self.full_speed = self.alerts.full_speed();
+                    if self.full_speed {
+                        eprintln!("Running every channel at 100% while the alert holds");
+                        self.dump_journal(journal::DumpTrigger::FullSpeed);
+                    } else {
+                        eprintln!("Alert cleared; back to the curves");
+                    }
+                }
+                let units = self.config.display_units;
+                let mut debug_lines = vec![match self.temperature_history.rate() {
+                    Some(rate) => format!(
+                        "CPU temp: {} ({}/s)",
+                        units.temperature(cpu_temp),
+                        units.delta(rate)
+                    ),
+                    None => format!("CPU temp: {}", units.temperature(cpu_temp)),
+                }];
+                if self.source_chain.sources().len() > 1 {
+                    debug_lines.push(format!(
+                        "Temperature from {}",
+                        self.source_chain.active().key()
+                    ));
+                }
+                let extrapolation = history::extrapolate(
+                    cpu_temp,
+                    self.temperature_history.rate(),
+                    self.source_chain.active().reading_latency_seconds(),
+                    self.config.max_extrapolation_celsius,
+                );
+                if let Some(extrapolation) = &extrapolation {
+                    debug_lines.push(format!("Following {}", extrapolation.describe(units)));
+                }
+                let throttle_pressure = self
+                    .throttle_monitor
+                    .as_mut()
+                    .map(throttle::ThrottleMonitor::poll);
+                if let Some(pressure) = throttle_pressure {
+                    debug_lines.push(format!("Throttle pressure: {:.0}%", pressure));
+                }
+                let rpms = self.read_loop_rpms().await;
+                let channel_rpms: Vec<alerts::ChannelRpm> = self
+                    .engine
+                    .curves()
+                    .iter()
+                    .zip(self.engine.last_speeds())
+                    .zip(&rpms)
+                    .map(|((fan_curve, speed_percent), rpm)| alerts::ChannelRpm {
+                        device_id: &fan_curve.device_id,
+                        channel: fan_curve.channel,
+                        speed_percent: *speed_percent,
+                        rpm: *rpm,
+                    })
+                    .collect();
+                for fired in self
+                    .alerts
+                    .observe_rpms(self.started.elapsed().as_secs_f64(), &channel_rpms)
+                {
+                    self.alerts.dispatch(&fired);
+                }
+                self.engine.set_rpms(&rpms);
+                self.engine.set_history(&self.temperature_history);
+                let engine_started = Instant::now();
+                let decisions = self.engine.decide(engine::Inputs {
+                    temperature: extrapolation
+                        .map_or(cpu_temp, |extrapolation| extrapolation.celsius),
+                    rate: self.temperature_history.rate(),
+                    elapsed: self.started.elapsed(),
+                    // A full_speed alert overrides any curve up to 100%
+                    override_percent: if self.full_speed { 100 } else { power_override },
+                    throttle_pressure,
+                });
+                let engine_time = engine_started.elapsed();
+                debug_lines.push(format!(
+                    "Following {:?} curve points",
+                    self.engine.direction()
+                ));
+                let fan_curves = self.engine.curves();
+
+                let mut status = status::Status {
+                    updated_at: status::now(),
+                    temperature_celsius: Some(cpu_temp),
+                    channels: Vec::new(),
+                    disabled_curves: disabled::disabled_labels(
+                        &self.config,
+                        &self.runtime_disabled,
+                    ),
+                    tick: None,
+                    safe_mode: self.safe_mode.clone(),
+                    devices: self.device_metadata.clone(),
+                    override_percent: self.override_percent,
+                    source: Some(self.source_health.status(source_name)),
+                    power_state,
+                    memory: None,
+                    stretched_interval_seconds: self.stretched_interval,
+                    extrapolation,
+                    throttle_pressure_percent: throttle_pressure,
+                };
+                // Changes eased in with substeps, written after the rest
+                let mut slewed = Vec::new();
+                for (fan_curve, decision) in fan_curves.iter().zip(decisions) {
+                    let speed = decision.speed_percent;
+                    let monitor_only = fan_curve.manage == config::Manage::MonitorOnly;
+                    let id = self
+                        .channel_names
+                        .get(&fan_curve.device_id, fan_curve.channel);
+                    debug_lines.push(format!(
+                        "{} {} (device {} channel {}) to {}% ({:?})",
+                        if monitor_only { "Would set" } else { "Setting" },
+                        id,
+                        fan_curve.device_id,
+                        fan_curve.channel,
+                        speed,
+                        decision.reason
+                    ));
+                    for line in explain::debug_lines(&id, &decision.steps) {
+                        println!("{}", line);
+                    }
+
+                    if !monitor_only {
+                        let command = hardware::SpeedCommand {
+                            device_id: fan_curve.device_id.clone(),
+                            channel: fan_curve.channel,
+                            mode: fan_curve.mode.clone(),
+                            speed_percent: speed,
+                            reason: decision.reason,
+                        };
+                        let slew_from = self
+                            .fan_controller
+                            .written_speed(&fan_curve.device_id, fan_curve.channel)
+                            .filter(|&from| {
+                                from != speed
+                                    && fan_curve.mode == ChannelMode::Manual
+                                    && slew::is_slewed(decision.reason)
+                            });
+                        match (fan_curve.slew_substeps, slew_from) {
+                            (Some(substeps), Some(from)) => {
+                                slewed.push((command, from.get(), substeps))
+                            }
+                            _ => self.fan_controller.enqueue(command),
+                        }
+                        self.duty_history.record(
+                            &fan_curve.device_id,
+                            fan_curve.channel,
+                            self.started.elapsed().as_secs_f64(),
+                            speed.get(),
+                        );
+                    }
+                    status.channels.push(status::ChannelStatus {
+                        curve: fan_curve.label(),
+                        id,
+                        device_id: fan_curve.device_id.clone(),
+                        channel: fan_curve.channel,
+                        mode: fan_curve.mode.clone(),
+                        speed_percent: speed.get(),
+                        fan_count: self
+                            .fan_controller
+                            .fan_count(&fan_curve.device_id, fan_curve.channel),
+                        group: fan_curve.group.clone(),
+                        group_winner: decision.group_winner.map(|w| fan_curves[w].label()),
+                        reason: decision.reason,
+                        previous: self
+                            .fan_controller
+                            .previous_state(&fan_curve.device_id, fan_curve.channel)
+                            .cloned(),
+                        quarantined: self.fan_controller.is_quarantined(&fan_curve.device_id),
+                        manage: fan_curve.manage,
+                        never_stop: fan_curve.never_stop,
+                        duty: self.duty_history.stats(
+                            &fan_curve.device_id,
+                            fan_curve.channel,
+                            self.started.elapsed().as_secs_f64(),
+                        ),
+                        write_budget: None,
+                    });
+                }
+
+                let mut write_failed = false;
+                let write_started = Instant::now();
+                let mut flushed = self.fan_controller.flush().await;
+                if !slewed.is_empty() {
+                    let interval = Duration::from_secs(
+                        self.stretched_interval
+                            .unwrap_or(self.config.interval_seconds),
+                    );
+                    let plan = slew::plan(&slewed, slew::window(interval));
+                    let slew_started = Instant::now();
+                    for (offset, commands) in &plan.steps {
+                        time::sleep_until(slew_started + *offset).await;
+                        self.fan_controller.write_substeps(commands).await;
+                    }
+                    time::sleep_until(slew_started + plan.final_at).await;
+                    for (command, _, _) in slewed {
+                        self.fan_controller.enqueue(command);
+                    }
+                    flushed.extend(self.fan_controller.flush().await);
+                }
+                let write_time = write_started.elapsed();
+                self.record_writes(&flushed, Some(cpu_temp));
+                for (command, result) in flushed {
+                    if let Err(e) = &result {
+                        write_failed = true;
+                        let firmware = self
+                            .fan_controller
+                            .metadata(&command.device_id)
+                            .map(|metadata| format!(", firmware {}", metadata.firmware()))
+                            .unwrap_or_default();
+                        eprintln!(
+                            "Error applying fan speed {}% ({:?}) to device {}{}: {}",
+                            command.speed_percent, command.reason, command.device_id, firmware, e
+                        );
+                        if self.config.on_permission_error == config::PermissionPolicy::Exit
+                            && backend::HardwareError::is_permission_denied(e)
+                        {
+                            self.dump_journal(journal::DumpTrigger::PermissionDenied);
+                            self.fan_controller.release();
+                            return ControlFlow::Break(crate::EXIT_PERMISSION_DENIED);
+                        }
+                    }
+                    for fired in self
+                        .alerts
+                        .observe_device(&command.device_id, result.is_ok())
+                    {
+                        self.alerts.dispatch(&fired);
+                    }
+                }
+                for channel in &mut status.channels {
+                    channel.write_budget = self
+                        .fan_controller
+                        .write_budget_remaining(&channel.device_id, channel.channel);
+                }
+                let tick_stats = timing::TickStats {
+                    sensor_ms: timing::millis(sensor_time),
+                    engine_ms: timing::millis(engine_time),
+                    write_ms: timing::millis(write_time),
+                    device_write_ms: self
+                        .fan_controller
+                        .write_times()
+                        .iter()
+                        .map(|(device_id, time)| (device_id.clone(), timing::millis(*time)))
+                        .collect(),
+                    device_queue_ms: self
+                        .fan_controller
+                        .queue_delays()
+                        .iter()
+                        .map(|(device_id, time)| (device_id.clone(), timing::millis(*time)))
+                        .collect(),
+                    source_ms: readings
+                        .readings()
+                        .iter()
+                        .map(|reading| {
+                            (
+                                names::source_id_from_key(&reading.key),
+                                timing::millis(reading.read_time),
+                            )
+                        })
+                        .collect(),
+                    total_ms: timing::millis(tick_started.elapsed()),
+                    scheduled_at: timing::unix_seconds(scheduled),
+                    started_at: timing::unix_seconds(tick_started),
+                    overruns: self.schedule.overruns(),
+                };
+                let interval_seconds = self
+                    .stretched_interval
+                    .unwrap_or(self.config.interval_seconds);
+                if tick_stats.over_budget(Duration::from_secs(interval_seconds)) {
+                    eprintln!(
+                        "Warning: tick overran the {}s interval, skipping to the next one. {}",
+                        interval_seconds,
+                        tick_stats.summary()
+                    );
+                }
+                if let Some(feasible) = self.interval_check.observe(tick_started.elapsed()) {
+                    let stretch = self.config.auto_stretch_interval;
+                    eprintln!(
+                        "{}",
+                        timing::interval_warning(self.config.interval_seconds, feasible, stretch)
+                    );
+                    if stretch {
+                        self.stretched_interval = Some(feasible);
+                        self.schedule.set_period(Duration::from_secs(feasible));
+                        status.stretched_interval_seconds = Some(feasible);
+                    }
+                }
+                if self.debug {
+                    let speeds: Vec<u8> = status.channels.iter().map(|c| c.speed_percent).collect();
+                    match self
+                        .tick_summarizer
+                        .observe(cpu_temp, &speeds, write_failed)
+                    {
+                        ticklog::TickLog::Full => {
+                            for line in &debug_lines {
+                                println!("{}", line);
+                            }
+                            println!("Write queue: {:?}", self.fan_controller.queue_stats());
+                            println!("Speed changes by reason: {:?}", self.engine.change_counts());
+                            println!("{}", tick_stats.summary());
+                        }
+                        ticklog::TickLog::Summary(line) => println!("{}", line),
+                        ticklog::TickLog::Quiet => {}
+                    }
+                }
+
+                status.tick = Some(tick_stats);
+                status.memory = Some(self.memory());
+                if let Err(e) = status::write_status(&self.status_path, &status) {
+                    if !self.status_error_logged {
+                        eprintln!("Could not write status file: {}", e);
+                        self.status_error_logged = true;
+                    }
+                }
+                self.last_status = Some(status);
+            }
+            None => {
+                if self.source_health.state() == sensors::SourceState::Lost {
+                    let flushed = drive_failsafe(
+                        &mut self.fan_controller,
+                        self.engine.curves(),
+                        self.config.fallback_speed_percent,
+                        self.config.never_stop_speed_percent,
+                    )
+                    .await;
+                    self.record_writes(&flushed, None);
+                    if source_lost {
+                        self.dump_journal(journal::DumpTrigger::SourceLost);
+                    }
+                } else if self.debug {
+                    eprintln!("Could not read the temperature. Continuing with previous settings.");
+                }
+                let memory = self.memory();
+                if let Some(status) = self.last_status.as_mut() {
+                    status.updated_at = status::now();
+                    status.memory = Some(memory);
+                    status.temperature_celsius = None;
+                    status.source = Some(self.source_health.status(source_name));
+                    if self.source_health.state() == sensors::SourceState::Lost {
+                        for channel in &mut status.channels {
+                            if channel.manage != config::Manage::MonitorOnly {
+                                channel.reason = engine::SpeedReason::SourceLost;
+                                channel.speed_percent = if channel.never_stop {
+                                    self.config
+                                        .fallback_speed_percent
+                                        .max(self.config.never_stop_speed_percent)
+                                } else {
+                                    self.config.fallback_speed_percent
+                                }
+                                .get();
+                            }
+                        }
+                    }
+                    if let Err(e) = status::write_status(&self.status_path, status) {
+                        if !self.status_error_logged {
+                            eprintln!("Could not write status file: {}", e);
+                            self.status_error_logged = true;
+                        }
+                    }
+                }
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Follows the CPUs' throttle counters when any of `fan_curves` has
+/// `throttle_points`. Without counters those curves just follow their
+/// points, which is logged.
+fn throttle_monitor(fan_curves: &[FanCurve]) -> Option<throttle::ThrottleMonitor> {
+    if fan_curves
+        .iter()
+        .all(|fan_curve| fan_curve.throttle_points.is_empty())
+    {
+        return None;
+    }
+    let monitor = throttle::ThrottleMonitor::discover(Path::new(throttle::CPU_ROOT));
+    if monitor.is_none() {
+        eprintln!(
+            "No thermal throttle counters in {}; throttle_points are ignored",
+            throttle::CPU_ROOT
+        );
+    }
+    monitor
+}
+
+/// Switches the channels whose mode a reload changed at once rather than on
+/// the next tick. A channel switched to Manual gets its curve's speed at the
+/// last temperature, or the fallback speed before the first reading.
+async fn apply_mode_changes(
+    fan_controller: &mut hardware::FanController,
+    fan_curves: &[config::FanCurve],
+    temperature: Option<f64>,
+    fallback_speed: config::SpeedPercent,
+    never_stop_speed: config::SpeedPercent,
+) -> Vec<(hardware::SpeedCommand, anyhow::Result<()>)> {
+    let channels: Vec<_> = fan_curves
+        .iter()
+        .filter(|fan_curve| fan_curve.manage == config::Manage::Full)
+        .map(|fan_curve| {
+            (
+                fan_curve.device_id.clone(),
+                fan_curve.channel,
+                fan_curve.mode.clone(),
+            )
+        })
+        .collect();
+    let changes = fan_controller.mode_changes(&channels);
+    if changes.is_empty() {
+        return Vec::new();
+    }
+
+    for (device_id, channel, mode) in changes {
+        let Some(fan_curve) = fan_curves
+            .iter()
+            .find(|c| c.device_id == device_id && c.channel == channel)
+        else {
+            continue;
+        };
+        println!(
+            "Switching device {} channel {} to {:?}",
+            device_id, channel, mode
+        );
+        let speed = match temperature {
+            Some(temperature) => curve::quantize(curve::adjust_duty(
+                fan_curve,
+                curve::calculate_fan_duty(fan_curve, temperature, fallback_speed),
+            )),
+            None => fallback_speed.get(),
+        };
+        let speed_percent = config::SpeedPercent::saturating(curve::never_stop_floor(
+            fan_curve,
+            speed,
+            never_stop_speed,
+        ));
+        fan_controller.enqueue(hardware::SpeedCommand {
+            device_id,
+            channel,
+            mode,
+            speed_percent,
+            reason: engine::SpeedReason::Curve,
+        });
+    }
+    let flushed = fan_controller.flush().await;
+    for (command, result) in &flushed {
+        if let Err(e) = result {
+            eprintln!(
+                "Could not switch device {} channel {} to {:?}: {}",
+                command.device_id, command.channel, command.mode, e
+            );
+        }
+    }
+    flushed
+}
+
+/// Runs every driven channel at the fallback speed while the temperature
+/// source is lost, never below a `never_stop` curve's floor.
+async fn drive_failsafe(
+    fan_controller: &mut hardware::FanController,
+    fan_curves: &[config::FanCurve],
+    fallback_speed: config::SpeedPercent,
+    never_stop_speed: config::SpeedPercent,
+) -> Vec<(hardware::SpeedCommand, anyhow::Result<()>)> {
+    for fan_curve in fan_curves {
+        if fan_curve.manage == config::Manage::MonitorOnly {
+            continue;
+        }
+        fan_controller.enqueue(hardware::SpeedCommand {
+            device_id: fan_curve.device_id.clone(),
+            channel: fan_curve.channel,
+            mode: fan_curve.mode.clone(),
+            speed_percent: config::SpeedPercent::saturating(curve::never_stop_floor(
+                fan_curve,
+                fallback_speed.get(),
+                never_stop_speed,
+            )),
+            reason: engine::SpeedReason::SourceLost,
+        });
+    }
+    let flushed = fan_controller.flush().await;
+    for (command, result) in &flushed {
+        if let Err(e) = result {
+            eprintln!(
+                "Error applying the fallback speed {}% to device {}: {}",
+                command.speed_percent, command.device_id, e
+            );
+        }
+    }
+    flushed
+}
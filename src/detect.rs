@@ -0,0 +1,272 @@
+use anyhow::Result;
+use std::fmt::Write;
+use std::time::Duration;
+use tokio::time;
+
+use crate::config::{DeviceId, FanCurve, ZeroRpmCheckSettings};
+use crate::hardware::{self, FanController};
+use crate::zero_rpm::{self, CheckResult};
+
+/// Longest probing one device's channels may take. A device that takes
+/// longer gets a curve on every channel.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Which channels of a device have fans, as `uni-sync-curve init` found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceChannels {
+    pub device_id: DeviceId,
+    pub channels: usize,
+    /// The channels with fans; None when that couldn't be found out.
+    pub populated: Option<Vec<usize>>,
+    /// How they were found, or why they weren't.
+    pub how: String,
+}
+
+impl DeviceChannels {
+    /// The channels to generate curves for: those with fans, or every
+    /// channel when that isn't known.
+    pub fn curve_channels(&self) -> Vec<usize> {
+        self.populated
+            .clone()
+            .unwrap_or_else(|| (0..self.channels).collect())
+    }
+}
+
+/// Finds the channels with fans on every hub. A hub that reports its fan
+/// counts is asked; one that only reports RPMs has each channel run at the
+/// zero-RPM check's test speed, as at startup, and is put back the way it
+/// was afterwards. Hubs that can report neither, or whose state can't be
+/// put back, aren't probed.
+pub async fn detect_channels(
+    fan_controller: &mut FanController,
+    settings: &ZeroRpmCheckSettings,
+) -> Vec<DeviceChannels> {
+    let mut device_ids: Vec<DeviceId> = fan_controller
+        .get_available_devices()
+        .into_iter()
+        .filter(|device_id| !device_id.is_sysfs_pwm())
+        .collect();
+    device_ids.sort();
+    fan_controller.read_previous_states().await;
+
+    let mut detected = Vec::new();
+    let mut probed = false;
+    for device_id in device_ids {
+        let channels = hardware::device_model(&device_id).channels;
+        let (populated, how) = match fan_controller.query_fan_counts(&device_id).await {
+            Ok(counts) => (
+                Some(
+                    (0..channels)
+                        .filter(|&channel| counts.get(channel).is_some_and(|&count| count > 0))
+                        .collect(),
+                ),
+                format!("fan counts {:?}", counts),
+            ),
+            Err(e) if fan_controller.restorable_state(&device_id, 0).is_none() => (
+                None,
+                format!("not probed, its state can't be restored: {}", e),
+            ),
+            Err(_) => {
+                probed = true;
+                let probe = probe_channels(fan_controller, &device_id, channels, settings);
+                match time::timeout(PROBE_TIMEOUT, probe).await {
+                    Ok(Ok(Some(rpms))) => (
+                        Some(
+                            (0..channels)
+                                .filter(|&channel| rpms[channel] >= settings.min_fan_rpm)
+                                .collect(),
+                        ),
+                        format!(
+                            "probed at {}%, RPMs {:?}",
+                            settings.test_speed_percent, rpms
+                        ),
+                    ),
+                    Ok(Ok(None)) => (None, "the hub can't report RPMs".to_string()),
+                    Ok(Err(e)) => (None, format!("probing failed: {:#}", e)),
+                    Err(_) => (
+                        None,
+                        format!("probing took over {}s", PROBE_TIMEOUT.as_secs()),
+                    ),
+                }
+            }
+        };
+        detected.push(DeviceChannels {
+            device_id,
+            channels,
+            populated,
+            how,
+        });
+    }
+    if probed {
+        fan_controller.restore_previous_states().await;
+    }
+    detected
+}
+
+/// Each channel's RPM at the test speed, or None if the hub can't report
+/// RPMs.
+async fn probe_channels(
+    fan_controller: &mut FanController,
+    device_id: &DeviceId,
+    channels: usize,
+    settings: &ZeroRpmCheckSettings,
+) -> Result<Option<Vec<u16>>> {
+    let mut rpms = Vec::new();
+    for channel in 0..channels {
+        match zero_rpm::run_check(fan_controller, device_id, channel, settings).await? {
+            CheckResult::Passed { rpm } | CheckResult::Failed { rpm, .. } => rpms.push(rpm),
+            CheckResult::Unsupported => return Ok(None),
+        }
+    }
+    Ok(Some(rpms))
+}
+
+/// One line per device, for `init` and `auto-detect-channels`.
+pub fn format_detection(detected: &[DeviceChannels]) -> String {
+    let mut text = String::new();
+    for device in detected {
+        let found = match &device.populated {
+            Some(channels) if channels.is_empty() => "no fans found".to_string(),
+            Some(channels) => format!("fans on channels {:?}", channels),
+            None => "fans unknown, every channel gets a curve".to_string(),
+        };
+        let _ = writeln!(
+            text,
+            "{} {}: {} ({})",
+            device.device_id,
+            hardware::device_model(&device.device_id).name,
+            found,
+            device.how
+        );
+    }
+    text
+}
+
+/// For the generated config's `"_note"`, when some devices got a curve on
+/// every channel for want of knowing better.
+pub fn note(detected: &[DeviceChannels]) -> Option<String> {
+    let unknown: Vec<String> = detected
+        .iter()
+        .filter(|device| device.populated.is_none())
+        .map(|device| device.device_id.to_string())
+        .collect();
+    (!unknown.is_empty()).then(|| {
+        format!(
+            "Which channels have fans is unknown for {}, so each of their channels has a curve; remove those for empty channels.",
+            unknown.join(", ")
+        )
+    })
+}
+
+/// Where `fan_curves` and what was detected disagree, for
+/// `auto-detect-channels` on an existing config.
+pub fn mismatches(detected: &[DeviceChannels], fan_curves: &[FanCurve]) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    for device in detected {
+        let Some(populated) = &device.populated else {
+            continue;
+        };
+        let curves: Vec<&FanCurve> = fan_curves
+            .iter()
+            .filter(|fan_curve| fan_curve.device_id == device.device_id)
+            .collect();
+        for fan_curve in &curves {
+            if !fan_curve.broadcast && !populated.contains(&fan_curve.channel) {
+                mismatches.push(format!(
+                    "Curve {} drives device {} channel {}, which has no fans",
+                    fan_curve.label(),
+                    device.device_id,
+                    fan_curve.channel
+                ));
+            }
+        }
+        for &channel in populated {
+            let driven = curves
+                .iter()
+                .any(|fan_curve| fan_curve.broadcast || fan_curve.channel == channel);
+            if !driven {
+                mismatches.push(format!(
+                    "No curve drives device {} channel {}, which has fans",
+                    device.device_id, channel
+                ));
+            }
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockBackend;
+
+    fn settings() -> ZeroRpmCheckSettings {
+        ZeroRpmCheckSettings {
+            settle_seconds: 0,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_detect_channels() {
+        let slv2 = DeviceId(0x0cf2, 0xa103, "V2".to_string());
+        let sl = DeviceId(0x0cf2, 0xa100, "V1".to_string());
+        let backend = MockBackend::new(vec![slv2.clone(), sl.clone()])
+            .with_reply(vec![224, 80, 0, 0], vec![224, 80, 3, 0, 2, 0]);
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_hub_reports(true);
+
+        let detected = detect_channels(&mut controller, &settings()).await;
+        assert_eq!(detected[0].device_id, sl);
+        assert_eq!(detected[0].populated, None);
+        assert_eq!(detected[0].curve_channels(), vec![0, 1, 2, 3]);
+        assert_eq!(detected[1].populated, Some(vec![0, 2]));
+        assert_eq!(note(&detected).unwrap().matches("0cf2").count(), 1);
+
+        let fan_curves = vec![
+            FanCurve {
+                device_id: slv2.clone(),
+                channel: 0,
+                ..Default::default()
+            },
+            FanCurve {
+                device_id: slv2.clone(),
+                channel: 3,
+                ..Default::default()
+            },
+        ];
+        let mismatches = mismatches(&detected, &fan_curves);
+        assert_eq!(mismatches.len(), 2);
+        assert!(mismatches[0].contains("channel 3, which has no fans"));
+        assert!(mismatches[1].contains("channel 2, which has fans"));
+    }
+
+    #[tokio::test]
+    async fn test_no_probe_without_restorable_state() {
+        // A hub that reports RPMs and its state but not its fan counts
+        let slv2 = DeviceId(0x0cf2, 0xa103, "V2".to_string());
+        let backend = MockBackend::new(vec![slv2.clone()])
+            .with_reply(
+                vec![224, 81, 0, 0],
+                vec![224, 81, 0, 0, 1, 0, 40, 50, 0, 60],
+            )
+            .with_reply(
+                vec![224, 82, 0, 0],
+                vec![224, 82, 0x04, 0x4c, 0, 20, 0x02, 0x58, 0, 0],
+            );
+        let packets = backend.packets();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_hub_reports(true);
+
+        // The state it reports isn't confirmed, so it couldn't be put back
+        // after probing, and no channel is run at the test speed
+        let detected = detect_channels(&mut controller, &settings()).await;
+        assert_eq!(detected[0].populated, None);
+        assert!(detected[0].how.contains("can't be restored"));
+        assert!(packets
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|(_, packet)| !(32..36).contains(&packet[1])));
+    }
+}
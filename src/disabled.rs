@@ -0,0 +1,129 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::config::{CurveConfig, FanCurve};
+
+/// Curves disabled with `uni-sync-curve curve disable`, kept next to the
+/// status file so they last until the next boot but not beyond.
+pub const DISABLED_FILE_NAME: &str = "disabled-curves.json";
+
+pub fn disabled_path(status_path: &Path) -> PathBuf {
+    status_path.with_file_name(DISABLED_FILE_NAME)
+}
+
+/// Curves whose decisions are logged with `uni-sync-curve curve debug`,
+/// kept like the disabled ones.
+pub const DEBUG_FILE_NAME: &str = "debug-curves.json";
+
+pub fn debug_path(status_path: &Path) -> PathBuf {
+    status_path.with_file_name(DEBUG_FILE_NAME)
+}
+
+/// Curve labels kept at runtime, such as the disabled curves. No file
+/// means none.
+pub fn read_labels(path: &Path) -> Result<BTreeSet<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(e) => Err(e).with_context(|| format!("Could not read {:?}", path)),
+    }
+}
+
+pub fn write_labels(path: &Path, labels: &BTreeSet<String>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, serde_json::to_string_pretty(labels)?)?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// The curve labelled `name`, or an error listing the labels there are.
+pub fn find_curve<'a>(config: &'a mut CurveConfig, name: &str) -> Result<&'a mut FanCurve> {
+    let labels: Vec<String> = config.fan_curves.iter().map(FanCurve::label).collect();
+    match labels.iter().position(|label| label == name) {
+        Some(index) => Ok(&mut config.fan_curves[index]),
+        None => bail!(
+            "No curve named {:?}; curves are: {}",
+            name,
+            labels.join(", ")
+        ),
+    }
+}
+
+pub fn is_active(fan_curve: &FanCurve, disabled: &BTreeSet<String>) -> bool {
+    fan_curve.enabled && !disabled.contains(&fan_curve.label())
+}
+
+/// The curves to drive, leaving out those disabled at runtime.
+pub fn active_curves(fan_curves: &[FanCurve], disabled: &BTreeSet<String>) -> Vec<FanCurve> {
+    fan_curves
+        .iter()
+        .filter(|fan_curve| is_active(fan_curve, disabled))
+        .cloned()
+        .collect()
+}
+
+/// Labels of the configured curves that are disabled either way.
+pub fn disabled_labels(config: &CurveConfig, disabled: &BTreeSet<String>) -> Vec<String> {
+    config
+        .fan_curves
+        .iter()
+        .filter(|fan_curve| !is_active(fan_curve, disabled))
+        .map(FanCurve::label)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_curves() {
+        let mut config: CurveConfig = serde_json::from_str(
+            r#"{
+                "interval_seconds": 5,
+                "fan_curves": [
+                    {"name": "front", "device_id": [3314, 41219, "ABC"], "channel": 0,
+                     "mode": "Manual", "curve_points": []},
+                    {"name": "rear", "device_id": [3314, 41219, "ABC"], "channel": 1,
+                     "mode": "Manual", "curve_points": [], "enabled": false},
+                    {"device_id": [3314, 41219, "ABC"], "channel": 2,
+                     "mode": "Manual", "curve_points": []}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert!(config.fan_curves[0].enabled);
+
+        let runtime = BTreeSet::from(["front".to_string()]);
+        assert_eq!(disabled_labels(&config, &runtime), vec!["front", "rear"]);
+
+        find_curve(&mut config, "rear").unwrap().enabled = true;
+        assert_eq!(
+            disabled_labels(&config, &BTreeSet::new()),
+            Vec::<String>::new()
+        );
+        let error = find_curve(&mut config, "top").unwrap_err().to_string();
+        assert!(
+            error.contains("front, rear, (0cf2, a103, ABC)/2"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_disabled_file_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("uni-sync-curve-disabled-{}", std::process::id()))
+            .join(DISABLED_FILE_NAME);
+        assert!(read_labels(&path).unwrap().is_empty());
+
+        let disabled = BTreeSet::from(["front".to_string()]);
+        write_labels(&path, &disabled).unwrap();
+        assert_eq!(read_labels(&path).unwrap(), disabled);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
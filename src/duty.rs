@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::config::DeviceId;
+use crate::ring::{BufferUsage, RingBuffer};
+
+/// How far back duty statistics look, in seconds.
+pub const DUTY_WINDOW_SECONDS: f64 = 24.0 * 60.0 * 60.0;
+/// Most speed changes kept per channel. A channel that changes speed more
+/// often than this in a window gets statistics over a shorter span.
+const MAX_TRANSITIONS: usize = 4096;
+
+/// What a channel's fans did over the last `window_seconds`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct DutyStats {
+    /// The span the statistics cover; shorter than the window after a restart.
+    pub window_seconds: f64,
+    /// Share of that time spent at 0%, in percent.
+    pub stopped_percent: f64,
+    /// Time-weighted average speed.
+    pub average_percent: f64,
+    pub max_percent: u8,
+}
+
+/// Each channel's speed changes, as (seconds since startup, new speed).
+/// Lives as long as the daemon, so reloading the config keeps it.
+#[derive(Debug)]
+pub struct DutyHistory {
+    channels: HashMap<(DeviceId, usize), RingBuffer<(f64, u8)>>,
+    capacity: usize,
+}
+
+impl DutyHistory {
+    /// A channel changes speed at most once a tick, so it needs room for a
+    /// window's worth of ticks, up to MAX_TRANSITIONS.
+    pub fn new(interval_seconds: u64) -> Self {
+        Self {
+            channels: HashMap::new(),
+            capacity: transitions_per_window(interval_seconds),
+        }
+    }
+
+    /// Resizes every channel's history for a new tick interval.
+    pub fn set_interval(&mut self, interval_seconds: u64) {
+        self.capacity = transitions_per_window(interval_seconds);
+        for transitions in self.channels.values_mut() {
+            transitions.set_capacity(self.capacity);
+        }
+    }
+
+    /// Records the speed a channel runs at `now`; only changes are stored.
+    pub fn record(&mut self, device_id: &DeviceId, channel: usize, now: f64, speed: u8) {
+        let transitions = self
+            .channels
+            .entry((device_id.clone(), channel))
+            .or_insert_with(|| RingBuffer::new(self.capacity));
+        if transitions.back().is_some_and(|&(_, last)| last == speed) {
+            return;
+        }
+        transitions.push((now, speed));
+        // Keep the last change before the window: it is the speed the window starts at
+        while transitions
+            .get(1)
+            .is_some_and(|&(at, _)| at <= now - DUTY_WINDOW_SECONDS)
+        {
+            transitions.pop_front();
+        }
+    }
+
+    /// Every channel's history, added up.
+    pub fn usage(&self) -> BufferUsage {
+        BufferUsage::sum(self.channels.values().map(RingBuffer::usage))
+    }
+
+    /// Statistics for a channel up to `now`, or None before its first record.
+    pub fn stats(&self, device_id: &DeviceId, channel: usize, now: f64) -> Option<DutyStats> {
+        let transitions = self.channels.get(&(device_id.clone(), channel))?;
+        let start = transitions.front()?.0.max(now - DUTY_WINDOW_SECONDS);
+
+        let mut stopped = 0.0;
+        let mut weighted = 0.0;
+        let mut max_percent = 0;
+        for (index, &(at, speed)) in transitions.iter().enumerate() {
+            let until = transitions.get(index + 1).map_or(now, |&(next, _)| next);
+            let duration = until.min(now) - at.max(start);
+            if duration <= 0.0 {
+                continue;
+            }
+            if speed == 0 {
+                stopped += duration;
+            }
+            weighted += duration * speed as f64;
+            max_percent = max_percent.max(speed);
+        }
+
+        let window_seconds = now - start;
+        if window_seconds <= 0.0 {
+            let speed = transitions.back()?.1;
+            return Some(DutyStats {
+                window_seconds: 0.0,
+                stopped_percent: if speed == 0 { 100.0 } else { 0.0 },
+                average_percent: speed as f64,
+                max_percent: speed,
+            });
+        }
+        Some(DutyStats {
+            window_seconds,
+            stopped_percent: stopped / window_seconds * 100.0,
+            average_percent: weighted / window_seconds,
+            max_percent,
+        })
+    }
+}
+
+fn transitions_per_window(interval_seconds: u64) -> usize {
+    let ticks = (DUTY_WINDOW_SECONDS / interval_seconds.max(1) as f64).ceil() as usize;
+    MAX_TRANSITIONS.min(ticks + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duty_stats() {
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let mut history = DutyHistory::new(1);
+        assert_eq!(history.stats(&hub, 0, 0.0), None);
+
+        // 0% for 60s, 50% for 30s, 100% for 10s
+        history.record(&hub, 0, 0.0, 0);
+        history.record(&hub, 0, 30.0, 0);
+        history.record(&hub, 0, 60.0, 50);
+        history.record(&hub, 0, 90.0, 100);
+        let stats = history.stats(&hub, 0, 100.0).unwrap();
+        assert_eq!(stats.window_seconds, 100.0);
+        assert_eq!(stats.stopped_percent, 60.0);
+        assert_eq!(stats.average_percent, 25.0);
+        assert_eq!(stats.max_percent, 100);
+
+        // A day later only the last day counts, starting at the speed then
+        let day = DUTY_WINDOW_SECONDS;
+        history.record(&hub, 0, day + 95.0, 20);
+        let stats = history.stats(&hub, 0, day + 100.0).unwrap();
+        assert_eq!(stats.window_seconds, day);
+        assert_eq!(stats.stopped_percent, 0.0);
+        assert_eq!(stats.max_percent, 100);
+        assert!((stats.average_percent - (100.0 * (day - 5.0) + 20.0 * 5.0) / day).abs() < 1e-9);
+
+        // Unchanged speeds aren't stored
+        let mut steady = DutyHistory::new(1);
+        for second in 0..10_000 {
+            steady.record(&hub, 1, second as f64, 40);
+        }
+        assert_eq!(steady.channels[&(hub.clone(), 1)].len(), 1);
+        let stats = steady.stats(&hub, 1, 10_000.0).unwrap();
+        assert_eq!(stats.average_percent, 40.0);
+    }
+
+    #[test]
+    fn test_transitions_are_bounded() {
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let mut history = DutyHistory::new(1);
+        for second in 0..3 * MAX_TRANSITIONS {
+            history.record(&hub, 0, second as f64, (second % 2) as u8);
+        }
+        assert_eq!(history.channels[&(hub.clone(), 0)].len(), MAX_TRANSITIONS);
+        let stats = history
+            .stats(&hub, 0, (3 * MAX_TRANSITIONS) as f64)
+            .unwrap();
+        assert_eq!(stats.window_seconds, MAX_TRANSITIONS as f64);
+        assert_eq!(stats.stopped_percent, 50.0);
+
+        // Slower ticks need less room, even for history kept from before
+        history.set_interval(60);
+        assert_eq!(history.usage().capacity, 1441);
+        assert_eq!(history.usage().len, 1441);
+        history.record(&hub, 1, 0.0, 30);
+        assert_eq!(history.usage().capacity, 2 * 1441);
+    }
+}
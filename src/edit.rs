@@ -0,0 +1,397 @@
+use anyhow::{bail, Context, Result};
+
+use crate::config::{CurvePoint, FanCurve, SpeedPercent};
+use crate::curve;
+use crate::units::{self, TemperatureUnit};
+
+/// A temperature in °C as the curve's points are written in the file:
+/// curves tagged Fahrenheit keep °F there. Rounded to a millionth of a
+/// degree so 33.3°C is written as 91.94.
+fn file_temperature(fan_curve: &FanCurve, celsius: f64) -> f64 {
+    match fan_curve.temperature_unit {
+        Some(TemperatureUnit::Fahrenheit) => {
+            (units::fahrenheit_from_celsius(celsius) * 1e6).round() / 1e6
+        }
+        _ => celsius,
+    }
+}
+
+/// Sets the speed at `temperature` °C, replacing the point there if there
+/// is one. Points stay sorted by temperature.
+pub fn set_point(fan_curve: &mut FanCurve, temperature: f64, speed: SpeedPercent) {
+    let temperature = file_temperature(fan_curve, temperature);
+    let points = &mut fan_curve.curve_points;
+    match points
+        .iter_mut()
+        .find(|point| point.temperature_celsius == temperature)
+    {
+        Some(point) => point.fan_speed_percent = speed,
+        None => points.push(CurvePoint {
+            temperature_celsius: temperature,
+            fan_speed_percent: speed,
+        }),
+    }
+    sort_points(points);
+}
+
+/// Removes the point at `temperature` °C.
+pub fn remove_point(fan_curve: &mut FanCurve, temperature: f64) -> Result<()> {
+    let written = file_temperature(fan_curve, temperature);
+    let points = &mut fan_curve.curve_points;
+    let Some(index) = points
+        .iter()
+        .position(|point| point.temperature_celsius == written)
+    else {
+        bail!(
+            "Curve {} has no point at {}°C",
+            fan_curve.label(),
+            temperature
+        );
+    };
+    points.remove(index);
+    Ok(())
+}
+
+/// Replaces the curve's points with `points`, given in °C.
+pub fn replace_points(fan_curve: &mut FanCurve, points: Vec<CurvePoint>) {
+    fan_curve.curve_points = points
+        .into_iter()
+        .map(|point| CurvePoint {
+            temperature_celsius: file_temperature(fan_curve, point.temperature_celsius),
+            ..point
+        })
+        .collect();
+}
+
+/// Parses points as "temperature:speed" pairs separated by commas, e.g.
+/// "30:20,50:40,70:80", sorted by temperature.
+pub fn parse_points(s: &str) -> Result<Vec<CurvePoint>> {
+    let mut points = Vec::new();
+    for pair in s.split(',') {
+        let Some((temperature, speed)) = pair.split_once(':') else {
+            bail!("Expected temperature:speed, got {:?}", pair);
+        };
+        let temperature: f64 = temperature
+            .trim()
+            .parse()
+            .with_context(|| format!("Bad temperature in {:?}", pair))?;
+        if !temperature.is_finite() {
+            bail!("Bad temperature in {:?}", pair);
+        }
+        let speed: u8 = speed
+            .trim()
+            .parse()
+            .with_context(|| format!("Bad speed in {:?}", pair))?;
+        if points
+            .iter()
+            .any(|point: &CurvePoint| point.temperature_celsius == temperature)
+        {
+            bail!("Two points at {}°C", temperature);
+        }
+        points.push(CurvePoint {
+            temperature_celsius: temperature,
+            fan_speed_percent: SpeedPercent::new(speed)?,
+        });
+    }
+    sort_points(&mut points);
+    Ok(points)
+}
+
+/// Parses a CSV of temperature,percent rows, as a spreadsheet exports
+/// them. A header row, blank lines and lines starting with # are skipped.
+/// Percents may have fractions and are rounded. The rows must make a curve
+/// whose speed never drops as the temperature rises.
+pub fn parse_csv(text: &str) -> Result<Vec<CurvePoint>> {
+    let mut points: Vec<CurvePoint> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let row = index + 1;
+        let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [temperature, speed] = columns[..] else {
+            bail!("Line {}: expected temperature,percent, got {:?}", row, line);
+        };
+        let Ok(temperature) = temperature.parse::<f64>() else {
+            if points.is_empty() && speed.parse::<f64>().is_err() {
+                continue; // header
+            }
+            bail!("Line {}: bad temperature {:?}", row, temperature);
+        };
+        if !temperature.is_finite() {
+            bail!("Line {}: bad temperature {:?}", row, temperature);
+        }
+        let speed: f64 = speed
+            .parse()
+            .with_context(|| format!("Line {}: bad percent {:?}", row, speed))?;
+        if !(0.0..=100.0).contains(&speed) {
+            bail!("Line {}: percent {} is outside 0-100", row, speed);
+        }
+        points.push(CurvePoint {
+            temperature_celsius: temperature,
+            fan_speed_percent: SpeedPercent::saturating(speed.round() as u8),
+        });
+    }
+
+    sort_points(&mut points);
+    if points.is_empty() {
+        bail!("No temperature,percent rows");
+    }
+    for pair in points.windows(2) {
+        let (cooler, hotter) = (&pair[0], &pair[1]);
+        if cooler.temperature_celsius == hotter.temperature_celsius {
+            bail!("Two rows at {}°C", cooler.temperature_celsius);
+        }
+        if cooler.fan_speed_percent > hotter.fan_speed_percent {
+            bail!(
+                "Not monotone: {}% at {}°C drops to {}% at {}°C",
+                cooler.fan_speed_percent,
+                cooler.temperature_celsius,
+                hotter.fan_speed_percent,
+                hotter.temperature_celsius
+            );
+        }
+    }
+    Ok(points)
+}
+
+/// Picks at most `max_points` of the sorted `points` with
+/// largest-triangle-three-buckets: the first and last points are kept, the
+/// ones between are split into buckets, and from each bucket the point
+/// that makes the largest triangle with the point kept before it and the
+/// average of the next bucket is kept. That keeps the corners of a curve
+/// and drops points on its straight stretches.
+pub fn downsample(points: &[CurvePoint], max_points: usize) -> Vec<CurvePoint> {
+    let count = points.len();
+    if count <= max_points || count < 3 {
+        return points.to_vec();
+    }
+    if max_points < 3 {
+        return vec![points[0].clone(), points[count - 1].clone()];
+    }
+
+    let xy = |point: &CurvePoint| {
+        (
+            point.temperature_celsius,
+            point.fan_speed_percent.get() as f64,
+        )
+    };
+    let buckets = max_points - 2;
+    let bucket_size = (count - 2) as f64 / buckets as f64;
+    let bucket_start = |bucket: usize| ((bucket as f64 * bucket_size) as usize + 1).min(count - 1);
+
+    let mut kept = vec![points[0].clone()];
+    let mut previous = 0;
+    for bucket in 0..buckets {
+        let (start, end) = (bucket_start(bucket), bucket_start(bucket + 1));
+        // The last bucket looks ahead to the last point alone
+        let next = &points[end..bucket_start(bucket + 2).max(end + 1).min(count)];
+        let (next_x, next_y) = next.iter().map(xy).fold((0.0, 0.0), |(x, y), (px, py)| {
+            (x + px / next.len() as f64, y + py / next.len() as f64)
+        });
+        let (ax, ay) = xy(&points[previous]);
+        let area = |point: &CurvePoint| {
+            let (x, y) = xy(point);
+            ((ax - next_x) * (y - ay) - (ax - x) * (next_y - ay)).abs()
+        };
+        let best = (start..end)
+            .max_by(|&a, &b| area(&points[a]).total_cmp(&area(&points[b])))
+            .unwrap_or(start);
+        kept.push(points[best].clone());
+        previous = best;
+    }
+    kept.push(points[count - 1].clone());
+    kept
+}
+
+/// How far, in percent, the curve through `kept` strays from `points`.
+pub fn max_deviation(points: &[CurvePoint], kept: &[CurvePoint]) -> f64 {
+    let simplified = FanCurve {
+        curve_points: kept.to_vec(),
+        ..Default::default()
+    };
+    points
+        .iter()
+        .map(|point| {
+            let duty = curve::calculate_fan_duty(
+                &simplified,
+                point.temperature_celsius,
+                point.fan_speed_percent,
+            );
+            (duty - point.fan_speed_percent.get() as f64).abs()
+        })
+        .fold(0.0, f64::max)
+}
+
+fn sort_points(points: &mut [CurvePoint]) {
+    points.sort_by(|a, b| a.temperature_celsius.total_cmp(&b.temperature_celsius));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speeds(fan_curve: &FanCurve) -> Vec<(f64, u8)> {
+        fan_curve
+            .curve_points
+            .iter()
+            .map(|point| (point.temperature_celsius, point.fan_speed_percent.get()))
+            .collect()
+    }
+
+    #[test]
+    fn test_edit_points() {
+        let mut fan_curve = FanCurve {
+            curve_points: parse_points("70:80, 30:20,50:40").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(speeds(&fan_curve), vec![(30.0, 20), (50.0, 40), (70.0, 80)]);
+
+        set_point(&mut fan_curve, 50.0, SpeedPercent::new(45).unwrap());
+        set_point(&mut fan_curve, 65.0, SpeedPercent::new(70).unwrap());
+        assert_eq!(
+            speeds(&fan_curve),
+            vec![(30.0, 20), (50.0, 45), (65.0, 70), (70.0, 80)]
+        );
+
+        remove_point(&mut fan_curve, 30.0).unwrap();
+        assert!(remove_point(&mut fan_curve, 31.0).is_err());
+        assert_eq!(speeds(&fan_curve), vec![(50.0, 45), (65.0, 70), (70.0, 80)]);
+    }
+
+    #[test]
+    fn test_edit_fahrenheit_curve() {
+        // The file keeps a tagged curve's points in °F; edits are given in °C
+        let mut fan_curve: FanCurve = serde_json::from_str(
+            r#"{ "device_id": [3314, 41219, "ABC"], "channel": 0, "mode": "Manual",
+                "temperature_unit": "fahrenheit", "curve_points": [
+                    {"temperature_celsius": 104, "fan_speed_percent": 30},
+                    {"temperature_celsius": 176, "fan_speed_percent": 80}
+                ] }"#,
+        )
+        .unwrap();
+        set_point(&mut fan_curve, 60.0, SpeedPercent::new(50).unwrap());
+        set_point(&mut fan_curve, 80.0, SpeedPercent::new(90).unwrap());
+        assert_eq!(
+            speeds(&fan_curve),
+            vec![(104.0, 30), (140.0, 50), (176.0, 90)]
+        );
+        remove_point(&mut fan_curve, 40.0).unwrap();
+        assert!(remove_point(&mut fan_curve, 104.0).is_err());
+        assert_eq!(speeds(&fan_curve), vec![(140.0, 50), (176.0, 90)]);
+
+        replace_points(&mut fan_curve, parse_points("33.3:20,90:100").unwrap());
+        assert_eq!(speeds(&fan_curve), vec![(91.94, 20), (194.0, 100)]);
+
+        // Loaded, they come back as the °C that was given
+        let mut config = crate::config::CurveConfig {
+            fan_curves: vec![fan_curve],
+            ..Default::default()
+        };
+        crate::config::convert_temperature_units(&mut config);
+        let celsius: Vec<f64> = config.fan_curves[0]
+            .curve_points
+            .iter()
+            .map(|point| (point.temperature_celsius * 100.0).round() / 100.0)
+            .collect();
+        assert_eq!(celsius, vec![33.3, 90.0]);
+    }
+
+    #[test]
+    fn test_parse_points_errors() {
+        assert!(parse_points("30:20,50").is_err());
+        assert!(parse_points("30:20,30:40").is_err());
+        assert!(parse_points("30:120").is_err());
+        assert!(parse_points("hot:20").is_err());
+        assert!(parse_points("NaN:20").is_err());
+    }
+
+    fn point(temperature_celsius: f64, speed: u8) -> CurvePoint {
+        CurvePoint {
+            temperature_celsius,
+            fan_speed_percent: SpeedPercent::new(speed).unwrap(),
+        }
+    }
+
+    fn temperatures(points: &[CurvePoint]) -> Vec<f64> {
+        points.iter().map(|p| p.temperature_celsius).collect()
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let points = parse_csv(
+            "temperature,percent\n\
+             # measured 2026-10-01\n\
+             50, 40.4\n\
+             30,20\n\
+             \n\
+             70,80.5\n",
+        )
+        .unwrap();
+        assert_eq!(
+            points
+                .iter()
+                .map(|p| (p.temperature_celsius, p.fan_speed_percent.get()))
+                .collect::<Vec<_>>(),
+            vec![(30.0, 20), (50.0, 40), (70.0, 81)]
+        );
+
+        assert!(parse_csv("").is_err());
+        assert!(parse_csv("30,20,1").is_err());
+        assert!(parse_csv("30,20\n40,hot").is_err());
+        assert!(parse_csv("30,20\n30,40").is_err());
+        assert!(parse_csv("30,120").is_err());
+        let error = parse_csv("30,50\n40,45").unwrap_err().to_string();
+        assert!(error.contains("Not monotone"), "{}", error);
+    }
+
+    #[test]
+    fn test_downsample_keeps_corners() {
+        // Flat at 20% up to 40°C, a steep climb to 90% at 60°C, then flat
+        let points: Vec<CurvePoint> = (20..=80)
+            .map(|t| {
+                let speed = match t {
+                    ..=40 => 20,
+                    41..=60 => 20 + (t - 40) * 7 / 2,
+                    _ => 90,
+                };
+                point(t as f64, speed as u8)
+            })
+            .collect();
+
+        let kept = downsample(&points, 6);
+        assert_eq!(kept.len(), 6);
+        assert_eq!(kept[0].temperature_celsius, 20.0);
+        assert_eq!(kept[5].temperature_celsius, 80.0);
+        // The two corners survive, so the curve barely changes
+        let kept_temperatures = temperatures(&kept);
+        assert!(kept_temperatures.contains(&40.0), "{:?}", kept_temperatures);
+        assert!(kept_temperatures.contains(&60.0), "{:?}", kept_temperatures);
+        assert!(max_deviation(&points, &kept) <= 1.0);
+        assert!(kept
+            .windows(2)
+            .all(|w| w[0].temperature_celsius < w[1].temperature_celsius));
+    }
+
+    #[test]
+    fn test_downsample_limits() {
+        let points: Vec<CurvePoint> = (0..10)
+            .map(|i| point(30.0 + i as f64, 20 + i * 5))
+            .collect();
+
+        assert_eq!(
+            temperatures(&downsample(&points, 10)),
+            temperatures(&points)
+        );
+        assert_eq!(
+            temperatures(&downsample(&points, 50)),
+            temperatures(&points)
+        );
+        assert_eq!(temperatures(&downsample(&points, 2)), vec![30.0, 39.0]);
+        assert_eq!(downsample(&points, 3).len(), 3);
+        // A straight line loses nothing when simplified
+        assert_eq!(max_deviation(&points, &downsample(&points, 2)), 0.0);
+        assert_eq!(downsample(&points[..1], 2).len(), 1);
+    }
+}
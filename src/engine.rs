@@ -0,0 +1,1233 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use crate::config::{CurveConfig, FanCurve, SpeedPercent, TemperatureFilter};
+use crate::control;
+use crate::curve;
+use crate::history::TemperatureHistory;
+use crate::rpm_loop::RpmLoop;
+
+/// Why a channel runs at the speed it does. When several stages change a
+/// speed, the one with the highest precedence is reported: group, then the
+/// never-stop floor, then override, then startup ramp, then throttle, then
+/// anticipation, then the plain curve (which includes per-channel trim).
+/// That is the reverse of the order the stages apply in, see PIPELINE.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum SpeedReason {
+    Curve,
+    Anticipation,
+    /// Raised by the curve's `throttle_points` while CPUs are throttled.
+    Throttle,
+    StartupRamp,
+    /// The interactive global override.
+    Override,
+    /// Held up at the minimum speed of a `never_stop` curve.
+    NeverStop,
+    /// Raised to the speed of another curve in its group.
+    Group,
+    /// The fallback speed while the temperature source is lost; set by the
+    /// daemon rather than the engine.
+    SourceLost,
+}
+
+/// The stages a speed goes through, in the order they apply. A stage only
+/// sees what the stages before it made of the speed, and the reason of the
+/// last one that changed it wins, so this order is also the reasons'
+/// precedence. Decisions check it as they go: a stage applied after a
+/// later one is a bug.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    Curve,
+    Rpm,
+    Anticipation,
+    Throttle,
+    Trim,
+    StartupRamp,
+    Override,
+    NeverStop,
+    Group,
+}
+
+pub const PIPELINE: [Stage; 9] = [
+    Stage::Curve,
+    Stage::Rpm,
+    Stage::Anticipation,
+    Stage::Throttle,
+    Stage::Trim,
+    Stage::StartupRamp,
+    Stage::Override,
+    Stage::NeverStop,
+    Stage::Group,
+];
+
+impl Stage {
+    /// As `explain` and `pipeline` show it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Curve => "curve",
+            Stage::Rpm => "rpm",
+            Stage::Anticipation => "anticipation",
+            Stage::Throttle => "throttle",
+            Stage::Group => "group",
+            Stage::Trim => "trim",
+            Stage::StartupRamp => "startup ramp",
+            Stage::Override => "override",
+            Stage::NeverStop => "never stop",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Stage::Curve => "the curve's points at the temperature",
+            Stage::Rpm => "replaced by the duty that holds `rpm_control`'s RPM",
+            Stage::Anticipation => "raised while the temperature rises quickly",
+            Stage::Throttle => "raised to `throttle_points` while CPUs are throttled",
+            Stage::Group => "raised to the fastest member of its group, as written",
+            Stage::Trim => "multiplier, offset, minimum and maximum",
+            Stage::StartupRamp => "eased in from the startup speed",
+            Stage::Override => "the interactive override, --full-speed or a power profile",
+            Stage::NeverStop => "held at or above the never-stop speed",
+        }
+    }
+
+    /// The reason a speed this stage changed is given; None for stages
+    /// that count as the curve itself.
+    pub fn reason(self) -> Option<SpeedReason> {
+        match self {
+            Stage::Curve => Some(SpeedReason::Curve),
+            Stage::Rpm | Stage::Trim => None,
+            Stage::Anticipation => Some(SpeedReason::Anticipation),
+            Stage::Throttle => Some(SpeedReason::Throttle),
+            Stage::Group => Some(SpeedReason::Group),
+            Stage::StartupRamp => Some(SpeedReason::StartupRamp),
+            Stage::Override => Some(SpeedReason::Override),
+            Stage::NeverStop => Some(SpeedReason::NeverStop),
+        }
+    }
+
+    /// Whether `config` makes this stage act on `fan_curve`. The
+    /// interactive override and --full-speed can act on every curve at
+    /// runtime whatever this says.
+    pub fn is_active(self, config: &CurveConfig, fan_curve: &FanCurve) -> bool {
+        match self {
+            Stage::Curve => true,
+            Stage::Rpm => fan_curve.rpm_control.is_some(),
+            Stage::Anticipation => fan_curve.anticipation.is_some(),
+            Stage::Throttle => !fan_curve.throttle_points.is_empty(),
+            Stage::Group => fan_curve.group.is_some(),
+            Stage::Trim => curve::has_trim(fan_curve),
+            Stage::StartupRamp => config.startup_ramp_seconds.is_some_and(|s| s > 0),
+            Stage::Override => config
+                .power_profiles
+                .values()
+                .any(|profile| profile.offset_percent != 0),
+            Stage::NeverStop => fan_curve.never_stop,
+        }
+    }
+}
+
+/// How fast the temperature must change, in °C/s, to count as rising or
+/// falling. Inside it the last direction holds, so a temperature hovering
+/// at its peak doesn't flip curves back and forth.
+pub const DIRECTION_DEADBAND: f64 = 0.1;
+
+/// Which of a curve's point sets applies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Direction {
+    #[default]
+    Rising,
+    Falling,
+}
+
+/// What the engine decided for one curve on one tick.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decision {
+    pub speed_percent: SpeedPercent,
+    pub reason: SpeedReason,
+    /// Index of the group member whose speed every member runs at.
+    pub group_winner: Option<usize>,
+    /// Every stage the speed went through, for explained and debugged
+    /// curves; empty for the rest.
+    pub steps: Vec<Step>,
+}
+
+/// Everything a tick's decisions depend on besides the curves.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Inputs {
+    pub temperature: f64,
+    /// Rate of change of the temperature in °C/s.
+    pub rate: Option<f64>,
+    /// Time since the daemon started.
+    pub elapsed: Duration,
+    pub override_percent: i8,
+    /// Percentage of CPUs throttled for heat since the last tick; None
+    /// where that can't be told.
+    pub throttle_pressure: Option<f64>,
+}
+
+/// Turns temperatures into channel speeds, one decision per curve.
+pub struct Engine {
+    curves: Vec<FanCurve>,
+    // Each curve with its falling points, for curves that have them
+    falling_curves: Vec<Option<FanCurve>>,
+    direction: Direction,
+    strict: bool,
+    fallback_speed: SpeedPercent,
+    never_stop_speed: SpeedPercent,
+    startup_ramp: Option<StartupRamp>,
+    last_speeds: Vec<Option<u8>>,
+    rpm_loops: Vec<RpmLoop>,
+    // Each curve's filtered temperature, for curves with a filter
+    filtered: Vec<Option<f64>>,
+    change_counts: BTreeMap<SpeedReason, u64>,
+    // Curves debugged at runtime, by label, on top of those with `debug`
+    debug_labels: BTreeSet<String>,
+}
+
+impl Engine {
+    pub fn new(
+        curves: Vec<FanCurve>,
+        strict: bool,
+        fallback_speed: SpeedPercent,
+        never_stop_speed: SpeedPercent,
+        startup_ramp: Option<StartupRamp>,
+    ) -> Self {
+        Self {
+            last_speeds: vec![None; curves.len()],
+            rpm_loops: vec![RpmLoop::default(); curves.len()],
+            filtered: vec![None; curves.len()],
+            falling_curves: curves.iter().map(FanCurve::falling).collect(),
+            direction: Direction::Rising,
+            curves,
+            strict,
+            fallback_speed,
+            never_stop_speed,
+            startup_ramp,
+            change_counts: BTreeMap::new(),
+            debug_labels: BTreeSet::new(),
+        }
+    }
+
+    /// Swaps in reloaded curves; the startup ramp keeps its original start.
+    pub fn set_curves(
+        &mut self,
+        curves: Vec<FanCurve>,
+        strict: bool,
+        fallback_speed: SpeedPercent,
+        never_stop_speed: SpeedPercent,
+    ) {
+        self.last_speeds = vec![None; curves.len()];
+        self.rpm_loops = vec![RpmLoop::default(); curves.len()];
+        self.filtered = vec![None; curves.len()];
+        self.falling_curves = curves.iter().map(FanCurve::falling).collect();
+        self.curves = curves;
+        self.strict = strict;
+        self.fallback_speed = fallback_speed;
+        self.never_stop_speed = never_stop_speed;
+    }
+
+    pub fn curves(&self) -> &[FanCurve] {
+        &self.curves
+    }
+
+    /// The speed each curve was last decided at, in curve order.
+    pub fn last_speeds(&self) -> &[Option<u8>] {
+        &self.last_speeds
+    }
+
+    /// Speed changes so far, by the reason for the new speed.
+    pub fn change_counts(&self) -> &BTreeMap<SpeedReason, u64> {
+        &self.change_counts
+    }
+
+    /// Traces the decisions of the curves labelled in `labels`, as well as
+    /// those with `debug` set. Outlasts set_curves.
+    pub fn set_debug_curves(&mut self, labels: BTreeSet<String>) {
+        self.debug_labels = labels;
+    }
+
+    fn is_debugged(&self, fan_curve: &FanCurve) -> bool {
+        fan_curve.debug || self.debug_labels.contains(&fan_curve.label())
+    }
+
+    /// The RPM of each curve's channel, in curve order, for curves with
+    /// `rpm_control`; None where there is no reading.
+    pub fn set_rpms(&mut self, rpms: &[Option<u16>]) {
+        for (rpm_loop, rpm) in self.rpm_loops.iter_mut().zip(rpms) {
+            rpm_loop.measure(*rpm);
+        }
+    }
+
+    /// Filters the temperature from `history` for curves with a `filter`,
+    /// which follow it instead of `Inputs::temperature` until the next call.
+    pub fn set_history(&mut self, history: &TemperatureHistory) {
+        self.filtered = self
+            .curves
+            .iter()
+            .map(|fan_curve| {
+                fan_curve.filter.and_then(|filter| match filter {
+                    TemperatureFilter::WindowAvg { seconds } => history.average_over(seconds),
+                })
+            })
+            .collect();
+    }
+
+    /// The temperature's direction as of the last decision.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// One decision per curve; debugged curves come with their steps.
+    pub fn decide(&mut self, inputs: Inputs) -> Vec<Decision> {
+        self.decide_traced(inputs, false)
+    }
+
+    /// Like decide, with the steps of every curve.
+    pub fn explain(&mut self, inputs: Inputs) -> Vec<Decision> {
+        self.decide_traced(inputs, true)
+    }
+
+    fn decide_traced(&mut self, inputs: Inputs, explain: bool) -> Vec<Decision> {
+        match inputs.rate {
+            Some(rate) if rate > DIRECTION_DEADBAND => self.direction = Direction::Rising,
+            Some(rate) if rate < -DIRECTION_DEADBAND => self.direction = Direction::Falling,
+            _ => {}
+        }
+
+        let mut traces: Vec<Trace> = self
+            .curves
+            .iter()
+            .map(|fan_curve| Trace::new(explain || self.is_debugged(fan_curve)))
+            .collect();
+        let mut reasons = Vec::with_capacity(self.curves.len());
+        // Duties stay fractional through every stage and are quantized to a
+        // whole percent only once, right before the never-stop floor
+        let duties: Vec<f64> = self
+            .curves
+            .iter()
+            .zip(&self.falling_curves)
+            .zip(&mut self.rpm_loops)
+            .zip(&self.filtered)
+            .zip(&mut traces)
+            .map(|((((fan_curve, falling), rpm_loop), filtered), trace)| {
+                let fan_curve = match (self.direction, falling) {
+                    (Direction::Falling, Some(falling)) => falling,
+                    _ => fan_curve,
+                };
+                let temperature = filtered.unwrap_or(inputs.temperature);
+                let plain = curve::calculate_fan_duty(fan_curve, temperature, self.fallback_speed);
+                trace.step(Stage::Curve, None, plain, || {
+                    let mut lookup = curve::describe_lookup(fan_curve, temperature);
+                    if let (Some(filter), Some(_)) = (fan_curve.filter, filtered) {
+                        lookup = format!("average over {}s, {}", filter.seconds(), lookup);
+                    }
+                    match falling {
+                        Some(_) => format!("{:?} points, {}", self.direction, lookup),
+                        None => lookup,
+                    }
+                });
+                // A curve holding an RPM follows its loop instead; validation
+                // keeps anticipation off such curves
+                let (plain, duty) = match &fan_curve.rpm_control {
+                    Some(control) => {
+                        let (duty, detail) =
+                            rpm_loop.update(control, temperature, plain, inputs.elapsed);
+                        trace.step(Stage::Rpm, Some(plain), duty, || detail);
+                        (duty, duty)
+                    }
+                    None => (
+                        plain,
+                        curve::calculate_anticipated_duty(
+                            fan_curve,
+                            temperature,
+                            inputs.rate,
+                            self.strict,
+                            self.fallback_speed,
+                        ),
+                    ),
+                };
+                if let Some(anticipation) = fan_curve.anticipation {
+                    trace.step(Stage::Anticipation, Some(plain), duty, || {
+                        match inputs.rate {
+                            Some(rate) => format!(
+                                "rising {:.2}°C/s, gain {}, boost up to {}%",
+                                rate,
+                                anticipation.degrees_per_second_gain,
+                                anticipation.max_boost_percent
+                            ),
+                            None => "no rate of change yet".to_string(),
+                        }
+                    });
+                }
+                let mut reason = if curve::quantize(duty) != curve::quantize(plain) {
+                    SpeedReason::Anticipation
+                } else {
+                    SpeedReason::Curve
+                };
+                let throttle = inputs.throttle_pressure.and_then(|pressure| {
+                    curve::calculate_throttle_duty(fan_curve, pressure)
+                        .map(|throttled| (pressure, throttled))
+                });
+                let duty = match throttle {
+                    Some((pressure, throttled)) => {
+                        let raised = duty.max(throttled);
+                        trace.step(Stage::Throttle, Some(duty), raised, || {
+                            format!("{:.0}% of CPUs throttled", pressure)
+                        });
+                        if curve::quantize(raised) != curve::quantize(duty) {
+                            reason = SpeedReason::Throttle;
+                        }
+                        raised
+                    }
+                    None => duty,
+                };
+                reasons.push(reason);
+                duty
+            })
+            .collect();
+
+        let mut speeds = Vec::with_capacity(self.curves.len());
+        for (index, fan_curve) in self.curves.iter().enumerate() {
+            let trace = &mut traces[index];
+            let reason = &mut reasons[index];
+            let mut duty = curve::adjust_duty(fan_curve, duties[index]);
+            if curve::has_trim(fan_curve) {
+                trace.step(Stage::Trim, Some(duties[index]), duty, || {
+                    curve::describe_trim(fan_curve)
+                });
+            }
+            if let Some(ramp) = &self.startup_ramp {
+                let ramped = ramp.apply(inputs.elapsed, duty);
+                if curve::quantize(ramped) != curve::quantize(duty) {
+                    *reason = SpeedReason::StartupRamp;
+                }
+                if inputs.elapsed < ramp.duration {
+                    trace.step(Stage::StartupRamp, Some(duty), ramped, || {
+                        format!(
+                            "{:.0}s of {:.0}s from {}%",
+                            inputs.elapsed.as_secs_f64(),
+                            ramp.duration.as_secs_f64(),
+                            ramp.start_speed
+                        )
+                    });
+                }
+                duty = ramped;
+            }
+            let overridden = control::apply_override(duty, inputs.override_percent);
+            if inputs.override_percent != 0 {
+                *reason = SpeedReason::Override;
+                trace.step(Stage::Override, Some(duty), overridden, || {
+                    format!("{:+}%", inputs.override_percent)
+                });
+            }
+            let speed = curve::quantize(overridden);
+
+            // After every stage that can lower a speed, so none can stop a pump
+            let floored = curve::never_stop_floor(fan_curve, speed, self.never_stop_speed);
+            if floored != speed {
+                *reason = SpeedReason::NeverStop;
+            }
+            if fan_curve.never_stop {
+                trace.step(Stage::NeverStop, Some(speed as f64), floored as f64, || {
+                    format!("floor {}%", self.never_stop_speed)
+                });
+            }
+            speeds.push(floored as f64);
+        }
+
+        // Groups go last, on the speeds as they will be written, so trim, the
+        // ramp or a floor on one member can't pull the group apart
+        let before_groups = speeds.clone();
+        let group_winners = curve::synchronize_groups(&self.curves, &mut speeds);
+
+        let mut decisions = Vec::with_capacity(self.curves.len());
+        for ((index, fan_curve), mut trace) in self.curves.iter().enumerate().zip(traces) {
+            let mut reason = reasons[index];
+            if speeds[index] != before_groups[index] {
+                reason = SpeedReason::Group;
+            }
+            if let Some(group) = &fan_curve.group {
+                trace.step(
+                    Stage::Group,
+                    Some(before_groups[index]),
+                    speeds[index],
+                    || match group_winners[index] {
+                        Some(winner) => {
+                            format!("group {}, set by {}", group, self.curves[winner].label())
+                        }
+                        None => format!("group {}", group),
+                    },
+                );
+            }
+            let speed = curve::quantize(speeds[index]);
+
+            self.rpm_loops[index].set_followed(reason == SpeedReason::Curve);
+            if self.last_speeds[index] != Some(speed) {
+                *self.change_counts.entry(reason).or_default() += 1;
+                self.last_speeds[index] = Some(speed);
+            }
+            decisions.push(Decision {
+                speed_percent: SpeedPercent::saturating(speed),
+                reason,
+                group_winner: group_winners[index],
+                steps: trace.steps,
+            });
+        }
+        decisions
+    }
+}
+
+/// One stage of a decision, as `uni-sync-curve explain` shows it, with
+/// speeds rounded to whole percents.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Step {
+    pub stage: &'static str,
+    /// The speed going in; None for the stage that starts from the temperature.
+    pub before: Option<u8>,
+    pub after: u8,
+    pub detail: String,
+}
+
+/// Collects the steps of one curve's decision when it is explained or
+/// debugged, and skips formatting their details otherwise. Every stage that
+/// acts on the curve goes through it, explained or not, so it also checks
+/// they come in PIPELINE order.
+struct Trace {
+    explain: bool,
+    last_stage: Option<Stage>,
+    steps: Vec<Step>,
+}
+
+impl Trace {
+    fn new(explain: bool) -> Self {
+        Self {
+            explain,
+            last_stage: None,
+            steps: Vec::new(),
+        }
+    }
+
+    fn step(
+        &mut self,
+        stage: Stage,
+        before: Option<f64>,
+        after: f64,
+        detail: impl FnOnce() -> String,
+    ) {
+        debug_assert!(
+            self.last_stage < Some(stage),
+            "stage {:?} applied after {:?}",
+            stage,
+            self.last_stage
+        );
+        self.last_stage = Some(stage);
+        if self.explain {
+            self.steps.push(Step {
+                stage: stage.name(),
+                before: before.map(curve::quantize),
+                after: curve::quantize(after),
+                detail: detail(),
+            });
+        }
+    }
+}
+
+/// Eases fans in after startup: for `duration`, applied speeds move linearly
+/// from `start_speed` to whatever the curves currently ask for.
+#[derive(Clone, Copy, Debug)]
+pub struct StartupRamp {
+    pub duration: Duration,
+    pub start_speed: SpeedPercent,
+}
+
+impl StartupRamp {
+    /// The duty to apply `elapsed` after startup when the curve asks for `target`.
+    pub fn apply(&self, elapsed: Duration, target: f64) -> f64 {
+        if elapsed >= self.duration {
+            return target;
+        }
+        let progress = elapsed.as_secs_f64() / self.duration.as_secs_f64();
+        let start = self.start_speed.get() as f64;
+        start + (target - start) * progress
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        Anticipation, BelowMin, CurvePoint, ThrottlePoint, DEFAULT_FALLBACK_SPEED_PERCENT,
+        DEFAULT_NEVER_STOP_SPEED_PERCENT,
+    };
+
+    fn linear(group: Option<&str>, offset: u8) -> FanCurve {
+        FanCurve {
+            group: group.map(str::to_string),
+            curve_points: vec![
+                CurvePoint {
+                    temperature_celsius: 20.0,
+                    fan_speed_percent: SpeedPercent::new(offset).unwrap(),
+                },
+                CurvePoint {
+                    temperature_celsius: 100.0,
+                    fan_speed_percent: SpeedPercent::new(80 + offset).unwrap(),
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn reasons(engine: &mut Engine, inputs: Inputs) -> Vec<(u8, SpeedReason)> {
+        engine
+            .decide(inputs)
+            .into_iter()
+            .map(|d| (d.speed_percent.get(), d.reason))
+            .collect()
+    }
+
+    #[test]
+    fn test_reason_precedence() {
+        let anticipating = FanCurve {
+            anticipation: Some(Anticipation {
+                degrees_per_second_gain: 5.0,
+                max_boost_percent: 10,
+            }),
+            ..linear(None, 0)
+        };
+        let curves = vec![
+            linear(Some("front"), 0),
+            linear(Some("front"), 10),
+            anticipating,
+        ];
+        let steady = Inputs {
+            temperature: 60.0,
+            elapsed: Duration::from_secs(600),
+            ..Default::default()
+        };
+        let ramp = StartupRamp {
+            duration: Duration::from_secs(60),
+            start_speed: SpeedPercent::ZERO,
+        };
+        let mut engine = Engine::new(
+            curves,
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            DEFAULT_NEVER_STOP_SPEED_PERCENT,
+            Some(ramp),
+        );
+
+        // Plain curve, raised by its group, raised by anticipation
+        assert_eq!(
+            reasons(
+                &mut engine,
+                Inputs {
+                    rate: Some(1.0),
+                    ..steady
+                }
+            ),
+            vec![
+                (50, SpeedReason::Group),
+                (50, SpeedReason::Curve),
+                (45, SpeedReason::Anticipation),
+            ]
+        );
+
+        // The startup ramp beats everything below it, and the group the ramp
+        assert_eq!(
+            reasons(
+                &mut engine,
+                Inputs {
+                    rate: Some(1.0),
+                    elapsed: Duration::from_secs(30),
+                    ..steady
+                }
+            ),
+            vec![
+                (25, SpeedReason::Group),
+                (25, SpeedReason::StartupRamp),
+                (23, SpeedReason::StartupRamp),
+            ]
+        );
+
+        // and the override beats the ramp
+        assert_eq!(
+            reasons(
+                &mut engine,
+                Inputs {
+                    rate: Some(1.0),
+                    elapsed: Duration::from_secs(30),
+                    override_percent: 10,
+                    ..steady
+                }
+            ),
+            vec![
+                (35, SpeedReason::Group),
+                (35, SpeedReason::Override),
+                (33, SpeedReason::Override),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pipeline_order() {
+        assert!(PIPELINE.windows(2).all(|pair| pair[0] < pair[1]));
+        // A later stage's reason always has the higher precedence
+        let reasons: Vec<SpeedReason> = PIPELINE.iter().filter_map(|s| s.reason()).collect();
+        assert!(reasons.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(reasons.len(), 7);
+    }
+
+    #[test]
+    fn test_conflicting_stages() {
+        let steady = Inputs {
+            temperature: 60.0,
+            elapsed: Duration::from_secs(600),
+            ..Default::default()
+        };
+        let ramp = StartupRamp {
+            duration: Duration::from_secs(60),
+            start_speed: SpeedPercent::ZERO,
+        };
+
+        // Every member of a group ends up at the same speed, however each
+        // one's trim, the startup ramp and the never-stop floor treat it:
+        // the group comes after them all
+        let capped = FanCurve {
+            max_speed_percent: Some(SpeedPercent::new(45).unwrap()),
+            ..linear(Some("front"), 0)
+        };
+        let trimmed = FanCurve {
+            speed_multiplier: 0.5,
+            min_speed_percent: Some(SpeedPercent::new(10).unwrap()),
+            ..linear(Some("front"), 20)
+        };
+        let pump = FanCurve {
+            never_stop: true,
+            ..linear(Some("front"), 0)
+        };
+        let mut engine = Engine::new(
+            vec![capped, trimmed, pump, linear(Some("front"), 20)],
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            DEFAULT_NEVER_STOP_SPEED_PERCENT,
+            Some(ramp),
+        );
+        let cold = Inputs {
+            temperature: 20.0,
+            ..steady
+        };
+        for inputs in [
+            steady,
+            cold,
+            Inputs {
+                elapsed: Duration::from_secs(15),
+                ..steady
+            },
+            Inputs {
+                override_percent: -100,
+                ..steady
+            },
+        ] {
+            let decisions = engine.decide(inputs);
+            assert!(
+                decisions
+                    .iter()
+                    .all(|decision| decision.speed_percent == decisions[3].speed_percent),
+                "{:?}: {:?}",
+                inputs,
+                decisions
+            );
+        }
+        assert_eq!(
+            reasons(&mut engine, steady),
+            vec![
+                (60, SpeedReason::Group),
+                (60, SpeedReason::Group),
+                (60, SpeedReason::Group),
+                (60, SpeedReason::Curve),
+            ]
+        );
+        // Below the others, the pump's floor sets the group's speed
+        assert_eq!(
+            reasons(&mut engine, cold),
+            vec![
+                (20, SpeedReason::Group),
+                (20, SpeedReason::Group),
+                (20, SpeedReason::NeverStop),
+                (20, SpeedReason::Curve),
+            ]
+        );
+        let steps: Vec<&str> = engine.explain(steady)[0]
+            .steps
+            .iter()
+            .map(|step| step.stage)
+            .collect();
+        assert_eq!(steps, vec!["curve", "trim", "group"]);
+
+        // The startup ramp eases in a throttled curve too
+        let throttled = FanCurve {
+            throttle_points: vec![ThrottlePoint {
+                pressure_percent: 0.0,
+                fan_speed_percent: SpeedPercent::FULL,
+            }],
+            ..linear(None, 0)
+        };
+        let mut engine = Engine::new(
+            vec![throttled],
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            DEFAULT_NEVER_STOP_SPEED_PERCENT,
+            Some(ramp),
+        );
+        assert_eq!(
+            reasons(
+                &mut engine,
+                Inputs {
+                    throttle_pressure: Some(10.0),
+                    elapsed: Duration::from_secs(30),
+                    ..steady
+                }
+            ),
+            vec![(50, SpeedReason::StartupRamp)]
+        );
+
+        // The override goes on top of the RPM loop's duty, which doesn't
+        // adjust to what the override did
+        let held = FanCurve {
+            rpm_control: Some(crate::config::RpmControl {
+                points: vec![crate::config::RpmPoint {
+                    temperature_celsius: 60.0,
+                    fan_rpm: 1000,
+                }],
+                ..Default::default()
+            }),
+            ..linear(None, 0)
+        };
+        let mut engine = Engine::new(
+            vec![held],
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            DEFAULT_NEVER_STOP_SPEED_PERCENT,
+            None,
+        );
+        let overridden = Inputs {
+            override_percent: 10,
+            ..steady
+        };
+        for _ in 0..3 {
+            engine.set_rpms(&[Some(500)]);
+            assert_eq!(
+                reasons(&mut engine, overridden),
+                vec![(50, SpeedReason::Override)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_throttle_points() {
+        let throttled = FanCurve {
+            group: Some("front".to_string()),
+            throttle_points: vec![
+                ThrottlePoint {
+                    pressure_percent: 0.0,
+                    fan_speed_percent: SpeedPercent::ZERO,
+                },
+                ThrottlePoint {
+                    pressure_percent: 25.0,
+                    fan_speed_percent: SpeedPercent::FULL,
+                },
+            ],
+            ..linear(None, 0)
+        };
+        let mut engine = Engine::new(
+            vec![throttled, linear(Some("front"), 0)],
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            DEFAULT_NEVER_STOP_SPEED_PERCENT,
+            None,
+        );
+        let at = |throttle_pressure| Inputs {
+            temperature: 60.0,
+            throttle_pressure,
+            ..Default::default()
+        };
+
+        // Without throttle counters, or below the curve, the curve holds
+        assert_eq!(
+            reasons(&mut engine, at(None)),
+            vec![(40, SpeedReason::Curve), (40, SpeedReason::Curve)]
+        );
+        assert_eq!(
+            reasons(&mut engine, at(Some(4.0))),
+            vec![(40, SpeedReason::Curve), (40, SpeedReason::Curve)]
+        );
+        // A steep sub-curve takes over as soon as CPUs throttle, and its
+        // group follows
+        assert_eq!(
+            reasons(&mut engine, at(Some(12.5))),
+            vec![(50, SpeedReason::Throttle), (50, SpeedReason::Group)]
+        );
+        assert_eq!(
+            reasons(&mut engine, at(Some(100.0))),
+            vec![(100, SpeedReason::Throttle), (100, SpeedReason::Group)]
+        );
+
+        let explained = engine.explain(at(Some(12.5)));
+        assert_eq!(
+            explained[0].steps[1],
+            Step {
+                stage: "throttle",
+                before: Some(40),
+                after: 50,
+                detail: "12% of CPUs throttled".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_never_stop_is_last() {
+        // Validation rejects never_stop with below_min zero; the engine must
+        // still hold the floor if such a curve gets through
+        let pump = FanCurve {
+            never_stop: true,
+            below_min: BelowMin::Zero,
+            ..linear(None, 0)
+        };
+        let ramp = StartupRamp {
+            duration: Duration::from_secs(60),
+            start_speed: SpeedPercent::ZERO,
+        };
+        let mut engine = Engine::new(
+            vec![pump, linear(None, 0)],
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            DEFAULT_NEVER_STOP_SPEED_PERCENT,
+            Some(ramp),
+        );
+        let steady = Inputs {
+            temperature: 60.0,
+            elapsed: Duration::from_secs(600),
+            ..Default::default()
+        };
+
+        // Above the floor the pump follows its curve
+        assert_eq!(
+            reasons(&mut engine, steady),
+            vec![(40, SpeedReason::Curve), (40, SpeedReason::Curve)]
+        );
+        // below_min zero
+        assert_eq!(
+            reasons(
+                &mut engine,
+                Inputs {
+                    temperature: 10.0,
+                    ..steady
+                }
+            ),
+            vec![(20, SpeedReason::NeverStop), (0, SpeedReason::Curve)]
+        );
+        // The startup ramp starting at 0
+        assert_eq!(
+            reasons(
+                &mut engine,
+                Inputs {
+                    elapsed: Duration::ZERO,
+                    ..steady
+                }
+            ),
+            vec![(20, SpeedReason::NeverStop), (0, SpeedReason::StartupRamp)]
+        );
+        // The override turning everything off
+        assert_eq!(
+            reasons(
+                &mut engine,
+                Inputs {
+                    override_percent: -100,
+                    ..steady
+                }
+            ),
+            vec![(20, SpeedReason::NeverStop), (0, SpeedReason::Override)]
+        );
+
+        // The floor follows the configuration
+        engine.set_curves(
+            engine.curves().to_vec(),
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            SpeedPercent::new(35).unwrap(),
+        );
+        assert_eq!(
+            reasons(
+                &mut engine,
+                Inputs {
+                    override_percent: -100,
+                    ..steady
+                }
+            ),
+            vec![(35, SpeedReason::NeverStop), (0, SpeedReason::Override)]
+        );
+    }
+
+    #[test]
+    fn test_filtered_temperature() {
+        let averaged = FanCurve {
+            filter: Some(TemperatureFilter::WindowAvg { seconds: 60.0 }),
+            ..linear(None, 0)
+        };
+        let mut engine = Engine::new(
+            vec![linear(None, 0), averaged],
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            DEFAULT_NEVER_STOP_SPEED_PERCENT,
+            None,
+        );
+        let hot = Inputs {
+            temperature: 80.0,
+            ..Default::default()
+        };
+        // Without history yet, both follow the reading
+        let speeds = |decisions: Vec<Decision>| -> Vec<u8> {
+            decisions.iter().map(|d| d.speed_percent.get()).collect()
+        };
+        assert_eq!(speeds(engine.decide(hot)), vec![60, 60]);
+
+        // 40°C for 30s, then a climb to 80°C over 30s: 50°C on average
+        let mut history = TemperatureHistory::default();
+        history.keep_for(60.0, 30);
+        for (time, temperature) in [(0.0, 40.0), (30.0, 40.0), (60.0, 80.0)] {
+            history.push(time, temperature);
+        }
+        engine.set_history(&history);
+        let decisions = engine.explain(hot);
+        assert_eq!(
+            decisions[1].steps[0].detail,
+            format!(
+                "average over 60s, {}",
+                curve::describe_lookup(&engine.curves()[1], 50.0)
+            )
+        );
+        assert_eq!(speeds(decisions), vec![60, 30]);
+    }
+
+    #[test]
+    fn test_change_counts() {
+        let mut engine = Engine::new(
+            vec![linear(None, 0)],
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            DEFAULT_NEVER_STOP_SPEED_PERCENT,
+            None,
+        );
+        let at = |temperature| Inputs {
+            temperature,
+            ..Default::default()
+        };
+
+        engine.decide(at(60.0));
+        engine.decide(at(60.0));
+        engine.decide(at(70.0));
+        engine.decide(Inputs {
+            override_percent: -5,
+            ..at(70.0)
+        });
+
+        assert_eq!(engine.change_counts().get(&SpeedReason::Curve), Some(&2));
+        assert_eq!(engine.change_counts().get(&SpeedReason::Override), Some(&1));
+    }
+
+    #[test]
+    fn test_debugged_curves() {
+        let named = |name: &str, debug| FanCurve {
+            name: Some(name.to_string()),
+            debug,
+            ..linear(None, 0)
+        };
+        let mut engine = Engine::new(
+            vec![
+                named("front", true),
+                named("rear", false),
+                named("top", false),
+            ],
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            DEFAULT_NEVER_STOP_SPEED_PERCENT,
+            None,
+        );
+        engine.set_debug_curves(BTreeSet::from(["top".to_string()]));
+        let inputs = Inputs {
+            temperature: 60.0,
+            ..Default::default()
+        };
+
+        let traced: Vec<bool> = engine
+            .decide(inputs)
+            .iter()
+            .map(|decision| !decision.steps.is_empty())
+            .collect();
+        assert_eq!(traced, vec![true, false, true]);
+        assert!(engine
+            .explain(inputs)
+            .iter()
+            .all(|decision| !decision.steps.is_empty()));
+    }
+
+    #[test]
+    fn test_rising_and_falling_points() {
+        let point = |temperature_celsius, speed| CurvePoint {
+            temperature_celsius,
+            fan_speed_percent: SpeedPercent::new(speed).unwrap(),
+        };
+        let fan_curve = FanCurve {
+            curve_points: vec![point(30.0, 20), point(70.0, 100)],
+            curve_points_falling: Some(vec![point(30.0, 10), point(70.0, 60)]),
+            ..Default::default()
+        };
+        let mut engine = Engine::new(
+            vec![fan_curve.clone(), linear(None, 0)],
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            DEFAULT_NEVER_STOP_SPEED_PERCENT,
+            None,
+        );
+
+        // Up by 1°C/s, hovering around the peak, then down by 1°C/s
+        let mut trace: Vec<f64> = (40..=70).map(f64::from).collect();
+        trace.extend([70.3, 69.8, 70.2, 69.9, 70.1, 70.0, 69.9, 70.2]);
+        trace.extend((40..70).rev().map(f64::from));
+
+        let mut history = crate::history::TemperatureHistory::default();
+        let mut ticks = Vec::new();
+        for (second, &temperature) in trace.iter().enumerate() {
+            history.push(second as f64, temperature);
+            let decisions = engine.decide(Inputs {
+                temperature,
+                rate: history.rate(),
+                ..Default::default()
+            });
+            ticks.push((
+                temperature,
+                engine.direction(),
+                decisions[0].speed_percent.get(),
+                decisions[1].speed_percent.get(),
+            ));
+        }
+
+        // One switch, on the way down, and none while hovering at the peak
+        let switches: Vec<usize> = ticks
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| pair[0].1 != pair[1].1)
+            .map(|(index, _)| index + 1)
+            .collect();
+        assert_eq!(switches.len(), 1);
+        assert!(switches[0] >= 31 + 8);
+        assert!(ticks[31..39].iter().all(|tick| tick.1 == Direction::Rising));
+
+        // Same temperature, different speed depending on the direction
+        let at_55: Vec<_> = ticks.iter().filter(|tick| tick.0 == 55.0).collect();
+        assert_eq!(at_55.len(), 2);
+        assert_eq!((at_55[0].1, at_55[0].2), (Direction::Rising, 70));
+        let falling = fan_curve.falling().unwrap();
+        assert_eq!(
+            (at_55[1].1, at_55[1].2),
+            (
+                Direction::Falling,
+                curve::calculate_fan_speed(&falling, 55.0, DEFAULT_FALLBACK_SPEED_PERCENT)
+            )
+        );
+        assert!(at_55[1].2 < 45);
+        // A curve without falling points follows the same points both ways
+        assert_eq!(at_55[0].3, at_55[1].3);
+    }
+
+    #[test]
+    fn test_quantized_once() {
+        let plain = linear(None, 10);
+        let trimmed = FanCurve {
+            speed_multiplier: 1.5,
+            ..linear(None, 0)
+        };
+        let mut engine = Engine::new(
+            vec![plain.clone(), trimmed.clone()],
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            DEFAULT_NEVER_STOP_SPEED_PERCENT,
+            None,
+        );
+
+        // A slow rise through the whole curve, 0.01°C per tick
+        let mut last = None;
+        for step in 0..8000 {
+            let temperature = 20.0 + step as f64 * 0.01;
+            let speeds: Vec<u8> = engine
+                .decide(Inputs {
+                    temperature,
+                    ..Default::default()
+                })
+                .iter()
+                .map(|d| d.speed_percent.get())
+                .collect();
+
+            // A plain curve runs exactly as before
+            assert_eq!(
+                speeds[0],
+                curve::calculate_fan_speed(&plain, temperature, DEFAULT_FALLBACK_SPEED_PERCENT)
+            );
+            // Rounding once, the trimmed curve never skips a percent, where
+            // rounding before the trim made it jump 2% at a time
+            if let Some(last) = last {
+                assert!(
+                    speeds[1] - last <= 1,
+                    "jumped from {}% at {:.2}°C",
+                    last,
+                    temperature
+                );
+            }
+            last = Some(speeds[1]);
+        }
+        let rounded_early = |temperature| {
+            curve::adjust_speed(
+                &trimmed,
+                curve::calculate_fan_speed(&trimmed, temperature, DEFAULT_FALLBACK_SPEED_PERCENT),
+            )
+        };
+        assert_eq!(rounded_early(40.4), 30);
+        assert_eq!(rounded_early(40.6), 32);
+    }
+
+    #[test]
+    fn test_startup_ramp() {
+        let ramp = StartupRamp {
+            duration: Duration::from_secs(60),
+            start_speed: SpeedPercent::new(20).unwrap(),
+        };
+        let ramped = |seconds, target: f64| {
+            curve::quantize(ramp.apply(Duration::from_secs(seconds), target))
+        };
+
+        assert_eq!(ramped(0, 100.0), 20);
+        assert_eq!(ramped(15, 100.0), 40);
+        assert_eq!(ramped(30, 100.0), 60);
+        // The load drops mid-ramp; the ramp follows the new target
+        assert_eq!(ramped(45, 40.0), 35);
+        // and back up
+        assert_eq!(ramped(50, 80.0), 70);
+        // A target below the start speed is approached from above
+        assert_eq!(ramped(30, 10.0), 15);
+
+        assert_eq!(ramped(60, 100.0), 100);
+        assert_eq!(ramped(61, 0.0), 0);
+        assert_eq!(ramped(600, 55.0), 55);
+    }
+
+    #[test]
+    fn test_zero_length_ramp() {
+        let ramp = StartupRamp {
+            duration: Duration::ZERO,
+            start_speed: SpeedPercent::new(20).unwrap(),
+        };
+        assert_eq!(ramp.apply(Duration::ZERO, 100.0), 100.0);
+    }
+}
@@ -0,0 +1,185 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::fmt::Write;
+
+use crate::config::{FanCurve, SpeedPercent};
+use crate::curve;
+use crate::units::DisplayUnits;
+
+/// Most temperatures a range may expand to.
+const MAX_RANGE_STEPS: usize = 1000;
+
+/// Temperatures from `start` to `end` inclusive, every `step` degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureRange {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+}
+
+impl std::str::FromStr for TemperatureRange {
+    type Err = anyhow::Error;
+
+    /// Parses "start:end:step" in °C, e.g. "30:80:5".
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let [start, end, step] = parts.as_slice() else {
+            bail!("Expected a range as start:end:step, got {:?}", s);
+        };
+        let range = TemperatureRange {
+            start: start.trim().parse()?,
+            end: end.trim().parse()?,
+            step: step.trim().parse()?,
+        };
+        // Written so NaN fails too
+        let ordered = range.step > 0.0 && range.start <= range.end;
+        if !ordered {
+            bail!("Range {:?} needs start <= end and a positive step", s);
+        }
+        if (range.end - range.start) / range.step >= MAX_RANGE_STEPS as f64 {
+            bail!("Range {:?} has more than {} steps", s, MAX_RANGE_STEPS);
+        }
+        Ok(range)
+    }
+}
+
+impl TemperatureRange {
+    pub fn temperatures(&self) -> Vec<f64> {
+        let steps = ((self.end - self.start) / self.step + 1e-9).floor() as usize;
+        (0..=steps)
+            .map(|i| self.start + i as f64 * self.step)
+            .collect()
+    }
+}
+
+/// One temperature evaluated on a curve.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Evaluation {
+    pub temperature: f64,
+    pub curve_speed: u8,
+    /// After the channel's multiplier, offset and min/max speed.
+    pub adjusted_speed: u8,
+}
+
+pub fn evaluate(
+    fan_curve: &FanCurve,
+    temperatures: &[f64],
+    fallback_speed: SpeedPercent,
+) -> Vec<Evaluation> {
+    temperatures
+        .iter()
+        .map(|&temperature| {
+            let curve_speed = curve::calculate_fan_speed(fan_curve, temperature, fallback_speed);
+            Evaluation {
+                temperature,
+                curve_speed,
+                adjusted_speed: curve::adjust_speed(fan_curve, curve_speed),
+            }
+        })
+        .collect()
+}
+
+/// One line for a single temperature, a table for several. `full_pipeline`
+/// adds the speed after the channel's trim and clamps.
+pub fn format_evaluations(
+    evaluations: &[Evaluation],
+    full_pipeline: bool,
+    units: DisplayUnits,
+) -> String {
+    let mut text = String::new();
+    if let [evaluation] = evaluations {
+        let _ = write!(
+            text,
+            "{}: {}%",
+            units.temperature(evaluation.temperature),
+            evaluation.curve_speed
+        );
+        if full_pipeline {
+            let _ = write!(
+                text,
+                ", {}% after trim and clamps",
+                evaluation.adjusted_speed
+            );
+        }
+        let _ = writeln!(text);
+        return text;
+    }
+
+    let _ = write!(text, "{:>8} {:>6}", "TEMP", "CURVE");
+    if full_pipeline {
+        let _ = write!(text, " {:>8}", "APPLIED");
+    }
+    let _ = writeln!(text);
+    for evaluation in evaluations {
+        let _ = write!(
+            text,
+            "{:>8} {:>5}%",
+            units.temperature(evaluation.temperature),
+            evaluation.curve_speed
+        );
+        if full_pipeline {
+            let _ = write!(text, " {:>7}%", evaluation.adjusted_speed);
+        }
+        let _ = writeln!(text);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CurvePoint;
+
+    fn point(temperature_celsius: f64, speed: u8) -> CurvePoint {
+        CurvePoint {
+            temperature_celsius,
+            fan_speed_percent: SpeedPercent::new(speed).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_temperature_range() {
+        let range: TemperatureRange = "30:40:2.5".parse().unwrap();
+        assert_eq!(range.temperatures(), vec![30.0, 32.5, 35.0, 37.5, 40.0]);
+        assert_eq!(
+            "30:31:0.3"
+                .parse::<TemperatureRange>()
+                .unwrap()
+                .temperatures(),
+            vec![30.0, 30.3, 30.6, 30.9]
+        );
+
+        assert!("30:40".parse::<TemperatureRange>().is_err());
+        assert!("40:30:5".parse::<TemperatureRange>().is_err());
+        assert!("30:40:0".parse::<TemperatureRange>().is_err());
+        assert!("0:100:0.01".parse::<TemperatureRange>().is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let fan_curve = FanCurve {
+            curve_points: vec![point(30.0, 20), point(80.0, 100)],
+            speed_offset_percent: 10,
+            max_speed_percent: SpeedPercent::new(90).ok(),
+            ..Default::default()
+        };
+        let evaluations = evaluate(&fan_curve, &[72.5, 80.0], SpeedPercent::FULL);
+        assert_eq!(evaluations[0].curve_speed, 88);
+        assert_eq!(evaluations[0].adjusted_speed, 90);
+
+        assert_eq!(
+            format_evaluations(&evaluations[..1], true, DisplayUnits::Celsius),
+            "72.5°C: 88%, 90% after trim and clamps\n"
+        );
+        assert_eq!(
+            format_evaluations(&evaluations[..1], false, DisplayUnits::Fahrenheit),
+            "162.5°F: 88%\n"
+        );
+        assert_eq!(
+            format_evaluations(&evaluations, true, DisplayUnits::Celsius),
+            "    TEMP  CURVE  APPLIED\n  \
+               72.5°C    88%      90%\n  \
+               80.0°C   100%      90%\n"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use serde::Serialize;
+use std::fmt::Write;
+
+use crate::config::{DeviceId, FanCurve, Manage};
+use crate::engine::{Decision, SpeedReason, Step};
+use crate::style::{Column, Style, Table};
+use crate::units::DisplayUnits;
+
+/// Why each curve runs at its speed, for `uni-sync-curve explain`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Report {
+    pub temperature_celsius: f64,
+    /// Where the temperature came from, e.g. "the running daemon".
+    pub temperature_from: String,
+    pub override_percent: i8,
+    pub curves: Vec<Explanation>,
+    /// What the daemon does that no curve decides, such as the failsafe.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Explanation {
+    pub curve: String,
+    pub device_id: DeviceId,
+    pub channel: usize,
+    pub steps: Vec<Step>,
+    pub speed_percent: u8,
+    pub reason: SpeedReason,
+    /// False for monitor-only curves, whose speed is never written.
+    pub written: bool,
+}
+
+/// Pairs each curve with its explained decision.
+pub fn explanations(fan_curves: &[FanCurve], explained: Vec<Decision>) -> Vec<Explanation> {
+    fan_curves
+        .iter()
+        .zip(explained)
+        .map(|(fan_curve, decision)| Explanation {
+            curve: fan_curve.label(),
+            device_id: fan_curve.device_id.clone(),
+            channel: fan_curve.channel,
+            steps: decision.steps,
+            speed_percent: decision.speed_percent.get(),
+            reason: decision.reason,
+            written: fan_curve.manage != Manage::MonitorOnly,
+        })
+        .collect()
+}
+
+/// A debugged curve's decision as log lines, one per step, e.g.
+/// "front: trim 90% -> 100% (+10%)".
+pub fn debug_lines(label: &str, steps: &[Step]) -> Vec<String> {
+    steps
+        .iter()
+        .map(|step| {
+            let before = step
+                .before
+                .map(|before| format!("{}% -> ", before))
+                .unwrap_or_default();
+            format!(
+                "{}: {} {}{}% ({})",
+                label, step.stage, before, step.after, step.detail
+            )
+        })
+        .collect()
+}
+
+pub fn format_report(report: &Report, units: DisplayUnits) -> String {
+    let mut text = String::new();
+    let _ = write!(
+        text,
+        "Temperature {} from {}",
+        units.temperature(report.temperature_celsius),
+        report.temperature_from
+    );
+    if report.override_percent != 0 {
+        let _ = write!(text, ", override {:+}%", report.override_percent);
+    }
+    let _ = writeln!(text);
+
+    for explanation in &report.curves {
+        let _ = writeln!(
+            text,
+            "{} {} ch{}",
+            explanation.curve, explanation.device_id, explanation.channel
+        );
+        let mut table = Table::new(vec![
+            Column::left(12, 2),
+            Column::right(0, 1),
+            Column::right(4, 1),
+            Column::left(0, 2),
+        ]);
+        for step in &explanation.steps {
+            table.row(
+                vec![
+                    step.stage.to_string(),
+                    step.before
+                        .map(|before| format!("{}% ->", before))
+                        .unwrap_or_default(),
+                    format!("{}%", step.after),
+                    step.detail.clone(),
+                ],
+                None,
+            );
+        }
+        table.row(
+            vec![
+                "command".to_string(),
+                String::new(),
+                format!("{}%", explanation.speed_percent),
+                if explanation.written {
+                    format!("({:?})", explanation.reason)
+                } else {
+                    format!("({:?}), monitor only, not written", explanation.reason)
+                },
+            ],
+            None,
+        );
+        for line in table.lines(Style::plain()) {
+            let _ = writeln!(text, "{}", line);
+        }
+    }
+    for note in &report.notes {
+        let _ = writeln!(text, "Note: {}", note);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CurvePoint, SpeedPercent, DEFAULT_FALLBACK_SPEED_PERCENT};
+    use crate::engine::{Engine, Inputs};
+
+    #[test]
+    fn test_explain() {
+        let point = |temperature_celsius, speed| CurvePoint {
+            temperature_celsius,
+            fan_speed_percent: SpeedPercent::new(speed).unwrap(),
+        };
+        let fan_curves = vec![
+            FanCurve {
+                name: Some("front".to_string()),
+                curve_points: vec![point(50.0, 40), point(70.0, 80)],
+                speed_offset_percent: 10,
+                group: Some("case".to_string()),
+                ..Default::default()
+            },
+            FanCurve {
+                name: Some("rear".to_string()),
+                channel: 1,
+                curve_points: vec![point(30.0, 90)],
+                group: Some("case".to_string()),
+                never_stop: true,
+                manage: Manage::MonitorOnly,
+                ..Default::default()
+            },
+        ];
+        let mut engine = Engine::new(
+            fan_curves.clone(),
+            false,
+            DEFAULT_FALLBACK_SPEED_PERCENT,
+            SpeedPercent::new(20).unwrap(),
+            None,
+        );
+        let explained = engine.explain(Inputs {
+            temperature: 60.0,
+            override_percent: -5,
+            ..Default::default()
+        });
+        let report = Report {
+            temperature_celsius: 60.0,
+            temperature_from: "--temp".to_string(),
+            override_percent: -5,
+            curves: explanations(&fan_curves, explained),
+            notes: Vec::new(),
+        };
+
+        assert_eq!(
+            format_report(&report, DisplayUnits::Celsius),
+            "Temperature 60.0°C from --temp, override -5%\n\
+             front (0000, 0000, ) ch0\n  \
+               curve                60%  between 50.0°C 40% and 70.0°C 80%\n  \
+               trim         60% ->  70%  +10%\n  \
+               override     70% ->  65%  -5%\n  \
+               group        65% ->  85%  group case, set by rear\n  \
+               command              85%  (Group)\n\
+             rear (0000, 0000, ) ch1\n  \
+               curve                90%  above the last point 30.0°C 90%, clamped\n  \
+               override     90% ->  85%  -5%\n  \
+               never stop   85% ->  85%  floor 20%\n  \
+               group        85% ->  85%  group case, set by rear\n  \
+               command              85%  (Override), monitor only, not written\n"
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["curves"][0]["steps"][1]["stage"], "trim");
+        assert_eq!(json["curves"][0]["steps"][1]["before"], 60);
+        assert_eq!(json["curves"][0]["reason"], "group");
+    }
+}
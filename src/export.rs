@@ -0,0 +1,666 @@
+use std::fmt::Write;
+
+use crate::config::{
+    AboveMax, BelowMin, ChannelMode, CurveConfig, DeviceId, FanCurve, Manage, TemperatureSource,
+};
+use crate::hardware;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Coolercontrol,
+    Fan2go,
+}
+
+/// Result of translating a config into another tool's format. Warnings
+/// describe settings that could not be carried over.
+#[derive(Debug)]
+pub struct Export {
+    pub text: String,
+    pub warnings: Vec<String>,
+}
+
+pub fn export(config: &CurveConfig, format: ExportFormat) -> Export {
+    match format {
+        ExportFormat::Coolercontrol => to_coolercontrol(config),
+        ExportFormat::Fan2go => to_fan2go(config),
+    }
+}
+
+fn curve_id(curve: &FanCurve) -> String {
+    if let Some(name) = &curve.name {
+        return name.clone();
+    }
+    let channel = if curve.broadcast {
+        "all".to_string()
+    } else {
+        format!("ch{}", curve.channel)
+    };
+    format!(
+        "uni-{:04x}-{}-{}",
+        curve.device_id.1,
+        curve.device_id.2.to_lowercase(),
+        channel
+    )
+}
+
+/// The channels `curve` drives: all of its device's for a broadcast curve.
+fn channels(curve: &FanCurve) -> Vec<usize> {
+    if curve.broadcast {
+        (0..hardware::device_model(&curve.device_id).channels).collect()
+    } else {
+        vec![curve.channel]
+    }
+}
+
+/// Neither tool can point one curve at a whole hub, so a broadcast curve is
+/// assigned to each channel of its device's model in turn.
+fn broadcast_warnings(curves: &[&FanCurve]) -> Vec<String> {
+    curves
+        .iter()
+        .filter(|curve| curve.broadcast)
+        .map(|curve| {
+            format!(
+                "{}: broadcast is exported as the {} channels of its device ({})",
+                curve_id(curve),
+                channels(curve).len(),
+                hardware::device_model(&curve.device_id).name
+            )
+        })
+        .collect()
+}
+
+fn sorted_points(curve: &FanCurve) -> Vec<(f64, u8)> {
+    let mut points: Vec<(f64, u8)> = curve
+        .curve_points
+        .iter()
+        .map(|p| (p.temperature_celsius, p.fan_speed_percent.get()))
+        .collect();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    points
+}
+
+fn percent_to_pwm(percent: u8) -> u8 {
+    (percent.min(100) as f64 * 255.0 / 100.0).round() as u8
+}
+
+/// Curves in PWM mode are driven by the motherboard header, so neither
+/// tool has anything to control for them. Disabled and monitor-only curves
+/// don't drive their channel here either.
+fn exportable_curves<'a>(config: &'a CurveConfig, warnings: &mut Vec<String>) -> Vec<&'a FanCurve> {
+    config
+        .fan_curves
+        .iter()
+        .filter(|curve| {
+            if !curve.enabled {
+                warnings.push(format!("{}: curve is disabled, skipped", curve_id(curve)));
+                false
+            } else if curve.manage == Manage::MonitorOnly {
+                warnings.push(format!(
+                    "{}: curve is monitor-only, skipped",
+                    curve_id(curve)
+                ));
+                false
+            } else if curve.mode == ChannelMode::PWM {
+                warnings.push(format!(
+                    "{}: channel is in PWM mode (motherboard controlled), skipped",
+                    curve_id(curve)
+                ));
+                false
+            } else if curve.curve_points.is_empty() {
+                warnings.push(format!("{}: curve has no points, skipped", curve_id(curve)));
+                false
+            } else {
+                true
+            }
+        })
+        .collect()
+}
+
+/// The settings of `curve` neither tool has, each as a warning. Both only
+/// map a temperature to a speed through the curve's points, clamped at
+/// either end. `broadcast` is carried over, see `broadcast_warnings`.
+fn dropped_settings(curve: &FanCurve, tool: &str) -> Vec<String> {
+    let mut dropped = Vec::new();
+    if curve.curve_points_falling.is_some() {
+        dropped.push(format!(
+            "curve_points_falling (only the rising points are exported; {} has no hysteresis curve)",
+            tool
+        ));
+    }
+    if curve.above_max != AboveMax::Clamp {
+        dropped.push(format!(
+            "above_max {} (held at the last point instead)",
+            serde_json::to_string(&curve.above_max).unwrap_or_default()
+        ));
+    }
+    if curve.below_min != BelowMin::Clamp {
+        dropped.push(format!(
+            "below_min {} (held at the first point instead)",
+            serde_json::to_string(&curve.below_min).unwrap_or_default()
+        ));
+    }
+    if curve.speed_multiplier != 1.0 {
+        dropped.push(format!("speed_multiplier {}", curve.speed_multiplier));
+    }
+    if curve.speed_offset_percent != 0 {
+        dropped.push(format!(
+            "speed_offset_percent {}",
+            curve.speed_offset_percent
+        ));
+    }
+    if let Some(min) = curve.min_speed_percent {
+        dropped.push(format!("min_speed_percent {}", min));
+    }
+    if let Some(max) = curve.max_speed_percent {
+        dropped.push(format!("max_speed_percent {}", max));
+    }
+    if let Some(group) = &curve.group {
+        dropped.push(format!("group {:?}", group));
+    }
+    if curve.anticipation.is_some() {
+        dropped.push("anticipation".to_string());
+    }
+    if !curve.throttle_points.is_empty() {
+        dropped.push("throttle_points".to_string());
+    }
+    if curve.rpm_control.is_some() {
+        dropped.push("rpm_control".to_string());
+    }
+    if curve.filter.is_some() {
+        dropped.push("filter".to_string());
+    }
+    if curve.never_stop {
+        dropped.push("never_stop".to_string());
+    }
+    if let Some(substeps) = curve.slew_substeps {
+        dropped.push(format!("slew_substeps {}", substeps));
+    }
+    if curve.manage == Manage::SpeedOnly {
+        dropped.push(format!(
+            "manage \"speed_only\" ({} also sets the channel mode)",
+            tool
+        ));
+    }
+    dropped
+        .into_iter()
+        .map(|setting| format!("{}: {} is dropped", curve_id(curve), setting))
+        .collect()
+}
+
+fn follows_cpu(config: &CurveConfig) -> bool {
+    matches!(config.temperature_source, TemperatureSource::Cpu { .. })
+}
+
+/// Both tools follow one CPU sensor, so anything else the config follows is
+/// left for the user to point the export at.
+fn source_warnings(config: &CurveConfig, tool: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if !follows_cpu(config) {
+        warnings.push(format!(
+            "temperature_source is {}, but the exported curves follow the CPU; point them at that sensor in {}",
+            config.temperature_source.name(),
+            tool
+        ));
+    }
+    if !config.fallback_sources.is_empty() {
+        let names: Vec<&str> = config
+            .fallback_sources
+            .iter()
+            .map(TemperatureSource::name)
+            .collect();
+        warnings.push(format!(
+            "fallback_sources ({}) are dropped; {} follows a single sensor",
+            names.join(", "),
+            tool
+        ));
+    }
+    warnings
+}
+
+fn to_coolercontrol(config: &CurveConfig) -> Export {
+    let mut warnings = Vec::new();
+    let curves = exportable_curves(config, &mut warnings);
+    for curve in &curves {
+        warnings.extend(dropped_settings(curve, "CoolerControl"));
+    }
+    warnings.extend(broadcast_warnings(&curves));
+    let mut text = String::new();
+
+    let _ = writeln!(
+        text,
+        "# Generated by uni-sync-curve export --format coolercontrol"
+    );
+    let _ = writeln!(
+        text,
+        "# CoolerControl assigns its own device uids; replace each \"<serial>\" device key"
+    );
+    let _ = writeln!(
+        text,
+        "# with the uid CoolerControl shows for that Lian Li UNI hub."
+    );
+
+    for (index, curve) in curves.iter().enumerate() {
+        let _ = writeln!(text);
+        let _ = writeln!(text, "[[profiles]]");
+        let _ = writeln!(text, "uid = \"uni-sync-curve-{}\"", index);
+        let _ = writeln!(text, "name = \"{}\"", curve_id(curve));
+        let _ = writeln!(text, "p_type = \"Graph\"");
+        let points = sorted_points(curve)
+            .iter()
+            .map(|(temp, speed)| format!("[{:.1}, {}]", temp, speed))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let _ = writeln!(text, "speed_profile = [{}]", points);
+        let _ = writeln!(
+            text,
+            "temp_source = {{ temp_name = \"CPU\", device_uid = \"cpu\" }}"
+        );
+    }
+
+    // TOML allows each table once, so every channel of a hub goes in its table
+    let mut devices: Vec<(&DeviceId, Vec<(usize, usize)>)> = Vec::new();
+    for (index, curve) in curves.iter().enumerate() {
+        let fans = channels(curve).into_iter().map(|channel| (channel, index));
+        match devices.iter_mut().find(|(id, _)| **id == curve.device_id) {
+            Some((_, device_fans)) => device_fans.extend(fans),
+            None => devices.push((&curve.device_id, fans.collect())),
+        }
+    }
+    for (device_id, fans) in devices {
+        let _ = writeln!(text);
+        let _ = writeln!(
+            text,
+            "# Lian Li UNI hub {:04x}:{:04x} serial {}",
+            device_id.0, device_id.1, device_id.2
+        );
+        let _ = writeln!(text, "[device-settings.\"{}\"]", device_id.2);
+        for (channel, index) in fans {
+            let _ = writeln!(
+                text,
+                "fan{} = {{ profile_uid = \"uni-sync-curve-{}\" }}",
+                channel + 1,
+                index
+            );
+        }
+    }
+
+    warnings.extend(source_warnings(config, "CoolerControl"));
+    if !curves.is_empty() && follows_cpu(config) {
+        warnings.push(
+            "temperature source is the hottest CPU sensor; CoolerControl profiles use a single \
+             sensor, mapped here to the \"CPU\" temp of the \"cpu\" device"
+                .to_string(),
+        );
+    }
+    warnings.push(format!(
+        "interval_seconds ({}) has no per-profile equivalent; set CoolerControl's poll rate instead",
+        config.interval_seconds
+    ));
+
+    Export { text, warnings }
+}
+
+fn to_fan2go(config: &CurveConfig) -> Export {
+    let mut warnings = Vec::new();
+    let curves = exportable_curves(config, &mut warnings);
+    for curve in &curves {
+        warnings.extend(dropped_settings(curve, "fan2go"));
+    }
+    warnings.extend(broadcast_warnings(&curves));
+    let mut text = String::new();
+
+    let _ = writeln!(text, "# Generated by uni-sync-curve export --format fan2go");
+    let _ = writeln!(
+        text,
+        "controllerAdjustmentTickRate: {}s",
+        config.interval_seconds
+    );
+    let _ = writeln!(text);
+    let _ = writeln!(text, "sensors:");
+    let _ = writeln!(text, "  - id: cpu");
+    let _ = writeln!(text, "    hwmon:");
+    let _ = writeln!(text, "      platform: coretemp");
+    let _ = writeln!(text, "      index: 1");
+
+    let _ = writeln!(text);
+    let _ = writeln!(text, "curves:");
+    for curve in &curves {
+        let _ = writeln!(text, "  - id: {}", curve_id(curve));
+        let _ = writeln!(text, "    linear:");
+        let _ = writeln!(text, "      sensor: cpu");
+        let _ = writeln!(text, "      steps:");
+        for (temp, speed) in sorted_points(curve) {
+            let _ = writeln!(text, "        - {}: {}", temp, percent_to_pwm(speed));
+        }
+    }
+
+    let _ = writeln!(text);
+    let _ = writeln!(text, "fans:");
+    for curve in &curves {
+        for channel in channels(curve) {
+            // Fan ids must be unique, so a broadcast curve's carry the channel
+            let fan_id = if curve.broadcast {
+                format!("{}-ch{}", curve_id(curve), channel)
+            } else {
+                curve_id(curve)
+            };
+            let _ = writeln!(
+                text,
+                "  # Lian Li UNI hub {:04x}:{:04x} serial {} channel {}",
+                curve.device_id.0, curve.device_id.1, curve.device_id.2, channel
+            );
+            let _ = writeln!(text, "  - id: {}", fan_id);
+            let _ = writeln!(text, "    cmd:");
+            let _ = writeln!(text, "      setPwm:");
+            let _ = writeln!(text, "        exec: /usr/local/bin/set-uni-fan-pwm");
+            let _ = writeln!(
+                text,
+                "        args: [\"{}\", \"{}\", \"%pwm%\"]",
+                curve.device_id.2, channel
+            );
+            let _ = writeln!(text, "    curve: {}", curve_id(curve));
+        }
+    }
+
+    warnings.extend(source_warnings(config, "fan2go"));
+    if !curves.is_empty() {
+        warnings.push(
+            "fan2go has no Lian Li UNI driver; fans are emitted as cmd fans calling a \
+             placeholder /usr/local/bin/set-uni-fan-pwm script"
+                .to_string(),
+        );
+    }
+    if !curves.is_empty() && follows_cpu(config) {
+        warnings.push(
+            "temperature source is the hottest CPU sensor; fan2go uses a single hwmon sensor, \
+             emitted as coretemp index 1 (use k10temp on AMD)"
+                .to_string(),
+        );
+    }
+
+    Export { text, warnings }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CurvePoint, DeviceId, SpeedPercent};
+
+    fn test_config() -> CurveConfig {
+        CurveConfig {
+            interval_seconds: 5,
+            fan_curves: vec![
+                FanCurve {
+                    device_id: DeviceId(0x0cf2, 0xa102, "ABC123".to_string()),
+                    channel: 1,
+                    mode: ChannelMode::Manual,
+                    curve_points: vec![
+                        CurvePoint {
+                            temperature_celsius: 60.0,
+                            fan_speed_percent: SpeedPercent::new(100).unwrap(),
+                        },
+                        CurvePoint {
+                            temperature_celsius: 30.0,
+                            fan_speed_percent: SpeedPercent::new(20).unwrap(),
+                        },
+                    ],
+                    ..Default::default()
+                },
+                FanCurve {
+                    device_id: DeviceId(0x0cf2, 0xa102, "ABC123".to_string()),
+                    channel: 2,
+                    mode: ChannelMode::PWM,
+                    curve_points: vec![],
+                    ..Default::default()
+                },
+                FanCurve {
+                    name: Some("spare".to_string()),
+                    device_id: DeviceId(0x0cf2, 0xa102, "ABC123".to_string()),
+                    channel: 3,
+                    curve_points: vec![CurvePoint {
+                        temperature_celsius: 40.0,
+                        fan_speed_percent: SpeedPercent::new(50).unwrap(),
+                    }],
+                    enabled: false,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_coolercontrol_export() {
+        let export = export(&test_config(), ExportFormat::Coolercontrol);
+
+        assert_eq!(
+            export.text,
+            r#"# Generated by uni-sync-curve export --format coolercontrol
+# CoolerControl assigns its own device uids; replace each "<serial>" device key
+# with the uid CoolerControl shows for that Lian Li UNI hub.
+
+[[profiles]]
+uid = "uni-sync-curve-0"
+name = "uni-a102-abc123-ch1"
+p_type = "Graph"
+speed_profile = [[30.0, 20], [60.0, 100]]
+temp_source = { temp_name = "CPU", device_uid = "cpu" }
+
+# Lian Li UNI hub 0cf2:a102 serial ABC123
+[device-settings."ABC123"]
+fan2 = { profile_uid = "uni-sync-curve-0" }
+"#
+        );
+        assert_eq!(export.warnings.len(), 4);
+        assert!(export.warnings[0].contains("PWM mode"));
+        assert_eq!(export.warnings[1], "spare: curve is disabled, skipped");
+    }
+
+    #[test]
+    fn test_coolercontrol_export_hub_channels() {
+        let hub = DeviceId(0x0cf2, 0xa103, "HUB1".to_string());
+        let curve = |name: &str, channel, speed| FanCurve {
+            name: Some(name.to_string()),
+            device_id: hub.clone(),
+            channel,
+            curve_points: vec![CurvePoint {
+                temperature_celsius: 40.0,
+                fan_speed_percent: SpeedPercent::new(speed).unwrap(),
+            }],
+            ..Default::default()
+        };
+        let config = CurveConfig {
+            interval_seconds: 2,
+            temperature_source: TemperatureSource::Gpu {
+                gpu: None,
+                sensor: Default::default(),
+                transform: None,
+                reading_latency_seconds: None,
+            },
+            fallback_sources: vec![TemperatureSource::default()],
+            fan_curves: vec![curve("front", 0, 30), curve("top", 2, 50)],
+            ..Default::default()
+        };
+        let export = export(&config, ExportFormat::Coolercontrol);
+
+        assert_eq!(
+            export.text,
+            r#"# Generated by uni-sync-curve export --format coolercontrol
+# CoolerControl assigns its own device uids; replace each "<serial>" device key
+# with the uid CoolerControl shows for that Lian Li UNI hub.
+
+[[profiles]]
+uid = "uni-sync-curve-0"
+name = "front"
+p_type = "Graph"
+speed_profile = [[40.0, 30]]
+temp_source = { temp_name = "CPU", device_uid = "cpu" }
+
+[[profiles]]
+uid = "uni-sync-curve-1"
+name = "top"
+p_type = "Graph"
+speed_profile = [[40.0, 50]]
+temp_source = { temp_name = "CPU", device_uid = "cpu" }
+
+# Lian Li UNI hub 0cf2:a103 serial HUB1
+[device-settings."HUB1"]
+fan1 = { profile_uid = "uni-sync-curve-0" }
+fan3 = { profile_uid = "uni-sync-curve-1" }
+"#
+        );
+        assert_eq!(export.warnings.len(), 3, "{:?}", export.warnings);
+        assert!(export.warnings[0].contains("temperature_source is gpu"));
+        assert!(export.warnings[1].contains("fallback_sources (cpu)"));
+        assert!(export.warnings[2].contains("interval_seconds"));
+    }
+
+    #[test]
+    fn test_broadcast_export() {
+        let config = CurveConfig {
+            fan_curves: vec![FanCurve {
+                name: Some("hub".to_string()),
+                device_id: DeviceId(0x0cf2, 0xa103, "HUB1".to_string()),
+                channel: 0,
+                curve_points: vec![CurvePoint {
+                    temperature_celsius: 40.0,
+                    fan_speed_percent: SpeedPercent::new(30).unwrap(),
+                }],
+                broadcast: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let coolercontrol = export(&config, ExportFormat::Coolercontrol);
+        assert!(coolercontrol.text.ends_with(
+            r#"[device-settings."HUB1"]
+fan1 = { profile_uid = "uni-sync-curve-0" }
+fan2 = { profile_uid = "uni-sync-curve-0" }
+fan3 = { profile_uid = "uni-sync-curve-0" }
+fan4 = { profile_uid = "uni-sync-curve-0" }
+"#
+        ));
+        assert_eq!(
+            coolercontrol.warnings[0],
+            "hub: broadcast is exported as the 4 channels of its device (UNI SL v2)"
+        );
+
+        let fan2go = export(&config, ExportFormat::Fan2go);
+        let fans: Vec<&str> = fan2go
+            .text
+            .lines()
+            .filter(|line| line.starts_with("  - id: hub"))
+            .collect();
+        assert_eq!(
+            fans,
+            [
+                "  - id: hub",
+                "  - id: hub-ch0",
+                "  - id: hub-ch1",
+                "  - id: hub-ch2",
+                "  - id: hub-ch3",
+            ]
+        );
+        assert!(fan2go.text.contains(r#"args: ["HUB1", "3", "%pwm%"]"#));
+        assert_eq!(fan2go.warnings[0], coolercontrol.warnings[0]);
+    }
+
+    #[test]
+    fn test_dropped_settings() {
+        let mut config = test_config();
+        let curve = &mut config.fan_curves[0];
+        curve.name = Some("front".to_string());
+        curve.curve_points_falling = Some(curve.curve_points.clone());
+        curve.above_max = AboveMax::Extrapolate;
+        curve.below_min = BelowMin::Zero;
+        curve.speed_multiplier = 1.2;
+        curve.speed_offset_percent = -5;
+        curve.min_speed_percent = Some(SpeedPercent::new(25).unwrap());
+        curve.max_speed_percent = Some(SpeedPercent::new(90).unwrap());
+        curve.group = Some("intake".to_string());
+        curve.slew_substeps = Some(4);
+        curve.manage = Manage::SpeedOnly;
+
+        for format in [ExportFormat::Coolercontrol, ExportFormat::Fan2go] {
+            let warnings: Vec<String> = export(&config, format)
+                .warnings
+                .into_iter()
+                .filter(|warning| warning.starts_with("front: "))
+                .collect();
+            assert_eq!(warnings.len(), 10, "{:?}", warnings);
+            assert!(warnings[0].starts_with("front: curve_points_falling"));
+            assert_eq!(
+                warnings[1],
+                r#"front: above_max "extrapolate" (held at the last point instead) is dropped"#
+            );
+            assert_eq!(
+                warnings[2],
+                r#"front: below_min "zero" (held at the first point instead) is dropped"#
+            );
+            assert_eq!(
+                warnings[3..9],
+                [
+                    "front: speed_multiplier 1.2 is dropped",
+                    "front: speed_offset_percent -5 is dropped",
+                    "front: min_speed_percent 25 is dropped",
+                    "front: max_speed_percent 90 is dropped",
+                    r#"front: group "intake" is dropped"#,
+                    "front: slew_substeps 4 is dropped",
+                ]
+            );
+            assert!(warnings[9].starts_with(r#"front: manage "speed_only" ("#));
+        }
+    }
+
+    #[test]
+    fn test_monitor_only_skipped() {
+        let mut config = test_config();
+        config.fan_curves[0].manage = Manage::MonitorOnly;
+
+        for format in [ExportFormat::Coolercontrol, ExportFormat::Fan2go] {
+            let export = export(&config, format);
+            assert!(!export.text.contains("uni-a102-abc123-ch1"));
+            assert_eq!(
+                export.warnings[0],
+                "uni-a102-abc123-ch1: curve is monitor-only, skipped"
+            );
+        }
+    }
+
+    #[test]
+    fn test_fan2go_export() {
+        let export = export(&test_config(), ExportFormat::Fan2go);
+
+        assert_eq!(
+            export.text,
+            r#"# Generated by uni-sync-curve export --format fan2go
+controllerAdjustmentTickRate: 5s
+
+sensors:
+  - id: cpu
+    hwmon:
+      platform: coretemp
+      index: 1
+
+curves:
+  - id: uni-a102-abc123-ch1
+    linear:
+      sensor: cpu
+      steps:
+        - 30: 51
+        - 60: 255
+
+fans:
+  # Lian Li UNI hub 0cf2:a102 serial ABC123 channel 1
+  - id: uni-a102-abc123-ch1
+    cmd:
+      setPwm:
+        exec: /usr/local/bin/set-uni-fan-pwm
+        args: ["ABC123", "1", "%pwm%"]
+    curve: uni-a102-abc123-ch1
+"#
+        );
+        assert_eq!(export.warnings.len(), 4);
+    }
+}
@@ -0,0 +1,343 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// Deepest an expression may nest; evaluation uses a stack this size, so a
+/// tick never allocates.
+pub const STACK_DEPTH: usize = 16;
+
+/// Deepest parentheses, function calls and unary minuses may nest. The
+/// parser recurses into each, so without a limit a long enough run of them
+/// overflows the thread's stack.
+const MAX_NESTING: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Number(f64),
+    X,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg,
+    Min,
+    Max,
+    Clamp,
+}
+
+impl Op {
+    // Values the op takes off the stack, and puts back
+    fn arity(self) -> (usize, usize) {
+        match self {
+            Op::Number(_) | Op::X => (0, 1),
+            Op::Neg => (1, 1),
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Min | Op::Max => (2, 1),
+            Op::Clamp => (3, 1),
+        }
+    }
+}
+
+/// An arithmetic expression over a source's raw value `x`, such as
+/// `x * 0.001 - 273.15`: numbers, `+ - * /`, parentheses and the functions
+/// `min(a, b)`, `max(a, b)` and `clamp(v, lo, hi)`. It is kept as the text
+/// the config gives and the ops parsed from it; text that doesn't parse
+/// keeps its error for `validate_config` to report with the setting's path.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transform {
+    text: String,
+    ops: Result<Vec<Op>, String>,
+}
+
+impl Transform {
+    pub fn parse(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            ops: Parser::new(text).parse(),
+        }
+    }
+
+    /// Why the text doesn't parse, if it doesn't.
+    pub fn error(&self) -> Option<&str> {
+        self.ops.as_ref().err().map(String::as_str)
+    }
+
+    /// The expression's value for `x`. An expression that didn't parse, which
+    /// validation rejects before the daemon runs, gives `x` unchanged.
+    pub fn eval(&self, x: f64) -> f64 {
+        let Ok(ops) = &self.ops else {
+            return x;
+        };
+        let mut stack = [0.0; STACK_DEPTH];
+        let mut len = 0;
+        for op in ops {
+            let (takes, _) = op.arity();
+            len -= takes;
+            let args = &stack[len..len + takes];
+            stack[len] = match *op {
+                Op::Number(value) => value,
+                Op::X => x,
+                Op::Add => args[0] + args[1],
+                Op::Sub => args[0] - args[1],
+                Op::Mul => args[0] * args[1],
+                Op::Div => args[0] / args[1],
+                Op::Neg => -args[0],
+                Op::Min => args[0].min(args[1]),
+                Op::Max => args[0].max(args[1]),
+                // Unlike f64::clamp, never panics on lo > hi
+                Op::Clamp => args[0].max(args[1]).min(args[2]),
+            };
+            len += 1;
+        }
+        stack[0]
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Serialize for Transform {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl<'de> Deserialize<'de> for Transform {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Transform::parse(&String::deserialize(deserializer)?))
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+    ops: Vec<Op>,
+    depth: usize,
+    nesting: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            position: 0,
+            ops: Vec::new(),
+            depth: 0,
+            nesting: 0,
+        }
+    }
+
+    fn parse(mut self) -> Result<Vec<Op>, String> {
+        self.expression()?;
+        match self.peek() {
+            None => Ok(self.ops),
+            Some(c) => Err(self.unexpected(c)),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.text[self.position..];
+        let trimmed = rest.trim_start();
+        self.position += rest.len() - trimmed.len();
+        trimmed.chars().next()
+    }
+
+    fn eat(&mut self, expected: char) -> Result<(), String> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += c.len_utf8();
+                Ok(())
+            }
+            Some(c) => Err(self.unexpected(c)),
+            None => Err(format!("expected '{}' at the end", expected)),
+        }
+    }
+
+    fn unexpected(&self, c: char) -> String {
+        format!("unexpected '{}' at {}", c, self.position + 1)
+    }
+
+    fn emit(&mut self, op: Op) -> Result<(), String> {
+        let (takes, gives) = op.arity();
+        self.depth = self.depth - takes + gives;
+        if self.depth > STACK_DEPTH {
+            return Err(format!("nested deeper than {} values", STACK_DEPTH));
+        }
+        self.ops.push(op);
+        Ok(())
+    }
+
+    // Runs `parse` one level deeper, failing past MAX_NESTING
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<(), String>,
+    ) -> Result<(), String> {
+        if self.nesting == MAX_NESTING {
+            return Err("nested too deeply".to_string());
+        }
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    // expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<(), String> {
+        self.term()?;
+        while let Some(c @ ('+' | '-')) = self.peek() {
+            self.position += 1;
+            self.term()?;
+            self.emit(if c == '+' { Op::Add } else { Op::Sub })?;
+        }
+        Ok(())
+    }
+
+    // term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<(), String> {
+        self.unary()?;
+        while let Some(c @ ('*' | '/')) = self.peek() {
+            self.position += 1;
+            self.unary()?;
+            self.emit(if c == '*' { Op::Mul } else { Op::Div })?;
+        }
+        Ok(())
+    }
+
+    // unary := '-' unary | primary
+    fn unary(&mut self) -> Result<(), String> {
+        if self.peek() == Some('-') {
+            self.position += 1;
+            self.nested(Self::unary)?;
+            return self.emit(Op::Neg);
+        }
+        self.primary()
+    }
+
+    // primary := number | 'x' | function '(' arguments ')' | '(' expression ')'
+    fn primary(&mut self) -> Result<(), String> {
+        match self.peek() {
+            None => Err("unexpected end".to_string()),
+            Some('(') => {
+                self.position += 1;
+                self.nested(Self::expression)?;
+                self.eat(')')
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.position;
+                let rest = &self.text[start..];
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                    .unwrap_or(rest.len());
+                self.position += len;
+                let value = rest[..len]
+                    .parse()
+                    .map_err(|_| format!("invalid number {:?} at {}", &rest[..len], start + 1))?;
+                self.emit(Op::Number(value))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let start = self.position;
+                let rest = &self.text[start..];
+                let len = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric())
+                    .unwrap_or(rest.len());
+                self.position += len;
+                let (op, arguments) = match &rest[..len] {
+                    "x" => return self.emit(Op::X),
+                    "min" => (Op::Min, 2),
+                    "max" => (Op::Max, 2),
+                    "clamp" => (Op::Clamp, 3),
+                    name => return Err(format!("unknown name {:?} at {}", name, start + 1)),
+                };
+                self.eat('(')?;
+                self.nested(|parser| {
+                    for index in 0..arguments {
+                        if index > 0 {
+                            parser.eat(',')?;
+                        }
+                        parser.expression()?;
+                    }
+                    parser.eat(')')
+                })?;
+                self.emit(op)
+            }
+            Some(c) => Err(self.unexpected(c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str, x: f64) -> f64 {
+        let transform = Transform::parse(text);
+        assert_eq!(transform.error(), None, "{}", text);
+        transform.eval(x)
+    }
+
+    #[test]
+    fn test_parse_and_eval() {
+        assert!((eval("x * 0.001 - 273.15", 323_150.0) - 50.0).abs() < 1e-9);
+        // Precedence and associativity
+        assert_eq!(eval("2 + 3 * x", 4.0), 14.0);
+        assert_eq!(eval("(2 + 3) * x", 4.0), 20.0);
+        assert_eq!(eval("x - 2 - 3", 10.0), 5.0);
+        assert_eq!(eval("x / 2 / 5", 100.0), 10.0);
+        assert_eq!(eval("-x * 2", 3.0), -6.0);
+        assert_eq!(eval("--x", 3.0), 3.0);
+        assert_eq!(eval("  x*2+1  ", 3.0), 7.0);
+        assert_eq!(eval("max(x, 20) + min(1, 2)", 10.0), 21.0);
+        assert_eq!(Transform::parse("x + 1").to_string(), "x + 1".to_string());
+    }
+
+    #[test]
+    fn test_clamp() {
+        assert_eq!(eval("clamp(x, 20, 90)", 10.0), 20.0);
+        assert_eq!(eval("clamp(x, 20, 90)", 50.0), 50.0);
+        assert_eq!(eval("clamp(x, 20, 90)", 120.0), 90.0);
+        assert_eq!(eval("clamp(x * 2, 0, 100) / 2", 80.0), 50.0);
+        // Bounds the wrong way round give the upper one rather than panic
+        assert_eq!(eval("clamp(x, 90, 20)", 50.0), 20.0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text: &str| Transform::parse(text).error().map(str::to_string);
+        assert_eq!(error("x *"), Some("unexpected end".to_string()));
+        assert_eq!(error("x + y"), Some("unknown name \"y\" at 5".to_string()));
+        assert_eq!(error("(x + 1"), Some("expected ')' at the end".to_string()));
+        assert_eq!(error("x 2"), Some("unexpected '2' at 3".to_string()));
+        assert_eq!(
+            error("clamp(x, 1)"),
+            Some("unexpected ')' at 11".to_string())
+        );
+        assert_eq!(
+            error("1.2.3"),
+            Some("invalid number \"1.2.3\" at 1".to_string())
+        );
+        assert_eq!(error("x ^ 2"), Some("unexpected '^' at 3".to_string()));
+        assert_eq!(error(""), Some("unexpected end".to_string()));
+        let deep = format!("{}x{}", "(1 + ".repeat(20), ")".repeat(20));
+        assert!(error(&deep).unwrap().contains("nested deeper"));
+        // Nesting that keeps few values on the stack still recursed once
+        // per level, and overflowed the thread's stack instead of failing
+        let nested = |open: &str, close: &str| {
+            error(&format!("{}x{}", open.repeat(5000), close.repeat(5000)))
+        };
+        assert_eq!(nested("(", ")"), Some("nested too deeply".to_string()));
+        assert_eq!(nested("-", ""), Some("nested too deeply".to_string()));
+        assert_eq!(
+            nested("max(", ", 1)"),
+            Some("nested too deeply".to_string())
+        );
+        assert_eq!(
+            eval(&format!("{}x{}", "(".repeat(64), ")".repeat(64)), 2.0),
+            2.0
+        );
+        // The text survives a failed parse, and x passes through
+        let broken = Transform::parse("x +");
+        assert_eq!(broken.eval(42.0), 42.0);
+        assert_eq!(serde_json::to_string(&broken).unwrap(), "\"x +\"");
+    }
+}
@@ -0,0 +1,317 @@
+//! What the fuzz target under `fuzz/` runs, which includes this file and the
+//! modules it needs by path. In this crate it is built for the tests only,
+//! which keep the regression inputs the fuzzer turned up.
+
+use anyhow::Result;
+
+use crate::config::{self, CurveConfig, SpeedPercent};
+use crate::curve;
+
+/// Temperatures every parsed curve is evaluated at, the odd ones included.
+const PROBE_TEMPERATURES: [f64; 9] = [
+    f64::NEG_INFINITY,
+    -273.15,
+    0.0,
+    35.5,
+    70.0,
+    1e9,
+    f64::INFINITY,
+    f64::NAN,
+    -0.0,
+];
+
+/// Stack `run_config` must fit in, far below a thread's default, so input
+/// that makes parsing recurse once per level of nesting overflows it at
+/// lengths the fuzzer tries rather than only at ones it never reaches.
+pub const STACK_SIZE: usize = 256 * 1024;
+
+/// `run_config` on a thread with a STACK_SIZE stack. Input that needs more
+/// aborts the process, which the fuzzer reports like a panic.
+pub fn run_config_on_small_stack(data: &[u8]) -> Result<CurveConfig> {
+    let data = data.to_vec();
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run_config(&data))?
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+/// Takes `data` through everything a hand-edited config file goes through
+/// before the daemon drives fans with it: parsing, validation, unit
+/// conversion, resolution against the devices it names and evaluation of
+/// every curve. Must never panic; anything wrong with `data` is an error.
+pub fn run_config(data: &[u8]) -> Result<CurveConfig> {
+    let config = config::parse_config(data)?;
+    let mut devices: Vec<config::DeviceId> = config
+        .fan_curves
+        .iter()
+        .map(|fan_curve| fan_curve.device_id.clone())
+        .collect();
+    devices.sort();
+    devices.dedup();
+    let (resolved, _notes) =
+        config::resolve_config(&config, &devices, |_| true, |_, _| None, |_| 4);
+    config::validate_config(&resolved)?;
+    let _ = config::validation_warnings(&resolved);
+    for fan_curve in &resolved.fan_curves {
+        for temperature in PROBE_TEMPERATURES {
+            for fallback in [SpeedPercent::ZERO, resolved.fallback_speed_percent] {
+                let duty = curve::calculate_fan_duty(fan_curve, temperature, fallback);
+                let duty = curve::adjust_duty(fan_curve, duty);
+                curve::quantize(duty);
+            }
+            curve::calculate_anticipated_duty(
+                fan_curve,
+                temperature,
+                Some(temperature),
+                false,
+                resolved.fallback_speed_percent,
+            );
+            curve::calculate_throttle_duty(fan_curve, temperature);
+        }
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inputs that once panicked or hung somewhere in `run_config`, with
+    /// whether they should parse.
+    const REGRESSIONS: &[(&str, bool)] = &[
+        // Points at one temperature: interpolating between them divided by
+        // zero, and extrapolating above them gave NaN
+        (
+            r#"{"interval_seconds": 5, "fan_curves": [{"device_id": [3314, 41219, "A"], "channel": 0,
+                "mode": "Manual", "above_max": "extrapolate", "curve_points": [
+                {"temperature_celsius": 40, "fan_speed_percent": 30},
+                {"temperature_celsius": 40, "fan_speed_percent": 60}]}]}"#,
+            true,
+        ),
+        // A Fahrenheit curve whose conversion overflows to infinity
+        (
+            r#"{"interval_seconds": 5, "fan_curves": [{"device_id": [3314, 41219, "A"], "channel": 0,
+                "mode": "Manual", "temperature_unit": "fahrenheit", "curve_points": [
+                {"temperature_celsius": 1.7e308, "fan_speed_percent": 30},
+                {"temperature_celsius": -1.7e308, "fan_speed_percent": 60}]}]}"#,
+            false,
+        ),
+        // Durations past what an Instant can hold
+        (
+            r#"{"interval_seconds": 18446744073709551615, "fan_curves": []}"#,
+            false,
+        ),
+        (
+            r#"{"interval_seconds": 5, "fan_curves": [], "shutdown_timeout_seconds": 18446744073709551615}"#,
+            false,
+        ),
+        // A transform nested thousands of parentheses deep overflowed the
+        // parser's stack
+        (
+            include_str!("../tests/regressions/deep-transform.json"),
+            false,
+        ),
+        (r#"{"interval_seconds": 5, "fan_curves": []}"#, true),
+        ("", false),
+        ("\u{feff}{}", false),
+        (r#"{"interval_seconds": -1, "fan_curves": []}"#, false),
+        (r#"[{"interval_seconds": 5}]"#, false),
+    ];
+
+    #[test]
+    fn test_config_regressions() {
+        for (input, parses) in REGRESSIONS {
+            let result = run_config_on_small_stack(input.as_bytes());
+            assert_eq!(result.is_ok(), *parses, "{}: {:?}", input, result.err());
+        }
+    }
+
+    /// xorshift64*, so the generated configs are reproducible without extra
+    /// crates.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn pick<'a>(&mut self, values: &[&'a str]) -> &'a str {
+            values[(self.next() % values.len() as u64) as usize]
+        }
+    }
+
+    /// Parentheses as deep as a transform may nest them, with the unary
+    /// minuses and calls that recurse too.
+    const DEEP_TRANSFORM: &str = concat!(
+        "((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((",
+        "-max(-(x), 1)",
+        "))))))))))))))))))))))))))))))))))))))))))))))))))))))))))))",
+    );
+
+    const NUMBERS: &[&str] = &[
+        "0",
+        "-0.0",
+        "1",
+        "-1",
+        "40",
+        "40",
+        "100",
+        "101",
+        "255",
+        "1e308",
+        "-1e308",
+        "1.7976931348623157e308",
+        "4294967296",
+        "18446744073709551615",
+        "5e-324",
+        "0.1",
+    ];
+
+    /// Mostly ordinary numbers, so most configs get past parsing to the
+    /// curves.
+    fn number(rng: &mut Rng) -> String {
+        if rng.next().is_multiple_of(4) {
+            rng.pick(NUMBERS).to_string()
+        } else {
+            rng.pick(&["1", "5", "30", "40", "55", "70", "90"])
+                .to_string()
+        }
+    }
+
+    fn points(rng: &mut Rng, first: &str, second: &str) -> String {
+        let count = rng.next() % 4;
+        let points: Vec<String> = (0..count)
+            .map(|_| {
+                format!(
+                    r#"{{"{}": {}, "{}": {}}}"#,
+                    first,
+                    number(rng),
+                    second,
+                    number(rng)
+                )
+            })
+            .collect();
+        format!("[{}]", points.join(", "))
+    }
+
+    /// A config with the right shape and arbitrary, mostly extreme values.
+    fn weird_config(rng: &mut Rng) -> String {
+        let curves: Vec<String> = (0..1 + rng.next() % 3)
+            .map(|_| {
+                let mut fields = vec![
+                    format!(r#""device_id": [3314, {}, "A"]"#, rng.pick(&["41219", "41216"])),
+                    format!(r#""channel": {}"#, rng.pick(&["0", "3", "255"])),
+                    format!(
+                        r#""mode": "{}""#,
+                        rng.pick(&["Manual", "Manual", "PWM", "Auto"])
+                    ),
+                    format!(
+                        r#""curve_points": {}"#,
+                        points(rng, "temperature_celsius", "fan_speed_percent")
+                    ),
+                ];
+                let optional = [
+                    format!(
+                        r#""curve_points_falling": {}"#,
+                        points(rng, "temperature_celsius", "fan_speed_percent")
+                    ),
+                    format!(
+                        r#""throttle_points": {}"#,
+                        points(rng, "pressure_percent", "fan_speed_percent")
+                    ),
+                    format!(r#""speed_multiplier": {}"#, number(rng)),
+                    format!(r#""speed_offset_percent": {}"#, number(rng)),
+                    format!(r#""min_speed_percent": {}"#, number(rng)),
+                    format!(r#""max_speed_percent": {}"#, number(rng)),
+                    format!(
+                        r#""above_max": "{}""#,
+                        rng.pick(&["clamp", "full_speed", "extrapolate"])
+                    ),
+                    format!(r#""below_min": "{}""#, rng.pick(&["clamp", "zero"])),
+                    format!(
+                        r#""temperature_unit": "{}""#,
+                        rng.pick(&["celsius", "fahrenheit"])
+                    ),
+                    format!(r#""broadcast": {}"#, rng.pick(&["true", "false"])),
+                    format!(r#""never_stop": {}"#, rng.pick(&["true", "false"])),
+                    format!(
+                        r#""anticipation": {{"degrees_per_second_gain": {}, "max_boost_percent": {}}}"#,
+                        number(rng),
+                        number(rng)
+                    ),
+                ];
+                fields.extend(optional.into_iter().filter(|_| rng.next().is_multiple_of(3)));
+                format!("{{{}}}", fields.join(", "))
+            })
+            .collect();
+        let mut fields = vec![
+            format!(r#""interval_seconds": {}"#, number(rng)),
+            format!(r#""fan_curves": [{}]"#, curves.join(", ")),
+        ];
+        let optional = [
+            format!(r#""fallback_speed_percent": {}"#, number(rng)),
+            format!(r#""max_extrapolation_celsius": {}"#, number(rng)),
+            format!(r#""shutdown_timeout_seconds": {}"#, number(rng)),
+            format!(r#""startup_ramp_seconds": {}"#, number(rng)),
+            format!(r#""reconcile_every_seconds": {}"#, number(rng)),
+            format!(r#""journal_entries": {}"#, number(rng)),
+            format!(r#""timer_jitter_percent": {}"#, number(rng)),
+            format!(
+                r#""temperature_source": {{"type": "cpu", "transform": "{}"}}"#,
+                rng.pick(&[
+                    "x / 0",
+                    "x * 1e308 * 10",
+                    "-x",
+                    "x +",
+                    DEEP_TRANSFORM,
+                    &DEEP_TRANSFORM[..DEEP_TRANSFORM.len() / 2],
+                ])
+            ),
+        ];
+        fields.extend(
+            optional
+                .into_iter()
+                .filter(|_| rng.next().is_multiple_of(3)),
+        );
+        format!("{{{}}}", fields.join(", "))
+    }
+
+    /// The fuzz target's seeds, each nested deeply one way.
+    const SEEDS: &[&str] = &[
+        include_str!("../fuzz/seeds/config/deep-parentheses.json"),
+        include_str!("../fuzz/seeds/config/deep-minus.json"),
+        include_str!("../fuzz/seeds/config/deep-calls.json"),
+        include_str!("../fuzz/seeds/config/deep-json.json"),
+    ];
+
+    #[test]
+    fn test_deep_nesting_fits_the_stack() {
+        assert_eq!(crate::expr::Transform::parse(DEEP_TRANSFORM).error(), None);
+        for seed in SEEDS {
+            assert!(
+                run_config_on_small_stack(seed.as_bytes()).is_err(),
+                "{}",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn test_weird_configs_never_panic() {
+        let mut rng = Rng(0x5eed_cafe_f00d_0001);
+        let mut parsed = 0;
+        for _ in 0..5000 {
+            let config = weird_config(&mut rng);
+            parsed += run_config_on_small_stack(config.as_bytes()).is_ok() as usize;
+            // Cut short, so half a config goes through the parser too
+            let cut = (rng.next() % (config.len() as u64 + 1)) as usize;
+            let _ = run_config_on_small_stack(&config.as_bytes()[..cut]);
+        }
+        // Enough get through validation to reach the curves
+        assert!(parsed > 100, "only {} configs parsed", parsed);
+    }
+}
@@ -0,0 +1,399 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// hwmon drivers of GPUs that report temperatures
+const GPU_DRIVERS: [&str; 5] = ["amdgpu", "radeon", "nouveau", "i915", "xe"];
+
+/// Which of a GPU's temperature sensors to read.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GpuSensorKind {
+    #[default]
+    Edge,
+    /// Hotspot; amdgpu only.
+    Junction,
+    Memory,
+}
+
+impl GpuSensorKind {
+    /// Maps a hwmon tempN_label; unlabelled sensors count as edge.
+    fn from_label(label: Option<&str>) -> Option<Self> {
+        match label {
+            None | Some("edge") | Some("GPU core") => Some(GpuSensorKind::Edge),
+            Some("junction") => Some(GpuSensorKind::Junction),
+            Some("mem") => Some(GpuSensorKind::Memory),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Picks one GPU: by its position in `list-sensors`, or by PCI bus id.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum GpuSelector {
+    Index(usize),
+    BusId(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gpu {
+    pub index: usize,
+    /// e.g. "0000:03:00.0"
+    pub pci_bus_id: String,
+    pub driver: String,
+    pub temperatures: Vec<(GpuSensorKind, f64)>,
+}
+
+impl Gpu {
+    pub fn matches(&self, selector: &GpuSelector) -> bool {
+        match selector {
+            GpuSelector::Index(index) => self.index == *index,
+            GpuSelector::BusId(bus_id) => self.pci_bus_id.eq_ignore_ascii_case(bus_id.trim()),
+        }
+    }
+
+    pub fn temperature(&self, kind: GpuSensorKind) -> Option<f64> {
+        self.temperatures
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, temperature)| *temperature)
+    }
+}
+
+/// Finds GPUs among the hwmon devices under `root`, in PCI bus order.
+pub fn discover(root: &Path) -> Vec<Gpu> {
+    let Ok(entries) = fs::read_dir(root) else {
+        return Vec::new();
+    };
+
+    let mut gpus: Vec<Gpu> = entries
+        .flatten()
+        .filter_map(|entry| read_gpu(&entry.path()))
+        .collect();
+    gpus.sort_by(|a, b| a.pci_bus_id.cmp(&b.pci_bus_id));
+    for (index, gpu) in gpus.iter_mut().enumerate() {
+        gpu.index = index;
+    }
+    gpus
+}
+
+// tempN files looked at on each GPU
+const SENSORS_PER_GPU: usize = 8;
+
+/// A GPU's tempN sensor, in °C, when it has one we know the kind of.
+fn read_sensor(hwmon: &Path, n: usize) -> Option<(GpuSensorKind, f64)> {
+    let raw = fs::read_to_string(hwmon.join(format!("temp{}_input", n))).ok()?;
+    let millidegrees = raw.trim().parse::<f64>().ok()?;
+    let label = fs::read_to_string(hwmon.join(format!("temp{}_label", n))).ok();
+    let kind = GpuSensorKind::from_label(label.as_deref().map(str::trim))?;
+    Some((kind, millidegrees / 1000.0))
+}
+
+fn pci_bus_id(hwmon: &Path) -> Option<String> {
+    let device = fs::canonicalize(hwmon.join("device")).ok()?;
+    Some(device.file_name()?.to_str()?.to_string())
+}
+
+fn read_gpu(hwmon: &Path) -> Option<Gpu> {
+    let driver = fs::read_to_string(hwmon.join("name"))
+        .ok()?
+        .trim()
+        .to_string();
+    if !GPU_DRIVERS.contains(&driver.as_str()) {
+        return None;
+    }
+    let pci_bus_id = pci_bus_id(hwmon)?;
+
+    let temperatures = (1..=SENSORS_PER_GPU)
+        .filter_map(|n| read_sensor(hwmon, n))
+        .collect();
+
+    Some(Gpu {
+        index: 0,
+        pci_bus_id,
+        driver,
+        temperatures,
+    })
+}
+
+/// The selected GPU's sensor, or the hottest such sensor over all GPUs when
+/// none is selected. None when the GPU is gone, e.g. during a driver reload.
+pub fn read_temperature(
+    root: &Path,
+    selector: Option<&GpuSelector>,
+    kind: GpuSensorKind,
+) -> Option<f64> {
+    discover(root)
+        .iter()
+        .filter(|gpu| selector.is_none_or(|selector| gpu.matches(selector)))
+        .filter_map(|gpu| gpu.temperature(kind))
+        .reduce(f64::max)
+}
+
+/// Where GPU sensors picked by bus id were found, kept next to the status
+/// file so a restart needn't walk every hwmon device again.
+pub const SENSOR_CACHE_FILE_NAME: &str = "sensor-cache.json";
+
+pub fn sensor_cache_path(status_path: &Path) -> PathBuf {
+    status_path.with_file_name(SENSOR_CACHE_FILE_NAME)
+}
+
+/// How long a sensor that couldn't be found is left before looking again,
+/// e.g. while its driver is still loading at boot.
+pub const RESOLVE_RETRY: Duration = Duration::from_secs(10);
+
+/// One sensor file of one GPU.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct ResolvedSensor {
+    hwmon: PathBuf,
+    n: usize,
+    pci_bus_id: String,
+}
+
+impl ResolvedSensor {
+    /// The reading, if the path still leads to the same GPU's `kind`
+    /// sensor. hwmon numbers change when drivers load in another order.
+    fn read(&self, kind: GpuSensorKind) -> Option<f64> {
+        if !pci_bus_id(&self.hwmon)?.eq_ignore_ascii_case(&self.pci_bus_id) {
+            return None;
+        }
+        let (found, temperature) = read_sensor(&self.hwmon, self.n)?;
+        (found == kind).then_some(temperature)
+    }
+}
+
+/// The sensor files of GPU sources picked by bus id, by source key, so a
+/// read goes straight to the file. An entry that no longer reads back as
+/// its sensor is dropped and looked for again.
+#[derive(Debug)]
+pub struct SensorCache {
+    path: PathBuf,
+    resolved: BTreeMap<String, ResolvedSensor>,
+    failed_at: HashMap<String, Instant>,
+}
+
+impl SensorCache {
+    /// Loads the cache kept at `path`; a missing or unreadable file gives
+    /// an empty one.
+    pub fn load(path: &Path) -> Self {
+        let resolved = fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        Self {
+            path: path.to_path_buf(),
+            resolved,
+            failed_at: HashMap::new(),
+        }
+    }
+
+    /// The `kind` sensor of the GPU at `bus_id`, found under `root` if the
+    /// cache has no valid entry for `key`. A sensor that can't be found is
+    /// looked for again RESOLVE_RETRY later.
+    pub fn read(
+        &mut self,
+        root: &Path,
+        key: &str,
+        bus_id: &str,
+        kind: GpuSensorKind,
+        now: Instant,
+    ) -> Option<f64> {
+        if let Some(resolved) = self.resolved.get(key) {
+            if let Some(temperature) = resolved.read(kind) {
+                return Some(temperature);
+            }
+            self.resolved.remove(key);
+            self.save();
+        }
+        if self
+            .failed_at
+            .get(key)
+            .is_some_and(|failed_at| now.saturating_duration_since(*failed_at) < RESOLVE_RETRY)
+        {
+            return None;
+        }
+
+        let found = fs::read_dir(root).ok()?.flatten().find_map(|entry| {
+            let hwmon = entry.path();
+            let gpu = read_gpu(&hwmon)
+                .filter(|gpu| gpu.pci_bus_id.eq_ignore_ascii_case(bus_id.trim()))?;
+            (1..=SENSORS_PER_GPU).find_map(|n| {
+                let resolved = ResolvedSensor {
+                    hwmon: hwmon.clone(),
+                    n,
+                    pci_bus_id: gpu.pci_bus_id.clone(),
+                };
+                Some((resolved.read(kind)?, resolved))
+            })
+        });
+        let Some((temperature, resolved)) = found else {
+            self.failed_at.insert(key.to_string(), now);
+            return None;
+        };
+        self.failed_at.remove(key);
+        self.resolved.insert(key.to_string(), resolved);
+        self.save();
+        Some(temperature)
+    }
+
+    // Best effort: without the file, the next start walks hwmon again
+    fn save(&self) {
+        if let Ok(text) = serde_json::to_string_pretty(&self.resolved) {
+            let _ = fs::write(&self.path, text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fake_gpu(root: &Path, hwmon: &str, bus_id: &str, driver: &str, temps: &[(&str, &str)]) {
+        let device = root.join("devices").join(bus_id);
+        fs::create_dir_all(&device).unwrap();
+        let hwmon = root.join("hwmon").join(hwmon);
+        fs::create_dir_all(&hwmon).unwrap();
+        std::os::unix::fs::symlink(&device, hwmon.join("device")).unwrap();
+        fs::write(hwmon.join("name"), format!("{}\n", driver)).unwrap();
+        for (n, (label, millidegrees)) in temps.iter().enumerate() {
+            fs::write(hwmon.join(format!("temp{}_input", n + 1)), millidegrees).unwrap();
+            fs::write(hwmon.join(format!("temp{}_label", n + 1)), label).unwrap();
+        }
+    }
+
+    fn fake_root() -> PathBuf {
+        let root = std::env::temp_dir().join(format!("uni-sync-curve-gpu-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fake_gpu(
+            &root,
+            "hwmon4",
+            "0000:0a:00.0",
+            "amdgpu",
+            &[("edge", "51000"), ("junction", "63000"), ("mem", "70000")],
+        );
+        fake_gpu(
+            &root,
+            "hwmon2",
+            "0000:03:00.0",
+            "amdgpu",
+            &[("edge", "40000"), ("junction", "44000"), ("mem", "80000")],
+        );
+        fake_gpu(&root, "hwmon1", "0000:00:1f.3", "nct6775", &[("", "30000")]);
+        root
+    }
+
+    #[test]
+    fn test_gpu_selection() {
+        let root = fake_root();
+        let hwmon = root.join("hwmon");
+
+        let gpus = discover(&hwmon);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].pci_bus_id, "0000:03:00.0");
+        assert_eq!(gpus[1].index, 1);
+        assert_eq!(gpus[1].temperature(GpuSensorKind::Junction), Some(63.0));
+
+        assert_eq!(
+            read_temperature(&hwmon, None, GpuSensorKind::Edge),
+            Some(51.0)
+        );
+        assert_eq!(
+            read_temperature(&hwmon, Some(&GpuSelector::Index(0)), GpuSensorKind::Edge),
+            Some(40.0)
+        );
+        let by_bus: GpuSelector = serde_json::from_str(r#""0000:0A:00.0""#).unwrap();
+        assert_eq!(
+            read_temperature(&hwmon, Some(&by_bus), GpuSensorKind::Memory),
+            Some(70.0)
+        );
+
+        // The GPU goes away
+        fs::remove_dir_all(hwmon.join("hwmon4")).unwrap();
+        assert_eq!(
+            read_temperature(&hwmon, Some(&by_bus), GpuSensorKind::Memory),
+            None
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_sensor_cache() {
+        let root = std::env::temp_dir().join(format!(
+            "uni-sync-curve-sensor-cache-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        let temps = |edge| [("edge", edge), ("junction", "60000")];
+        fake_gpu(&root, "hwmon4", "0000:0a:00.0", "amdgpu", &temps("51000"));
+        fake_gpu(&root, "hwmon2", "0000:03:00.0", "amdgpu", &temps("40000"));
+        let hwmon = root.join("hwmon");
+        let path = root.join(SENSOR_CACHE_FILE_NAME);
+        let key = "gpu 0000:0a:00.0 junction";
+        let now = Instant::now();
+
+        let mut cache = SensorCache::load(&path);
+        assert_eq!(
+            cache.read(&hwmon, key, "0000:0a:00.0", GpuSensorKind::Junction, now),
+            Some(60.0)
+        );
+        let cached = cache.resolved[key].clone();
+        assert_eq!(
+            (cached.hwmon.file_name().unwrap(), cached.n),
+            ("hwmon4".as_ref(), 2)
+        );
+
+        // A restart reads the entry straight from the file, without a walk
+        let mut restarted = SensorCache::load(&path);
+        assert_eq!(restarted.resolved[key], cached);
+        assert_eq!(
+            restarted.read(
+                &root.join("missing"),
+                key,
+                "0000:0a:00.0",
+                GpuSensorKind::Junction,
+                now
+            ),
+            Some(60.0)
+        );
+
+        // Renumbered: hwmon4 is now the other GPU, so the entry is stale
+        fs::rename(hwmon.join("hwmon4"), hwmon.join("hwmon7")).unwrap();
+        fs::rename(hwmon.join("hwmon2"), hwmon.join("hwmon4")).unwrap();
+        let edge = "gpu 0000:0a:00.0 edge";
+        assert_eq!(
+            restarted.read(&hwmon, edge, "0000:0a:00.0", GpuSensorKind::Edge, now),
+            Some(51.0)
+        );
+        assert_eq!(
+            restarted.read(&hwmon, key, "0000:0a:00.0", GpuSensorKind::Junction, now),
+            Some(60.0)
+        );
+        assert_eq!(restarted.resolved[key].hwmon.file_name().unwrap(), "hwmon7");
+        assert_eq!(SensorCache::load(&path).resolved, restarted.resolved);
+
+        // Gone at boot: looked for again only after RESOLVE_RETRY
+        fs::remove_dir_all(hwmon.join("hwmon7")).unwrap();
+        assert_eq!(
+            restarted.read(&hwmon, key, "0000:0a:00.0", GpuSensorKind::Junction, now),
+            None
+        );
+        fake_gpu(&root, "hwmon9", "0000:0a:00.0", "amdgpu", &temps("51000"));
+        let read_at = |cache: &mut SensorCache, later| {
+            cache.read(
+                &hwmon,
+                key,
+                "0000:0a:00.0",
+                GpuSensorKind::Junction,
+                now + later,
+            )
+        };
+        assert_eq!(read_at(&mut restarted, Duration::from_secs(1)), None);
+        assert_eq!(read_at(&mut restarted, RESOLVE_RETRY), Some(60.0));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
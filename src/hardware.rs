@@ -1,155 +1,3084 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use hidapi::{self, HidDevice};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::CString;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use sysinfo::Components;
-use tokio::time;
+use tokio::time::{self, Instant};
 
-use crate::config::{ChannelMode, DeviceId};
+use crate::backend::{Command, DeviceMetadata, DeviceWriter, FanBackend, HardwareError, Report};
+use crate::budget::{Admission, WriteBudget};
+use crate::capture::{CaptureBackend, CaptureSink};
+use crate::config::{
+    self, ChannelMode, DeviceId, LightingControl, Manage, PermissionPolicy, SpeedPercent,
+    WriteBudgetSettings,
+};
+use crate::engine::SpeedReason;
+use crate::io_thread::DeviceThread;
+use crate::monitor::ReadOnlyBackend;
+use crate::presets::{self, SensorPreset};
+use crate::quarantine::{Quarantine, PROBE_BACKOFF, QUARANTINE_AFTER_FAILURES, RESET_AFTER_PROBES};
+use crate::ring::{BufferUsage, RingBuffer};
+use crate::sysfs::{self, SysfsPwmBackend};
 
 // Lian Li Uni-Sync Fans - Vendor ID and Product IDs
 const VENDOR_IDS: [u16; 1] = [0x0cf2];
 const PRODUCT_IDS: [u16; 7] = [0x7750, 0xa100, 0xa101, 0xa102, 0xa103, 0xa104, 0xa105];
 
-pub struct FanController {
-    hidapi: hidapi::HidApi,
-    device_configs: HashMap<DeviceId, hidapi::DeviceInfo>,
+/// Which HID devices discovery picks up. The default takes every UNI hub.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceFilter {
+    pub vendor_ids: HashSet<u16>,
+    pub product_ids: HashSet<u16>,
+    /// Only devices with these serials, compared like DeviceId serials;
+    /// None takes every serial.
+    pub serials: Option<HashSet<String>>,
 }
 
-impl FanController {
-    pub fn new() -> Result<Self> {
-        let hidapi = hidapi::HidApi::new()?;
-        let device_configs = hidapi
+impl Default for DeviceFilter {
+    fn default() -> Self {
+        Self {
+            vendor_ids: VENDOR_IDS.into(),
+            product_ids: PRODUCT_IDS.into(),
+            serials: None,
+        }
+    }
+}
+
+impl DeviceFilter {
+    /// Narrows the filter down to devices with one of `serials`.
+    pub fn with_serials<'a>(mut self, serials: impl IntoIterator<Item = &'a str>) -> Self {
+        self.serials = Some(serials.into_iter().map(config::normalize_serial).collect());
+        self
+    }
+
+    pub fn matches(&self, vendor_id: u16, product_id: u16, serial: &str) -> bool {
+        self.vendor_ids.contains(&vendor_id)
+            && self.product_ids.contains(&product_id)
+            && self
+                .serials
+                .as_ref()
+                .is_none_or(|serials| serials.contains(&config::normalize_serial(serial)))
+    }
+}
+
+/// Devices that appeared and disappeared between two scans.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceChanges {
+    pub added: Vec<DeviceId>,
+    pub removed: Vec<DeviceId>,
+}
+
+impl DeviceChanges {
+    pub fn between(before: &[DeviceId], after: &[DeviceId]) -> Self {
+        Self {
+            added: after
+                .iter()
+                .filter(|device_id| !before.contains(device_id))
+                .cloned()
+                .collect(),
+            removed: before
+                .iter()
+                .filter(|device_id| !after.contains(device_id))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Lian Li UNI hubs, driven over HID.
+pub struct HidBackend {
+    // Shared with the I/O threads, which open their devices through it;
+    // hidapi isn't thread-safe, so opening and enumerating take turns
+    hidapi: Arc<Mutex<hidapi::HidApi>>,
+    filter: DeviceFilter,
+    device_configs: HashMap<DeviceId, HidEntry>,
+}
+
+struct HidEntry {
+    info: hidapi::DeviceInfo,
+    metadata: DeviceMetadata,
+}
+
+// Some hubs report empty or padded strings; treat those as missing
+fn report_string(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+impl HidBackend {
+    pub fn with_filter(filter: DeviceFilter) -> Result<Self> {
+        let mut backend = Self {
+            hidapi: Arc::new(Mutex::new(hidapi::HidApi::new()?)),
+            filter,
+            device_configs: HashMap::new(),
+        };
+        backend.collect_devices();
+        Ok(backend)
+    }
+
+    // Picks the filter's devices out of hidapi's last enumeration
+    fn collect_devices(&mut self) {
+        let filter = &self.filter;
+        let hidapi = self.hidapi.lock().unwrap();
+        let found = hidapi
             .device_list()
             .filter_map(|d| {
-                if VENDOR_IDS.contains(&d.vendor_id()) && PRODUCT_IDS.contains(&d.product_id()) {
-                    Some((
-                        DeviceId(
-                            d.vendor_id(),
-                            d.product_id(),
-                            d.serial_number()?.to_string(),
-                        ),
-                        d.clone(),
-                    ))
-                } else {
-                    None
+                let serial = d.serial_number()?.trim();
+                if !filter.matches(d.vendor_id(), d.product_id(), serial) {
+                    return None;
                 }
+                let path = d.path().to_string_lossy();
+                let port_path = usb_port(&path).map(str::to_string);
+                Some((
+                    DeviceId(d.vendor_id(), d.product_id(), serial.to_string()),
+                    port_path.clone().unwrap_or_else(|| path.to_string()),
+                    HidEntry {
+                        info: d.clone(),
+                        metadata: DeviceMetadata {
+                            manufacturer: report_string(d.manufacturer_string()),
+                            product: report_string(d.product_string()),
+                            release_number: d.release_number(),
+                            port_path,
+                        },
+                    },
+                ))
             })
             .collect();
+        drop(hidapi);
+        let (devices, warnings) = disambiguate(found);
+        for warning in warnings {
+            eprintln!("==================== WARNING ====================");
+            eprintln!("{}", warning);
+            eprintln!("=================================================");
+        }
+        self.device_configs = devices.into_iter().collect();
+    }
+}
 
-        Ok(Self {
-            hidapi,
-            device_configs,
+/// The USB port part of a HID path, e.g. "1-4.2" of "1-4.2:1.0" (libusb) or
+/// of "/sys/devices/.../usb1/1-4/1-4.2/1-4.2:1.0/..." (hidraw). Paths
+/// without one, such as on macOS and Windows, have none.
+fn usb_port(path: &str) -> Option<&str> {
+    let is_port = |part: &str| {
+        part.split_once('-').is_some_and(|(bus, ports)| {
+            !bus.is_empty()
+                && bus.chars().all(|c| c.is_ascii_digit())
+                && !ports.is_empty()
+                && ports.chars().all(|c| c.is_ascii_digit() || c == '.')
         })
+    };
+    path.split('/')
+        .filter_map(|component| component.split(':').next())
+        .rev()
+        .find(|part| is_port(part))
+}
+
+// Orders "1-10" after "1-9", comparing numbers rather than text
+fn port_order(port: &str) -> Vec<u32> {
+    port.split(|c: char| !c.is_ascii_digit())
+        .filter_map(|number| number.parse().ok())
+        .collect()
+}
+
+/// Gives hubs that report the same serial distinct ids, by appending #1,
+/// #2, ... to the serial in the order of their USB ports. Interfaces of one
+/// hub share a port and stay one device. Returns a warning for each
+/// serial that collided.
+fn disambiguate<T>(found: Vec<(DeviceId, String, T)>) -> (Vec<(DeviceId, T)>, Vec<String>) {
+    let mut by_id: Vec<(DeviceId, Vec<(String, T)>)> = Vec::new();
+    for (device_id, port, entry) in found {
+        let ports = match by_id.iter_mut().find(|(id, _)| *id == device_id) {
+            Some((_, ports)) => ports,
+            None => {
+                by_id.push((device_id, Vec::new()));
+                &mut by_id.last_mut().unwrap().1
+            }
+        };
+        if !ports.iter().any(|(seen, _)| *seen == port) {
+            ports.push((port, entry));
+        }
     }
 
-    pub async fn set_fan_speed(
-        &mut self,
-        device_id: &DeviceId,
-        channel: usize,
-        mode: &ChannelMode,
-        speed_percent: u8,
-    ) -> Result<()> {
+    let mut devices = Vec::new();
+    let mut warnings = Vec::new();
+    for (device_id, mut ports) in by_id {
+        if ports.len() == 1 {
+            devices.push((device_id, ports.pop().unwrap().1));
+            continue;
+        }
+        ports.sort_by(|(a, _), (b, _)| port_order(a).cmp(&port_order(b)).then(a.cmp(b)));
+        let names: Vec<String> = ports
+            .iter()
+            .enumerate()
+            .map(|(index, (port, _))| format!("{}#{} on port {}", device_id.2, index + 1, port))
+            .collect();
+        warnings.push(format!(
+            "{} hubs report the same serial {:?} ({:04x}:{:04x}); addressing them as {}. \
+             The numbers follow the USB ports, so moving a hub to another port can swap them: \
+             check the config's device_ids after replugging.",
+            ports.len(),
+            device_id.2,
+            device_id.0,
+            device_id.1,
+            names.join(", ")
+        ));
+        for (index, (_, entry)) in ports.into_iter().enumerate() {
+            let serial = format!("{}#{}", device_id.2, index + 1);
+            devices.push((DeviceId(device_id.0, device_id.1, serial), entry));
+        }
+    }
+    (devices, warnings)
+}
+
+impl HidBackend {
+    fn path(&self, device_id: &DeviceId) -> Result<CString> {
         let hiddevice = self
             .device_configs
             .get(device_id)
             .ok_or_else(|| anyhow!("Device with given device id {} not available", device_id))?;
+        Ok(hiddevice.info.path().to_owned())
+    }
 
-        let hid: HidDevice = match self.hidapi.open_path(hiddevice.path()) {
-            Ok(hid) => hid,
-            Err(_) => {
-                eprintln!("Please run uni-sync with elevated permissions.");
-                std::process::exit(0);
-            }
-        };
+    fn open(&self, device_id: &DeviceId) -> Result<HidDevice> {
+        Ok(open_path(&self.hidapi, device_id, &self.path(device_id)?)?)
+    }
+}
 
-        let sync_rgb: bool = false;
+fn open_path(
+    hidapi: &Mutex<hidapi::HidApi>,
+    device_id: &DeviceId,
+    path: &CString,
+) -> Result<HidDevice, HardwareError> {
+    hidapi
+        .lock()
+        .unwrap()
+        .open_path(path)
+        .map_err(|_| HardwareError::PermissionDenied {
+            device_id: device_id.clone(),
+        })
+}
 
-        // Send Command to Sync to RGB Header
-        let sync_byte: u8 = if sync_rgb { 1 } else { 0 };
-        let _ = match &hiddevice.product_id() {
-            0xa100 | 0x7750 => hid.write(&[224, 16, 48, sync_byte, 0, 0, 0]), // SL
-            0xa101 => hid.write(&[224, 16, 65, sync_byte, 0, 0, 0]),          // AL
-            0xa102 => hid.write(&[224, 16, 97, sync_byte, 0, 0, 0]),          // SLI
-            0xa103 | 0xa105 => hid.write(&[224, 16, 97, sync_byte, 0, 0, 0]), // SLv2
-            0xa104 => hid.write(&[224, 16, 97, sync_byte, 0, 0, 0]),          // ALv2
-            _ => hid.write(&[224, 16, 48, sync_byte, 0, 0, 0]),               // SL
-        };
+/// Writes `request` and waits for the hub's reply to it.
+fn exchange_report(
+    hid: &HidDevice,
+    device_id: &DeviceId,
+    request: &[u8],
+) -> Result<Vec<u8>, HardwareError> {
+    hid.write(request).map_err(anyhow::Error::from)?;
+
+    let mut reply = [0u8; 65];
+    let len = hid
+        .read_timeout(&mut reply, REPLY_TIMEOUT_MS)
+        .map_err(anyhow::Error::from)?;
+    if len == 0 {
+        return Err(anyhow!("No reply from device {}", device_id).into());
+    }
+    Ok(reply[..len].to_vec())
+}
+
+fn supported_request(device_id: &DeviceId, report: Report) -> Result<Vec<u8>, HardwareError> {
+    report_request(device_id.1, report).ok_or(HardwareError::Unsupported {
+        device_id: device_id.clone(),
+        operation: report.operation(),
+    })
+}
 
-        // Avoid Race Condition
-        time::sleep(time::Duration::from_millis(200)).await;
+/// A hub's handle, opened on its I/O thread at the first packet and kept
+/// open there.
+struct HidWriter {
+    hidapi: Arc<Mutex<hidapi::HidApi>>,
+    device_id: DeviceId,
+    path: CString,
+    hid: Option<HidDevice>,
+}
 
-        // Disable Sync to fan header
-        let mut channel_byte = 0x10 << channel;
-        if matches!(mode, ChannelMode::PWM) {
-            channel_byte |= 0x1 << channel;
+impl HidWriter {
+    fn hid(&mut self) -> Result<&HidDevice, HardwareError> {
+        if self.hid.is_none() {
+            self.hid = Some(open_path(&self.hidapi, &self.device_id, &self.path)?);
         }
+        Ok(self.hid.as_ref().unwrap())
+    }
+}
 
-        let _ = match &hiddevice.product_id() {
-            0xa100 | 0x7750 => hid.write(&[224, 16, 49, channel_byte]), // SL
-            0xa101 => hid.write(&[224, 16, 66, channel_byte]),          // AL
-            0xa102 => hid.write(&[224, 16, 98, channel_byte]),          // SLI
-            0xa103 | 0xa105 => hid.write(&[224, 16, 98, channel_byte]), // SLv2
-            0xa104 => hid.write(&[224, 16, 98, channel_byte]),          // ALv2
-            _ => hid.write(&[224, 16, 49, channel_byte]),               // SL
-        };
+impl DeviceWriter for HidWriter {
+    fn send(&mut self, command: &Command) -> Result<()> {
+        let packet = encode_packet(self.device_id.1, command)?;
+        self.hid()?.write(&packet)?;
+        Ok(())
+    }
+
+    fn read_report(&mut self, report: Report) -> Result<Vec<u8>, HardwareError> {
+        let request = supported_request(&self.device_id, report)?;
+        let device_id = self.device_id.clone();
+        exchange_report(self.hid()?, &device_id, &request)
+    }
+}
+
+const REPLY_TIMEOUT_MS: i32 = 200;
+
+/// What we know about a kind of fan device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceModel {
+    pub name: &'static str,
+    pub channels: usize,
+    /// What the speed byte spans, for hubs; hwmon headers take a PWM duty.
+    pub rpm_range: Option<RpmRange>,
+}
+
+/// The RPM a hub family aims for from 0% to 100%, linear in between.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RpmRange {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl RpmRange {
+    /// The RPM the hub aims for at `speed`.
+    pub fn rpm(&self, speed: SpeedPercent) -> u16 {
+        let span = (self.max - self.min) as f64;
+        (self.min as f64 + span * speed.get() as f64 / 100.0).round() as u16
+    }
+
+    /// The speed whose RPM comes closest to `rpm`, 0% or 100% outside the range.
+    pub fn speed(&self, rpm: u16) -> SpeedPercent {
+        let span = (self.max - self.min) as f64;
+        let percent = (rpm.clamp(self.min, self.max) - self.min) as f64 / span * 100.0;
+        SpeedPercent::saturating(percent.round() as u8)
+    }
+}
+
+const HWMON_PWM_HEADER: DeviceModel = DeviceModel {
+    name: "hwmon PWM header",
+    channels: 1,
+    rpm_range: None,
+};
+
+// Speed bytes per family; see encode_packet
+const RPM_800_1900: RpmRange = RpmRange {
+    min: 800,
+    max: 1900,
+};
+const RPM_200_2100: RpmRange = RpmRange {
+    min: 200,
+    max: 2100,
+};
+const RPM_250_2000: RpmRange = RpmRange {
+    min: 250,
+    max: 2000,
+};
+
+// Unknown hubs are written like an SL
+const UNKNOWN_MODEL: DeviceModel = DeviceModel {
+    name: "unknown",
+    channels: 4,
+    rpm_range: Some(RPM_800_1900),
+};
+
+/// UNI hub models by product id.
+const HUB_MODELS: &[(&[u16], DeviceModel)] = &[
+    (
+        &[0xa100, 0x7750],
+        DeviceModel {
+            name: "UNI SL",
+            channels: 4,
+            rpm_range: Some(RPM_800_1900),
+        },
+    ),
+    (
+        &[0xa101],
+        DeviceModel {
+            name: "UNI AL",
+            channels: 4,
+            rpm_range: Some(RPM_800_1900),
+        },
+    ),
+    (
+        &[0xa102],
+        DeviceModel {
+            name: "UNI SL-Infinity",
+            channels: 4,
+            rpm_range: Some(RPM_200_2100),
+        },
+    ),
+    (
+        &[0xa103, 0xa105],
+        DeviceModel {
+            name: "UNI SL v2",
+            channels: 4,
+            rpm_range: Some(RPM_250_2000),
+        },
+    ),
+    (
+        &[0xa104],
+        DeviceModel {
+            name: "UNI AL v2",
+            channels: 4,
+            rpm_range: Some(RPM_250_2000),
+        },
+    ),
+];
+
+/// Short family names, for commands that work without the hub attached.
+pub const FAMILIES: &[(&str, u16)] = &[
+    ("sl", 0xa100),
+    ("al", 0xa101),
+    ("sli", 0xa102),
+    ("slv2", 0xa103),
+    ("alv2", 0xa104),
+];
+
+pub fn device_model(device_id: &DeviceId) -> DeviceModel {
+    if device_id.is_sysfs_pwm() {
+        return HWMON_PWM_HEADER;
+    }
+    HUB_MODELS
+        .iter()
+        .find(|(product_ids, _)| product_ids.contains(&device_id.1))
+        .map(|(_, model)| *model)
+        .unwrap_or(UNKNOWN_MODEL)
+}
+
+/// The model of a family named in FAMILIES, in any case.
+pub fn family_model(name: &str) -> Option<DeviceModel> {
+    FAMILIES
+        .iter()
+        .find(|(family, _)| family.eq_ignore_ascii_case(name))
+        .map(|(_, product_id)| device_model(&DeviceId(0x0cf2, *product_id, String::new())))
+}
+
+/// Fan-count report request. Only the v2 hubs (SLv2/ALv2) know how many fans
+/// are daisy-chained on each channel. These bytes and the reply layout are
+/// unverified, not taken from a capture, so they are only sent with
+/// `experimental_hub_reports` on.
+pub fn fan_count_request(product_id: u16) -> Option<Vec<u8>> {
+    match product_id {
+        0xa103..=0xa105 => Some(vec![224, 80, 0, 0]), // SLv2, ALv2
+        _ => None,
+    }
+}
 
-        // Avoid Race Condition
-        time::sleep(time::Duration::from_millis(200)).await;
+/// Parses a fan-count reply: the request's two header bytes followed by one
+/// fan count per channel.
+pub fn parse_fan_counts(reply: &[u8]) -> Result<Vec<u8>> {
+    match reply {
+        [224, 80, counts @ ..] if counts.len() >= 4 => Ok(counts[..4].to_vec()),
+        _ => bail!("Unexpected fan count reply {:02x?}", reply),
+    }
+}
+
+/// Channel state report request, again v2 hubs only and, like the fan count
+/// request, unverified. The reply is taken to carry the request's header
+/// bytes, one mode byte per channel (0 manual, 1 PWM) and one speed byte per
+/// channel in percent.
+pub fn channel_state_request(product_id: u16) -> Option<Vec<u8>> {
+    match product_id {
+        0xa103..=0xa105 => Some(vec![224, 81, 0, 0]), // SLv2, ALv2
+        _ => None,
+    }
+}
+
+/// Fan RPM report request, v2 hubs only and unverified like the other
+/// reports. The reply is taken to carry the request's header bytes and one
+/// big-endian u16 RPM per channel.
+pub fn rpm_request(product_id: u16) -> Option<Vec<u8>> {
+    match product_id {
+        0xa103..=0xa105 => Some(vec![224, 82, 0, 0]), // SLv2, ALv2
+        _ => None,
+    }
+}
+
+pub fn parse_rpms(reply: &[u8]) -> Result<Vec<u16>> {
+    match reply {
+        [224, 82, rpms @ ..] if rpms.len() >= 8 => Ok(rpms[..8]
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect()),
+        _ => bail!("Unexpected RPM reply {:02x?}", reply),
+    }
+}
+
+/// The request packet for `report` on the given UNI hub model, if it has one.
+pub fn report_request(product_id: u16, report: Report) -> Option<Vec<u8>> {
+    match report {
+        Report::FanCounts => fan_count_request(product_id),
+        Report::ChannelStates => channel_state_request(product_id),
+        Report::Rpms => rpm_request(product_id),
+    }
+}
+
+/// The RPM range a model's speed percentages map onto, as in encode_packet.
+pub fn rpm_range(product_id: u16) -> (u16, u16) {
+    match product_id {
+        0xa102 => (200, 2100),          // SLI
+        0xa103..=0xa105 => (250, 2000), // SLv2, ALv2
+        _ => (800, 1900),               // SL, AL
+    }
+}
+
+/// Whether a measured RPM is plausible for the commanded speed, allowing
+/// `VERIFY_TOLERANCE` of the model's RPM range either way.
+pub fn rpm_matches(product_id: u16, speed_percent: SpeedPercent, rpm: u16) -> bool {
+    let (min, max) = rpm_range(product_id);
+    let span = (max - min) as f64;
+    let expected = min as f64 + span * speed_percent.get() as f64 / 100.0;
+    (rpm as f64 - expected).abs() <= span * VERIFY_TOLERANCE
+}
 
-        // Set Channel Speed
-        if matches!(mode, ChannelMode::Manual) {
-            let speed = (speed_percent as f64).clamp(0.0, 100.0);
+/// What a channel was doing before the daemon took it over.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChannelState {
+    pub mode: ChannelMode,
+    pub speed_percent: SpeedPercent,
+}
 
-            let speed_800_1900: u8 = ((800.0 + (11.0 * speed)) as usize / 19).try_into().unwrap();
-            let speed_250_2000: u8 = ((250.0 + (17.5 * speed)) as usize / 20).try_into().unwrap();
-            let speed_200_2100: u8 = ((200.0 + (19.0 * speed)) as usize / 21).try_into().unwrap();
+/// Whether channel state replies are known to be laid out the way
+/// parse_channel_states reads them. No capture has shown one yet, so states
+/// read from them are only shown, never written back: `restore_on_exit`
+/// leaves channels at the daemon's last write, and detection doesn't probe
+/// hubs it couldn't put back. Set this once captured bytes confirm the layout.
+pub const CHANNEL_STATES_CONFIRMED: bool = false;
 
-            let _ = match &hiddevice.product_id() {
-                0xa100 | 0x7750 => {
-                    hid.write(&[224, (channel + 32).try_into().unwrap(), 0, speed_800_1900])
-                } // SL
-                0xa101 => hid.write(&[224, (channel + 32).try_into().unwrap(), 0, speed_800_1900]), // AL
-                0xa102 => hid.write(&[224, (channel + 32).try_into().unwrap(), 0, speed_200_2100]), // SLI
-                0xa103 | 0xa105 => {
-                    hid.write(&[224, (channel + 32).try_into().unwrap(), 0, speed_250_2000])
-                } // SLv2
-                0xa104 => hid.write(&[224, (channel + 32).try_into().unwrap(), 0, speed_250_2000]), // ALv2
-                _ => hid.write(&[224, (channel + 32).try_into().unwrap(), 0, speed_800_1900]), // SL
+/// Parses a channel state reply. Since the states get written back with
+/// `restore_on_exit`, anything short of a well-formed reply is an error and
+/// leaves the state unknown: all-zero states, which is what an echoed or
+/// zero-padded request looks like and would stop every fan, and speeds
+/// over 100%.
+pub fn parse_channel_states(reply: &[u8]) -> Result<Vec<ChannelState>> {
+    let [224, 81, states @ ..] = reply else {
+        bail!("Unexpected channel state reply {:02x?}", reply);
+    };
+    if states.len() < 8 {
+        bail!("Unexpected channel state reply {:02x?}", reply);
+    }
+    if states[..8].iter().all(|&byte| byte == 0) {
+        bail!(
+            "Channel state reply {:02x?} is blank, not trusting it",
+            reply
+        );
+    }
+    states[..4]
+        .iter()
+        .zip(&states[4..8])
+        .map(|(&mode, &speed_percent)| {
+            let mode = match mode {
+                0 => ChannelMode::Manual,
+                1 => ChannelMode::PWM,
+                _ => bail!("Unknown channel mode {} in {:02x?}", mode, reply),
             };
+            let speed_percent = SpeedPercent::new(speed_percent)
+                .with_context(|| format!("In channel state reply {:02x?}", reply))?;
+            Ok(ChannelState {
+                mode,
+                speed_percent,
+            })
+        })
+        .collect()
+}
+
+/// Commands that reinitialize a hub that ignores speed packets: every
+/// channel is handed to its PWM header and taken back. Only the known UNI
+/// hub models have one.
+pub fn reset_sequence(device_id: &DeviceId) -> Option<Vec<Command>> {
+    if device_id.is_sysfs_pwm() {
+        return None;
+    }
+    let (_, model) = HUB_MODELS
+        .iter()
+        .find(|(product_ids, _)| product_ids.contains(&device_id.1))?;
+    let toggle = |mode: ChannelMode| {
+        (0..model.channels).map(move |channel| Command::SetMode {
+            channel,
+            mode: mode.clone(),
+        })
+    };
+    Some(
+        toggle(ChannelMode::PWM)
+            .chain(toggle(ChannelMode::Manual))
+            .collect(),
+    )
+}
+
+/// Encodes a command as the HID report understood by the given UNI hub model.
+pub fn encode_packet(product_id: u16, command: &Command) -> Result<Vec<u8>> {
+    let packet = match command {
+        Command::SyncRgb(sync_rgb) => {
+            // Send Command to Sync to RGB Header
+            let sync_byte: u8 = if *sync_rgb { 1 } else { 0 };
+            match product_id {
+                0xa100 | 0x7750 => vec![224, 16, 48, sync_byte, 0, 0, 0], // SL
+                0xa101 => vec![224, 16, 65, sync_byte, 0, 0, 0],          // AL
+                0xa102 => vec![224, 16, 97, sync_byte, 0, 0, 0],          // SLI
+                0xa103 | 0xa105 => vec![224, 16, 97, sync_byte, 0, 0, 0], // SLv2
+                0xa104 => vec![224, 16, 97, sync_byte, 0, 0, 0],          // ALv2
+                _ => vec![224, 16, 48, sync_byte, 0, 0, 0],               // SL
+            }
+        }
+        Command::SetMode { channel, mode } => {
+            // Disable Sync to fan header
+            let mut channel_byte = 0x10 << channel;
+            if matches!(mode, ChannelMode::PWM) {
+                channel_byte |= 0x1 << channel;
+            }
+
+            match product_id {
+                0xa100 | 0x7750 => vec![224, 16, 49, channel_byte], // SL
+                0xa101 => vec![224, 16, 66, channel_byte],          // AL
+                0xa102 => vec![224, 16, 98, channel_byte],          // SLI
+                0xa103 | 0xa105 => vec![224, 16, 98, channel_byte], // SLv2
+                0xa104 => vec![224, 16, 98, channel_byte],          // ALv2
+                _ => vec![224, 16, 49, channel_byte],               // SL
+            }
+        }
+        Command::SetSpeed {
+            channel,
+            speed_percent,
+        } => {
+            let speed = speed_percent.get() as f64;
 
-            // Avoid Race Condition
-            time::sleep(time::Duration::from_millis(100)).await;
+            let speed_800_1900: u8 = ((800.0 + (11.0 * speed)) as usize / 19).try_into()?;
+            let speed_250_2000: u8 = ((250.0 + (17.5 * speed)) as usize / 20).try_into()?;
+            let speed_200_2100: u8 = ((200.0 + (19.0 * speed)) as usize / 21).try_into()?;
+
+            let channel_byte: u8 = (channel + 32).try_into()?;
+            match product_id {
+                0xa100 | 0x7750 => vec![224, channel_byte, 0, speed_800_1900], // SL
+                0xa101 => vec![224, channel_byte, 0, speed_800_1900],          // AL
+                0xa102 => vec![224, channel_byte, 0, speed_200_2100],          // SLI
+                0xa103 | 0xa105 => vec![224, channel_byte, 0, speed_250_2000], // SLv2
+                0xa104 => vec![224, channel_byte, 0, speed_250_2000],          // ALv2
+                _ => vec![224, channel_byte, 0, speed_800_1900],               // SL
+            }
         }
+    };
+    Ok(packet)
+}
+
+impl FanBackend for HidBackend {
+    fn devices(&self) -> Vec<DeviceId> {
+        self.device_configs.keys().cloned().collect()
+    }
+
+    fn metadata(&self, device_id: &DeviceId) -> Option<DeviceMetadata> {
+        self.device_configs
+            .get(device_id)
+            .map(|entry| entry.metadata.clone())
+    }
 
+    fn send(&mut self, device_id: &DeviceId, command: &Command) -> Result<()> {
+        let hid = self.open(device_id)?;
+        hid.write(&encode_packet(device_id.1, command)?)?;
         Ok(())
     }
 
-    pub fn get_available_devices(&self) -> Vec<DeviceId> {
-        self.device_configs.keys().cloned().collect()
+    fn writer(&mut self, device_id: &DeviceId) -> Result<Option<Box<dyn DeviceWriter>>> {
+        Ok(Some(Box::new(HidWriter {
+            hidapi: self.hidapi.clone(),
+            device_id: device_id.clone(),
+            path: self.path(device_id)?,
+            hid: None,
+        })))
+    }
+
+    fn read_report(
+        &mut self,
+        device_id: &DeviceId,
+        report: Report,
+    ) -> Result<Vec<u8>, HardwareError> {
+        let request = supported_request(device_id, report)?;
+        exchange_report(&self.open(device_id)?, device_id, &request)
+    }
+
+    fn rescan(&mut self) -> Result<()> {
+        self.hidapi.lock().unwrap().refresh_devices()?;
+        self.collect_devices();
+        Ok(())
+    }
+
+    // Only I/O threads keep handles between packets, and the controller
+    // drops the hub's thread before a reset, so this enumerates again, in
+    // case the hub came back on another path, and opens it once
+    fn reopen(&mut self, device_id: &DeviceId) -> Result<()> {
+        self.rescan()?;
+        self.open(device_id)?;
+        Ok(())
+    }
+
+    fn settle_time(&self, command: &Command) -> Duration {
+        hid_settle_time(command)
+    }
+}
+
+/// How long a UNI hub needs after `command` before it takes the next one.
+/// Speed packets within a burst get the full wait too: no capture measures
+/// a hub taking them closer together, so only a `command_delay_ms` from
+/// `bench` shortens it.
+pub fn hid_settle_time(command: &Command) -> Duration {
+    // Avoid Race Condition
+    match command {
+        Command::SyncRgb(_) | Command::SetMode { .. } => Duration::from_millis(200),
+        Command::SetSpeed { .. } => Duration::from_millis(100),
     }
 }
 
-const CPU_KEYWORDS: [&str; 4] = ["cpu", "core", "processor", "tctl"];
+/// A desired speed for one channel, as produced by the curve engine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpeedCommand {
+    pub device_id: DeviceId,
+    pub channel: usize,
+    pub mode: ChannelMode,
+    pub speed_percent: SpeedPercent,
+    pub reason: SpeedReason,
+}
+
+/// Commands waiting for a device beyond this are dropped oldest-first.
+const MAX_QUEUE_DEPTH: usize = 16;
+
+/// With PermissionPolicy::Retry, how often and how far apart a write that
+/// was denied access is retried within one tick.
+const PERMISSION_RETRIES: usize = 5;
+const PERMISSION_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// With verify_writes, how long after a speed write its RPM is checked.
+pub const VERIFY_DELAY: Duration = Duration::from_secs(3);
+/// Fraction of the RPM range a reading may be off before the write is
+/// considered ignored. Generous, since fans differ and RPM lags.
+const VERIFY_TOLERANCE: f64 = 0.35;
+/// Most commands verification re-sends per tick, so a hub that never
+/// reports sensible RPMs can't turn every tick into a resend storm.
+const MAX_RESENDS_PER_TICK: usize = 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteQueueStats {
+    pub enqueued: u64,
+    /// Queued commands replaced by a newer one for the same channel.
+    pub coalesced: u64,
+    /// Queued commands discarded because the queue was full.
+    pub dropped: u64,
+    pub written: u64,
+    /// Queued commands not written because their device was quarantined.
+    pub skipped: u64,
+    /// Times a device was quarantined.
+    pub quarantined: u64,
+    /// Speed writes whose RPM didn't match afterwards.
+    pub verification_failures: u64,
+    /// Commands re-sent after a failed verification.
+    pub resent: u64,
+    /// Commands written by a reconciliation pass, also counted in `written`.
+    pub reconciled: u64,
+    /// Speed changes held back because their channel's write budget was spent.
+    pub deferred: u64,
+    /// Unchanged speeds not re-sent because the write budget was spent.
+    pub budget_skipped: u64,
+    /// Devices reset, by hand or after failing their quarantine probes.
+    pub resets: u64,
+    /// Writes given up on because their I/O thread didn't finish them.
+    pub io_timeouts: u64,
+    /// Intermediate speeds of slewed changes written, not counted in
+    /// `written`.
+    pub substeps: u64,
+}
+
+pub struct FanController {
+    backends: Vec<Box<dyn FanBackend>>,
+    queues: HashMap<DeviceId, RingBuffer<SpeedCommand>>,
+    queue_stats: WriteQueueStats,
+    lighting_control: HashMap<DeviceId, LightingControl>,
+    // Channels not under full management
+    channel_management: HashMap<(DeviceId, usize), Manage>,
+    // None manages every discovered device
+    managed_devices: Option<HashSet<DeviceId>>,
+    // Fans per channel, for devices that report it
+    fan_counts: HashMap<DeviceId, Vec<u8>>,
+    // Whether devices are asked for reports at all; see CurveConfig::experimental_hub_reports
+    hub_reports: bool,
+    // Devices that already received the RGB sync packet; it only needs to be
+    // sent once, and re-sending it every tick glitches externally managed lighting.
+    rgb_synced: HashSet<DeviceId>,
+    // Replaces each backend's own settle time between commands
+    command_delay: Option<Duration>,
+    // Some(timeout) writes through a thread per device
+    io_timeout: Option<Duration>,
+    device_threads: HashMap<DeviceId, DeviceThread>,
+    // Channel states read back at startup, for devices that report them
+    previous_states: HashMap<DeviceId, Vec<ChannelState>>,
+    quarantine: Quarantine,
+    permission_policy: PermissionPolicy,
+    permission_retry_delay: Duration,
+    verify_writes: bool,
+    // Speed writes waiting for their RPM check, with when it is due
+    pending_verifications: Vec<(Instant, SpeedCommand)>,
+    // Devices that can't report RPMs, already logged once
+    unverifiable: HashSet<DeviceId>,
+    // How long the last flush spent on each device
+    write_times: Vec<(DeviceId, Duration)>,
+    // How long each device waited for the ones written before it
+    queue_delays: Vec<(DeviceId, Duration)>,
+    // Mode last written to each channel; it is only sent again once it changes
+    applied_modes: HashMap<(DeviceId, usize), ChannelMode>,
+    // Devices whose next flush is a reconciliation
+    reconciling: HashSet<DeviceId>,
+    write_budget: WriteBudget,
+    // The latest speed change per channel waiting for write budget
+    deferred: HashMap<(DeviceId, usize), SpeedCommand>,
+    // Speed last written to each channel
+    written_speeds: HashMap<(DeviceId, usize), SpeedPercent>,
+}
+
+impl FanController {
+    pub fn new() -> Result<Self> {
+        Self::with_filter(DeviceFilter::default())
+    }
+
+    /// Discovers UNI hubs that pass `filter`, and every hwmon PWM header.
+    pub fn with_filter(filter: DeviceFilter) -> Result<Self> {
+        Ok(Self::with_hubs(Box::new(HidBackend::with_filter(filter)?)))
+    }
+
+    /// Drives the UNI hubs through `hubs`, e.g. a RemoteBackend to the
+    /// helper, and discovers every hwmon PWM header.
+    pub fn with_hubs(hubs: Box<dyn FanBackend>) -> Self {
+        let mut backends = vec![hubs];
+        match SysfsPwmBackend::discover(Path::new(sysfs::HWMON_ROOT)) {
+            Ok(backend) => backends.push(Box::new(backend)),
+            Err(e) => eprintln!("Could not scan hwmon PWM headers: {}", e),
+        }
+
+        Self::with_backends(backends)
+    }
+
+    /// Records every packet to `sink` from now on; see CaptureBackend.
+    pub fn capture_to(&mut self, sink: CaptureSink) {
+        self.backends = std::mem::take(&mut self.backends)
+            .into_iter()
+            .map(|backend| {
+                Box::new(CaptureBackend::new(backend, sink.clone())) as Box<dyn FanBackend>
+            })
+            .collect();
+    }
+
+    /// Refuses every write from now on; see ReadOnlyBackend.
+    pub fn read_only(&mut self) {
+        self.backends = std::mem::take(&mut self.backends)
+            .into_iter()
+            .map(|backend| Box::new(ReadOnlyBackend::new(backend)) as Box<dyn FanBackend>)
+            .collect();
+    }
+
+    pub fn with_backends(backends: Vec<Box<dyn FanBackend>>) -> Self {
+        Self {
+            backends,
+            queues: HashMap::new(),
+            queue_stats: WriteQueueStats::default(),
+            lighting_control: HashMap::new(),
+            channel_management: HashMap::new(),
+            managed_devices: None,
+            fan_counts: HashMap::new(),
+            hub_reports: false,
+            rgb_synced: HashSet::new(),
+            command_delay: None,
+            io_timeout: None,
+            device_threads: HashMap::new(),
+            previous_states: HashMap::new(),
+            quarantine: Quarantine::default(),
+            permission_policy: PermissionPolicy::default(),
+            permission_retry_delay: PERMISSION_RETRY_DELAY,
+            verify_writes: false,
+            pending_verifications: Vec::new(),
+            unverifiable: HashSet::new(),
+            write_times: Vec::new(),
+            queue_delays: Vec::new(),
+            applied_modes: HashMap::new(),
+            reconciling: HashSet::new(),
+            write_budget: WriteBudget::new(WriteBudgetSettings::default()),
+            deferred: HashMap::new(),
+            written_speeds: HashMap::new(),
+        }
+    }
+
+    /// Asks devices for their reports. Off, every report is Unsupported and
+    /// nothing is sent to ask for it.
+    pub fn set_hub_reports(&mut self, hub_reports: bool) {
+        self.hub_reports = hub_reports;
+    }
+
+    /// Fails with Unsupported unless reports are turned on.
+    fn reports_enabled(&self, device_id: &DeviceId, report: Report) -> Result<(), HardwareError> {
+        if self.hub_reports {
+            return Ok(());
+        }
+        Err(HardwareError::Unsupported {
+            device_id: device_id.clone(),
+            operation: match report {
+                Report::FanCounts => "fan count reports unless experimental_hub_reports is on",
+                Report::ChannelStates => {
+                    "channel state reports unless experimental_hub_reports is on"
+                }
+                Report::Rpms => "RPM reports unless experimental_hub_reports is on",
+            },
+        })
+    }
+
+    /// Checks the RPM of every speed write a few seconds later and re-sends
+    /// writes the hub seems to have ignored.
+    pub fn set_verify_writes(&mut self, verify_writes: bool) {
+        self.verify_writes = verify_writes;
+    }
+
+    pub fn set_write_budget(&mut self, settings: WriteBudgetSettings) {
+        self.write_budget.set_settings(settings);
+    }
+
+    /// Speed writes a channel has left before changes are deferred.
+    pub fn write_budget_remaining(&self, device_id: &DeviceId, channel: usize) -> Option<u32> {
+        self.write_budget.remaining(device_id, channel)
+    }
+
+    pub fn set_permission_policy(&mut self, policy: PermissionPolicy) {
+        self.permission_policy = policy;
+    }
+
+    /// Waits `delay` after every command instead of the backend's settle time.
+    pub fn set_command_delay(&mut self, delay: Option<Duration>) {
+        self.command_delay = delay;
+    }
+
+    /// Talks to each device from an OS thread of its own, failing a write or
+    /// report read that takes longer than `timeout`; None does both from the
+    /// caller.
+    pub fn set_io_timeout(&mut self, timeout: Option<Duration>) {
+        self.io_timeout = timeout;
+        if timeout.is_none() {
+            self.device_threads.clear();
+        }
+    }
+
+    /// Restricts all writes to the given devices. Discovery still reports
+    /// every device through get_available_devices.
+    pub fn set_managed_devices(&mut self, managed_devices: Option<Vec<DeviceId>>) {
+        self.managed_devices = managed_devices.map(|devices| devices.into_iter().collect());
+    }
+
+    pub fn is_managed(&self, device_id: &DeviceId) -> bool {
+        self.managed_devices
+            .as_ref()
+            .is_none_or(|managed| managed.contains(device_id))
+    }
+
+    pub fn set_lighting_control(&mut self, device_id: &DeviceId, lighting: LightingControl) {
+        self.lighting_control.insert(device_id.clone(), lighting);
+    }
+
+    /// Replaces how much of each channel may be written; unlisted channels
+    /// are managed fully.
+    pub fn set_channel_management(&mut self, management: Vec<(DeviceId, usize, Manage)>) {
+        self.channel_management = management
+            .into_iter()
+            .map(|(device_id, channel, manage)| ((device_id, channel), manage))
+            .collect();
+    }
+
+    fn management(&self, device_id: &DeviceId, channel: usize) -> Manage {
+        self.channel_management
+            .get(&(device_id.clone(), channel))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn backend_for<'a>(
+        backends: &'a mut [Box<dyn FanBackend>],
+        device_id: &DeviceId,
+    ) -> Result<&'a mut Box<dyn FanBackend>> {
+        if let Some(index) = backends
+            .iter()
+            .position(|backend| backend.devices().contains(device_id))
+        {
+            return Ok(&mut backends[index]);
+        }
+
+        let available: Vec<DeviceId> = backends.iter().flat_map(|b| b.devices()).collect();
+        match device_id.closest_match(&available) {
+            Some(candidate) => bail!(
+                "Device with given device id {} not available; did you mean device {}?",
+                device_id,
+                candidate
+            ),
+            None => bail!("Device with given device id {} not available", device_id),
+        }
+    }
+
+    /// Queues a command for its device. A command still waiting for the same
+    /// channel is replaced, so a slow device only ever receives the latest speed.
+    pub fn enqueue(&mut self, command: SpeedCommand) {
+        self.queue_stats.enqueued += 1;
+        let queue = self
+            .queues
+            .entry(command.device_id.clone())
+            .or_insert_with(|| RingBuffer::new(MAX_QUEUE_DEPTH));
+
+        if let Some(queued) = queue
+            .iter_mut()
+            .find(|queued| queued.channel == command.channel)
+        {
+            *queued = command;
+            self.queue_stats.coalesced += 1;
+            return;
+        }
+
+        if queue.push(command).is_some() {
+            self.queue_stats.dropped += 1;
+        }
+    }
 
-pub fn get_max_cpu_temperature() -> Option<f64> {
-    let components = Components::new_with_refreshed_list();
+    /// Writes every queued command, device by device in queue order.
+    ///
+    /// A device whose writes keep failing is quarantined: its commands are
+    /// skipped, except for a single probe command on a backoff schedule,
+    /// until a probe succeeds.
+    ///
+    /// A speed change to a channel out of write budget waits for the next
+    /// flush with budget, unless a newer command for the channel replaces it.
+    pub async fn flush(&mut self) -> Vec<(SpeedCommand, Result<()>)> {
+        let now = Instant::now();
+        if self.verify_writes {
+            self.verify_due(now).await;
+        }
+        for (_, command) in std::mem::take(&mut self.deferred) {
+            let queue = self
+                .queues
+                .entry(command.device_id.clone())
+                .or_insert_with(|| RingBuffer::new(MAX_QUEUE_DEPTH));
+            if !queue.iter().any(|queued| queued.channel == command.channel) {
+                queue.push(command);
+            }
+        }
+        let mut device_ids: Vec<DeviceId> = self.queues.keys().cloned().collect();
+        device_ids.sort();
+        // Healthy devices first, so probing a quarantined one never delays them
+        device_ids.sort_by_key(|device_id| self.quarantine.is_quarantined(device_id));
 
-    let mut max_temp = None;
+        let mut results = Vec::new();
+        self.write_times.clear();
+        self.queue_delays.clear();
+        let writes_started = Instant::now();
+        for device_id in device_ids {
+            let mut commands: Vec<SpeedCommand> = self
+                .queues
+                .get_mut(&device_id)
+                .map(|queue| queue.drain().collect())
+                .unwrap_or_default();
+            let quarantined = self.quarantine.is_quarantined(&device_id);
+            if quarantined {
+                let probe = if self.quarantine.probe_due(&device_id, now) {
+                    1
+                } else {
+                    0
+                };
+                self.queue_stats.skipped += commands.len().saturating_sub(probe) as u64;
+                commands.truncate(probe);
+            }
+            if commands.is_empty() {
+                continue;
+            }
 
-    for component in &components {
-        let name = component.label().to_lowercase();
-        if CPU_KEYWORDS.iter().any(|&kw| name.contains(kw)) {
-            let temp = component.temperature() as f64;
-            match max_temp {
-                None => max_temp = Some(temp),
-                Some(current_max) => {
-                    if temp > current_max {
-                        max_temp = Some(temp);
+            let reconciling = self.reconciling.remove(&device_id);
+            let started = Instant::now();
+            self.queue_delays
+                .push((device_id.clone(), started - writes_started));
+            let mut admitted = Vec::new();
+            for command in commands {
+                match self.admit(&command, reconciling, now) {
+                    Admission::Write => admitted.push(command),
+                    Admission::Defer => {
+                        self.queue_stats.deferred += 1;
+                        self.deferred
+                            .insert((device_id.clone(), command.channel), command);
+                    }
+                    Admission::Skip => self.queue_stats.budget_skipped += 1,
+                }
+            }
+            let mut cycle_ok = true;
+            let mut denied = false;
+            let mut timed_out = false;
+            let mut written = self.write_commands(&device_id, &admitted).await;
+            for (index, result) in written.drain(..) {
+                let command = admitted[index].clone();
+                if result.is_ok() {
+                    self.queue_stats.written += 1;
+                    self.written_speeds
+                        .insert((device_id.clone(), command.channel), command.speed_percent);
+                    if reconciling {
+                        self.queue_stats.reconciled += 1;
+                    }
+                    if self.verify_writes {
+                        self.schedule_verification(&command, now);
                     }
                 }
+                cycle_ok &= result.is_ok();
+                denied |= matches!(&result, Err(e) if HardwareError::is_permission_denied(e));
+                timed_out |= matches!(&result, Err(e) if HardwareError::is_timeout(e));
+                results.push((command, result));
+            }
+            self.write_times
+                .push((device_id.clone(), started.elapsed()));
+
+            // A hung write or a denied device won't do better next tick
+            let quarantine_now = if timed_out {
+                Some("a write hung")
+            } else if denied && self.permission_policy == PermissionPolicy::SkipDevice {
+                Some("permission denied")
+            } else {
+                None
+            };
+            if let Some(why) = quarantine_now {
+                if self.quarantine.quarantine_now(&device_id, now) {
+                    self.queue_stats.quarantined += 1;
+                    eprintln!(
+                        "Quarantining device {}: {}; probing it again in {:?}",
+                        device_id, why, PROBE_BACKOFF[0]
+                    );
+                } else {
+                    self.quarantine.record(&device_id, false, now);
+                }
+            } else if self.quarantine.record(&device_id, cycle_ok, now) {
+                self.queue_stats.quarantined += 1;
+                eprintln!(
+                    "Quarantining device {} after {} failed write cycles; probing it again in {:?}",
+                    device_id, QUARANTINE_AFTER_FAILURES, PROBE_BACKOFF[0]
+                );
+            } else if quarantined && cycle_ok {
+                eprintln!("Device {} is responding again", device_id);
+            }
+
+            if quarantined && !cycle_ok && self.quarantine.take_reset(&device_id) {
+                match self.reset_device(&device_id).await {
+                    Ok(()) => eprintln!(
+                        "Reset device {} after {} failed probes",
+                        device_id, RESET_AFTER_PROBES
+                    ),
+                    Err(HardwareError::Unsupported { .. }) => {}
+                    Err(e) => eprintln!("Could not reset device {}: {}", device_id, e),
+                }
             }
         }
+        results
     }
 
-    max_temp
+    /// Reinitializes a device that stopped taking commands: opens it again,
+    /// sends its family's reset sequence and makes the next flush write its
+    /// full state. Channels that aren't fully managed keep their mode.
+    pub async fn reset_device(&mut self, device_id: &DeviceId) -> Result<(), HardwareError> {
+        let mut sequence = reset_sequence(device_id).ok_or(HardwareError::Unsupported {
+            device_id: device_id.clone(),
+            operation: "resets",
+        })?;
+        sequence.retain(|command| match command {
+            Command::SetMode { channel, .. } => {
+                self.management(device_id, *channel) == Manage::Full
+            }
+            _ => true,
+        });
+        self.device_threads.remove(device_id);
+        Self::backend_for(&mut self.backends, device_id)?.reopen(device_id)?;
+        self.send_commands(device_id, &sequence).await?;
+
+        self.queue_stats.resets += 1;
+        self.rgb_synced.remove(device_id);
+        self.reconcile(device_id);
+        self.quarantine.record(device_id, true, Instant::now());
+        Ok(())
+    }
+
+    /// The speed last written to a channel, by a flush or as a substep.
+    pub fn written_speed(&self, device_id: &DeviceId, channel: usize) -> Option<SpeedPercent> {
+        self.written_speeds
+            .get(&(device_id.clone(), channel))
+            .copied()
+    }
+
+    /// Writes intermediate speeds of slewed changes at once, bypassing the
+    /// queues. Quarantined devices are left out, as are channels whose
+    /// budget can't cover the substep and the final write after it. Failed
+    /// substeps aren't reported: the final write that follows will be.
+    pub async fn write_substeps(&mut self, commands: &[SpeedCommand]) {
+        let now = Instant::now();
+        let mut by_device: BTreeMap<DeviceId, Vec<SpeedCommand>> = BTreeMap::new();
+        for command in commands {
+            if self.quarantine.is_quarantined(&command.device_id)
+                || !self.write_budget.admit_substep(
+                    &command.device_id,
+                    command.channel,
+                    command.speed_percent.get(),
+                    now,
+                )
+            {
+                continue;
+            }
+            by_device
+                .entry(command.device_id.clone())
+                .or_default()
+                .push(command.clone());
+        }
+        for (device_id, commands) in by_device {
+            for (index, result) in self.write_commands(&device_id, &commands).await {
+                if result.is_ok() {
+                    self.queue_stats.substeps += 1;
+                    self.written_speeds.insert(
+                        (device_id.clone(), commands[index].channel),
+                        commands[index].speed_percent,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Mode changes and reconciliation always go out; only speed writes are
+    /// held to the budget.
+    fn admit(&mut self, command: &SpeedCommand, reconciling: bool, now: Instant) -> Admission {
+        let mode_change = self.management(&command.device_id, command.channel) == Manage::Full
+            && self.applied_mode(&command.device_id, command.channel) != Some(&command.mode);
+        if command.mode != ChannelMode::Manual || reconciling || mode_change {
+            return Admission::Write;
+        }
+        self.write_budget.admit(
+            &command.device_id,
+            command.channel,
+            command.speed_percent.get(),
+            now,
+        )
+    }
+
+    /// How long the last flush spent writing to each device it wrote to.
+    pub fn write_times(&self) -> &[(DeviceId, Duration)] {
+        &self.write_times
+    }
+
+    /// How long each device the last flush wrote to waited for the devices
+    /// written before it. Devices are written one at a time, so these add up.
+    pub fn queue_delays(&self) -> &[(DeviceId, Duration)] {
+        &self.queue_delays
+    }
+
+    /// Every device's write queue, added up.
+    pub fn queue_usage(&self) -> BufferUsage {
+        BufferUsage::sum(self.queues.values().map(RingBuffer::usage))
+    }
+
+    fn schedule_verification(&mut self, command: &SpeedCommand, now: Instant) {
+        if command.mode != ChannelMode::Manual || self.unverifiable.contains(&command.device_id) {
+            return;
+        }
+        let same_channel = |pending: &SpeedCommand| {
+            pending.device_id == command.device_id && pending.channel == command.channel
+        };
+        // A repeat of the pending speed keeps its due time, so checks still
+        // come due when ticks are shorter than VERIFY_DELAY
+        if self.pending_verifications.iter().any(|(_, pending)| {
+            same_channel(pending) && pending.speed_percent == command.speed_percent
+        }) {
+            return;
+        }
+        self.pending_verifications
+            .retain(|(_, pending)| !same_channel(pending));
+        self.pending_verifications
+            .push((now + VERIFY_DELAY, command.clone()));
+    }
+
+    /// Asks a device for the RPM of each of its channels. A reply that
+    /// doesn't look like RPMs, such as a bare echo of the request or nothing
+    /// but zeros, is Unsupported, so no one acts on a guessed layout.
+    pub async fn query_rpms(&mut self, device_id: &DeviceId) -> Result<Vec<u16>, HardwareError> {
+        self.reports_enabled(device_id, Report::Rpms)?;
+        let reply = self.read_report(device_id, Report::Rpms).await?;
+        match parse_rpms(&reply) {
+            Ok(rpms) if rpms.iter().any(|&rpm| rpm != 0) => Ok(rpms),
+            _ => Err(HardwareError::Unsupported {
+                device_id: device_id.clone(),
+                operation: "RPM reports; its reply doesn't look like RPMs",
+            }),
+        }
+    }
+
+    /// Checks the RPMs of writes whose verification is due, and re-sends
+    /// those the hub appears to have ignored.
+    pub async fn verify_due(&mut self, now: Instant) {
+        let (due, waiting): (Vec<_>, Vec<_>) = self
+            .pending_verifications
+            .drain(..)
+            .partition(|(due_at, _)| *due_at <= now);
+        self.pending_verifications = waiting;
+
+        let mut rpms: HashMap<DeviceId, Option<Vec<u16>>> = HashMap::new();
+        let mut resends = 0;
+        for (_, command) in due {
+            let device_id = &command.device_id;
+            if !rpms.contains_key(device_id) {
+                let reading = match self.query_rpms(device_id).await {
+                    Ok(reading) => Some(reading),
+                    Err(e @ HardwareError::Unsupported { .. }) => {
+                        if self.unverifiable.insert(device_id.clone()) {
+                            eprintln!("Not verifying writes: {}", e);
+                        }
+                        None
+                    }
+                    Err(e) => {
+                        eprintln!("Could not read RPMs of {}: {}", device_id, e);
+                        None
+                    }
+                };
+                rpms.insert(device_id.clone(), reading);
+            }
+            let Some(rpm) = rpms[device_id]
+                .as_ref()
+                .and_then(|reading| reading.get(command.channel).copied())
+            else {
+                continue;
+            };
+            if self.fan_count(device_id, command.channel) == Some(0)
+                || rpm_matches(device_id.1, command.speed_percent, rpm)
+            {
+                continue;
+            }
+
+            self.queue_stats.verification_failures += 1;
+            if resends >= MAX_RESENDS_PER_TICK {
+                continue;
+            }
+            resends += 1;
+            eprintln!(
+                "Device {} channel {} runs at {} RPM after being set to {}%, re-sending",
+                device_id, command.channel, rpm, command.speed_percent
+            );
+            // Re-send the mode too, in case the hub lost it
+            self.applied_modes
+                .remove(&(device_id.clone(), command.channel));
+            let written = self
+                .write_commands(device_id, std::slice::from_ref(&command))
+                .await
+                .pop()
+                .map_or(Ok(()), |(_, result)| result);
+            match written {
+                Ok(()) => {
+                    self.queue_stats.resent += 1;
+                    self.schedule_verification(&command, now);
+                }
+                Err(e) => eprintln!("Error re-sending fan speed: {}", e),
+            }
+        }
+    }
+
+    /// Writes one device's queued commands in a single apply, retrying the
+    /// ones denied access if the permission policy says so. Results are by
+    /// index into `commands`, for the commands that were attempted.
+    async fn write_commands(
+        &mut self,
+        device_id: &DeviceId,
+        commands: &[SpeedCommand],
+    ) -> Vec<(usize, Result<()>)> {
+        let channels: Vec<(usize, ChannelMode, SpeedPercent)> = commands
+            .iter()
+            .map(|command| (command.channel, command.mode.clone(), command.speed_percent))
+            .collect();
+        let mut results: Vec<Option<Result<()>>> = commands.iter().map(|_| None).collect();
+        let mut pending: Vec<usize> = (0..commands.len()).collect();
+        let mut retries = 0;
+        loop {
+            let batch: Vec<_> = pending
+                .iter()
+                .map(|&index| channels[index].clone())
+                .collect();
+            for (position, result) in self.apply_channel_speeds(device_id, &batch).await {
+                results[pending[position]] = Some(result);
+            }
+            // Denied writes, and the ones the denial stopped, get another go
+            pending.retain(|&index| match &results[index] {
+                None => true,
+                Some(Err(e)) => HardwareError::is_permission_denied(e),
+                Some(Ok(())) => false,
+            });
+            let denied = pending.iter().any(|&index| results[index].is_some());
+            if !denied
+                || self.permission_policy != PermissionPolicy::Retry
+                || retries == PERMISSION_RETRIES
+            {
+                break;
+            }
+            retries += 1;
+            time::sleep(self.permission_retry_delay).await;
+        }
+        results
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, result)| Some((index, result?)))
+            .collect()
+    }
+
+    pub fn is_quarantined(&self, device_id: &DeviceId) -> bool {
+        self.quarantine.is_quarantined(device_id)
+    }
+
+    pub fn queue_stats(&self) -> WriteQueueStats {
+        self.queue_stats
+    }
+
+    pub async fn set_fan_speed(
+        &mut self,
+        device_id: &DeviceId,
+        channel: usize,
+        mode: &ChannelMode,
+        speed_percent: SpeedPercent,
+    ) -> Result<()> {
+        self.apply_channel_speeds(device_id, &[(channel, mode.clone(), speed_percent)])
+            .await
+            .pop()
+            .map_or(Ok(()), |(_, result)| result)
+    }
+
+    /// Sets several channels of one device at once: first the mode changes
+    /// they need, each with its settle time, then every speed packet back to
+    /// back, so the fans change speed close together rather than with mode
+    /// changes between them. The speed packets are spaced by the backend's
+    /// settle time too, as no capture shows a hub taking them faster;
+    /// `command_delay` (as `bench` measures it) opts into a tighter burst.
+    ///
+    /// Results are by index into `channels`, in order. A write that hangs,
+    /// or is denied access under `skip_device`, stops the rest, and the
+    /// channels it stopped have no result.
+    pub async fn apply_channel_speeds(
+        &mut self,
+        device_id: &DeviceId,
+        channels: &[(usize, ChannelMode, SpeedPercent)],
+    ) -> Vec<(usize, Result<()>)> {
+        let skip_denied = self.permission_policy == PermissionPolicy::SkipDevice;
+        let stops = |result: &Result<()>| {
+            matches!(result, Err(e) if HardwareError::is_timeout(e)
+                || (skip_denied && HardwareError::is_permission_denied(e)))
+        };
+        if !self.is_managed(device_id) {
+            return (0..channels.len())
+                .map(|index| {
+                    let error = anyhow::anyhow!("Device {} is not in managed_devices", device_id);
+                    (index, Err(error))
+                })
+                .collect();
+        }
+        let mut results: Vec<Option<Result<()>>> = channels.iter().map(|_| None).collect();
+        let mut stopped = false;
+
+        for (index, (channel, mode, _)) in channels.iter().enumerate() {
+            let manage = self.management(device_id, *channel);
+            if manage == Manage::MonitorOnly {
+                results[index] = Some(Err(anyhow::anyhow!(
+                    "Device {} channel {} is monitor-only",
+                    device_id,
+                    channel
+                )));
+            } else if manage == Manage::Full && self.applied_mode(device_id, *channel) != Some(mode)
+            {
+                let commands = self.mode_commands(device_id, *channel, mode);
+                if let Err(e) = self.send_commands(device_id, &commands).await {
+                    let result = Err(e);
+                    stopped = stops(&result);
+                    results[index] = Some(result);
+                    if stopped {
+                        break;
+                    }
+                    continue;
+                }
+            }
+            if !matches!(mode, ChannelMode::Manual) {
+                results[index] = Some(Ok(()));
+            }
+        }
+
+        let burst: Vec<(usize, Command)> = channels
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| results[*index].is_none())
+            .map(|(index, (channel, _, speed_percent))| {
+                let command = Command::SetSpeed {
+                    channel: *channel,
+                    speed_percent: *speed_percent,
+                };
+                (index, command)
+            })
+            .collect();
+        if !stopped {
+            for (position, (index, command)) in burst.iter().enumerate() {
+                if position > 0 {
+                    time::sleep(self.settle_time(device_id, command)).await;
+                }
+                let result = self.send_command(device_id, command).await;
+                if result.is_err() {
+                    // The device may have reset, so write its modes again
+                    self.applied_modes
+                        .retain(|(applied, _), _| applied != device_id);
+                }
+                stopped = stops(&result);
+                results[*index] = Some(result);
+                if stopped {
+                    break;
+                }
+            }
+            if let Some((_, command)) = burst.last().filter(|_| !stopped) {
+                time::sleep(self.settle_time(device_id, command)).await;
+            }
+        }
+
+        results
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, result)| Some((index, result?)))
+            .collect()
+    }
+
+    /// How long to wait after sending `command` to the device.
+    fn settle_time(&mut self, device_id: &DeviceId, command: &Command) -> Duration {
+        match self.command_delay {
+            Some(delay) => delay,
+            None => Self::backend_for(&mut self.backends, device_id)
+                .map_or(Duration::ZERO, |backend| backend.settle_time(command)),
+        }
+    }
+
+    /// Sends the RGB sync (once per device) and channel mode that a full
+    /// write starts with, for channels whose speeds alone are written.
+    pub async fn apply_defaults(
+        &mut self,
+        device_id: &DeviceId,
+        channel: usize,
+        mode: &ChannelMode,
+    ) -> Result<()> {
+        let commands = self.mode_commands(device_id, channel, mode);
+        self.send_commands(device_id, &commands).await
+    }
+
+    fn applied_mode(&self, device_id: &DeviceId, channel: usize) -> Option<&ChannelMode> {
+        self.applied_modes.get(&(device_id.clone(), channel))
+    }
+
+    /// Makes the next write to every channel send its mode again.
+    pub fn forget_modes(&mut self) {
+        self.applied_modes.clear();
+    }
+
+    /// Makes the next flush write `device_id`'s full state, modes included,
+    /// and count those writes as reconciled.
+    pub fn reconcile(&mut self, device_id: &DeviceId) {
+        self.applied_modes
+            .retain(|(applied, _), _| applied != device_id);
+        self.reconciling.insert(device_id.clone());
+    }
+
+    /// The channels among `channels` whose mode differs from the one last
+    /// written to them, e.g. after a reload switched a curve from Manual to
+    /// PWM. Channels never written yet get their mode with their first write.
+    pub fn mode_changes(
+        &self,
+        channels: &[(DeviceId, usize, ChannelMode)],
+    ) -> Vec<(DeviceId, usize, ChannelMode)> {
+        channels
+            .iter()
+            .filter(|(device_id, channel, mode)| {
+                self.applied_mode(device_id, *channel)
+                    .is_some_and(|applied| applied != mode)
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn mode_commands(
+        &self,
+        device_id: &DeviceId,
+        channel: usize,
+        mode: &ChannelMode,
+    ) -> Vec<Command> {
+        let mut commands = Vec::new();
+
+        let lighting = self
+            .lighting_control
+            .get(device_id)
+            .copied()
+            .unwrap_or_default();
+        if lighting == LightingControl::Daemon && !self.rgb_synced.contains(device_id) {
+            let sync_rgb: bool = false;
+            commands.push(Command::SyncRgb(sync_rgb));
+        }
+
+        commands.push(Command::SetMode {
+            channel,
+            mode: mode.clone(),
+        });
+        commands
+    }
+
+    async fn send_commands(&mut self, device_id: &DeviceId, commands: &[Command]) -> Result<()> {
+        if !self.is_managed(device_id) {
+            bail!("Device {} is not in managed_devices", device_id);
+        }
+
+        for command in commands {
+            if let Err(e) = self.send_command(device_id, command).await {
+                // The device may have reset, so write its modes again
+                self.applied_modes
+                    .retain(|(applied, _), _| applied != device_id);
+                return Err(e);
+            }
+            match command {
+                Command::SyncRgb(_) => {
+                    self.rgb_synced.insert(device_id.clone());
+                }
+                Command::SetMode { channel, mode } => {
+                    self.applied_modes
+                        .insert((device_id.clone(), *channel), mode.clone());
+                }
+                Command::SetSpeed { .. } => {}
+            }
+            time::sleep(self.settle_time(device_id, command)).await;
+        }
+
+        Ok(())
+    }
+
+    /// Sends one command on the device's I/O thread, starting one if there
+    /// is none, or from here when I/O threads are off or the backend has no
+    /// writer for it. A failed write drops the thread, abandoning it if the
+    /// write hung, so the next write starts afresh with a new handle.
+    async fn send_command(&mut self, device_id: &DeviceId, command: &Command) -> Result<()> {
+        let backend = Self::backend_for(&mut self.backends, device_id)?;
+        let Some(timeout) = self.io_timeout else {
+            return backend.send(device_id, command);
+        };
+        if !self.device_threads.contains_key(device_id) {
+            let Some(writer) = backend.writer(device_id)? else {
+                return backend.send(device_id, command);
+            };
+            self.device_threads
+                .insert(device_id.clone(), DeviceThread::spawn(device_id, writer)?);
+        }
+
+        let result = self.device_threads[device_id]
+            .send(device_id, command, timeout)
+            .await;
+        if let Err(e) = &result {
+            if HardwareError::is_timeout(e) {
+                self.queue_stats.io_timeouts += 1;
+            }
+            self.device_threads.remove(device_id);
+        }
+        result
+    }
+
+    /// Reads a report on the device's I/O thread, the way send_command
+    /// writes. A device without the report keeps its thread; any other
+    /// failure drops it.
+    async fn read_report(
+        &mut self,
+        device_id: &DeviceId,
+        report: Report,
+    ) -> Result<Vec<u8>, HardwareError> {
+        let backend = Self::backend_for(&mut self.backends, device_id)?;
+        let Some(timeout) = self.io_timeout else {
+            return backend.read_report(device_id, report);
+        };
+        if !self.device_threads.contains_key(device_id) {
+            let Some(writer) = backend.writer(device_id)? else {
+                return backend.read_report(device_id, report);
+            };
+            self.device_threads
+                .insert(device_id.clone(), DeviceThread::spawn(device_id, writer)?);
+        }
+
+        let result = self.device_threads[device_id]
+            .read_report(device_id, report, timeout)
+            .await;
+        match &result {
+            Ok(_) | Err(HardwareError::Unsupported { .. }) => {}
+            Err(HardwareError::Timeout { .. }) => {
+                self.queue_stats.io_timeouts += 1;
+                self.device_threads.remove(device_id);
+            }
+            Err(_) => {
+                self.device_threads.remove(device_id);
+            }
+        }
+        result
+    }
+
+    /// Looks for devices again, reusing each backend, forgets what it knew
+    /// about the ones that are gone and reads the fan counts of new ones.
+    pub async fn rescan(&mut self) -> Result<DeviceChanges> {
+        let before = self.get_available_devices();
+        for backend in &mut self.backends {
+            backend.rescan()?;
+        }
+        let changes = DeviceChanges::between(&before, &self.get_available_devices());
+        for device_id in &changes.removed {
+            self.queues.remove(device_id);
+            self.device_threads.remove(device_id);
+            self.fan_counts.remove(device_id);
+            self.rgb_synced.remove(device_id);
+            self.applied_modes
+                .retain(|(applied, _), _| applied != device_id);
+            self.pending_verifications
+                .retain(|(_, pending)| &pending.device_id != device_id);
+        }
+        for device_id in &changes.added {
+            if self.is_managed(device_id) {
+                self.refresh_device_fan_counts(device_id).await;
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Every device of every backend, sorted rather than in discovery
+    /// order, which changes from run to run.
+    pub fn get_available_devices(&self) -> Vec<DeviceId> {
+        let mut devices: Vec<DeviceId> = self
+            .backends
+            .iter()
+            .flat_map(|backend| backend.devices())
+            .collect();
+        devices.sort();
+        devices
+    }
+
+    /// The USB port of each available device whose port is known.
+    pub fn port_paths(&self) -> Vec<(DeviceId, String)> {
+        self.get_available_devices()
+            .into_iter()
+            .filter_map(|device_id| {
+                let port_path = self.metadata(&device_id)?.port_path?;
+                Some((device_id, port_path))
+            })
+            .collect()
+    }
+
+    /// What the device reported about itself at discovery.
+    pub fn metadata(&self, device_id: &DeviceId) -> Option<DeviceMetadata> {
+        self.backends
+            .iter()
+            .find_map(|backend| backend.metadata(device_id))
+    }
+
+    /// Asks a device how many fans are connected to each of its channels.
+    pub async fn query_fan_counts(
+        &mut self,
+        device_id: &DeviceId,
+    ) -> Result<Vec<u8>, HardwareError> {
+        self.reports_enabled(device_id, Report::FanCounts)?;
+        let reply = self.read_report(device_id, Report::FanCounts).await?;
+        Ok(parse_fan_counts(&reply)?)
+    }
+
+    /// Re-reads the fan counts of every managed device that can report them.
+    /// Meant for startup, not for every tick; rescans read those of devices
+    /// plugged in later.
+    pub async fn refresh_fan_counts(&mut self) {
+        self.fan_counts.clear();
+        for device_id in self.get_managed_devices() {
+            self.refresh_device_fan_counts(&device_id).await;
+        }
+    }
+
+    async fn refresh_device_fan_counts(&mut self, device_id: &DeviceId) {
+        match self.query_fan_counts(device_id).await {
+            Ok(counts) => {
+                self.fan_counts.insert(device_id.clone(), counts);
+            }
+            Err(HardwareError::Unsupported { .. }) => {}
+            Err(e) => eprintln!("Could not read fan counts of {}: {}", device_id, e),
+        }
+    }
+
+    /// Asks a device for the mode and speed of each of its channels.
+    pub async fn query_channel_states(
+        &mut self,
+        device_id: &DeviceId,
+    ) -> Result<Vec<ChannelState>, HardwareError> {
+        self.reports_enabled(device_id, Report::ChannelStates)?;
+        let reply = self.read_report(device_id, Report::ChannelStates).await?;
+        Ok(parse_channel_states(&reply)?)
+    }
+
+    /// Records what every managed device that can report it is currently
+    /// doing, before the daemon writes anything.
+    pub async fn read_previous_states(&mut self) {
+        self.previous_states.clear();
+        for device_id in self.get_managed_devices() {
+            match self.query_channel_states(&device_id).await {
+                Ok(states) => {
+                    self.previous_states.insert(device_id, states);
+                }
+                Err(HardwareError::Unsupported { .. }) => {}
+                Err(e) => eprintln!("Could not read channel states of {}: {}", device_id, e),
+            }
+        }
+    }
+
+    /// A channel's state from before startup, or None when the device can't tell.
+    pub fn previous_state(&self, device_id: &DeviceId, channel: usize) -> Option<&ChannelState> {
+        self.previous_states.get(device_id)?.get(channel)
+    }
+
+    /// A channel's state from before startup, if it may be written back;
+    /// see CHANNEL_STATES_CONFIRMED.
+    pub fn restorable_state(&self, device_id: &DeviceId, channel: usize) -> Option<&ChannelState> {
+        self.previous_state(device_id, channel)
+            .filter(|_| CHANNEL_STATES_CONFIRMED)
+    }
+
+    /// Puts every channel with a restorable previous state back the way it
+    /// was. Only states read from a well-formed, confirmed reply are
+    /// restorable; the rest are left to the daemon's last write.
+    pub async fn restore_previous_states(&mut self) {
+        if !CHANNEL_STATES_CONFIRMED {
+            return;
+        }
+        self.forget_modes();
+        let mut devices: Vec<_> = self.previous_states.clone().into_iter().collect();
+        devices.sort_by(|(a, _), (b, _)| (a.0, a.1, &a.2).cmp(&(b.0, b.1, &b.2)));
+        for (device_id, states) in devices {
+            for (channel, state) in states.iter().enumerate() {
+                if self.management(&device_id, channel) == Manage::MonitorOnly {
+                    continue;
+                }
+                if let Err(e) = self
+                    .set_fan_speed(&device_id, channel, &state.mode, state.speed_percent)
+                    .await
+                {
+                    eprintln!(
+                        "Could not restore device {} channel {}: {}",
+                        device_id, channel, e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Fans on a channel, or None when the device can't tell.
+    pub fn fan_count(&self, device_id: &DeviceId, channel: usize) -> Option<u8> {
+        self.fan_counts.get(device_id)?.get(channel).copied()
+    }
+
+    pub fn get_managed_devices(&self) -> Vec<DeviceId> {
+        self.get_available_devices()
+            .into_iter()
+            .filter(|device_id| self.is_managed(device_id))
+            .collect()
+    }
+
+    /// Restores automatic control on every backend that supports it.
+    pub fn release(&mut self) {
+        self.device_threads.clear();
+        for backend in &mut self.backends {
+            if let Err(e) = backend.release() {
+                eprintln!("Error releasing fan control: {}", e);
+            }
+        }
+    }
+}
+
+/// Every temperature sensor, as (label, °C).
+pub fn read_sensors() -> Vec<(String, f64)> {
+    Components::new_with_refreshed_list()
+        .iter()
+        .map(|component| {
+            (
+                component.label().to_string(),
+                component.temperature() as f64,
+            )
+        })
+        .collect()
+}
+
+/// The hottest sensor `preset` reads.
+pub fn get_max_temperature(preset: &SensorPreset) -> Option<f64> {
+    presets::max_temperature(preset, &read_sensors())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockBackend;
+
+    fn percent(speed: u8) -> SpeedPercent {
+        SpeedPercent::new(speed).unwrap()
+    }
+
+    fn is_sync_packet(packet: &[u8]) -> bool {
+        packet.len() == 7
+            && packet[0] == 224
+            && packet[1] == 16
+            && [48, 65, 97].contains(&packet[2])
+    }
+
+    async fn run_ticks(lighting: LightingControl, mode: ChannelMode, ticks: usize) -> Vec<Vec<u8>> {
+        let device_id = DeviceId(0x0cf2, 0xa102, "TEST123".to_string());
+        let backend = MockBackend::new(vec![device_id.clone()]);
+        let packets = backend.packets();
+
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_lighting_control(&device_id, lighting);
+        for _ in 0..ticks {
+            controller
+                .set_fan_speed(&device_id, 1, &mode, percent(50))
+                .await
+                .unwrap();
+        }
+
+        let packets = packets.lock().unwrap();
+        packets.iter().map(|(_, packet)| packet.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_daemon_lighting_syncs_once() {
+        let packets = run_ticks(LightingControl::Daemon, ChannelMode::Manual, 2).await;
+        assert_eq!(
+            packets,
+            vec![
+                vec![224, 16, 97, 0, 0, 0, 0],
+                vec![224, 16, 98, 0x20],
+                vec![224, 33, 0, 54],
+                vec![224, 33, 0, 54],
+            ]
+        );
+
+        let packets = run_ticks(LightingControl::Daemon, ChannelMode::PWM, 2).await;
+        assert_eq!(
+            packets,
+            vec![vec![224, 16, 97, 0, 0, 0, 0], vec![224, 16, 98, 0x22]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mode_changes_on_reload() {
+        let device_id = DeviceId(0x0cf2, 0xa102, "TEST123".to_string());
+        let backend = MockBackend::new(vec![device_id.clone()]);
+        let packets = backend.packets();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_lighting_control(&device_id, LightingControl::External);
+        let channels = |modes: [ChannelMode; 3]| -> Vec<(DeviceId, usize, ChannelMode)> {
+            modes
+                .into_iter()
+                .enumerate()
+                .map(|(channel, mode)| (device_id.clone(), channel, mode))
+                .collect()
+        };
+        let take_packets = || -> Vec<Vec<u8>> {
+            packets
+                .lock()
+                .unwrap()
+                .drain(..)
+                .map(|(_, packet)| packet)
+                .collect()
+        };
+
+        let startup = channels([ChannelMode::Manual, ChannelMode::Manual, ChannelMode::PWM]);
+        // Nothing written yet, so nothing to change
+        assert!(controller.mode_changes(&startup).is_empty());
+        for (device_id, channel, mode) in &startup {
+            controller
+                .set_fan_speed(device_id, *channel, mode, percent(50))
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            take_packets(),
+            vec![
+                vec![224, 16, 98, 0x10],
+                vec![224, 32, 0, 54],
+                vec![224, 16, 98, 0x20],
+                vec![224, 33, 0, 54],
+                vec![224, 16, 98, 0x44],
+            ]
+        );
+
+        // An unchanged reload changes nothing, and ticks only send speeds
+        assert!(controller.mode_changes(&startup).is_empty());
+        controller
+            .set_fan_speed(&device_id, 0, &ChannelMode::Manual, percent(50))
+            .await
+            .unwrap();
+        assert_eq!(take_packets(), vec![vec![224, 32, 0, 54]]);
+
+        // Manual -> PWM hands the speed to the header; PWM -> Manual sets
+        // the mode and writes a speed at once
+        let reloaded = channels([ChannelMode::PWM, ChannelMode::Manual, ChannelMode::Manual]);
+        let changes = controller.mode_changes(&reloaded);
+        assert_eq!(
+            changes,
+            vec![
+                (device_id.clone(), 0, ChannelMode::PWM),
+                (device_id.clone(), 2, ChannelMode::Manual),
+            ]
+        );
+        for (device_id, channel, mode) in &changes {
+            controller
+                .set_fan_speed(device_id, *channel, mode, percent(50))
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            take_packets(),
+            vec![
+                vec![224, 16, 98, 0x11],
+                vec![224, 16, 98, 0x40],
+                vec![224, 34, 0, 54],
+            ]
+        );
+        assert!(controller.mode_changes(&reloaded).is_empty());
+
+        // A failed write sends the device's modes again next time
+        let failing = MockBackend::new(vec![device_id.clone()]);
+        failing.fail_writes_to(&device_id);
+        let failing_packets = failing.packets();
+        controller.backends[0] = Box::new(failing);
+        assert!(controller
+            .set_fan_speed(&device_id, 1, &ChannelMode::Manual, percent(50))
+            .await
+            .is_err());
+        assert!(failing_packets.lock().unwrap().is_empty());
+        assert!(controller.mode_changes(&reloaded).is_empty());
+        assert_eq!(controller.applied_mode(&device_id, 0), None);
+    }
+
+    #[test]
+    fn test_device_filter() {
+        let filter = DeviceFilter::default();
+        assert!(filter.matches(0x0cf2, 0xa103, "ABC"));
+        assert!(!filter.matches(0x0cf2, 0xa1ff, "ABC"));
+        assert!(!filter.matches(0x1234, 0xa103, "ABC"));
+
+        let filter = filter.with_serials(["a02b 1c3d"]);
+        assert!(filter.matches(0x0cf2, 0xa103, "A02B1C3D"));
+        assert!(!filter.matches(0x0cf2, 0xa103, "ABC"));
+    }
+
+    #[test]
+    fn test_device_changes() {
+        let hub = |serial: &str| DeviceId(0x0cf2, 0xa103, serial.to_string());
+        assert_eq!(
+            DeviceChanges::between(&[hub("A"), hub("B")], &[hub("B"), hub("A")]),
+            DeviceChanges::default()
+        );
+        assert_eq!(
+            DeviceChanges::between(&[hub("A"), hub("B")], &[hub("b"), hub("C")]),
+            DeviceChanges {
+                added: vec![hub("C")],
+                removed: vec![hub("A")],
+            }
+        );
+        assert_eq!(
+            DeviceChanges::between(&[], &[hub("A")]).added,
+            vec![hub("A")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rescan() {
+        let kept = DeviceId(0x0cf2, 0xa102, "KEPT".to_string());
+        let unplugged = DeviceId(0x0cf2, 0xa102, "UNPLUGGED".to_string());
+        let plugged = DeviceId(0x0cf2, 0xa103, "PLUGGED".to_string());
+        let backend = MockBackend::new(vec![kept.clone(), unplugged.clone()])
+            .with_rescan(vec![kept.clone(), plugged.clone()])
+            .with_reply(vec![224, 80, 0, 0], vec![224, 80, 2, 0, 1, 0]);
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_hub_reports(true);
+        controller
+            .set_fan_speed(&unplugged, 0, &ChannelMode::Manual, percent(50))
+            .await
+            .unwrap();
+        controller.enqueue(SpeedCommand {
+            device_id: unplugged.clone(),
+            channel: 0,
+            mode: ChannelMode::Manual,
+            speed_percent: percent(60),
+            reason: SpeedReason::Curve,
+        });
+
+        let changes = controller.rescan().await.unwrap();
+        assert_eq!(changes.added, vec![plugged.clone()]);
+        assert_eq!(changes.removed, vec![unplugged.clone()]);
+        assert!(controller.get_available_devices().contains(&plugged));
+        // The hub plugged in has its fan counts read
+        assert_eq!(controller.fan_count(&plugged, 0), Some(2));
+        assert_eq!(controller.fan_count(&plugged, 1), Some(0));
+        // Nothing is left queued for, or known about, the unplugged hub
+        assert_eq!(controller.applied_mode(&unplugged, 0), None);
+        assert!(controller.flush().await.is_empty());
+
+        assert_eq!(controller.rescan().await.unwrap(), DeviceChanges::default());
+    }
+
+    #[tokio::test]
+    async fn test_reset_device() {
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let unknown = DeviceId(0x0cf2, 0xa1ff, "DEF".to_string());
+        let backend = MockBackend::new(vec![hub.clone(), unknown.clone()]);
+        let packets = backend.packets();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_lighting_control(&hub, LightingControl::External);
+        controller.set_channel_management(vec![(hub.clone(), 3, Manage::SpeedOnly)]);
+        controller
+            .set_fan_speed(&hub, 0, &ChannelMode::Manual, percent(50))
+            .await
+            .unwrap();
+        packets.lock().unwrap().clear();
+
+        controller.reset_device(&hub).await.unwrap();
+        // Channel 3 is speed-only, so its mode stays
+        let sent: Vec<Vec<u8>> = packets.lock().unwrap().drain(..).map(|(_, p)| p).collect();
+        assert_eq!(
+            sent,
+            vec![
+                vec![224, 16, 98, 0x11],
+                vec![224, 16, 98, 0x22],
+                vec![224, 16, 98, 0x44],
+                vec![224, 16, 98, 0x10],
+                vec![224, 16, 98, 0x20],
+                vec![224, 16, 98, 0x40],
+            ]
+        );
+        assert_eq!(controller.queue_stats().resets, 1);
+
+        // The next flush writes the mode again
+        controller.enqueue(SpeedCommand {
+            device_id: hub.clone(),
+            channel: 0,
+            mode: ChannelMode::Manual,
+            speed_percent: percent(50),
+            reason: SpeedReason::Curve,
+        });
+        controller.flush().await;
+        let sent: Vec<Vec<u8>> = packets.lock().unwrap().drain(..).map(|(_, p)| p).collect();
+        assert_eq!(sent, vec![vec![224, 16, 98, 0x10], vec![224, 32, 0, 56]]);
+
+        assert!(matches!(
+            controller.reset_device(&unknown).await,
+            Err(HardwareError::Unsupported { .. })
+        ));
+        assert!(packets.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_resends_modes() {
+        let hub = DeviceId(0x0cf2, 0xa102, "ABC".to_string());
+        let other = DeviceId(0x0cf2, 0xa102, "DEF".to_string());
+        let backend = MockBackend::new(vec![hub.clone(), other.clone()]);
+        let packets = backend.packets();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        let tick = |controller: &mut FanController| {
+            for device_id in [&hub, &other] {
+                controller.set_lighting_control(device_id, LightingControl::External);
+                controller.enqueue(SpeedCommand {
+                    device_id: device_id.clone(),
+                    channel: 0,
+                    mode: ChannelMode::Manual,
+                    speed_percent: percent(50),
+                    reason: SpeedReason::Curve,
+                });
+            }
+        };
+        let take_packets =
+            || -> Vec<(DeviceId, Vec<u8>)> { packets.lock().unwrap().drain(..).collect() };
+
+        tick(&mut controller);
+        controller.flush().await;
+        take_packets();
+        tick(&mut controller);
+        controller.flush().await;
+        assert_eq!(take_packets().len(), 2);
+
+        // Only the reconciled device gets its mode again, and only once
+        controller.reconcile(&other);
+        tick(&mut controller);
+        controller.flush().await;
+        assert_eq!(
+            take_packets(),
+            vec![
+                (hub.clone(), vec![224, 32, 0, 54]),
+                (other.clone(), vec![224, 16, 98, 0x10]),
+                (other.clone(), vec![224, 32, 0, 54]),
+            ]
+        );
+        tick(&mut controller);
+        controller.flush().await;
+        assert_eq!(take_packets().len(), 2);
+
+        let stats = controller.queue_stats();
+        assert_eq!(stats.written, 8);
+        assert_eq!(stats.reconciled, 1);
+    }
+
+    #[tokio::test]
+    async fn test_write_budget_defers_changes() {
+        let hub = DeviceId(0x0cf2, 0xa102, "ABC".to_string());
+        let backend = MockBackend::new(vec![hub.clone()]);
+        let packets = backend.packets();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_lighting_control(&hub, LightingControl::External);
+        controller.set_write_budget(WriteBudgetSettings {
+            max_writes_per_hour: 2,
+            urgent_delta_percent: 20,
+        });
+        let command = |speed| SpeedCommand {
+            device_id: hub.clone(),
+            channel: 0,
+            mode: ChannelMode::Manual,
+            speed_percent: percent(speed),
+            reason: SpeedReason::Curve,
+        };
+        let speeds_written = || -> Vec<u8> {
+            packets
+                .lock()
+                .unwrap()
+                .drain(..)
+                .filter(|(_, packet)| packet[1] == 32)
+                .map(|(_, packet)| packet[3])
+                .collect()
+        };
+
+        // The first write sets the mode too, which is never held back
+        for speed in [40, 45, 50, 55] {
+            controller.enqueue(command(speed));
+            controller.flush().await;
+        }
+        assert_eq!(controller.write_budget_remaining(&hub, 0), Some(0));
+        // Nothing new queued: the deferred change is retried, and still waits
+        controller.flush().await;
+        // A newer change replaces it
+        controller.enqueue(command(52));
+        controller.flush().await;
+        // while a big jump goes out at once
+        controller.enqueue(command(90));
+        controller.flush().await;
+        controller.enqueue(command(90));
+        controller.flush().await;
+
+        // 40%, 45%, 50% and 90%
+        assert_eq!(speeds_written(), vec![45, 50, 54, 90]);
+        let stats = controller.queue_stats();
+        assert_eq!(stats.deferred, 3);
+        assert_eq!(stats.budget_skipped, 1);
+        assert_eq!(stats.written, 4);
+    }
+
+    #[tokio::test]
+    async fn test_queue_coalesces_per_channel() {
+        let device_id = DeviceId(0x0cf2, 0xa102, "TEST123".to_string());
+        let backend = MockBackend::new(vec![device_id.clone()]);
+        let packets = backend.packets();
+
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_lighting_control(&device_id, LightingControl::External);
+        for speed_percent in 0..10 {
+            controller.enqueue(SpeedCommand {
+                device_id: device_id.clone(),
+                channel: 0,
+                mode: ChannelMode::Manual,
+                speed_percent: percent(speed_percent * 10),
+                reason: SpeedReason::Curve,
+            });
+        }
+        controller.enqueue(SpeedCommand {
+            device_id: device_id.clone(),
+            channel: 1,
+            mode: ChannelMode::Manual,
+            speed_percent: percent(100),
+            reason: SpeedReason::Curve,
+        });
+
+        let results = controller.flush().await;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0.speed_percent, 90);
+        let write_times: Vec<&DeviceId> = controller.write_times().iter().map(|(d, _)| d).collect();
+        assert_eq!(write_times, vec![&device_id]);
+        // The only device written doesn't wait for any other
+        assert_eq!(controller.queue_delays().len(), 1);
+        assert!(controller.queue_delays()[0].1 < Duration::from_millis(50));
+        assert_eq!(
+            controller.queue_stats(),
+            WriteQueueStats {
+                enqueued: 11,
+                coalesced: 9,
+                dropped: 0,
+                written: 2,
+                ..Default::default()
+            }
+        );
+
+        let packets = packets.lock().unwrap();
+        let speed_packets: Vec<&Vec<u8>> = packets
+            .iter()
+            .map(|(_, packet)| packet)
+            .filter(|packet| packet[1] != 16)
+            .collect();
+        assert_eq!(
+            speed_packets,
+            vec![&vec![224, 32, 0, 90], &vec![224, 33, 0, 100]]
+        );
+    }
+
+    #[test]
+    fn test_queue_is_bounded() {
+        let device_id = DeviceId(0x0cf2, 0xa102, "TEST123".to_string());
+        let mut controller = FanController::with_backends(vec![]);
+        for channel in 0..MAX_QUEUE_DEPTH + 3 {
+            controller.enqueue(SpeedCommand {
+                device_id: device_id.clone(),
+                channel,
+                mode: ChannelMode::Manual,
+                speed_percent: percent(50),
+                reason: SpeedReason::Curve,
+            });
+        }
+
+        assert_eq!(controller.queues[&device_id].len(), MAX_QUEUE_DEPTH);
+        assert_eq!(controller.queues[&device_id].front().unwrap().channel, 3);
+        assert_eq!(controller.queue_stats().dropped, 3);
+    }
+
+    #[test]
+    fn test_colliding_serials() {
+        let hub = |serial: &str| DeviceId(0x0cf2, 0xa100, serial.to_string());
+        let found = vec![
+            (hub("1234567890"), "1-10".to_string(), "third"),
+            (hub("1234567890"), "1-2".to_string(), "first"),
+            (hub("OTHER"), "1-3".to_string(), "other"),
+            // A second interface of the same hub
+            (hub("1234567890"), "1-2".to_string(), "first again"),
+            (hub("1234567890"), "1-9".to_string(), "second"),
+        ];
+
+        let (devices, warnings) = disambiguate(found);
+        let mut devices: Vec<(String, &str)> = devices
+            .into_iter()
+            .map(|(device_id, entry)| (device_id.2, entry))
+            .collect();
+        devices.sort();
+        assert_eq!(
+            devices,
+            vec![
+                ("1234567890#1".to_string(), "first"),
+                ("1234567890#2".to_string(), "second"),
+                ("1234567890#3".to_string(), "third"),
+                ("OTHER".to_string(), "other"),
+            ]
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].contains("1234567890#3 on port 1-10"),
+            "{}",
+            warnings[0]
+        );
+    }
+
+    #[test]
+    fn test_usb_port() {
+        // hidraw
+        assert_eq!(
+            usb_port("/sys/devices/pci0000:00/0000:00:14.0/usb1/1-4/1-4.2/1-4.2:1.0/0003:0CF2:A100.0005/hidraw/hidraw3"),
+            Some("1-4.2")
+        );
+        assert_eq!(usb_port("/dev/hidraw3"), None);
+        // libusb, and its older bus:address:interface form
+        assert_eq!(usb_port("1-4.2:1.0"), Some("1-4.2"));
+        assert_eq!(usb_port("12-10.3.1:1.1"), Some("12-10.3.1"));
+        assert_eq!(usb_port("0001:0004:00"), None);
+        // macOS and Windows
+        assert_eq!(
+            usb_port("IOService:/AppleACPIPlatformExpert/PCI0@0/AppleACPIPCI/XHC1@14/XHC1@14000000/HS02@14200000/USB2.0 Hub@14200000"),
+            None
+        );
+        assert_eq!(
+            usb_port(
+                r"\\?\hid#vid_0cf2&pid_a100#7&1a2b3c4d&0&0000#{4d1e55b2-f16f-11cf-88cb-001111000030}"
+            ),
+            None
+        );
+        assert_eq!(usb_port(""), None);
+    }
+
+    #[test]
+    fn test_devices_are_sorted() {
+        let devices = [
+            DeviceId(0x0cf2, 0xa103, "b2".to_string()),
+            DeviceId(0x0cf2, 0xa100, "Z9".to_string()),
+            DeviceId(0, 0, "hwmon2/pwm1".to_string()),
+            DeviceId(0x0cf2, 0xa103, "A1".to_string()),
+        ];
+        let discover = |order: &[usize]| {
+            let (first, second) = order.split_at(2);
+            let backend = |indices: &[usize]| -> Box<dyn FanBackend> {
+                Box::new(MockBackend::new(
+                    indices.iter().map(|&i| devices[i].clone()).collect(),
+                ))
+            };
+            FanController::with_backends(vec![backend(first), backend(second)])
+                .get_available_devices()
+        };
+
+        let sorted = vec![
+            devices[2].clone(),
+            devices[1].clone(),
+            devices[3].clone(),
+            devices[0].clone(),
+        ];
+        for order in [[0, 1, 2, 3], [3, 2, 1, 0], [1, 3, 0, 2]] {
+            assert_eq!(discover(&order), sorted);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fan_counts() {
+        let slv2 = DeviceId(0x0cf2, 0xa103, "V2".to_string());
+        let sl = DeviceId(0x0cf2, 0xa100, "V1".to_string());
+        let backend = MockBackend::new(vec![slv2.clone(), sl.clone()])
+            .with_reply(vec![224, 80, 0, 0], vec![224, 80, 3, 2, 0, 0]);
+        let packets = backend.packets();
+
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        // Off by default: nothing is asked
+        controller.refresh_fan_counts().await;
+        assert_eq!(controller.fan_count(&slv2, 0), None);
+        assert!(packets.lock().unwrap().is_empty());
+
+        controller.set_hub_reports(true);
+        controller.refresh_fan_counts().await;
+
+        assert_eq!(controller.fan_count(&slv2, 0), Some(3));
+        assert_eq!(controller.fan_count(&slv2, 2), Some(0));
+        assert_eq!(controller.fan_count(&sl, 0), None);
+        assert!(matches!(
+            controller.query_fan_counts(&sl).await,
+            Err(HardwareError::Unsupported { .. })
+        ));
+        assert_eq!(
+            packets.lock().unwrap()[0],
+            (slv2.clone(), vec![224, 80, 0, 0])
+        );
+        assert!(parse_fan_counts(&[224, 81, 1, 1, 1, 1]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failing_device_is_quarantined() {
+        let healthy = DeviceId(0x0cf2, 0xa102, "GOOD".to_string());
+        let flaky = DeviceId(0x0cf2, 0xa103, "FLAKY".to_string());
+        let backend = MockBackend::new(vec![healthy.clone(), flaky.clone()]);
+        let packets = backend.packets();
+        backend.fail_writes_to(&flaky);
+
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        let tick = |controller: &mut FanController| {
+            for device_id in [&healthy, &flaky] {
+                for channel in 0..2 {
+                    controller.enqueue(SpeedCommand {
+                        device_id: device_id.clone(),
+                        channel,
+                        mode: ChannelMode::Manual,
+                        speed_percent: percent(50),
+                        reason: SpeedReason::Curve,
+                    });
+                }
+            }
+        };
+
+        for _ in 0..QUARANTINE_AFTER_FAILURES {
+            tick(&mut controller);
+            let results = controller.flush().await;
+            assert_eq!(results.iter().filter(|(_, r)| r.is_err()).count(), 2);
+        }
+        assert!(controller.is_quarantined(&flaky));
+        assert!(!controller.is_quarantined(&healthy));
+        assert_eq!(controller.queue_stats().quarantined, 1);
+
+        // The flaky hub is skipped until its probe is due; the healthy one
+        // is written as usual
+        packets.lock().unwrap().clear();
+        tick(&mut controller);
+        let results = controller.flush().await;
+        assert_eq!(results.len(), 2);
+        assert!(results
+            .iter()
+            .all(|(c, r)| c.device_id == healthy && r.is_ok()));
+        assert_eq!(controller.queue_stats().skipped, 2);
+        assert!(packets
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|(device_id, _)| *device_id == healthy));
+    }
+
+    #[tokio::test]
+    async fn test_hung_write_times_out() {
+        let device_id = DeviceId(0x0cf2, 0xa103, "HUNG".to_string());
+        let backend = MockBackend::new(vec![device_id.clone()]);
+        let packets = backend.packets();
+        let hangs = backend.hangs();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_io_timeout(Some(Duration::from_millis(200)));
+        let speed = |controller: &mut FanController, speed_percent| {
+            controller.enqueue(SpeedCommand {
+                device_id: device_id.clone(),
+                channel: 0,
+                mode: ChannelMode::Manual,
+                speed_percent: percent(speed_percent),
+                reason: SpeedReason::Curve,
+            });
+        };
+
+        speed(&mut controller, 50);
+        assert!(controller.flush().await.iter().all(|(_, r)| r.is_ok()));
+        assert_eq!(packets.lock().unwrap().len(), 3);
+
+        // A hung write fails the flush instead of blocking it, and the
+        // device is quarantined straight away
+        hangs.hang(&device_id);
+        speed(&mut controller, 60);
+        let results = controller.flush().await;
+        assert!(HardwareError::is_timeout(
+            results[0].1.as_ref().unwrap_err()
+        ));
+        assert!(controller.is_quarantined(&device_id));
+        assert_eq!(controller.queue_stats().io_timeouts, 1);
+
+        // The probe runs on a fresh thread
+        hangs.release(&device_id);
+        time::pause();
+        time::advance(PROBE_BACKOFF[0]).await;
+        time::resume();
+        speed(&mut controller, 70);
+        assert!(controller.flush().await.iter().all(|(_, r)| r.is_ok()));
+        assert!(!controller.is_quarantined(&device_id));
+        assert_eq!(
+            packets.lock().unwrap().last().unwrap().1,
+            vec![224, 32, 0, 73]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hung_report_read_times_out() {
+        let device_id = DeviceId(0x0cf2, 0xa103, "HUNG".to_string());
+        let backend = MockBackend::new(vec![device_id.clone()])
+            .with_reply(vec![224, 80, 0, 0], vec![224, 80, 3, 2, 0, 0]);
+        let hangs = backend.hangs();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_hub_reports(true);
+        controller.set_io_timeout(Some(Duration::from_millis(200)));
+
+        hangs.hang(&device_id);
+        assert!(matches!(
+            controller.query_fan_counts(&device_id).await,
+            Err(HardwareError::Timeout { .. })
+        ));
+        assert_eq!(controller.queue_stats().io_timeouts, 1);
+
+        // The next read starts a fresh thread
+        hangs.release(&device_id);
+        assert_eq!(
+            controller.query_fan_counts(&device_id).await.unwrap(),
+            vec![3, 2, 0, 0]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_apply_channel_speeds_bursts() {
+        let device_id = DeviceId(0x0cf2, 0xa103, "BURST".to_string());
+        let backend = MockBackend::new(vec![device_id.clone()]).with_hid_settle_times();
+        let packets = backend.packets();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        let channels: Vec<_> = (0..4)
+            .map(|channel| (channel, ChannelMode::Manual, percent(50)))
+            .collect();
+        let sent = || -> Vec<Vec<u8>> {
+            let sent = packets.lock().unwrap().drain(..).map(|(_, p)| p).collect();
+            sent
+        };
+
+        // Every mode change first, then the speeds back to back
+        let started = Instant::now();
+        let results = controller.apply_channel_speeds(&device_id, &channels).await;
+        assert_eq!(
+            results.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(
+            sent(),
+            vec![
+                vec![224, 16, 97, 0, 0, 0, 0],
+                vec![224, 16, 98, 16],
+                vec![224, 16, 98, 32],
+                vec![224, 16, 98, 64],
+                vec![224, 16, 98, 128],
+                vec![224, 32, 0, 56],
+                vec![224, 33, 0, 56],
+                vec![224, 34, 0, 56],
+                vec![224, 35, 0, 56],
+            ]
+        );
+        // The RGB sync, four modes and four speeds each wait out the hub's
+        // settle time
+        assert_eq!(started.elapsed(), Duration::from_millis(5 * 200 + 4 * 100));
+
+        // Modes already set: only the burst, spaced as the hub needs
+        let started = Instant::now();
+        controller.apply_channel_speeds(&device_id, &channels).await;
+        assert_eq!(sent().len(), 4);
+        assert_eq!(started.elapsed(), Duration::from_millis(4 * 100));
+
+        // A configured command delay, such as bench recommends, spaces the
+        // burst instead, and follows it
+        controller.set_command_delay(Some(Duration::from_millis(50)));
+        let started = Instant::now();
+        controller.apply_channel_speeds(&device_id, &channels).await;
+        assert_eq!(started.elapsed(), Duration::from_millis(200));
+        assert_eq!(sent().len(), 4);
+
+        // A channel left to the motherboard only gets its mode
+        let results = controller
+            .apply_channel_speeds(
+                &device_id,
+                &[
+                    (0, ChannelMode::PWM, percent(0)),
+                    (1, ChannelMode::Manual, percent(50)),
+                ],
+            )
+            .await;
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(sent(), vec![vec![224, 16, 98, 17], vec![224, 33, 0, 56]]);
+    }
+
+    async fn run_denied(policy: PermissionPolicy, denials: usize) -> (FanController, Vec<bool>) {
+        let device_id = DeviceId(0x0cf2, 0xa103, "LOCKED".to_string());
+        let backend = MockBackend::new(vec![device_id.clone()]);
+        backend.deny_access_to(&device_id, denials);
+
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_permission_policy(policy);
+        controller.permission_retry_delay = Duration::from_millis(1);
+        for channel in 0..2 {
+            controller.enqueue(SpeedCommand {
+                device_id: device_id.clone(),
+                channel,
+                mode: ChannelMode::Manual,
+                speed_percent: percent(50),
+                reason: SpeedReason::Curve,
+            });
+        }
+        let results = controller.flush().await;
+        let denied = results
+            .iter()
+            .map(|(_, r)| matches!(r, Err(e) if HardwareError::is_permission_denied(e)))
+            .collect();
+        (controller, denied)
+    }
+
+    #[tokio::test]
+    async fn test_permission_policies() {
+        let locked = DeviceId(0x0cf2, 0xa103, "LOCKED".to_string());
+
+        // exit: the error reaches the caller, which stops the daemon
+        let (controller, denied) = run_denied(PermissionPolicy::Exit, 1).await;
+        assert_eq!(denied, vec![true, false]);
+        assert!(!controller.is_quarantined(&locked));
+
+        // retry: udev catches up after a few attempts
+        let (controller, denied) = run_denied(PermissionPolicy::Retry, 3).await;
+        assert_eq!(denied, vec![false, false]);
+        assert!(!controller.is_quarantined(&locked));
+        let (_, denied) = run_denied(PermissionPolicy::Retry, usize::MAX).await;
+        assert_eq!(denied, vec![true, true]);
+
+        // skip_device: quarantined on the first denial, the rest is skipped
+        let (controller, denied) = run_denied(PermissionPolicy::SkipDevice, usize::MAX).await;
+        assert_eq!(denied, vec![true]);
+        assert!(controller.is_quarantined(&locked));
+        assert_eq!(controller.queue_stats().quarantined, 1);
+    }
+
+    #[tokio::test]
+    async fn test_verify_writes() {
+        let slv2 = DeviceId(0x0cf2, 0xa103, "V2".to_string());
+        let sl = DeviceId(0x0cf2, 0xa100, "V1".to_string());
+        // Channel 0 runs at ~50%, channel 1 is stuck at idle
+        let backend = MockBackend::new(vec![slv2.clone(), sl.clone()]).with_reply(
+            vec![224, 82, 0, 0],
+            [224u16, 82, 1100, 300, 0, 0]
+                .iter()
+                .enumerate()
+                .flat_map(|(i, &v)| {
+                    if i < 2 {
+                        vec![v as u8]
+                    } else {
+                        v.to_be_bytes().to_vec()
+                    }
+                })
+                .collect(),
+        );
+        let packets = backend.packets();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_lighting_control(&slv2, LightingControl::External);
+        controller.set_lighting_control(&sl, LightingControl::External);
+        controller.set_hub_reports(true);
+        controller.set_verify_writes(true);
+
+        for device_id in [&slv2, &sl] {
+            for channel in 0..2 {
+                controller.enqueue(SpeedCommand {
+                    device_id: device_id.clone(),
+                    channel,
+                    mode: ChannelMode::Manual,
+                    speed_percent: percent(50),
+                    reason: SpeedReason::Curve,
+                });
+            }
+        }
+        controller.flush().await;
+        packets.lock().unwrap().clear();
+
+        // Not due yet
+        controller.verify_due(Instant::now()).await;
+        assert!(packets.lock().unwrap().is_empty());
+
+        controller.verify_due(Instant::now() + VERIFY_DELAY).await;
+        let packets: Vec<_> = packets.lock().unwrap().clone();
+        assert_eq!(
+            packets,
+            vec![
+                (slv2.clone(), vec![224, 82, 0, 0]),
+                (slv2.clone(), vec![224, 16, 98, 0x20]),
+                (slv2.clone(), vec![224, 33, 0, 56]),
+            ]
+        );
+        let stats = controller.queue_stats();
+        assert_eq!(stats.verification_failures, 1);
+        assert_eq!(stats.resent, 1);
+        // The SL hub can't report RPMs and is left alone from now on
+        assert!(controller.unverifiable.contains(&sl));
+
+        assert!(rpm_matches(0xa103, percent(50), 1125));
+        assert!(!rpm_matches(0xa103, percent(100), 300));
+        assert!(parse_rpms(&[224, 82, 0, 1]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_verify_writes_distrusts_blank_replies() {
+        let blank = DeviceId(0x0cf2, 0xa103, "BLANK".to_string());
+        let echo = DeviceId(0x0cf2, 0xa103, "ECHO".to_string());
+        let off = DeviceId(0x0cf2, 0xa103, "OFF".to_string());
+        let blank_backend = MockBackend::new(vec![blank.clone(), off.clone()])
+            .with_reply(vec![224, 82, 0, 0], vec![224, 82, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let echo_backend = MockBackend::new(vec![echo.clone()])
+            .with_reply(vec![224, 82, 0, 0], vec![224, 82, 0, 0]);
+        let mut controller =
+            FanController::with_backends(vec![Box::new(blank_backend), Box::new(echo_backend)]);
+        controller.set_hub_reports(true);
+        controller.set_verify_writes(true);
+        assert!(matches!(
+            controller.query_rpms(&blank).await,
+            Err(HardwareError::Unsupported { .. })
+        ));
+
+        for device_id in [&blank, &echo] {
+            controller.enqueue(SpeedCommand {
+                device_id: device_id.clone(),
+                channel: 0,
+                mode: ChannelMode::Manual,
+                speed_percent: percent(80),
+                reason: SpeedReason::Curve,
+            });
+        }
+        controller.flush().await;
+        controller.verify_due(Instant::now() + VERIFY_DELAY).await;
+
+        // Neither reply counts against the writes, and neither hub is asked again
+        let stats = controller.queue_stats();
+        assert_eq!(stats.verification_failures, 0);
+        assert_eq!(stats.resent, 0);
+        assert!(controller.unverifiable.contains(&blank));
+        assert!(controller.unverifiable.contains(&echo));
+
+        // Without experimental_hub_reports nothing is asked at all
+        controller.set_hub_reports(false);
+        assert!(matches!(
+            controller.query_rpms(&off).await,
+            Err(HardwareError::Unsupported { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_channel_management() {
+        let device_id = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let backend = MockBackend::new(vec![device_id.clone()]);
+        let packets = backend.packets();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_channel_management(vec![
+            (device_id.clone(), 1, Manage::SpeedOnly),
+            (device_id.clone(), 2, Manage::MonitorOnly),
+        ]);
+        let take_packets = || -> Vec<Vec<u8>> {
+            packets
+                .lock()
+                .unwrap()
+                .drain(..)
+                .map(|(_, packet)| packet)
+                .collect()
+        };
+
+        // Full: RGB sync once, mode, speed
+        for channel in 0..3 {
+            let _ = controller
+                .set_fan_speed(&device_id, channel, &ChannelMode::Manual, percent(50))
+                .await;
+        }
+        assert_eq!(
+            take_packets(),
+            vec![
+                vec![224, 16, 97, 0, 0, 0, 0],
+                vec![224, 16, 98, 0x10],
+                vec![224, 32, 0, 56],
+                // Speed-only: just the speed
+                vec![224, 33, 0, 56],
+                // Monitor-only: nothing
+            ]
+        );
+        assert!(controller
+            .set_fan_speed(&device_id, 2, &ChannelMode::Manual, percent(50))
+            .await
+            .is_err());
+        // A speed-only channel in PWM mode has nothing to write
+        controller
+            .set_fan_speed(&device_id, 1, &ChannelMode::PWM, percent(0))
+            .await
+            .unwrap();
+        assert!(take_packets().is_empty());
+
+        // apply-defaults sends the mode, and the RGB sync if not sent yet
+        let backend = MockBackend::new(vec![device_id.clone()]);
+        let packets = backend.packets();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller
+            .apply_defaults(&device_id, 1, &ChannelMode::Manual)
+            .await
+            .unwrap();
+        let packets: Vec<Vec<u8>> = packets
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, p)| p.clone())
+            .collect();
+        assert_eq!(
+            packets,
+            vec![vec![224, 16, 97, 0, 0, 0, 0], vec![224, 16, 98, 0x20]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_previous_states() {
+        let slv2 = DeviceId(0x0cf2, 0xa103, "V2".to_string());
+        let sl = DeviceId(0x0cf2, 0xa100, "V1".to_string());
+        let backend = MockBackend::new(vec![slv2.clone(), sl.clone()]).with_reply(
+            vec![224, 81, 0, 0],
+            vec![224, 81, 0, 1, 0, 0, 30, 0, 45, 100],
+        );
+        let packets = backend.packets();
+
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_lighting_control(&slv2, LightingControl::External);
+        controller.read_previous_states().await;
+        assert_eq!(controller.previous_state(&slv2, 0), None);
+        assert!(packets.lock().unwrap().is_empty());
+
+        controller.set_hub_reports(true);
+        controller.read_previous_states().await;
+
+        assert_eq!(
+            controller.previous_state(&slv2, 0),
+            Some(&ChannelState {
+                mode: ChannelMode::Manual,
+                speed_percent: percent(30)
+            })
+        );
+        assert_eq!(
+            controller.previous_state(&slv2, 1).map(|s| &s.mode),
+            Some(&ChannelMode::PWM)
+        );
+        assert_eq!(controller.previous_state(&sl, 0), None);
+        assert_eq!(
+            *packets.lock().unwrap(),
+            vec![(slv2.clone(), vec![224, 81, 0, 0])]
+        );
+
+        // The layout is a guess, so the states are shown but not restored
+        assert_eq!(controller.restorable_state(&slv2, 0), None);
+        packets.lock().unwrap().clear();
+        controller.restore_previous_states().await;
+        assert!(packets.lock().unwrap().is_empty());
+
+        assert!(parse_channel_states(&[224, 81, 2, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(parse_channel_states(&[224, 81, 0, 0]).is_err());
+        assert!(parse_channel_states(&[224, 81, 0, 0, 0, 0, 101, 0, 0, 0]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_blank_channel_states_are_not_restored() {
+        let slv2 = DeviceId(0x0cf2, 0xa103, "V2".to_string());
+        // An echo of the request, zero-padded, reads as Manual 0% everywhere
+        let backend = MockBackend::new(vec![slv2.clone()]).with_reply(
+            vec![224, 81, 0, 0],
+            vec![224, 81, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        );
+        let packets = backend.packets();
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_hub_reports(true);
+        controller.read_previous_states().await;
+
+        assert_eq!(controller.previous_state(&slv2, 0), None);
+        packets.lock().unwrap().clear();
+        controller.restore_previous_states().await;
+        assert!(packets.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_serial_suggests_match() {
+        let device_id = DeviceId(0x0cf2, 0xa103, "A02B1C3D".to_string());
+        let mut controller =
+            FanController::with_backends(vec![Box::new(MockBackend::new(vec![device_id]))]);
+
+        controller
+            .set_fan_speed(
+                &DeviceId(0x0cf2, 0xa103, "a02b 1c3d".to_string()),
+                0,
+                &ChannelMode::Manual,
+                percent(50),
+            )
+            .await
+            .unwrap();
+
+        let error = controller
+            .set_fan_speed(
+                &DeviceId(0x0cf2, 0xa103, "A02B1C3E".to_string()),
+                0,
+                &ChannelMode::Manual,
+                percent(50),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Device with given device id (0cf2, a103, A02B1C3E) not available; \
+             did you mean device (0cf2, a103, A02B1C3D)?"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unmanaged_devices_are_never_written() {
+        let managed = DeviceId(0x0cf2, 0xa102, "MINE".to_string());
+        let other = DeviceId(0x0cf2, 0xa102, "OTHER".to_string());
+        let backend = MockBackend::new(vec![managed.clone(), other.clone()]);
+        let packets = backend.packets();
+
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_managed_devices(Some(vec![managed.clone()]));
+        assert_eq!(controller.get_available_devices().len(), 2);
+        assert_eq!(controller.get_managed_devices(), vec![managed.clone()]);
+
+        assert!(controller
+            .set_fan_speed(&other, 0, &ChannelMode::Manual, percent(50))
+            .await
+            .is_err());
+        controller
+            .set_fan_speed(&managed, 0, &ChannelMode::Manual, percent(50))
+            .await
+            .unwrap();
+
+        let packets = packets.lock().unwrap();
+        assert!(packets.iter().all(|(device_id, _)| *device_id == managed));
+    }
+
+    #[tokio::test]
+    async fn test_external_lighting_never_syncs() {
+        let packets = run_ticks(LightingControl::External, ChannelMode::Manual, 3).await;
+        assert!(!packets.iter().any(|packet| is_sync_packet(packet)));
+        assert_eq!(
+            packets,
+            vec![
+                vec![224, 16, 98, 0x20],
+                vec![224, 33, 0, 54],
+                vec![224, 33, 0, 54],
+                vec![224, 33, 0, 54],
+            ]
+        );
+
+        let packets = run_ticks(LightingControl::External, ChannelMode::PWM, 3).await;
+        assert!(!packets.iter().any(|packet| is_sync_packet(packet)));
+    }
+
+    #[test]
+    fn test_device_metadata() {
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let other = DeviceId(0x0cf2, 0xa101, "DEF".to_string());
+        let metadata = DeviceMetadata {
+            manufacturer: Some("ENE".to_string()),
+            product: Some("LianLi-UNI FAN-SL-v2.0".to_string()),
+            release_number: 0x0102,
+            port_path: Some("1-4.2".to_string()),
+        };
+        let backend = MockBackend::new(vec![hub.clone(), other.clone()])
+            .with_metadata(&hub, metadata.clone());
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.capture_to(std::sync::Arc::new(std::sync::Mutex::new(Vec::new())));
+
+        assert_eq!(controller.metadata(&hub), Some(metadata.clone()));
+        assert_eq!(controller.metadata(&other), None);
+        assert_eq!(
+            metadata.to_string(),
+            "ENE LianLi-UNI FAN-SL-v2.0, firmware 1.02, on USB port 1-4.2"
+        );
+        assert_eq!(controller.port_paths(), vec![(hub, "1-4.2".to_string())]);
+        assert_eq!(report_string(Some("  ")), None);
+    }
+
+    #[test]
+    fn test_rpm_ranges() {
+        let percent = |p| SpeedPercent::new(p).unwrap();
+        let expected = [
+            ("sl", "UNI SL", 800, 1900),
+            ("al", "UNI AL", 800, 1900),
+            ("sli", "UNI SL-Infinity", 200, 2100),
+            ("slv2", "UNI SL v2", 250, 2000),
+            ("alv2", "UNI AL v2", 250, 2000),
+        ];
+        assert_eq!(FAMILIES.len(), expected.len());
+        for (family, name, min, max) in expected {
+            let model = family_model(family).unwrap();
+            assert_eq!(model.name, name);
+            let range = model.rpm_range.unwrap();
+            assert_eq!((range.rpm(percent(0)), range.rpm(percent(100))), (min, max));
+            assert_eq!(range.speed(min), percent(0));
+            assert_eq!(range.speed(max), percent(100));
+            // Outside the range is as close as the hub gets
+            assert_eq!(range.speed(0), percent(0));
+            assert_eq!(range.speed(u16::MAX), percent(100));
+            for p in [1, 50, 99] {
+                assert_eq!(range.speed(range.rpm(percent(p))), percent(p));
+            }
+        }
+        assert_eq!(family_model("SLv2"), family_model("slv2"));
+        assert_eq!(family_model("tl"), None);
+
+        let slv2 = family_model("slv2").unwrap().rpm_range.unwrap();
+        assert_eq!(slv2.rpm(percent(55)), 1213);
+        assert_eq!(slv2.speed(1200), percent(54));
+        let header = DeviceId(0, 0, "hwmon:/sys/class/hwmon/hwmon2/pwm1".to_string());
+        assert_eq!(device_model(&header).rpm_range, None);
+    }
 }
@@ -0,0 +1,507 @@
+//! The privileged helper: a small process that alone opens the hubs'
+//! hidraw nodes, so the daemon itself can run as an unprivileged user. With
+//! `--helper` the daemon starts it and drives the hubs through a
+//! RemoteBackend over a socketpair; the helper (`uni-sync-curve helper`)
+//! serves the HID backend on its stdin and stdout.
+//!
+//! Each message is a big-endian u32 length followed by that many bytes of
+//! JSON: a Request from the daemon, answered by one Response.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::process::{Child, Command as Process, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::backend::{Command, DeviceMetadata, FanBackend, HardwareError, Report};
+use crate::config::DeviceId;
+use crate::hardware;
+
+/// Largest message either side accepts, far above any real one.
+const MAX_MESSAGE_LEN: u32 = 1 << 20;
+/// Longest the daemon waits for the helper to answer before giving it up
+/// for dead.
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Shortest time between two starts of a helper that keeps exiting.
+pub const RESPAWN_DELAY: Duration = Duration::from_secs(5);
+
+/// What the daemon asks of the helper: the FanBackend calls that reach the
+/// hardware. Every request is typed, and the helper builds the packets from
+/// its own protocol table, so the daemon can't have it write arbitrary bytes.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum Request {
+    /// The devices found, with their metadata.
+    Devices,
+    Send {
+        device_id: DeviceId,
+        command: Command,
+    },
+    ReadReport {
+        device_id: DeviceId,
+        report: Report,
+    },
+    Rescan,
+    Reopen {
+        device_id: DeviceId,
+    },
+    Release,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum Response {
+    Done,
+    Devices(Vec<(DeviceId, Option<DeviceMetadata>)>),
+    Reply(Vec<u8>),
+    Failed(RemoteError),
+}
+
+/// A HardwareError sent across, so the daemon handles a hub the helper
+/// can't open like one it can't open itself.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum RemoteError {
+    Unsupported {
+        device_id: DeviceId,
+        operation: String,
+    },
+    PermissionDenied {
+        device_id: DeviceId,
+    },
+    Other(String),
+}
+
+// The operations a backend reports as unsupported, to give them back their
+// static names on the daemon's side
+const OPERATIONS: &[&str] = &[
+    "resets",
+    "RPM reports",
+    "fan count reports",
+    "channel state reports",
+];
+
+impl From<HardwareError> for RemoteError {
+    fn from(e: HardwareError) -> Self {
+        match e {
+            HardwareError::Unsupported {
+                device_id,
+                operation,
+            } => RemoteError::Unsupported {
+                device_id,
+                operation: operation.to_string(),
+            },
+            HardwareError::PermissionDenied { device_id } => {
+                RemoteError::PermissionDenied { device_id }
+            }
+            e => RemoteError::Other(format!("{:#}", anyhow::Error::from(e))),
+        }
+    }
+}
+
+impl From<anyhow::Error> for RemoteError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<HardwareError>() {
+            Ok(e) => e.into(),
+            Err(e) => RemoteError::Other(format!("{:#}", e)),
+        }
+    }
+}
+
+impl From<RemoteError> for HardwareError {
+    fn from(e: RemoteError) -> Self {
+        match e {
+            RemoteError::Unsupported {
+                device_id,
+                operation,
+            } => HardwareError::Unsupported {
+                device_id,
+                operation: OPERATIONS
+                    .iter()
+                    .find(|known| **known == operation)
+                    .copied()
+                    .unwrap_or("this through the helper"),
+            },
+            RemoteError::PermissionDenied { device_id } => {
+                HardwareError::PermissionDenied { device_id }
+            }
+            RemoteError::Other(message) => HardwareError::Other(anyhow!(message)),
+        }
+    }
+}
+
+pub fn write_message<T: Serialize>(writer: &mut impl Write, message: &T) -> Result<()> {
+    let body = serde_json::to_vec(message)?;
+    let len = u32::try_from(body.len())
+        .ok()
+        .filter(|&len| len <= MAX_MESSAGE_LEN)
+        .ok_or_else(|| anyhow!("Message of {} bytes is too long", body.len()))?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(&body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// The next message, or None when the other side closed the stream between
+/// messages.
+pub fn read_message<T: DeserializeOwned>(reader: &mut impl Read) -> Result<Option<T>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len);
+    if len > MAX_MESSAGE_LEN {
+        bail!("Message of {} bytes is too long", len);
+    }
+    let mut body = vec![0u8; len as usize];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn handle(backend: &mut dyn FanBackend, request: Request) -> Response {
+    let done = |result: Result<()>| match result {
+        Ok(()) => Response::Done,
+        Err(e) => Response::Failed(e.into()),
+    };
+    match request {
+        Request::Devices => Response::Devices(
+            backend
+                .devices()
+                .into_iter()
+                .map(|device_id| {
+                    let metadata = backend.metadata(&device_id);
+                    (device_id, metadata)
+                })
+                .collect(),
+        ),
+        Request::Send { device_id, command } => done(backend.send(&device_id, &command)),
+        Request::ReadReport { device_id, report } => {
+            match backend.read_report(&device_id, report) {
+                Ok(reply) => Response::Reply(reply),
+                Err(e) => Response::Failed(e.into()),
+            }
+        }
+        Request::Rescan => done(backend.rescan()),
+        Request::Reopen { device_id } => done(backend.reopen(&device_id)),
+        Request::Release => done(backend.release()),
+    }
+}
+
+/// Answers requests on `reader` with `backend` until the daemon closes it.
+pub fn serve(
+    backend: &mut dyn FanBackend,
+    mut reader: impl Read,
+    mut writer: impl Write,
+) -> Result<()> {
+    while let Some(request) = read_message(&mut reader)? {
+        write_message(&mut writer, &handle(backend, request))?;
+    }
+    Ok(())
+}
+
+/// A running helper: the daemon's end of the socketpair, and the process
+/// when the daemon started one.
+pub struct Connection {
+    stream: UnixStream,
+    child: Option<Child>,
+}
+
+impl Connection {
+    pub fn new(stream: UnixStream, child: Option<Child>) -> Result<Self> {
+        stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+        Ok(Self { stream, child })
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+pub type Spawn = Box<dyn FnMut() -> Result<Connection> + Send>;
+
+/// Starts `command` with one end of a socketpair as its stdin and stdout.
+/// The command is split on whitespace, without any quoting or escaping, so
+/// a program or argument containing spaces needs a wrapper script.
+pub fn spawn_command(command: &str) -> Result<Spawn> {
+    let words: Vec<String> = command.split_whitespace().map(str::to_string).collect();
+    let Some((program, args)) = words.split_first() else {
+        bail!("The helper command is empty");
+    };
+    let (program, args) = (program.clone(), args.to_vec());
+    Ok(Box::new(move || {
+        let (ours, theirs) = UnixStream::pair()?;
+        let child = Process::new(&program)
+            .args(&args)
+            .stdin(Stdio::from(OwnedFd::from(theirs.try_clone()?)))
+            .stdout(Stdio::from(OwnedFd::from(theirs)))
+            .spawn()
+            .with_context(|| format!("Could not start the helper {:?}", program))?;
+        Connection::new(ours, Some(child))
+    }))
+}
+
+/// Drives the hubs through a helper. A helper that exits or stops answering
+/// fails the call it was asked, which the controller counts against the
+/// device as any failed write, and is started again on a later call, at
+/// most once per `RESPAWN_DELAY`.
+pub struct RemoteBackend {
+    spawn: Spawn,
+    connection: Option<Connection>,
+    devices: Vec<(DeviceId, Option<DeviceMetadata>)>,
+    last_spawn: Option<Instant>,
+    respawn_delay: Duration,
+    /// How often the helper was started, the first time included.
+    spawns: usize,
+}
+
+impl RemoteBackend {
+    /// Starts the helper and asks it for its devices.
+    pub fn new(spawn: Spawn) -> Result<Self> {
+        let mut backend = Self {
+            spawn,
+            connection: None,
+            devices: Vec::new(),
+            last_spawn: None,
+            respawn_delay: RESPAWN_DELAY,
+            spawns: 0,
+        };
+        backend.refresh_devices()?;
+        Ok(backend)
+    }
+
+    fn call(&mut self, request: &Request) -> Result<Response> {
+        if self.connection.is_none() {
+            if let Some(elapsed) = self.last_spawn.map(|at| at.elapsed()) {
+                if elapsed < self.respawn_delay {
+                    bail!(
+                        "The helper exited; starting it again in {:?}",
+                        self.respawn_delay - elapsed
+                    );
+                }
+            }
+            self.last_spawn = Some(Instant::now());
+            self.spawns += 1;
+            self.connection = Some((self.spawn)()?);
+        }
+        let connection = self.connection.as_mut().expect("connected above");
+        let result = write_message(&mut connection.stream, request).and_then(|()| {
+            read_message(&mut connection.stream)?.ok_or_else(|| anyhow!("The helper exited"))
+        });
+        if result.is_err() {
+            self.connection = None;
+        }
+        result.context("Lost the hub helper")
+    }
+
+    fn expect_done(&mut self, request: &Request) -> Result<()> {
+        match self.call(request)? {
+            Response::Done => Ok(()),
+            Response::Failed(e) => Err(HardwareError::from(e).into()),
+            response => bail!("Unexpected answer from the helper: {:?}", response),
+        }
+    }
+
+    fn refresh_devices(&mut self) -> Result<()> {
+        match self.call(&Request::Devices)? {
+            Response::Devices(devices) => self.devices = devices,
+            response => bail!("Unexpected answer from the helper: {:?}", response),
+        }
+        Ok(())
+    }
+}
+
+impl FanBackend for RemoteBackend {
+    fn devices(&self) -> Vec<DeviceId> {
+        self.devices
+            .iter()
+            .map(|(device_id, _)| device_id.clone())
+            .collect()
+    }
+
+    fn metadata(&self, device_id: &DeviceId) -> Option<DeviceMetadata> {
+        self.devices
+            .iter()
+            .find(|(id, _)| id == device_id)
+            .and_then(|(_, metadata)| metadata.clone())
+    }
+
+    fn send(&mut self, device_id: &DeviceId, command: &Command) -> Result<()> {
+        self.expect_done(&Request::Send {
+            device_id: device_id.clone(),
+            command: command.clone(),
+        })
+    }
+
+    fn rescan(&mut self) -> Result<()> {
+        self.expect_done(&Request::Rescan)?;
+        self.refresh_devices()
+    }
+
+    fn reopen(&mut self, device_id: &DeviceId) -> Result<()> {
+        self.expect_done(&Request::Reopen {
+            device_id: device_id.clone(),
+        })
+    }
+
+    fn settle_time(&self, command: &Command) -> Duration {
+        hardware::hid_settle_time(command)
+    }
+
+    fn read_report(
+        &mut self,
+        device_id: &DeviceId,
+        report: Report,
+    ) -> Result<Vec<u8>, HardwareError> {
+        let request = Request::ReadReport {
+            device_id: device_id.clone(),
+            report,
+        };
+        match self.call(&request)? {
+            Response::Reply(reply) => Ok(reply),
+            Response::Failed(e) => Err(e.into()),
+            response => Err(anyhow!("Unexpected answer from the helper: {:?}", response).into()),
+        }
+    }
+
+    fn release(&mut self) -> Result<()> {
+        self.expect_done(&Request::Release)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SpeedPercent;
+    use crate::mock::MockBackend;
+    use std::net::Shutdown;
+    use std::sync::{Arc, Mutex};
+
+    /// Helpers served by MockBackends on threads; each spawn takes the next
+    /// backend. `helpers` gets each helper's end of the socketpair, to cut
+    /// it off as if the process died.
+    fn fake_helper(mut backends: Vec<MockBackend>, helpers: Arc<Mutex<Vec<UnixStream>>>) -> Spawn {
+        backends.reverse();
+        Box::new(move || {
+            let mut backend = backends.pop().ok_or_else(|| anyhow!("No more helpers"))?;
+            let (ours, theirs) = UnixStream::pair()?;
+            helpers.lock().unwrap().push(theirs.try_clone()?);
+            std::thread::spawn(move || {
+                let reader = theirs.try_clone().unwrap();
+                let _ = serve(&mut backend, reader, theirs);
+            });
+            Connection::new(ours, None)
+        })
+    }
+
+    fn hub() -> DeviceId {
+        DeviceId(0x0cf2, 0xa103, "ABC".to_string())
+    }
+
+    #[test]
+    fn test_messages() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &Request::Rescan).unwrap();
+        write_message(&mut buffer, &Response::Reply(vec![224, 82])).unwrap();
+        assert_eq!(&buffer[..4], &[0, 0, 0, 8]);
+        let mut reader = buffer.as_slice();
+        assert_eq!(
+            read_message::<Request>(&mut reader).unwrap(),
+            Some(Request::Rescan)
+        );
+        assert_eq!(
+            read_message::<Response>(&mut reader).unwrap(),
+            Some(Response::Reply(vec![224, 82]))
+        );
+        assert_eq!(read_message::<Request>(&mut reader).unwrap(), None);
+
+        let too_long = (MAX_MESSAGE_LEN + 1).to_be_bytes();
+        assert!(read_message::<Request>(&mut too_long.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_remote_backend() {
+        let metadata = DeviceMetadata {
+            release_number: 0x0102,
+            ..DeviceMetadata::default()
+        };
+        let mock = MockBackend::new(vec![hub()])
+            .with_metadata(&hub(), metadata.clone())
+            .with_reply(vec![224, 80, 0, 0], vec![224, 80, 1, 2, 3, 4]);
+        let packets = mock.packets();
+        mock.deny_access_to(&hub(), 1);
+        let mut remote = RemoteBackend::new(fake_helper(vec![mock], Arc::default())).unwrap();
+
+        assert_eq!(remote.devices(), vec![hub()]);
+        assert_eq!(remote.metadata(&hub()), Some(metadata));
+
+        let command = Command::SetSpeed {
+            channel: 1,
+            speed_percent: SpeedPercent::new(50).unwrap(),
+        };
+        let denied = remote.send(&hub(), &command).unwrap_err();
+        assert!(HardwareError::is_permission_denied(&denied));
+        remote.send(&hub(), &command).unwrap();
+        assert_eq!(
+            remote.read_report(&hub(), Report::FanCounts).unwrap(),
+            vec![224, 80, 1, 2, 3, 4]
+        );
+        assert!(remote.read_report(&hub(), Report::ChannelStates).is_err());
+        let sl = DeviceId(0x0cf2, 0xa100, "ABC".to_string());
+        assert!(matches!(
+            remote.read_report(&sl, Report::Rpms),
+            Err(HardwareError::Unsupported {
+                operation: "RPM reports",
+                ..
+            })
+        ));
+        assert_eq!(
+            *packets.lock().unwrap(),
+            vec![
+                (hub(), vec![224, 33, 0, 56]),
+                (hub(), vec![224, 80, 0, 0]),
+                (hub(), vec![224, 81, 0, 0]),
+            ]
+        );
+        assert_eq!(remote.spawns, 1);
+    }
+
+    #[test]
+    fn test_helper_restart() {
+        let helpers = Arc::new(Mutex::new(Vec::new()));
+        let first = MockBackend::new(vec![hub()]);
+        let second = MockBackend::new(vec![hub()]);
+        let packets = second.packets();
+        let mut remote =
+            RemoteBackend::new(fake_helper(vec![first, second], helpers.clone())).unwrap();
+        remote.respawn_delay = Duration::from_secs(3600);
+
+        helpers.lock().unwrap()[0].shutdown(Shutdown::Both).unwrap();
+        let command = Command::SyncRgb(false);
+        assert!(remote.send(&hub(), &command).is_err());
+        // Not started again straight away
+        let error = remote.send(&hub(), &command).unwrap_err();
+        assert!(error.to_string().contains("starting it again"));
+        assert_eq!(remote.spawns, 1);
+
+        remote.respawn_delay = Duration::ZERO;
+        remote.send(&hub(), &command).unwrap();
+        assert_eq!(remote.spawns, 2);
+        assert_eq!(packets.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_helper_process_exits() {
+        assert!(spawn_command("   ").is_err());
+        let error = RemoteBackend::new(spawn_command("true").unwrap())
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("Lost the hub helper"));
+    }
+}
@@ -0,0 +1,333 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ring::{BufferUsage, RingBuffer};
+use crate::units::DisplayUnits;
+
+/// How many recent samples the rate of change is estimated from.
+pub const HISTORY_SAMPLES: usize = 5;
+
+/// Recent temperature samples, for signals that depend on more than the
+/// current reading.
+#[derive(Debug)]
+pub struct TemperatureHistory {
+    // (seconds since start, °C), oldest first
+    samples: RingBuffer<(f64, f64)>,
+    // How far back rate_over may look, beyond the last HISTORY_SAMPLES
+    keep_seconds: f64,
+}
+
+impl Default for TemperatureHistory {
+    fn default() -> Self {
+        Self {
+            samples: RingBuffer::new(HISTORY_SAMPLES),
+            keep_seconds: 0.0,
+        }
+    }
+}
+
+impl TemperatureHistory {
+    pub fn push(&mut self, time_seconds: f64, temperature: f64) {
+        self.samples.push((time_seconds, temperature));
+        // Keep one sample at or before the cutoff, for rate_over to start from
+        let cutoff = time_seconds - self.keep_seconds;
+        while self.samples.len() > HISTORY_SAMPLES
+            && self.samples.get(1).is_some_and(|&(time, _)| time <= cutoff)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Keeps enough samples for rate_over to look `seconds` back, taking one
+    /// every `interval_seconds`. Samples come a little early when a tick's
+    /// read is quick, so there is room for one more than the span needs.
+    pub fn keep_for(&mut self, seconds: f64, interval_seconds: u64) {
+        self.keep_seconds = seconds;
+        let span = (seconds / interval_seconds.max(1) as f64).ceil() as usize;
+        self.samples.set_capacity(HISTORY_SAMPLES.max(span + 2));
+    }
+
+    pub fn usage(&self) -> BufferUsage {
+        self.samples.usage()
+    }
+
+    /// Least-squares slope of the last HISTORY_SAMPLES samples in °C per
+    /// second, once there are at least two samples spread over time.
+    pub fn rate(&self) -> Option<f64> {
+        let recent = self.samples.len().min(HISTORY_SAMPLES);
+        let samples = || self.samples.iter().skip(self.samples.len() - recent);
+        if recent < 2 {
+            return None;
+        }
+        let n = recent as f64;
+        let mean_time = samples().map(|(t, _)| t).sum::<f64>() / n;
+        let mean_temperature = samples().map(|(_, c)| c).sum::<f64>() / n;
+
+        let mut covariance = 0.0;
+        let mut variance = 0.0;
+        for (time, temperature) in samples() {
+            covariance += (time - mean_time) * (temperature - mean_temperature);
+            variance += (time - mean_time).powi(2);
+        }
+        (variance > 0.0).then(|| covariance / variance)
+    }
+
+    /// Average rate of change in °C per second from the newest sample at
+    /// least `seconds` old to the latest one. None until the kept samples
+    /// go back that far; see keep_for.
+    pub fn rate_over(&self, seconds: f64) -> Option<f64> {
+        let &(latest_time, latest) = self.samples.back()?;
+        let &(start_time, start) = self
+            .samples
+            .iter()
+            .rev()
+            .find(|(time, _)| *time <= latest_time - seconds)?;
+        (latest_time > start_time).then(|| (latest - start) / (latest_time - start_time))
+    }
+
+    /// Average temperature over the last `seconds` up to the latest sample.
+    /// Each stretch between two samples counts for its length, with the
+    /// temperature taken to change linearly across it, so the average
+    /// doesn't depend on how often samples came. While the kept samples
+    /// don't go back that far, it is over those there are; see keep_for.
+    pub fn average_over(&self, seconds: f64) -> Option<f64> {
+        let &(latest_time, latest) = self.samples.back()?;
+        let start = latest_time - seconds;
+        let mut area = 0.0;
+        let mut covered = 0.0;
+        let mut previous: Option<(f64, f64)> = None;
+        for &(time, temperature) in self.samples.iter() {
+            if let Some((previous_time, previous_temperature)) = previous {
+                if time > start {
+                    // Only the part of the stretch inside the window
+                    let (from, from_temperature) = if previous_time < start {
+                        let progress = (start - previous_time) / (time - previous_time);
+                        (
+                            start,
+                            previous_temperature + (temperature - previous_temperature) * progress,
+                        )
+                    } else {
+                        (previous_time, previous_temperature)
+                    };
+                    area += (from_temperature + temperature) / 2.0 * (time - from);
+                    covered += time - from;
+                }
+            }
+            previous = Some((time, temperature));
+        }
+        Some(if covered > 0.0 {
+            area / covered
+        } else {
+            latest
+        })
+    }
+}
+
+/// A stale reading carried forward along its trend to estimate the
+/// temperature now.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Extrapolation {
+    pub raw_celsius: f64,
+    /// The trend, in °C per second.
+    pub rate: f64,
+    pub latency_seconds: f64,
+    pub celsius: f64,
+}
+
+impl Extrapolation {
+    pub fn describe(&self, units: DisplayUnits) -> String {
+        format!(
+            "{} ({}/s) extrapolated to {} over {}s of reading latency",
+            units.temperature(self.raw_celsius),
+            units.delta(self.rate),
+            units.temperature(self.celsius),
+            self.latency_seconds
+        )
+    }
+}
+
+/// `raw` moved by `rate` °C/s over `latency_seconds`, by at most
+/// `max_celsius` either way. None without a latency or a trend, when the
+/// reading is used as it is.
+pub fn extrapolate(
+    raw: f64,
+    rate: Option<f64>,
+    latency_seconds: f64,
+    max_celsius: f64,
+) -> Option<Extrapolation> {
+    let rate = rate.filter(|_| latency_seconds > 0.0)?;
+    Some(Extrapolation {
+        raw_celsius: raw,
+        rate,
+        latency_seconds,
+        celsius: raw + (rate * latency_seconds).clamp(-max_celsius, max_celsius),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate() {
+        let mut history = TemperatureHistory::default();
+        assert_eq!(history.rate(), None);
+        history.push(0.0, 40.0);
+        assert_eq!(history.rate(), None);
+
+        for second in 1..10 {
+            history.push(second as f64, 40.0 + 2.0 * second as f64);
+        }
+        assert_eq!(history.rate(), Some(2.0));
+
+        for second in 10..15 {
+            history.push(second as f64, 50.0);
+        }
+        assert_eq!(history.rate(), Some(0.0));
+    }
+
+    #[test]
+    fn test_rate_over() {
+        let mut history = TemperatureHistory::default();
+        history.keep_for(10.0, 1);
+        for second in 0..=10 {
+            history.push(second as f64, 40.0);
+        }
+        assert_eq!(history.rate_over(10.0), Some(0.0));
+        // Looking further back than kept says nothing
+        assert_eq!(history.rate_over(20.0), None);
+
+        // A single jump is averaged over the whole window
+        history.push(11.0, 45.0);
+        assert_eq!(history.rate_over(10.0), Some(0.5));
+        let climb = |history: &mut TemperatureHistory, seconds| {
+            for second in seconds {
+                history.push(second as f64, 45.0 + 2.0 * (second - 11) as f64);
+            }
+        };
+        climb(&mut history, 12..=15);
+        assert_eq!(history.rate_over(10.0), Some(1.3));
+        // rate only follows the last few samples
+        assert_eq!(history.rate(), Some(2.0));
+
+        climb(&mut history, 16..=21);
+        assert_eq!(history.rate_over(10.0), Some(2.0));
+        assert_eq!(history.samples.len(), 11);
+    }
+
+    #[test]
+    fn test_average_over() {
+        let mut history = TemperatureHistory::default();
+        assert_eq!(history.average_over(120.0), None);
+        history.push(0.0, 40.0);
+        assert_eq!(history.average_over(120.0), Some(40.0));
+
+        // Irregular samples count for the time they cover, not one each:
+        // 40°C for 10s, a 2s climb, then 60°C for 88s
+        history.keep_for(120.0, 5);
+        for (time, temperature) in [(10.0, 40.0), (12.0, 60.0), (100.0, 60.0)] {
+            history.push(time, temperature);
+        }
+        // Not 100s of samples yet, so over those there are
+        assert_eq!(history.average_over(120.0), Some(57.8));
+        assert_eq!(history.average_over(50.0), Some(60.0));
+        // Cut partway through the climb at 11s: 50°C to 60°C for 1s
+        assert_eq!(
+            history.average_over(89.0),
+            Some((55.0 + 60.0 * 88.0) / 89.0)
+        );
+    }
+
+    #[test]
+    fn test_average_over_missed_ticks() {
+        let mut history = TemperatureHistory::default();
+        history.keep_for(30.0, 5);
+        for tick in 0..=20 {
+            history.push(tick as f64 * 5.0, 40.0);
+        }
+        // Ticks from 100s to 130s were missed
+        history.push(130.0, 70.0);
+        assert_eq!(history.average_over(30.0), Some(55.0));
+        history.push(131.0, 70.0);
+        let average = history.average_over(30.0).unwrap();
+        assert!((average - (55.5 * 29.0 + 70.0) / 30.0).abs() < 1e-9);
+        // A sample from before the window stays kept to start it from
+        assert!(history.samples.iter().next().unwrap().0 <= 101.0);
+    }
+
+    #[test]
+    fn test_extrapolate() {
+        assert_eq!(extrapolate(50.0, None, 5.0, 5.0), None);
+        assert_eq!(extrapolate(50.0, Some(1.0), 0.0, 5.0), None);
+        assert_eq!(
+            extrapolate(50.0, Some(0.5), 4.0, 5.0).unwrap().celsius,
+            52.0
+        );
+        // Clamped both ways
+        assert_eq!(
+            extrapolate(50.0, Some(3.0), 4.0, 5.0).unwrap().celsius,
+            55.0
+        );
+        assert_eq!(
+            extrapolate(50.0, Some(-3.0), 4.0, 5.0).unwrap().celsius,
+            45.0
+        );
+        assert_eq!(
+            extrapolate(50.0, Some(0.5), 4.0, 5.0)
+                .unwrap()
+                .describe(DisplayUnits::Celsius),
+            "50.0°C (+0.5°C/s) extrapolated to 52.0°C over 4s of reading latency"
+        );
+    }
+
+    #[test]
+    fn test_extrapolation_reduces_lag() {
+        // 40°C, then a climb of 1°C/s to 80°C, read 5s late
+        let actual = |second: i64| (40.0 + (second - 10) as f64).clamp(40.0, 80.0);
+        let delayed = |second: i64| actual(second - 5);
+        let crossing = |latency_seconds: f64| {
+            let mut history = TemperatureHistory::default();
+            let mut crossed = None;
+            let mut errors = Vec::new();
+            for second in 0..80 {
+                let reading = delayed(second);
+                history.push(second as f64, reading);
+                let estimate = extrapolate(reading, history.rate(), latency_seconds, 5.0)
+                    .map_or(reading, |extrapolation| extrapolation.celsius);
+                if estimate >= 60.0 && crossed.is_none() {
+                    crossed = Some(second);
+                }
+                errors.push((estimate - actual(second)).abs());
+            }
+            (crossed.unwrap(), errors)
+        };
+
+        let (plain, plain_errors) = crossing(0.0);
+        let (compensated, compensated_errors) = crossing(5.0);
+        // 60°C is reached at 30s
+        assert_eq!(plain, 35);
+        assert_eq!(compensated, 30);
+        let total = |errors: &[f64]| errors.iter().sum::<f64>();
+        assert!(total(&compensated_errors) < total(&plain_errors) / 2.0);
+        // Once the temperature settles, so does the estimate
+        assert_eq!(compensated_errors[79], 0.0);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut history = TemperatureHistory::default();
+        history.keep_for(60.0, 2);
+        assert_eq!(history.usage().capacity, 32);
+        // A day of samples, some coming early
+        for tick in 0..43_200 {
+            let jitter = if tick % 7 == 0 { 0.5 } else { 0.0 };
+            history.push(tick as f64 * 2.0 - jitter, 40.0);
+            assert!(history.usage().len <= history.usage().capacity);
+        }
+        assert_eq!(history.rate_over(60.0), Some(0.0));
+
+        // Shorter windows shrink it
+        history.keep_for(0.0, 2);
+        assert_eq!(history.usage().capacity, HISTORY_SAMPLES);
+        assert_eq!(history.usage().len, HISTORY_SAMPLES);
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::{anyhow, Result};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time;
+
+use crate::backend::{Command, DeviceWriter, HardwareError, Report};
+use crate::config::DeviceId;
+
+/// Commands waiting for a device's I/O thread beyond this fail at once.
+const IO_QUEUE_DEPTH: usize = 8;
+
+enum Job {
+    Send(Command, oneshot::Sender<Result<()>>),
+    Read(Report, oneshot::Sender<Result<Vec<u8>, HardwareError>>),
+}
+
+/// An OS thread that owns one device's writer and does all its blocking
+/// I/O, opening the device included, so a wedged USB stack stalls that
+/// thread rather than the daemon.
+pub struct DeviceThread {
+    jobs: SyncSender<Job>,
+}
+
+impl DeviceThread {
+    pub fn spawn(device_id: &DeviceId, mut writer: Box<dyn DeviceWriter>) -> Result<Self> {
+        let (jobs, queued) = mpsc::sync_channel::<Job>(IO_QUEUE_DEPTH);
+        std::thread::Builder::new()
+            .name(format!("io {}", device_id))
+            .spawn(move || {
+                // Ends once the DeviceThread is dropped, or after the write
+                // or read it was abandoned in finally returns
+                for job in queued {
+                    match job {
+                        Job::Send(command, done) => {
+                            let _ = done.send(writer.send(&command));
+                        }
+                        Job::Read(report, done) => {
+                            let _ = done.send(writer.read_report(report));
+                        }
+                    }
+                }
+            })?;
+        Ok(Self { jobs })
+    }
+
+    /// Sends `command` on the thread and waits up to `timeout` for it. A
+    /// write that takes longer fails with HardwareError::Timeout; the thread
+    /// is still stuck in it, so the caller should drop this DeviceThread.
+    pub async fn send(
+        &self,
+        device_id: &DeviceId,
+        command: &Command,
+        timeout: Duration,
+    ) -> Result<()> {
+        let (done, result) = oneshot::channel();
+        self.queue(device_id, Job::Send(command.clone(), done))?;
+        match time::timeout(timeout, result).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(anyhow!("I/O thread for device {} has exited", device_id)),
+            Err(_) => Err(HardwareError::Timeout {
+                device_id: device_id.clone(),
+                timeout,
+            }
+            .into()),
+        }
+    }
+
+    /// Reads `report` on the thread and waits up to `timeout` for it. Like
+    /// `send`, a read that takes longer fails with HardwareError::Timeout
+    /// and leaves the thread stuck.
+    pub async fn read_report(
+        &self,
+        device_id: &DeviceId,
+        report: Report,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, HardwareError> {
+        let (done, result) = oneshot::channel();
+        self.queue(device_id, Job::Read(report, done))?;
+        match time::timeout(timeout, result).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(anyhow!("I/O thread for device {} has exited", device_id).into()),
+            Err(_) => Err(HardwareError::Timeout {
+                device_id: device_id.clone(),
+                timeout,
+            }),
+        }
+    }
+
+    fn queue(&self, device_id: &DeviceId, job: Job) -> Result<()> {
+        self.jobs.try_send(job).map_err(|e| match e {
+            TrySendError::Full(_) => anyhow!("I/O queue for device {} is full", device_id),
+            TrySendError::Disconnected(_) => {
+                anyhow!("I/O thread for device {} has exited", device_id)
+            }
+        })
+    }
+}
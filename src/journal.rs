@@ -0,0 +1,280 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::time::Instant;
+
+use crate::config::DeviceId;
+use crate::engine::SpeedReason;
+use crate::hardware::SpeedCommand;
+use crate::names::ChannelNames;
+use crate::ring::{BufferUsage, RingBuffer};
+use crate::timing;
+use crate::units::DisplayUnits;
+
+/// Where the daemon dumps its journal, next to the status file. Only the
+/// latest dump is kept.
+pub const JOURNAL_FILE_NAME: &str = "journal.json";
+
+/// Created by `uni-sync-curve journal` to ask the running daemon for a
+/// dump, and removed by the daemon once it wrote one.
+pub const JOURNAL_REQUEST_FILE_NAME: &str = "journal.request";
+
+pub fn journal_path(status_path: &Path) -> PathBuf {
+    status_path.with_file_name(JOURNAL_FILE_NAME)
+}
+
+pub fn request_path(status_path: &Path) -> PathBuf {
+    status_path.with_file_name(JOURNAL_REQUEST_FILE_NAME)
+}
+
+/// One speed command the daemon tried to write.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JournalEntry {
+    /// When the write finished, in seconds since the Unix epoch.
+    pub at: f64,
+    /// The channel's identifier, as `uni-sync-curve names` lists it.
+    pub channel_id: String,
+    pub device_id: DeviceId,
+    pub channel: usize,
+    pub speed_percent: u8,
+    pub reason: SpeedReason,
+    /// The identifier of the source the daemon followed, and its
+    /// temperature then if it had a reading.
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature_celsius: Option<f64>,
+    /// Why the write failed; none for a write that went through.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What made the daemon dump its journal.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DumpTrigger {
+    /// Asked for with `uni-sync-curve journal`.
+    Request,
+    SourceLost,
+    /// A `full_speed` alert started.
+    FullSpeed,
+    PermissionDenied,
+    Shutdown,
+    Panic,
+}
+
+/// The journal as it was dumped.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JournalDump {
+    pub dumped_at: f64,
+    pub trigger: DumpTrigger,
+    /// Oldest first.
+    pub entries: Vec<JournalEntry>,
+}
+
+/// The last speed commands the daemon wrote, with why and at what
+/// temperature, for working out afterwards what a fan did. It never holds
+/// more than its capacity.
+#[derive(Debug)]
+pub struct Journal {
+    entries: RingBuffer<JournalEntry>,
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: RingBuffer::new(capacity),
+        }
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.entries.set_capacity(capacity);
+    }
+
+    pub fn usage(&self) -> BufferUsage {
+        self.entries.usage()
+    }
+
+    /// Adds the commands of one flush, written at `at` while following the
+    /// source identified `source` at `temperature_celsius`.
+    pub fn record(
+        &mut self,
+        at: f64,
+        flushed: &[(SpeedCommand, Result<()>)],
+        names: &ChannelNames,
+        source: &str,
+        temperature_celsius: Option<f64>,
+    ) {
+        for (command, result) in flushed {
+            self.entries.push(JournalEntry {
+                at,
+                channel_id: names.get(&command.device_id, command.channel),
+                device_id: command.device_id.clone(),
+                channel: command.channel,
+                speed_percent: command.speed_percent.get(),
+                reason: command.reason,
+                source: source.to_string(),
+                temperature_celsius,
+                error: result.as_ref().err().map(|e| e.to_string()),
+            });
+        }
+    }
+
+    pub fn dump(&self, dumped_at: f64, trigger: DumpTrigger) -> JournalDump {
+        JournalDump {
+            dumped_at,
+            trigger,
+            entries: self.entries.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Writes `dump` to `path`, replacing the previous one.
+pub fn write_dump(path: &Path, dump: &JournalDump) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, serde_json::to_string_pretty(dump)?)?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+pub fn read_dump(path: &Path) -> Result<JournalDump> {
+    let content = std::fs::read_to_string(path).with_context(|| {
+        format!(
+            "Could not read {:?}; has the daemon dumped its journal?",
+            path
+        )
+    })?;
+    serde_json::from_str(&content).with_context(|| format!("Could not parse {:?}", path))
+}
+
+/// Removes the request for a dump, telling whether there was one.
+pub fn take_request(path: &Path) -> bool {
+    std::fs::remove_file(path).is_ok()
+}
+
+/// Dumps the journal to `path` when the daemon panics, before the panic
+/// message is printed. A journal locked at the time is left out rather
+/// than waited for.
+pub fn install_panic_hook(journal: Arc<Mutex<Journal>>, path: PathBuf) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let journal = match journal.try_lock() {
+            Ok(journal) => Some(journal),
+            Err(std::sync::TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(std::sync::TryLockError::WouldBlock) => None,
+        };
+        if let Some(journal) = journal {
+            let dump = journal.dump(timing::unix_seconds(Instant::now()), DumpTrigger::Panic);
+            let _ = write_dump(&path, &dump);
+        }
+        default_hook(info);
+    }));
+}
+
+/// Locks a journal shared with the panic hook.
+pub fn lock(journal: &Mutex<Journal>) -> std::sync::MutexGuard<'_, Journal> {
+    journal.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// One line per entry, newest last, timed relative to the dump.
+pub fn format_dump(dump: &JournalDump, units: DisplayUnits) -> String {
+    let mut text = format!(
+        "{} entries, dumped on {:?}\n",
+        dump.entries.len(),
+        dump.trigger
+    );
+    for entry in &dump.entries {
+        let _ = write!(
+            text,
+            "{:>9.1}s  {}  {:>3}%  {:?}",
+            entry.at - dump.dumped_at,
+            entry.channel_id,
+            entry.speed_percent,
+            entry.reason
+        );
+        match entry.temperature_celsius {
+            Some(temperature) => {
+                let _ = write!(
+                    text,
+                    "  {} {}",
+                    entry.source,
+                    units.temperature(temperature)
+                );
+            }
+            None => {
+                let _ = write!(text, "  {} unavailable", entry.source);
+            }
+        }
+        if let Some(error) = &entry.error {
+            let _ = write!(text, "  FAILED: {}", error);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ChannelMode, SpeedPercent};
+
+    fn command(channel: usize, speed: u8) -> SpeedCommand {
+        SpeedCommand {
+            device_id: "0cf2:a100:1".parse().unwrap(),
+            channel,
+            mode: ChannelMode::Manual,
+            speed_percent: SpeedPercent::saturating(speed),
+            reason: SpeedReason::Curve,
+        }
+    }
+
+    #[test]
+    fn test_journal_is_bounded_and_round_trips() {
+        let mut journal = Journal::new(3);
+        for tick in 0..5u8 {
+            journal.record(
+                100.0 + f64::from(tick),
+                &[(command(0, 30 + tick), Ok(()))],
+                &ChannelNames::default(),
+                "cpu",
+                Some(40.0 + f64::from(tick)),
+            );
+        }
+        journal.record(
+            105.0,
+            &[(command(1, 50), Err(anyhow::anyhow!("device busy")))],
+            &ChannelNames::default(),
+            "cpu",
+            None,
+        );
+        assert_eq!(
+            journal.usage(),
+            BufferUsage {
+                len: 3,
+                capacity: 3
+            }
+        );
+
+        let dump = journal.dump(106.0, DumpTrigger::SourceLost);
+        let speeds: Vec<u8> = dump.entries.iter().map(|e| e.speed_percent).collect();
+        assert_eq!(speeds, vec![33, 34, 50]);
+        assert_eq!(dump.entries[2].error.as_deref(), Some("device busy"));
+        assert_eq!(dump.entries[2].temperature_celsius, None);
+
+        let path = std::env::temp_dir().join(format!(
+            "uni-sync-curve-journal-{}/journal.json",
+            std::process::id()
+        ));
+        write_dump(&path, &dump).unwrap();
+        assert_eq!(read_dump(&path).unwrap(), dump);
+        let text = format_dump(&dump, DisplayUnits::Celsius);
+        assert!(text.starts_with("3 entries, dumped on SourceLost\n"));
+        assert!(text
+            .contains("-1.0s  0cf2-a100-1-1   50%  Curve  cpu unavailable  FAILED: device busy"));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
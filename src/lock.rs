@@ -0,0 +1,195 @@
+use anyhow::{bail, Context, Result};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config::DeviceId;
+
+pub const LOCK_DIR: &str = "/run/uni-sync-curve";
+
+/// Exclusive per-device locks, so several daemon instances can each manage
+/// a disjoint set of devices. Locks are released when this is dropped or the
+/// process exits.
+pub struct DeviceLocks {
+    _files: Vec<File>,
+}
+
+fn lock_path(lock_dir: &Path, device_id: &DeviceId) -> PathBuf {
+    // Normalized, so differently written serials of one hub share a lock
+    let serial: String = device_id
+        .normalized_serial()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    lock_dir.join(format!(
+        "device-{:04x}-{:04x}-{}.lock",
+        device_id.0, device_id.1, serial
+    ))
+}
+
+impl DeviceLocks {
+    pub fn acquire(lock_dir: &Path, devices: &[DeviceId]) -> Result<Self> {
+        fs::create_dir_all(lock_dir)
+            .with_context(|| format!("creating lock directory {:?}", lock_dir))?;
+
+        let mut files = Vec::new();
+        for device_id in devices {
+            let path = lock_path(lock_dir, device_id);
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .with_context(|| format!("opening lock file {:?}", path))?;
+
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => {
+                    let owner = fs::read_to_string(&path).unwrap_or_default();
+                    let owner = owner.trim();
+                    bail!(
+                        "Device {} is already managed by another uni-sync-curve instance{} \
+                         (lock file {:?}). Use managed_devices to give each instance a \
+                         disjoint set of devices.",
+                        device_id,
+                        if owner.is_empty() {
+                            String::new()
+                        } else {
+                            format!(" (pid {})", owner)
+                        },
+                        path
+                    );
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(e).with_context(|| format!("locking {:?}", path));
+                }
+            }
+
+            file.set_len(0)?;
+            writeln!(file, "{}", std::process::id())?;
+            files.push(file);
+        }
+
+        Ok(Self { _files: files })
+    }
+}
+
+/// How long a config writer waits for another one to finish.
+pub const CONFIG_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const CONFIG_LOCK_POLL: Duration = Duration::from_millis(20);
+
+/// Held around each read-modify-write of the config file, so writers take
+/// turns instead of losing each other's changes. Separate from the device
+/// locks: it sits next to the config as `<file name>.lock`, and is released
+/// when this is dropped.
+pub struct ConfigLock {
+    _file: File,
+}
+
+impl ConfigLock {
+    /// Waits up to `timeout` for the lock on `config_path`.
+    pub fn acquire(config_path: &Path, timeout: Duration) -> Result<Self> {
+        let mut name = config_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock");
+        let path = config_path.with_file_name(name);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("opening lock file {:?}", path))?;
+
+        let started = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) if started.elapsed() < timeout => {
+                    std::thread::sleep(CONFIG_LOCK_POLL);
+                }
+                Err(TryLockError::WouldBlock) => {
+                    let owner = fs::read_to_string(&path).unwrap_or_default();
+                    bail!(
+                        "{:?} is being modified by pid {}; still locked after {:?} (lock file {:?})",
+                        config_path,
+                        match owner.trim() {
+                            "" => "unknown",
+                            owner => owner,
+                        },
+                        timeout,
+                        path
+                    );
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(e).with_context(|| format!("locking {:?}", path));
+                }
+            }
+        }
+
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disjoint_and_overlapping_locks() {
+        let lock_dir =
+            std::env::temp_dir().join(format!("uni-sync-curve-locks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&lock_dir);
+
+        let hub_a = DeviceId(0x0cf2, 0xa102, "AAAA".to_string());
+        let hub_b = DeviceId(0x0cf2, 0xa102, "BBBB".to_string());
+        let header = DeviceId(0, 0, "hwmon3/pwm2".to_string());
+
+        let first = DeviceLocks::acquire(&lock_dir, &[hub_a.clone(), header.clone()]).unwrap();
+        let second = DeviceLocks::acquire(&lock_dir, std::slice::from_ref(&hub_b)).unwrap();
+
+        let error = DeviceLocks::acquire(&lock_dir, std::slice::from_ref(&hub_b))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("BBBB"), "{}", error);
+        assert!(error.contains(&std::process::id().to_string()), "{}", error);
+        assert!(DeviceLocks::acquire(&lock_dir, std::slice::from_ref(&header)).is_err());
+
+        drop(first);
+        drop(second);
+        assert!(DeviceLocks::acquire(&lock_dir, &[hub_a, hub_b, header]).is_ok());
+
+        fs::remove_dir_all(&lock_dir).unwrap();
+    }
+
+    #[test]
+    fn test_config_lock_times_out() {
+        let dir =
+            std::env::temp_dir().join(format!("uni-sync-curve-config-lock-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("uni-sync-curve.json");
+
+        let held = ConfigLock::acquire(&path, CONFIG_LOCK_TIMEOUT).unwrap();
+        assert!(dir.join("uni-sync-curve.json.lock").exists());
+        let started = Instant::now();
+        let error = ConfigLock::acquire(&path, Duration::from_millis(100))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert!(
+            error.contains(&format!("is being modified by pid {}", std::process::id())),
+            "{}",
+            error
+        );
+
+        drop(held);
+        ConfigLock::acquire(&path, Duration::ZERO).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
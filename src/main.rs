@@ -1,11 +1,70 @@
-mod hardware;
+mod alerts;
+mod backend;
+mod banner;
+mod bench;
+mod budget;
+mod bundle;
+mod capture;
 mod config;
+mod container;
+mod control;
 mod curve;
+mod daemon;
+mod detect;
+mod disabled;
+mod duty;
+mod edit;
+mod engine;
+mod eval;
+mod explain;
+mod export;
+mod expr;
+#[cfg(test)]
+mod fuzz;
+mod gpu;
+mod hardware;
+mod helper;
+mod history;
+mod io_thread;
+mod journal;
+mod lock;
+#[cfg(test)]
+mod mock;
+mod monitor;
+mod names;
+#[cfg(feature = "notify")]
+mod notify;
+mod output;
+mod pipeline;
+mod power;
+mod presets;
+mod quarantine;
+mod ring;
+mod rpm_loop;
+mod sensors;
+mod shutdown;
+#[cfg(test)]
+mod sim;
+mod slew;
+mod state;
+mod status;
+mod style;
+mod sysfs;
+mod throttle;
+mod ticklog;
+mod timing;
+mod units;
+mod vectors;
+mod zero_rpm;
 
-use anyhow::{bail, Result};
-use clap::Parser;
-use std::path::Path;
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use std::io::IsTerminal;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::sync::mpsc;
 use tokio::time;
 
 #[derive(Parser, Debug)]
@@ -20,73 +79,1828 @@ pub struct Args {
 
     #[arg(long, help = "Enable debug logging")]
     pub debug: bool,
+
+    #[arg(long, help = "Don't print the startup summary")]
+    pub quiet: bool,
+
+    #[arg(
+        long,
+        help = "Accept keyboard commands on a terminal: p pause/resume, +/- override, r reload, s status, q quit"
+    )]
+    pub interactive: bool,
+
+    #[arg(
+        long = "status-file",
+        help = "Where the daemon publishes its status (default: /run/uni-sync-curve/status.json)"
+    )]
+    pub status_file: Option<String>,
+
+    #[arg(
+        long,
+        help = "Append every packet sent to and received from the fan devices to this file"
+    )]
+    pub capture: Option<String>,
+
+    #[arg(
+        long,
+        help = "If the configuration can't be loaded, run the built-in default curve on every channel of every hub instead of exiting"
+    )]
+    pub safe_mode_on_config_error: bool,
+
+    #[arg(
+        long,
+        help = "Compute and report speeds but never write to a device, whatever the config's mode"
+    )]
+    pub monitor: bool,
+
+    #[arg(
+        long = "device-serial",
+        help = "Only discover UNI hubs with this serial; may be given several times"
+    )]
+    pub device_serials: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Open the UNI hubs through a helper started with this command, e.g. \"sudo -n uni-sync-curve helper\", so the daemon itself needs no access to hidraw; the command is split on whitespace without quoting, and --device-serial goes to the helper"
+    )]
+    pub helper: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "When the config file is missing: write defaults to it, run defaults without writing, or exit with an error (default: write, error in packaged builds)"
+    )]
+    pub missing_config: Option<config::CreateIfMissing>,
+
+    #[arg(
+        long,
+        conflicts_with = "missing_config",
+        help = "Never write a config file; same as --missing-config error"
+    )]
+    pub no_create_config: bool,
+
+    #[arg(
+        long,
+        help = "Create the config file's missing parent directories, wherever they are"
+    )]
+    pub create_config_dirs: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        global = true,
+        default_value = "text",
+        help = "Output of list-devices, list-sensors, status, eval, convert, names, explain, journal and bench: text, or JSON with a schema_version whose fields stay stable"
+    )]
+    pub output: output::OutputFormat,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
 }
 
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    #[command(about = "Translate the configured fan curves into another tool's config format")]
+    Export {
+        #[arg(long, value_enum)]
+        format: export::ExportFormat,
+    },
+    #[command(about = "Show what the running daemon last applied")]
+    Status {
+        #[arg(
+            long,
+            help = "Also show each channel's time stopped, average and max speed over the last day"
+        )]
+        stats: bool,
+    },
+    #[command(about = "Write a default configuration for the connected devices")]
+    Init {
+        #[arg(long, help = "Replace an existing configuration file")]
+        force: bool,
+        #[arg(
+            long,
+            help = "Temperature source the curves follow: cpu, gpu or gpu:<index or PCI bus id> (see list-sensors). Defaults to the CPU, or the hottest sensor without one"
+        )]
+        source: Option<config::SourceRequest>,
+    },
+    #[command(about = "List the fan devices that can be controlled")]
+    ListDevices,
+    #[command(about = "List the temperature sensors a temperature_source can use")]
+    ListSensors,
+    #[command(about = "Find which hub channels have fans, and compare with the configured curves")]
+    AutoDetectChannels,
+    #[command(about = "Measure how fast a device accepts commands and recommend command_delay_ms")]
+    Bench {
+        #[arg(
+            long,
+            help = "Device to benchmark, as vid:pid:serial in hex (see list-devices)"
+        )]
+        device: config::DeviceId,
+        #[arg(
+            long,
+            default_value_t = 5,
+            help = "Write sequences per channel and delay"
+        )]
+        runs: usize,
+    },
+    #[command(
+        about = "Reinitialize a hub that ignores speed packets, then write its configured state again"
+    )]
+    ResetDevice {
+        #[arg(help = "Device to reset, as vid:pid:serial in hex (see list-devices)")]
+        device: config::DeviceId,
+    },
+    #[command(about = "Stop or resume driving one curve's channel")]
+    Curve {
+        #[command(subcommand)]
+        command: CurveCommand,
+    },
+    #[command(
+        about = "Put every speed_only and full channel into its configured mode, once",
+        long_about = "Sends the RGB sync and channel mode packets a full write starts with, for channels the daemon only writes speeds to (manage: speed_only)"
+    )]
+    ApplyDefaults,
+    #[command(about = "Show the speed a configured curve gives at some temperatures")]
+    Eval {
+        #[arg(
+            long,
+            help = "Curve name, or device/channel for unnamed curves (see status)"
+        )]
+        curve: String,
+        #[arg(long = "temp", help = "Temperature in °C; may be given several times")]
+        temps: Vec<f64>,
+        #[arg(long, help = "Temperatures as start:end:step in °C, e.g. 30:80:5")]
+        range: Option<eval::TemperatureRange>,
+        #[arg(
+            long,
+            help = "Also show the speed after the channel's multiplier, offset and min/max speed"
+        )]
+        full_pipeline: bool,
+    },
+    #[command(about = "Show why each curve runs at its speed, stage by stage")]
+    Explain {
+        #[arg(
+            long,
+            help = "Only this curve: its name, or device/channel for unnamed curves"
+        )]
+        curve: Option<String>,
+        #[arg(
+            long,
+            help = "Temperature in °C, instead of the running daemon's last reading"
+        )]
+        temp: Option<f64>,
+        #[arg(
+            long,
+            help = "Read the temperature source now, instead of asking the running daemon"
+        )]
+        once: bool,
+    },
+    #[command(about = "Inspect the configuration")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    #[command(about = "Save or restore runtime state, e.g. across a reboot")]
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+    #[command(about = "Convert between a hub's fan speed in percent and the RPM it aims for")]
+    Convert {
+        #[arg(
+            long,
+            help = "Hub family (sl, al, sli, slv2, alv2), or a device as vid:pid:serial in hex"
+        )]
+        device: String,
+        #[arg(
+            long,
+            required_unless_present = "percent",
+            conflicts_with = "percent",
+            help = "RPM to convert to a speed"
+        )]
+        rpm: Option<u16>,
+        #[arg(
+            long,
+            value_parser = clap::value_parser!(u8).range(0..=100),
+            help = "Speed in percent to convert to RPM"
+        )]
+        percent: Option<u8>,
+    },
+    #[command(
+        about = "Show the last speed commands the daemon wrote, with why and at what temperature",
+        long_about = "Asks the running daemon to dump its journal and prints it. Without a running daemon, prints the last dump, such as the one written when it panicked or shut down"
+    )]
+    Journal,
+    #[command(
+        about = "List the identifier of each channel and temperature source",
+        long_about = "Each channel and source has one identifier, used wherever one is named for machines: the status file, the speed journal and debug logs. A channel's comes from its curve's name, or from the device and channel for unnamed curves"
+    )]
+    Names,
+    #[command(
+        about = "Show the order of the stages a speed goes through, and which act on each channel",
+        long_about = "Every speed starts from its curve and goes through the same stages in the same order; the last stage to change it gives the reason status and --debug report. Stages only act on curves whose config asks for them, except the override, which can act on any channel at runtime"
+    )]
+    Pipeline,
+    #[command(about = "Share curves with another machine, without device serials")]
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommand,
+    },
+    #[command(
+        hide = true,
+        about = "Serve the UNI hubs to a daemon started with --helper, on stdin and stdout"
+    )]
+    Helper,
+    #[command(about = "Check the protocol table against the conformance vectors")]
+    Vectors {
+        #[command(subcommand)]
+        command: VectorsCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum VectorsCommand {
+    #[command(
+        about = "Run every vector file in a directory against the packets this build sends",
+        long_about = "Each file names a hub family and lists operations with the packet expected for each, as hex bytes; see tests/vectors. Exits with an error if any vector does not match"
+    )]
+    Check {
+        #[arg(default_value = vectors::VECTOR_DIR, help = "Directory of vector files")]
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BundleCommand {
+    #[command(about = "Write the configured curves to a bundle file")]
+    Export {
+        #[arg(long, help = "Bundle file to write")]
+        out: PathBuf,
+        #[arg(
+            long = "curve",
+            help = "Only this curve: its name, or device/channel for unnamed curves; may be given several times"
+        )]
+        curves: Vec<String>,
+    },
+    #[command(about = "Add the curves of a bundle to the configuration file")]
+    Import {
+        #[arg(help = "Bundle file written by bundle export")]
+        file: PathBuf,
+        #[arg(
+            long = "map",
+            help = "Where a curve goes, as name=vid:pid:serial/channel; may be given several times"
+        )]
+        maps: Vec<String>,
+        #[arg(
+            long,
+            help = "Skip curves without --map instead of asking for their channel"
+        )]
+        skip_unmapped: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StateCommand {
+    #[command(about = "Print the runtime-disabled curves and the override as JSON")]
+    Export,
+    #[command(about = "Restore state printed by state export")]
+    Import {
+        #[arg(help = "File written from state export")]
+        file: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    #[command(about = "Print the configuration")]
+    Show {
+        #[arg(
+            long,
+            help = "Print the configuration as the daemon would run it on this machine"
+        )]
+        resolved: bool,
+    },
+    #[command(about = "Show what resolution changes relative to the configuration file")]
+    Diff,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CurveCommand {
+    #[command(
+        about = "Stop driving a curve's channel until it is enabled again or the machine reboots"
+    )]
+    Disable {
+        #[arg(help = "Curve name, or device/channel for unnamed curves (see status)")]
+        name: String,
+        #[arg(long, help = "Also set enabled: false in the config file")]
+        persist: bool,
+    },
+    #[command(about = "Resume driving a disabled curve's channel")]
+    Enable {
+        #[arg(help = "Curve name, or device/channel for unnamed curves (see status)")]
+        name: String,
+        #[arg(long, help = "Also set enabled: true in the config file")]
+        persist: bool,
+    },
+    #[command(
+        about = "Set the speed at one temperature in the config file, adding a point if needed"
+    )]
+    SetPoint {
+        #[arg(help = "Curve name, or device/channel for unnamed curves (see status)")]
+        name: String,
+        #[arg(
+            long,
+            help = "Temperature in °C, written in °F to curves tagged \"temperature_unit\": \"fahrenheit\""
+        )]
+        temp: f64,
+        #[arg(long, help = "Speed in percent")]
+        speed: u8,
+    },
+    #[command(about = "Remove the point at one temperature from the config file")]
+    RemovePoint {
+        #[arg(help = "Curve name, or device/channel for unnamed curves (see status)")]
+        name: String,
+        #[arg(
+            long,
+            help = "Temperature in °C, written in °F to curves tagged \"temperature_unit\": \"fahrenheit\""
+        )]
+        temp: f64,
+    },
+    #[command(about = "Replace every point of a curve in the config file")]
+    Set {
+        #[arg(help = "Curve name, or device/channel for unnamed curves (see status)")]
+        name: String,
+        #[arg(
+            help = "Points as °C:speed pairs, e.g. 30:20,50:40,70:80,85:100, written in °F to curves tagged Fahrenheit"
+        )]
+        points: String,
+    },
+    #[command(about = "Replace every point of a curve with the rows of a temperature,percent CSV")]
+    ImportCsv {
+        #[arg(help = "Curve name, or device/channel for unnamed curves (see status)")]
+        name: String,
+        #[arg(
+            help = "CSV file with a temperature in °C and a percent on each row, written in °F to curves tagged Fahrenheit"
+        )]
+        file: PathBuf,
+        #[arg(
+            long,
+            value_parser = clap::value_parser!(u16).range(2..),
+            help = "Simplify the curve to at most this many points"
+        )]
+        max_points: Option<u16>,
+    },
+    #[command(
+        about = "Log every stage of one curve's decision each tick, without --debug, until the machine reboots"
+    )]
+    Debug {
+        #[arg(help = "Curve name, or device/channel for unnamed curves (see status)")]
+        name: String,
+        #[arg(value_enum)]
+        state: Toggle,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Toggle {
+    On,
+    Off,
+}
+
+const DEFAULT_CONFIG_PATH: &str = "/etc/uni-sync-curve.json";
+
+/// Packaged builds leave /etc to the package manager or the admin.
+#[cfg(feature = "packaged")]
+const DEFAULT_CREATE_IF_MISSING: config::CreateIfMissing = config::CreateIfMissing::Error;
+#[cfg(not(feature = "packaged"))]
+const DEFAULT_CREATE_IF_MISSING: config::CreateIfMissing = config::CreateIfMissing::Write;
+
+/// Exit code when a device can't be opened for lack of permission
+/// (EX_NOPERM from sysexits.h).
+const EXIT_PERMISSION_DENIED: i32 = 77;
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    let mut fan_controller = hardware::FanController::new()?;
-    let available_devices = fan_controller.get_available_devices();
-    if args.debug {
-        println!("Available devices: {:?}", available_devices);
+    match args.command {
+        Some(Command::Export { format }) => run_export(&args, format),
+        Some(Command::Status { stats }) => run_status(&args, stats),
+        Some(Command::Init { force, ref source }) => run_init(&args, force, source.as_ref()).await,
+        Some(Command::ListDevices) => run_list_devices(&args).await,
+        Some(Command::ListSensors) => run_list_sensors(&args),
+        Some(Command::AutoDetectChannels) => run_auto_detect_channels(&args).await,
+        Some(Command::Curve { ref command }) => run_curve(&args, command),
+        Some(Command::ApplyDefaults) => run_apply_defaults(&args).await,
+        Some(Command::Config { ref command }) => run_config(&args, command).await,
+        Some(Command::State { ref command }) => run_state(&args, command),
+        Some(Command::Bundle { ref command }) => run_bundle(&args, command),
+        Some(Command::Helper) => run_helper(&args),
+        Some(Command::Vectors {
+            command: VectorsCommand::Check { ref dir },
+        }) => run_vectors_check(dir),
+        Some(Command::Journal) => run_journal(&args),
+        Some(Command::Names) => run_names(&args),
+        Some(Command::Pipeline) => run_pipeline(&args),
+        Some(Command::Convert {
+            ref device,
+            rpm,
+            percent,
+        }) => run_convert(&args, device, rpm, percent),
+        Some(Command::Eval {
+            ref curve,
+            ref temps,
+            range,
+            full_pipeline,
+        }) => run_eval(&args, curve, temps, range, full_pipeline),
+        Some(Command::Explain {
+            ref curve,
+            temp,
+            once,
+        }) => run_explain(&args, curve.as_deref(), temp, once),
+        Some(Command::Bench { ref device, runs }) => run_bench(&args, device, runs).await,
+        Some(Command::ResetDevice { ref device }) => run_reset_device(&args, device).await,
+        None => run_daemon(&args).await,
     }
+}
 
-    if available_devices.is_empty() {
-        bail!("No Lian Li UNI devices found. Please ensure your devices are connected and you have the necessary permissions.");
+fn status_path(args: &Args) -> &Path {
+    Path::new(args.status_file.as_deref().unwrap_or(status::STATUS_FILE))
+}
+
+fn run_status(args: &Args, stats: bool) -> Result<()> {
+    let status = status::read_status(status_path(args))?;
+    // Only for display, so a config that can't be read just means defaults
+    let config = config::read_config(config_path(args)).unwrap_or_default();
+    let style = style::Style::for_stdout(style::TemperatureThresholds::from_alerts(&config.alerts));
+    output::print(
+        args.output,
+        &output::StatusReport {
+            status,
+            now: status::now(),
+            units: config.display_units,
+            style,
+            stats,
+        },
+    )
+}
+
+fn run_names(args: &Args) -> Result<()> {
+    let config = config::read_config(config_path(args))?;
+    // Only to expand broadcast curves, so no devices need to be present
+    let (resolved, _) = config::resolve_config(
+        &config,
+        &[],
+        |_| true,
+        |_, _| None,
+        |device_id| hardware::device_model(device_id).channels,
+    );
+    output::print(args.output, &names::names_report(&resolved))
+}
+
+fn run_pipeline(args: &Args) -> Result<()> {
+    let config = config::read_config(config_path(args))?;
+    let (resolved, _) = config::resolve_config(
+        &config,
+        &[],
+        |_| true,
+        |_, _| None,
+        |device_id| hardware::device_model(device_id).channels,
+    );
+    print!("{}", pipeline::format_pipeline(&resolved));
+    Ok(())
+}
+
+fn run_journal(args: &Args) -> Result<()> {
+    let config = config::read_config(config_path(args)).unwrap_or_default();
+    let request_path = journal::request_path(status_path(args));
+    // Only a daemon that updated its status lately can answer
+    let running = status::read_status(status_path(args)).is_ok_and(|status| {
+        status::now().saturating_sub(status.updated_at) <= 3 * config.interval_seconds
+    });
+    if running {
+        std::fs::write(&request_path, b"")
+            .with_context(|| format!("Could not write {:?}", request_path))?;
+        let deadline =
+            std::time::Instant::now() + Duration::from_secs(2 * config.interval_seconds + 1);
+        while request_path.exists() && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        if journal::take_request(&request_path) {
+            eprintln!("The daemon did not answer; showing its last dump");
+        }
     }
+    let dump = journal::read_dump(&journal::journal_path(status_path(args)))?;
+    output::print(
+        args.output,
+        &output::JournalReport {
+            dump,
+            units: config.display_units,
+        },
+    )
+}
 
-    let config_path = args
-        .config_file
-        .as_deref()
-        .unwrap_or("/etc/uni-sync-curve.json");
+/// The configured display units, or Celsius if the config can't be read.
+fn display_units(args: &Args) -> units::DisplayUnits {
+    config::read_config(config_path(args))
+        .map(|config| config.display_units)
+        .unwrap_or_default()
+}
+
+fn config_path(args: &Args) -> &Path {
+    Path::new(args.config_file.as_deref().unwrap_or(DEFAULT_CONFIG_PATH))
+}
 
-    let config = config::load_config(Path::new(config_path), available_devices)?;
+fn create_if_missing(args: &Args) -> config::CreateIfMissing {
+    if args.no_create_config {
+        return config::CreateIfMissing::Error;
+    }
+    args.missing_config.unwrap_or(DEFAULT_CREATE_IF_MISSING)
+}
 
-    println!("Using config file: {}", config_path);
+async fn run_init(args: &Args, force: bool, source: Option<&config::SourceRequest>) -> Result<()> {
+    let path = config_path(args);
+    if path.exists() && !force {
+        bail!(
+            "{} already exists; use --force to replace it",
+            path.display()
+        );
+    }
+    let mut fan_controller = new_controller(args)?;
+    let source = default_source(source.cloned().unwrap_or_default());
+    let detected = detect::detect_channels(
+        &mut fan_controller,
+        &config::ZeroRpmCheckSettings::default(),
+    )
+    .await;
+    print!("{}", detect::format_detection(&detected));
+    let mut config = config::get_default_config_for_channels(
+        detected
+            .iter()
+            .map(|device| (device.device_id.clone(), device.curve_channels()))
+            .collect(),
+        source,
+    );
+    let notes: Vec<String> = config
+        .note
+        .take()
+        .into_iter()
+        .chain(detect::note(&detected))
+        .collect();
+    config.note = (!notes.is_empty()).then(|| notes.join(" "));
+    config::write_default_config(path, &config, args.create_config_dirs)?;
     println!(
-        "Loaded configuration with {} fan curves",
+        "Wrote {} with {} fan curves",
+        path.display(),
         config.fan_curves.len()
     );
-    println!("Update interval: {} seconds", config.interval_seconds);
+    Ok(())
+}
 
-    let mut interval = time::interval(Duration::from_secs(config.interval_seconds));
-    loop {
-        tokio::select! {
-            _ = tokio::signal::ctrl_c() => {
-                println!("Exiting.");
-                break;
+fn run_export(args: &Args, format: export::ExportFormat) -> Result<()> {
+    let config = config::read_config(config_path(args))?;
+
+    let export = export::export(&config, format);
+    for warning in &export.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    print!("{}", export.text);
+    Ok(())
+}
+
+/// Edits one curve in the config file. The file is only replaced if the
+/// edited config validates.
+fn edit_curve(
+    args: &Args,
+    name: &str,
+    edit: impl FnOnce(&mut config::FanCurve) -> Result<String>,
+) -> Result<()> {
+    let change = config::modify_config(config_path(args), |config| {
+        edit(disabled::find_curve(config, name)?)
+    })
+    .with_context(|| format!("Left {:?} unchanged", config_path(args)))?;
+    println!(
+        "Curve {}: {} in {:?}; reload the daemon to apply it there",
+        name,
+        change,
+        config_path(args)
+    );
+    Ok(())
+}
+
+fn run_state(args: &Args, command: &StateCommand) -> Result<()> {
+    let disabled_path = disabled::disabled_path(status_path(args));
+    match command {
+        StateCommand::Export => {
+            // Without a running daemon there is no override to export
+            let override_percent = status::read_status(status_path(args))
+                .map(|status| status.override_percent)
+                .unwrap_or_default();
+            let state = state::RuntimeState {
+                exported_at: status::now(),
+                disabled_curves: disabled::read_labels(&disabled_path)?,
+                override_percent,
+            };
+            println!("{}", serde_json::to_string_pretty(&state)?);
+        }
+        StateCommand::Import { file } => {
+            let content = std::fs::read_to_string(file)
+                .with_context(|| format!("Could not read {:?}", file))?;
+            let state: state::RuntimeState = serde_json::from_str(&content)?;
+            let config = config::read_config(config_path(args))?;
+            let import = state::import(&state, &config);
+            // The running daemon picks this up on its next tick
+            disabled::write_labels(&disabled_path, &import.disabled_curves)?;
+            println!("Restored {} disabled curves", import.disabled_curves.len());
+            for skipped in &import.skipped {
+                eprintln!("Skipped {}", skipped);
             }
-            _ = interval.tick() => {}
         }
+    }
+    Ok(())
+}
+
+fn run_bundle(args: &Args, command: &BundleCommand) -> Result<()> {
+    let mut config = config::read_config(config_path(args))?;
+    match command {
+        BundleCommand::Export { out, curves } => {
+            let fan_curves = if curves.is_empty() {
+                config.fan_curves.clone()
+            } else {
+                curves
+                    .iter()
+                    .map(|name| Ok(disabled::find_curve(&mut config, name)?.clone()))
+                    .collect::<Result<Vec<_>>>()?
+            };
+            let bundle = bundle::export(&fan_curves)?;
+            bundle::write_bundle(out, &bundle)?;
+            println!("Wrote {} curves to {:?}", bundle.curves.len(), out);
+        }
+        BundleCommand::Import {
+            file,
+            maps,
+            skip_unmapped,
+        } => {
+            let bundle = bundle::read_bundle(file)?;
+            let mut targets = std::collections::BTreeMap::new();
+            for map in maps {
+                let Some((name, target)) = map.split_once('=') else {
+                    bail!("Expected --map name=vid:pid:serial/channel, got {:?}", map);
+                };
+                if !bundle.curves.iter().any(|curve| curve.name == name) {
+                    bail!("The bundle has no curve named {:?}", name);
+                }
+                targets.insert(name.to_string(), bundle::parse_target(target)?);
+            }
 
-        match hardware::get_max_cpu_temperature() {
-            Some(cpu_temp) => {
-                if args.debug {
-                    println!("CPU temp: {:.1}°C", cpu_temp);
+            let unmapped: Vec<&str> = bundle
+                .curves
+                .iter()
+                .map(|curve| curve.name.as_str())
+                .filter(|name| !targets.contains_key(*name))
+                .collect();
+            if !unmapped.is_empty() && !skip_unmapped {
+                if !std::io::stdin().is_terminal() {
+                    bail!(
+                        "No --map for curves {}; pass --map name=vid:pid:serial/channel or --skip-unmapped",
+                        unmapped.join(", ")
+                    );
+                }
+                println!("Devices:");
+                for device_id in new_controller(args)?.get_available_devices() {
+                    let model = hardware::device_model(&device_id);
+                    println!(
+                        "  {} ({}, channels 0-{})",
+                        device_id,
+                        model.name,
+                        model.channels - 1
+                    );
                 }
-                for fan_curve in &config.fan_curves {
-                    let speed = curve::calculate_fan_speed(fan_curve, cpu_temp);
-                    if args.debug {
-                        println!(
-                            "Setting device {} channel {} to {}%",
-                            fan_curve.device_id, fan_curve.channel, speed
-                        );
+                for name in unmapped {
+                    if let Some(target) = ask_target(name)? {
+                        targets.insert(name.to_string(), target);
                     }
+                }
+            }
+
+            // The prompts may take a while, so start again from the file as
+            // it is now
+            let notes = config::modify_config(config_path(args), |config| {
+                bundle::import(config, &bundle, &targets)
+            })?;
+            for note in notes {
+                println!("{}", note);
+            }
+            println!(
+                "Wrote {:?}; reload the daemon to apply it there",
+                config_path(args)
+            );
+        }
+    }
+    Ok(())
+}
 
-                    if let Err(e) = fan_controller.set_fan_speed(
-                        &fan_curve.device_id,
-                        fan_curve.channel,
-                        &fan_curve.mode,
-                        speed,
-                    ).await {
-                        eprintln!("Error applying fan speed: {}", e);
+/// Asks on the terminal where curve `name` goes, until the answer parses.
+/// An empty answer skips the curve.
+fn ask_target(name: &str) -> Result<Option<(config::DeviceId, usize)>> {
+    loop {
+        print!(
+            "Channel for curve {} (vid:pid:serial/channel, empty to skip): ",
+            name
+        );
+        std::io::Write::flush(&mut std::io::stdout())?;
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            return Ok(None);
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        match bundle::parse_target(answer) {
+            Ok(target) => return Ok(Some(target)),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+fn run_curve(args: &Args, command: &CurveCommand) -> Result<()> {
+    match command {
+        CurveCommand::Disable { name, persist } => set_curve_enabled(args, name, false, *persist),
+        CurveCommand::Enable { name, persist } => set_curve_enabled(args, name, true, *persist),
+        CurveCommand::SetPoint { name, temp, speed } => {
+            let speed = config::SpeedPercent::new(*speed)?;
+            edit_curve(args, name, |fan_curve| {
+                edit::set_point(fan_curve, *temp, speed);
+                Ok(format!("set {}°C to {}%", temp, speed))
+            })
+        }
+        CurveCommand::RemovePoint { name, temp } => edit_curve(args, name, |fan_curve| {
+            edit::remove_point(fan_curve, *temp)?;
+            Ok(format!("removed the point at {}°C", temp))
+        }),
+        CurveCommand::ImportCsv {
+            name,
+            file,
+            max_points,
+        } => {
+            let text = std::fs::read_to_string(file)
+                .with_context(|| format!("Could not read {:?}", file))?;
+            let rows = edit::parse_csv(&text).with_context(|| format!("In {:?}", file))?;
+            let points = match max_points {
+                Some(max_points) => edit::downsample(&rows, *max_points as usize),
+                None => rows.clone(),
+            };
+            let change = if points.len() < rows.len() {
+                format!(
+                    "imported {} rows simplified to {} points, at most {:.1}% off",
+                    rows.len(),
+                    points.len(),
+                    edit::max_deviation(&rows, &points)
+                )
+            } else {
+                format!("imported {} points", points.len())
+            };
+            edit_curve(args, name, |fan_curve| {
+                edit::replace_points(fan_curve, points);
+                Ok(change)
+            })
+        }
+        CurveCommand::Debug { name, state } => set_curve_debug(args, name, *state == Toggle::On),
+        CurveCommand::Set { name, points } => {
+            let points = edit::parse_points(points)?;
+            edit_curve(args, name, |fan_curve| {
+                edit::replace_points(fan_curve, points);
+                Ok(format!(
+                    "replaced the points with {}",
+                    fan_curve.curve_points.len()
+                ))
+            })
+        }
+    }
+}
+
+fn set_curve_debug(args: &Args, name: &str, debug: bool) -> Result<()> {
+    let mut config = config::read_config(config_path(args))?;
+    let label = disabled::find_curve(&mut config, name)?.label();
+
+    // The running daemon picks this up on its next tick
+    let debug_path = disabled::debug_path(status_path(args));
+    let mut runtime_debug = disabled::read_labels(&debug_path)?;
+    if debug {
+        runtime_debug.insert(label.clone());
+    } else {
+        runtime_debug.remove(&label);
+    }
+    disabled::write_labels(&debug_path, &runtime_debug)?;
+    println!(
+        "Curve {} {} debugged",
+        label,
+        if debug { "is" } else { "is no longer" }
+    );
+    Ok(())
+}
+
+fn set_curve_enabled(args: &Args, name: &str, enabled: bool, persist: bool) -> Result<()> {
+    let mut config = config::read_config(config_path(args))?;
+    let label = disabled::find_curve(&mut config, name)?.label();
+
+    if persist {
+        config::modify_config(config_path(args), |config| {
+            disabled::find_curve(config, name)?.enabled = enabled;
+            Ok(())
+        })?;
+        println!(
+            "Curve {} {} in {:?}; reload the daemon to apply it there",
+            label,
+            if enabled { "enabled" } else { "disabled" },
+            config_path(args)
+        );
+    }
+
+    // The running daemon picks this up on its next tick
+    let disabled_path = disabled::disabled_path(status_path(args));
+    let mut runtime_disabled = disabled::read_labels(&disabled_path)?;
+    if enabled {
+        runtime_disabled.remove(&label);
+    } else {
+        runtime_disabled.insert(label.clone());
+    }
+    disabled::write_labels(&disabled_path, &runtime_disabled)?;
+
+    if !enabled
+        && !config
+            .fan_curves
+            .iter()
+            .any(|fan_curve| disabled::is_active(fan_curve, &runtime_disabled))
+    {
+        eprintln!("Warning: every curve is disabled now; no channel is being driven");
+    }
+    if enabled
+        && !persist
+        && !config
+            .fan_curves
+            .iter()
+            .any(|c| c.label() == label && c.enabled)
+    {
+        eprintln!(
+            "Warning: curve {} is disabled in the config file; pass --persist to enable it there",
+            label
+        );
+    }
+    Ok(())
+}
+
+fn run_eval(
+    args: &Args,
+    name: &str,
+    temps: &[f64],
+    range: Option<eval::TemperatureRange>,
+    full_pipeline: bool,
+) -> Result<()> {
+    let mut config = config::read_config(config_path(args))?;
+    let fallback_speed = config.fallback_speed_percent;
+    let fan_curve = disabled::find_curve(&mut config, name)?.clone();
+
+    let mut temperatures = temps.to_vec();
+    if let Some(range) = range {
+        temperatures.extend(range.temperatures());
+    }
+    if temperatures.is_empty() {
+        bail!("Give at least one --temp or a --range");
+    }
+
+    output::print(
+        args.output,
+        &output::EvalReport {
+            curve: fan_curve.label(),
+            evaluations: eval::evaluate(&fan_curve, &temperatures, fallback_speed),
+            full_pipeline,
+            units: config.display_units,
+        },
+    )?;
+    if full_pipeline {
+        eprintln!(
+            "Note: anticipation, fan groups, the startup ramp and the interactive override depend on the running daemon and are not applied"
+        );
+    }
+    Ok(())
+}
+
+fn run_explain(args: &Args, curve: Option<&str>, temp: Option<f64>, once: bool) -> Result<()> {
+    let mut config = config::read_config(config_path(args))?;
+    let runtime_disabled = disabled::read_labels(&disabled::disabled_path(status_path(args)))?;
+    if let Some(name) = curve {
+        let fan_curve = disabled::find_curve(&mut config, name)?;
+        if !disabled::is_active(fan_curve, &runtime_disabled) {
+            bail!("Curve {} is disabled", name);
+        }
+    }
+
+    let mut notes = Vec::new();
+    // Only the running daemon follows the throttle counters from tick to tick
+    let mut throttle_pressure = None;
+    let (temperature, temperature_from, override_percent) = match temp {
+        Some(temperature) => (temperature, "--temp".to_string(), 0),
+        None if once => {
+            let chain = config.source_chain();
+            let (index, temperature) = chain
+                .iter()
+                .enumerate()
+                .find_map(|(index, source)| {
+                    read_temperature_source(source)
+                        .filter(|temperature| sensors::PLAUSIBLE_CELSIUS.contains(temperature))
+                        .map(|temperature| (index, temperature))
+                })
+                .context("Could not read the temperature source")?;
+            if index > 0 {
+                notes.push(format!(
+                    "the {} source has no plausible reading, so this follows its fallback",
+                    chain[0].key()
+                ));
+            }
+            let latency = chain[index].reading_latency_seconds();
+            if latency > 0.0 {
+                notes.push(format!(
+                    "the source's {}s reading latency is compensated along the running daemon's trend, so this uses the reading as it is",
+                    latency
+                ));
+            }
+            (temperature, format!("the {} source", chain[index].key()), 0)
+        }
+        None => {
+            let status = status::read_status(status_path(args))
+                .context("Use --once to read the sensor instead")?;
+            let mut temperature = status
+                .temperature_celsius
+                .context("The daemon has no temperature reading; use --once or --temp")?;
+            if let Some(extrapolation) = &status.extrapolation {
+                temperature = extrapolation.celsius;
+                notes.push(format!(
+                    "the daemon follows {}",
+                    extrapolation.describe(config.display_units)
+                ));
+            }
+            if let Some(source) = &status.source {
+                if source.state == sensors::SourceState::Lost {
+                    notes.push(format!(
+                        "the {} source is lost, so the daemon runs every channel at the fallback speed of {}% instead",
+                        source.name, config.fallback_speed_percent
+                    ));
+                }
+            }
+            let profile = status
+                .power_state
+                .and_then(|power_state| config.power_profiles.get(&power_state));
+            throttle_pressure = status.throttle_pressure_percent;
+            (
+                temperature,
+                match &status.source {
+                    Some(source) => format!("the running daemon's {} source", source.name),
+                    None => "the running daemon".to_string(),
+                },
+                power::effective_override(status.override_percent, profile),
+            )
+        }
+    };
+    notes.push(
+        "the startup ramp and anticipation depend on the running daemon's history and are left out"
+            .to_string(),
+    );
+
+    let fan_curves = disabled::active_curves(&config.fan_curves, &runtime_disabled);
+    let mut engine = engine::Engine::new(
+        fan_curves.clone(),
+        config.strict,
+        config.fallback_speed_percent,
+        config.never_stop_speed_percent,
+        None,
+    );
+    let explained = engine.explain(engine::Inputs {
+        temperature,
+        override_percent,
+        throttle_pressure,
+        ..Default::default()
+    });
+    let mut curves = explain::explanations(&fan_curves, explained);
+    if let Some(name) = curve {
+        curves.retain(|explanation| explanation.curve == name);
+    }
+    let report = explain::Report {
+        temperature_celsius: temperature,
+        temperature_from,
+        override_percent,
+        curves,
+        notes,
+    };
+
+    output::print(
+        args.output,
+        &output::ExplainReport {
+            report,
+            units: config.display_units,
+        },
+    )
+}
+
+/// The controller for every discovered device, recording packets when
+/// `--capture` is given.
+fn new_controller(args: &Args) -> Result<hardware::FanController> {
+    let mut fan_controller = if let Some(command) = &args.helper {
+        hardware::FanController::with_hubs(Box::new(helper::RemoteBackend::new(
+            helper::spawn_command(command)?,
+        )?))
+    } else if args.device_serials.is_empty() {
+        hardware::FanController::new()?
+    } else {
+        hardware::FanController::with_filter(
+            hardware::DeviceFilter::default()
+                .with_serials(args.device_serials.iter().map(String::as_str)),
+        )?
+    };
+    if let Some(path) = &args.capture {
+        fan_controller.capture_to(capture::open(Path::new(path))?);
+    }
+    if args.monitor {
+        fan_controller.read_only();
+    }
+    Ok(fan_controller)
+}
+
+fn run_helper(args: &Args) -> Result<()> {
+    let mut hubs = hardware::HidBackend::with_filter(
+        hardware::DeviceFilter::default()
+            .with_serials(args.device_serials.iter().map(String::as_str)),
+    )?;
+    helper::serve(&mut hubs, std::io::stdin().lock(), std::io::stdout().lock())
+}
+
+fn run_vectors_check(dir: &Path) -> Result<()> {
+    let (count, mismatches) = vectors::check_dir(dir)?;
+    print!("{}", vectors::format_check(count, &mismatches));
+    if !mismatches.is_empty() {
+        bail!("{} of {} vectors do not match", mismatches.len(), count);
+    }
+    Ok(())
+}
+
+fn run_convert(args: &Args, device: &str, rpm: Option<u16>, percent: Option<u8>) -> Result<()> {
+    let model = match hardware::family_model(device) {
+        Some(model) => model,
+        None => {
+            let device_id: config::DeviceId = device.parse().with_context(|| {
+                format!(
+                    "{:?} is neither a hub family (sl, al, sli, slv2, alv2) nor a device",
+                    device
+                )
+            })?;
+            hardware::device_model(&device_id)
+        }
+    };
+    let Some(range) = model.rpm_range else {
+        bail!("A {} takes a PWM duty rather than a target RPM", model.name);
+    };
+    let speed = match (rpm, percent) {
+        (Some(rpm), _) => range.speed(rpm),
+        (None, Some(percent)) => config::SpeedPercent::saturating(percent),
+        (None, None) => bail!("Give --rpm or --percent"),
+    };
+    output::print(
+        args.output,
+        &output::ConvertReport {
+            model: model.name.to_string(),
+            requested_rpm: rpm,
+            speed_percent: speed.get(),
+            rpm: range.rpm(speed),
+            min_rpm: range.min,
+            max_rpm: range.max,
+        },
+    )
+}
+
+async fn run_list_devices(args: &Args) -> Result<()> {
+    let mut fan_controller = new_controller(args)?;
+    if let Ok(config) = config::read_config(config_path(args)) {
+        fan_controller.set_hub_reports(config.experimental_hub_reports);
+    }
+    fan_controller.refresh_fan_counts().await;
+
+    let devices = fan_controller
+        .get_available_devices()
+        .into_iter()
+        .map(|device_id| {
+            let model = hardware::device_model(&device_id);
+            output::DeviceEntry {
+                model: model.name.to_string(),
+                metadata: fan_controller.metadata(&device_id),
+                fans_per_channel: (0..model.channels)
+                    .map(|channel| fan_controller.fan_count(&device_id, channel))
+                    .collect(),
+                device_id,
+            }
+        })
+        .collect();
+    output::print(args.output, &output::DevicesReport { devices })
+}
+
+async fn run_auto_detect_channels(args: &Args) -> Result<()> {
+    let mut fan_controller = new_controller(args)?;
+    let config = config::read_config(config_path(args)).ok();
+    let settings = config
+        .as_ref()
+        .map(|config| config.zero_rpm_check)
+        .unwrap_or_default();
+    fan_controller.set_hub_reports(
+        config
+            .as_ref()
+            .is_some_and(|config| config.experimental_hub_reports),
+    );
+    let detected = detect::detect_channels(&mut fan_controller, &settings).await;
+    print!("{}", detect::format_detection(&detected));
+    if let Some(config) = config {
+        for mismatch in detect::mismatches(&detected, &config.fan_curves) {
+            println!("{}", mismatch);
+        }
+    }
+    Ok(())
+}
+
+fn detect_sensors() -> config::DetectedSensors {
+    config::DetectedSensors {
+        cpu_celsius: hardware::get_max_temperature(presets::CPU),
+        gpus: gpu::discover(Path::new(sysfs::HWMON_ROOT)),
+    }
+}
+
+/// The source for a generated config, warning when it isn't what was asked
+/// for or reads nothing.
+fn default_source(request: config::SourceRequest) -> config::DefaultSource {
+    let source = config::choose_default_source(&request, &detect_sensors());
+    if let Some(note) = &source.note {
+        eprintln!("warning: {}", note);
+    }
+    source
+}
+
+fn run_list_sensors(args: &Args) -> Result<()> {
+    let sensors = detect_sensors();
+    let report = output::SensorsReport {
+        cpu_celsius: sensors.cpu_celsius,
+        soc_celsius: hardware::get_max_temperature(presets::SOC),
+        gpus: sensors
+            .gpus
+            .into_iter()
+            .map(|gpu| output::GpuEntry {
+                index: gpu.index,
+                pci_bus_id: gpu.pci_bus_id,
+                driver: gpu.driver,
+                temperatures: gpu
+                    .temperatures
+                    .into_iter()
+                    .map(|(sensor, celsius)| output::GpuTemperature { sensor, celsius })
+                    .collect(),
+            })
+            .collect(),
+        sensors: hardware::read_sensors()
+            .into_iter()
+            .map(|(label, celsius)| output::SensorEntry {
+                source: presets::preset_for(&label).map(|preset| preset.name.to_string()),
+                label,
+                celsius,
+            })
+            .collect(),
+        units: display_units(args),
+    };
+    print_environment_hints(
+        true,
+        report.cpu_celsius.is_some() || report.soc_celsius.is_some() || !report.gpus.is_empty(),
+    );
+    output::print(args.output, &report)
+}
+
+/// Hints on stderr for when running in a container or VM looks like why
+/// no hub or no temperature was found.
+fn print_environment_hints(devices_found: bool, sensors_found: bool) {
+    let environment = container::Environment::detect(Path::new("/"));
+    for hint in container::diagnostics(&environment, devices_found, sensors_found) {
+        eprintln!("hint: {}", hint);
+    }
+}
+
+fn read_temperature_source(source: &config::TemperatureSource) -> Option<f64> {
+    match source {
+        config::TemperatureSource::Cpu { .. } => hardware::get_max_temperature(presets::CPU),
+        config::TemperatureSource::Soc { .. } => hardware::get_max_temperature(presets::SOC),
+        config::TemperatureSource::Gpu { gpu, sensor, .. } => {
+            gpu::read_temperature(Path::new(sysfs::HWMON_ROOT), gpu.as_ref(), *sensor)
+        }
+    }
+}
+
+/// A reader for the daemon. GPUs picked by bus id are read through
+/// `sensor_cache` rather than by walking every hwmon device each time.
+fn new_temperature_reader(
+    source: &config::TemperatureSource,
+    sensor_cache: Arc<Mutex<gpu::SensorCache>>,
+) -> sensors::TemperatureReader {
+    let source = source.clone();
+    let key = source.key();
+    sensors::TemperatureReader::new(
+        move || match &source {
+            config::TemperatureSource::Gpu {
+                gpu: Some(gpu::GpuSelector::BusId(bus_id)),
+                sensor,
+                ..
+            } => sensor_cache.lock().unwrap().read(
+                Path::new(sysfs::HWMON_ROOT),
+                &key,
+                bus_id,
+                *sensor,
+                std::time::Instant::now(),
+            ),
+            _ => read_temperature_source(&source),
+        },
+        sensors::SENSOR_READ_TIMEOUT,
+        sensors::STALE_READING_MAX_AGE,
+    )
+}
+
+fn channel_management(
+    config: &config::CurveConfig,
+) -> Vec<(config::DeviceId, usize, config::Manage)> {
+    config
+        .fan_curves
+        .iter()
+        .map(|fan_curve| {
+            (
+                fan_curve.device_id.clone(),
+                fan_curve.channel,
+                fan_curve.manage,
+            )
+        })
+        .collect()
+}
+
+fn configure_controller(
+    config: &config::CurveConfig,
+    fan_controller: &mut hardware::FanController,
+) {
+    let (config, _) = pin_port_paths(config, fan_controller);
+    let config = &config;
+    for device in &config.devices {
+        fan_controller.set_lighting_control(&device.device_id, device.lighting_control);
+    }
+    fan_controller.set_managed_devices(config.managed_devices.clone());
+    fan_controller.set_command_delay(config.command_delay_ms.map(Duration::from_millis));
+    fan_controller.set_io_timeout(config.io_timeout_ms.map(Duration::from_millis));
+    fan_controller.set_permission_policy(config.on_permission_error);
+    fan_controller.set_hub_reports(config.experimental_hub_reports);
+    fan_controller.set_verify_writes(config.verify_writes);
+    fan_controller.set_write_budget(config.write_budget);
+    fan_controller.set_channel_management(channel_management(config));
+}
+
+async fn run_bench(args: &Args, device_id: &config::DeviceId, runs: usize) -> Result<()> {
+    let mut fan_controller = new_controller(args)?;
+    let Some(device_id) = fan_controller
+        .get_available_devices()
+        .into_iter()
+        .find(|d| d == device_id)
+    else {
+        bail!("Device {} not found; see list-devices", device_id);
+    };
+    let _device_lock =
+        lock::DeviceLocks::acquire(Path::new(lock::LOCK_DIR), std::slice::from_ref(&device_id))
+            .context("Stop the daemon driving this device before benchmarking it")?;
+
+    // Benchmark at the speeds the daemon last applied, so the fans keep
+    // running as they were
+    let last_speeds = last_speeds(args, &device_id);
+    // Channels the daemon never reported run at the configured fallback
+    let fallback_speed = config::read_config(config_path(args))
+        .map(|config| config.fallback_speed_percent)
+        .unwrap_or(config::DEFAULT_FALLBACK_SPEED_PERCENT);
+    let speeds: Vec<config::SpeedPercent> = last_speeds
+        .iter()
+        .map(|speed| speed.unwrap_or(fallback_speed))
+        .collect();
+
+    let results = bench::bench(
+        &mut fan_controller,
+        &device_id,
+        &speeds,
+        &bench::BENCH_DELAYS_MS,
+        runs,
+    )
+    .await;
+    // Hands hwmon headers back to automatic control
+    fan_controller.release();
+
+    let report = bench::BenchReport {
+        recommended_delay_ms: bench::recommend_delay_ms(&results),
+        device_id,
+        results,
+    };
+    output::print(args.output, &report)?;
+
+    if !report.device_id.is_sysfs_pwm() {
+        for (channel, speed) in last_speeds.iter().enumerate() {
+            if speed.is_none() {
+                eprintln!(
+                    "Channel {} was left at {}%: its previous speed is unknown",
+                    channel, fallback_speed
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The Manual speed the daemon last applied to each channel of `device_id`,
+/// as its status file tells.
+fn last_speeds(args: &Args, device_id: &config::DeviceId) -> Vec<Option<config::SpeedPercent>> {
+    let last_status = status::read_status(status_path(args)).ok();
+    (0..hardware::device_model(device_id).channels)
+        .map(|channel| {
+            last_status.as_ref().and_then(|status| {
+                status
+                    .channels
+                    .iter()
+                    .find(|c| {
+                        c.device_id == *device_id
+                            && c.channel == channel
+                            && c.mode == config::ChannelMode::Manual
+                    })
+                    .map(|c| config::SpeedPercent::saturating(c.speed_percent))
+            })
+        })
+        .collect()
+}
+
+async fn run_reset_device(args: &Args, device_id: &config::DeviceId) -> Result<()> {
+    let config = config::read_config(config_path(args))?;
+    let mut fan_controller = new_controller(args)?;
+    configure_controller(&config, &mut fan_controller);
+    let Some(device_id) = fan_controller
+        .get_available_devices()
+        .into_iter()
+        .find(|d| d == device_id)
+    else {
+        bail!("Device {} not found; see list-devices", device_id);
+    };
+    let _device_lock =
+        lock::DeviceLocks::acquire(Path::new(lock::LOCK_DIR), std::slice::from_ref(&device_id))
+            .context(
+            "Stop the daemon before resetting a device; it resets devices that keep failing itself",
+        )?;
+
+    fan_controller.reset_device(&device_id).await?;
+    println!("Reset device {}", device_id);
+
+    // Put the configured modes and the last applied speeds back
+    fan_controller.refresh_fan_counts().await;
+    let (resolved, _) = resolve_config(&config, &fan_controller);
+    let last_speeds = last_speeds(args, &device_id);
+    for fan_curve in &resolved.fan_curves {
+        if fan_curve.device_id != device_id || fan_curve.manage == config::Manage::MonitorOnly {
+            continue;
+        }
+        let speed = last_speeds
+            .get(fan_curve.channel)
+            .copied()
+            .flatten()
+            .unwrap_or(config.fallback_speed_percent);
+        match fan_controller
+            .set_fan_speed(&device_id, fan_curve.channel, &fan_curve.mode, speed)
+            .await
+        {
+            Ok(()) => println!(
+                "{}: {:?} mode, {}%",
+                fan_curve.label(),
+                fan_curve.mode,
+                speed
+            ),
+            Err(e) => eprintln!("{}: {}", fan_curve.label(), e),
+        }
+    }
+    fan_controller.release();
+    Ok(())
+}
+
+async fn run_apply_defaults(args: &Args) -> Result<()> {
+    let config = config::read_config(config_path(args))?;
+    let mut fan_controller = new_controller(args)?;
+    configure_controller(&config, &mut fan_controller);
+    let _device_locks = lock::DeviceLocks::acquire(
+        Path::new(lock::LOCK_DIR),
+        &fan_controller.get_managed_devices(),
+    )
+    .context("Stop the daemon before applying defaults")?;
+    fan_controller.refresh_fan_counts().await;
+    let (resolved, notes) = resolve_config(&config, &fan_controller);
+    for note in notes {
+        eprintln!("{}", note);
+    }
+
+    for fan_curve in &resolved.fan_curves {
+        if fan_curve.manage == config::Manage::MonitorOnly {
+            continue;
+        }
+        match fan_controller
+            .apply_defaults(&fan_curve.device_id, fan_curve.channel, &fan_curve.mode)
+            .await
+        {
+            Ok(()) => println!("{}: {:?} mode", fan_curve.label(), fan_curve.mode),
+            Err(e) => eprintln!("{}: {}", fan_curve.label(), e),
+        }
+    }
+    fan_controller.release();
+    Ok(())
+}
+
+/// `config` with devices pinned to USB ports moved to the hubs on them.
+fn pin_port_paths(
+    config: &config::CurveConfig,
+    fan_controller: &hardware::FanController,
+) -> (config::CurveConfig, Vec<String>) {
+    let mut pinned = config.clone();
+    let notes = config::pin_port_paths(&mut pinned, &fan_controller.port_paths());
+    (pinned, notes)
+}
+
+/// Resolves `config` against the devices on this machine, the same way the
+/// daemon does on startup. Fan counts must already be refreshed.
+fn resolve_config(
+    config: &config::CurveConfig,
+    fan_controller: &hardware::FanController,
+) -> (config::CurveConfig, Vec<String>) {
+    let (pinned, mut notes) = pin_port_paths(config, fan_controller);
+    let (resolved, resolve_notes) = config::resolve_config(
+        &pinned,
+        &fan_controller.get_available_devices(),
+        |device_id| fan_controller.is_managed(device_id),
+        |device_id, channel| fan_controller.fan_count(device_id, channel),
+        |device_id| hardware::device_model(device_id).channels,
+    );
+    notes.extend(resolve_notes);
+    (resolved, notes)
+}
+
+async fn run_config(args: &Args, command: &ConfigCommand) -> Result<()> {
+    let content = std::fs::read_to_string(config_path(args))?;
+    if let ConfigCommand::Show { resolved: false } = command {
+        print!("{}", content);
+        return Ok(());
+    }
+
+    let config: config::CurveConfig = serde_json::from_str(&content)?;
+    config::validate_config(&config)?;
+    let mut fan_controller = new_controller(args)?;
+    configure_controller(&config, &mut fan_controller);
+    fan_controller.refresh_fan_counts().await;
+    let (resolved, notes) = resolve_config(&config, &fan_controller);
+
+    match command {
+        ConfigCommand::Show { .. } => {
+            for note in notes {
+                eprintln!("{}", note);
+            }
+            println!("{}", serde_json::to_string_pretty(&resolved)?);
+        }
+        ConfigCommand::Diff => {
+            let on_disk: serde_json::Value = serde_json::from_str(&content)?;
+            for change in config::diff_json(&on_disk, &serde_json::to_value(&resolved)?) {
+                println!("{}", change);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn run_daemon(args: &Args) -> Result<()> {
+    let mut fan_controller = new_controller(args)?;
+    let available_devices = fan_controller.get_available_devices();
+    if args.debug {
+        println!("Available devices: {:?}", available_devices);
+    }
+
+    if available_devices.is_empty() {
+        let sensors = detect_sensors();
+        print_environment_hints(
+            false,
+            sensors.cpu_celsius.is_some() || !sensors.gpus.is_empty(),
+        );
+        bail!("No Lian Li UNI devices or hwmon PWM headers found. Please ensure your devices are connected and you have the necessary permissions.");
+    }
+
+    let mut safe_mode = None;
+    let (mut config, config_origin) = match config::load_config(
+        config_path(args),
+        available_devices.clone(),
+        create_if_missing(args),
+        args.create_config_dirs,
+        || default_source(config::SourceRequest::Auto),
+    ) {
+        Ok(loaded) => loaded,
+        Err(e) if args.safe_mode_on_config_error => {
+            eprintln!("==================== SAFE MODE ====================");
+            eprintln!(
+                "Could not load configuration {:?}: {:#}",
+                config_path(args),
+                e
+            );
+            eprintln!("Running the built-in curve on every channel of every hub.");
+            eprintln!("Fix the file and reload (r with --interactive) or restart.");
+            eprintln!("===================================================");
+            safe_mode = Some(format!("{:#}", e));
+            let devices: Vec<_> = available_devices
+                .iter()
+                .map(|device_id| {
+                    (
+                        device_id.clone(),
+                        hardware::device_model(device_id).channels,
+                    )
+                })
+                .collect();
+            (
+                config::safe_mode_config(&devices),
+                config::ConfigOrigin::SafeMode,
+            )
+        }
+        Err(e) => return Err(e),
+    };
+    if args.monitor {
+        config.mode = config::DaemonMode::Monitor;
+    }
+    if config.mode == config::DaemonMode::Monitor && !args.monitor {
+        fan_controller.read_only();
+    }
+    config::apply_daemon_mode(&mut config);
+    configure_controller(&config, &mut fan_controller);
+
+    let managed_devices = fan_controller.get_managed_devices();
+    if managed_devices.is_empty() {
+        bail!("None of the discovered devices are listed in managed_devices.");
+    }
+    let _device_locks = lock::DeviceLocks::acquire(Path::new(lock::LOCK_DIR), &managed_devices)?;
+
+    fan_controller.refresh_fan_counts().await;
+    fan_controller.read_previous_states().await;
+    let device_metadata: Vec<_> = managed_devices
+        .iter()
+        .filter_map(|device_id| {
+            fan_controller
+                .metadata(device_id)
+                .map(|metadata| (device_id.clone(), metadata))
+        })
+        .collect();
+
+    for warning in config::validation_warnings(&config) {
+        eprintln!("Warning: {}", warning);
+    }
+    print_environment_hints(
+        true,
+        config
+            .source_chain()
+            .iter()
+            .any(|source| read_temperature_source(source).is_some()),
+    );
+
+    let (resolved, notes) = resolve_config(&config, &fan_controller);
+    for note in notes {
+        eprintln!("{}", note);
+    }
+    let mut all_fan_curves = resolved.fan_curves;
+    check_zero_rpm(
+        &mut fan_controller,
+        &mut all_fan_curves,
+        &config.zero_rpm_check,
+        &zero_rpm::results_path(config_path(args)),
+    )
+    .await;
+    let sensor_cache = Arc::new(Mutex::new(gpu::SensorCache::load(&gpu::sensor_cache_path(
+        status_path(args),
+    ))));
+    let mut daemon = daemon::Daemon::new(
+        config,
+        fan_controller,
+        all_fan_curves,
+        sensors::SourceReaders::new(move |source| {
+            new_temperature_reader(source, sensor_cache.clone())
+        }),
+        status_path(args),
+        args.debug,
+    );
+    daemon.safe_mode = safe_mode;
+    daemon.device_metadata = device_metadata;
+    journal::install_panic_hook(daemon.journal.clone(), daemon.journal_path.clone());
+
+    if !args.quiet {
+        let devices: Vec<_> = available_devices
+            .into_iter()
+            .map(|device_id| {
+                let managed = daemon.fan_controller.is_managed(&device_id);
+                let metadata = daemon.fan_controller.metadata(&device_id);
+                (device_id, managed, metadata)
+            })
+            .collect();
+        print!(
+            "{}",
+            banner::format_banner(
+                config_path(args),
+                config_origin,
+                &daemon.config,
+                &devices,
+                daemon.engine.curves(),
+                style::Style::for_stdout(style::TemperatureThresholds::from_alerts(
+                    &daemon.config.alerts
+                ))
+            )
+        );
+    }
+
+    let (control_tx, mut control_rx) = mpsc::channel(control::CONTROL_QUEUE_DEPTH);
+    if args.interactive {
+        if std::io::stdin().is_terminal() {
+            control::spawn_stdin_reader(control_tx.clone(), daemon.token.clone());
+        } else {
+            eprintln!("Ignoring --interactive: stdin is not a terminal");
+        }
+    }
+
+    loop {
+        let deadline = daemon.next_deadline();
+        tokio::select! {
+            _ = shutdown::signalled() => break,
+            Some(command) = control_rx.recv() => {
+                match command {
+                    control::ControlCommand::Quit => break,
+                    control::ControlCommand::TogglePause => daemon.paused = !daemon.paused,
+                    control::ControlCommand::Nudge(step) => {
+                        if let ControlFlow::Break(code) = daemon.nudge(step).await {
+                            std::process::exit(code);
+                        }
                     }
+                    control::ControlCommand::Reload => match reload_config(args, &daemon.config, &mut daemon.fan_controller).await {
+                        Ok((new_config, mut new_fan_curves)) => {
+                            check_zero_rpm(
+                                &mut daemon.fan_controller,
+                                &mut new_fan_curves,
+                                &new_config.zero_rpm_check,
+                                &zero_rpm::results_path(config_path(args)),
+                            )
+                            .await;
+                            daemon.apply_reload(new_config, new_fan_curves).await;
+                        }
+                        Err(e) => eprintln!("Could not reload configuration: {}", e),
+                    },
+                    control::ControlCommand::PrintStatus => match &daemon.last_status {
+                        Some(status) => print!(
+                            "{}",
+                            status::format_status(
+                                status,
+                                status::now(),
+                                daemon.config.display_units,
+                                style::Style::for_stdout(style::TemperatureThresholds::from_alerts(
+                                    &daemon.config.alerts
+                                ))
+                            )
+                        ),
+                        None => println!("No status yet"),
+                    },
                 }
+                println!(
+                    "{}",
+                    control::status_bar(
+                        daemon.paused,
+                        daemon.override_percent,
+                        daemon.last_status.as_ref(),
+                        daemon.config.display_units
+                    )
+                );
+                continue;
             }
-            _ => eprintln!("Could not read CPU temperature. Continuing with previous settings."),
+            _ = time::sleep_until(deadline) => {}
+        }
+        if let ControlFlow::Break(code) = daemon.tick().await {
+            std::process::exit(code);
         }
     }
 
+    println!("Exiting.");
+    let timeout = Duration::from_secs(daemon.config.shutdown_timeout_seconds);
+    let report = daemon.shutdown(timeout).await;
+    if let Some(warning) = report.warning(timeout) {
+        eprintln!("{}", warning);
+    }
+
     Ok(())
 }
+
+/// Re-reads the configuration file for a running daemon. The set of managed
+/// devices stays as it was at startup, since their locks are already held.
+/// Checks each channel a curve would stop that has no kept result, and
+/// keeps curves from stopping channels that didn't pass.
+async fn check_zero_rpm(
+    fan_controller: &mut hardware::FanController,
+    fan_curves: &mut [config::FanCurve],
+    settings: &config::ZeroRpmCheckSettings,
+    path: &Path,
+) {
+    if !settings.required {
+        return;
+    }
+    let mut results = zero_rpm::read_results(path).unwrap_or_else(|e| {
+        eprintln!("Checking zero-RPM channels again: {}", e);
+        zero_rpm::CheckResults::new()
+    });
+    let mut checked = false;
+    for fan_curve in fan_curves
+        .iter()
+        .filter(|curve| zero_rpm::needs_check(curve))
+    {
+        let key = zero_rpm::channel_key(&fan_curve.device_id, fan_curve.channel);
+        if results.contains_key(&key) || !fan_controller.is_managed(&fan_curve.device_id) {
+            continue;
+        }
+        println!(
+            "Checking that {} drives fans before letting it stop them",
+            fan_curve.label()
+        );
+        match zero_rpm::run_check(
+            fan_controller,
+            &fan_curve.device_id,
+            fan_curve.channel,
+            settings,
+        )
+        .await
+        {
+            Ok(result) => {
+                results.insert(key, result);
+                checked = true;
+            }
+            Err(e) => eprintln!("Could not check {}: {:#}", fan_curve.label(), e),
+        }
+    }
+    if checked {
+        if let Err(e) = zero_rpm::write_results(path, &results) {
+            eprintln!("Could not keep zero-RPM check results: {:#}", e);
+        }
+    }
+    for warning in zero_rpm::apply_results(fan_curves, &results) {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+async fn reload_config(
+    args: &Args,
+    current: &config::CurveConfig,
+    fan_controller: &mut hardware::FanController,
+) -> Result<(config::CurveConfig, Vec<config::FanCurve>)> {
+    let mut config = config::read_config(config_path(args))?;
+    if args.monitor {
+        config.mode = config::DaemonMode::Monitor;
+    }
+    if config.mode != current.mode {
+        bail!(
+            "mode is now {:?} but the daemon runs in {:?} mode; switching modes takes a restart",
+            config.mode.to_string(),
+            current.mode.to_string()
+        );
+    }
+    config::apply_daemon_mode(&mut config);
+    match fan_controller.rescan().await {
+        Ok(changes) => {
+            for device_id in &changes.added {
+                println!("Found device {}", device_id);
+            }
+            for device_id in &changes.removed {
+                println!("Device {} is gone", device_id);
+            }
+        }
+        Err(e) => eprintln!("Could not look for new devices: {}", e),
+    }
+    if config.managed_devices != current.managed_devices {
+        eprintln!("Warning: changes to managed_devices take effect after a restart");
+    }
+    let (pinned, _) = pin_port_paths(&config, fan_controller);
+    for device in &pinned.devices {
+        fan_controller.set_lighting_control(&device.device_id, device.lighting_control);
+    }
+    fan_controller.set_command_delay(config.command_delay_ms.map(Duration::from_millis));
+    fan_controller.set_io_timeout(config.io_timeout_ms.map(Duration::from_millis));
+    fan_controller.set_permission_policy(config.on_permission_error);
+    fan_controller.set_hub_reports(config.experimental_hub_reports);
+    fan_controller.set_verify_writes(config.verify_writes);
+    fan_controller.set_write_budget(config.write_budget);
+    fan_controller.set_channel_management(channel_management(&pinned));
+    for warning in config::validation_warnings(&config) {
+        eprintln!("Warning: {}", warning);
+    }
+
+    let (resolved, notes) = resolve_config(&config, fan_controller);
+    for note in notes {
+        eprintln!("{}", note);
+    }
+    Ok((config, resolved.fan_curves))
+}
@@ -0,0 +1,282 @@
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use crate::backend::{Command, DeviceMetadata, DeviceWriter, FanBackend, HardwareError, Report};
+use crate::config::DeviceId;
+use crate::hardware::{encode_packet, hid_settle_time, report_request};
+
+pub type PacketLog = Arc<Mutex<Vec<(DeviceId, Vec<u8>)>>>;
+pub type FailingDevices = Arc<Mutex<HashSet<DeviceId>>>;
+
+/// Devices whose writes on an I/O thread block until released, like a
+/// wedged USB stack.
+#[derive(Clone, Default)]
+pub struct Hangs(Arc<(Mutex<HashSet<DeviceId>>, Condvar)>);
+
+impl Hangs {
+    pub fn hang(&self, device_id: &DeviceId) {
+        self.0 .0.lock().unwrap().insert(device_id.clone());
+    }
+
+    pub fn release(&self, device_id: &DeviceId) {
+        self.0 .0.lock().unwrap().remove(device_id);
+        self.0 .1.notify_all();
+    }
+
+    fn wait(&self, device_id: &DeviceId) {
+        let (hung, released) = &*self.0;
+        let mut hung = hung.lock().unwrap();
+        while hung.contains(device_id) {
+            hung = released.wait(hung).unwrap();
+        }
+    }
+}
+
+/// In-memory UNI hub backend for tests. Every command is encoded exactly as
+/// the HID backend would and recorded, so tests can assert on the bytes.
+pub struct MockBackend {
+    devices: Vec<DeviceId>,
+    packets: PacketLog,
+    // request packet -> reply packet
+    replies: HashMap<Vec<u8>, Vec<u8>>,
+    failing: FailingDevices,
+    // device -> writes left that fail as if the device node were not accessible
+    denied: Arc<Mutex<HashMap<DeviceId, usize>>>,
+    metadata: HashMap<DeviceId, DeviceMetadata>,
+    // What a rescan finds
+    rescanned: Option<Vec<DeviceId>>,
+    hangs: Hangs,
+    // (channel, last speed byte written to it) -> RPM
+    rpm_model: Option<fn(usize, u8) -> u16>,
+    hid_settle_times: bool,
+}
+
+impl MockBackend {
+    pub fn new(devices: Vec<DeviceId>) -> Self {
+        Self {
+            devices,
+            packets: Arc::new(Mutex::new(Vec::new())),
+            replies: HashMap::new(),
+            failing: Arc::new(Mutex::new(HashSet::new())),
+            denied: Arc::new(Mutex::new(HashMap::new())),
+            metadata: HashMap::new(),
+            rescanned: None,
+            hangs: Hangs::default(),
+            rpm_model: None,
+            hid_settle_times: false,
+        }
+    }
+
+    /// Makes the next `writes` writes to `device_id` fail with
+    /// HardwareError::PermissionDenied, like an EACCES opening the hidraw node.
+    pub fn deny_access_to(&self, device_id: &DeviceId, writes: usize) {
+        self.denied
+            .lock()
+            .unwrap()
+            .insert(device_id.clone(), writes);
+    }
+
+    /// Makes every write to `device_id` fail from now on.
+    pub fn fail_writes_to(&self, device_id: &DeviceId) {
+        self.failing.lock().unwrap().insert(device_id.clone());
+    }
+
+    /// Answers `request` with `reply`; other requests get no reply.
+    pub fn with_reply(mut self, request: Vec<u8>, reply: Vec<u8>) -> Self {
+        self.replies.insert(request, reply);
+        self
+    }
+
+    /// Answers RPM requests with what `model` gives for the speed byte last
+    /// written to each channel, 0 before any, like fans that follow their
+    /// duty.
+    pub fn with_rpm_model(mut self, model: fn(usize, u8) -> u16) -> Self {
+        self.rpm_model = Some(model);
+        self
+    }
+
+    /// Reports `metadata` for `device_id`, as HID discovery would.
+    pub fn with_metadata(mut self, device_id: &DeviceId, metadata: DeviceMetadata) -> Self {
+        self.metadata.insert(device_id.clone(), metadata);
+        self
+    }
+
+    /// Needs as long after each command as a UNI hub, instead of no time.
+    pub fn with_hid_settle_times(mut self) -> Self {
+        self.hid_settle_times = true;
+        self
+    }
+
+    /// Finds `devices` on the next rescan, as if some were plugged in or out.
+    pub fn with_rescan(mut self, devices: Vec<DeviceId>) -> Self {
+        self.rescanned = Some(devices);
+        self
+    }
+
+    /// Handle to hang and release writes on I/O threads.
+    pub fn hangs(&self) -> Hangs {
+        self.hangs.clone()
+    }
+
+    /// Handle to the devices whose writes fail, to unplug and replug them
+    /// after the backend is moved into a FanController.
+    pub fn failing(&self) -> FailingDevices {
+        self.failing.clone()
+    }
+
+    /// Handle to the recorded packets that stays valid after the backend is
+    /// moved into a FanController.
+    pub fn packets(&self) -> PacketLog {
+        self.packets.clone()
+    }
+}
+
+impl FanBackend for MockBackend {
+    fn devices(&self) -> Vec<DeviceId> {
+        self.devices.clone()
+    }
+
+    fn metadata(&self, device_id: &DeviceId) -> Option<DeviceMetadata> {
+        self.metadata.get(device_id).cloned()
+    }
+
+    fn settle_time(&self, command: &Command) -> Duration {
+        if self.hid_settle_times {
+            hid_settle_time(command)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    fn rescan(&mut self) -> Result<()> {
+        if let Some(devices) = self.rescanned.take() {
+            self.devices = devices;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, device_id: &DeviceId, command: &Command) -> Result<()> {
+        if let Some(left) = self.denied.lock().unwrap().get_mut(device_id) {
+            if *left > 0 {
+                *left -= 1;
+                return Err(HardwareError::PermissionDenied {
+                    device_id: device_id.clone(),
+                }
+                .into());
+            }
+        }
+        if self.failing.lock().unwrap().contains(device_id) {
+            return Err(anyhow!("Write to {} timed out", device_id));
+        }
+        let packet = encode_packet(device_id.1, command)?;
+        self.packets
+            .lock()
+            .unwrap()
+            .push((device_id.clone(), packet));
+        Ok(())
+    }
+
+    fn writer(&mut self, device_id: &DeviceId) -> Result<Option<Box<dyn DeviceWriter>>> {
+        Ok(Some(Box::new(MockWriter {
+            device_id: device_id.clone(),
+            packets: self.packets.clone(),
+            failing: self.failing.clone(),
+            hangs: self.hangs.clone(),
+            replies: self.replies.clone(),
+            rpm_model: self.rpm_model,
+        })))
+    }
+
+    fn read_report(
+        &mut self,
+        device_id: &DeviceId,
+        report: Report,
+    ) -> Result<Vec<u8>, HardwareError> {
+        answer_report(
+            device_id,
+            report,
+            &self.packets,
+            &self.replies,
+            self.rpm_model,
+        )
+    }
+}
+
+/// Logs the request for `report` and replies from `rpm_model` or `replies`.
+fn answer_report(
+    device_id: &DeviceId,
+    report: Report,
+    packets: &PacketLog,
+    replies: &HashMap<Vec<u8>, Vec<u8>>,
+    rpm_model: Option<fn(usize, u8) -> u16>,
+) -> Result<Vec<u8>, HardwareError> {
+    let request = report_request(device_id.1, report).ok_or(HardwareError::Unsupported {
+        device_id: device_id.clone(),
+        operation: report.operation(),
+    })?;
+    packets
+        .lock()
+        .unwrap()
+        .push((device_id.clone(), request.clone()));
+    if let (Some(model), Report::Rpms) = (rpm_model, report) {
+        let packets = packets.lock().unwrap();
+        let mut reply = vec![224, 82];
+        for channel in 0..4 {
+            let speed = packets
+                .iter()
+                .rev()
+                .find(|(id, packet)| {
+                    id == device_id
+                        && packet.len() == 4
+                        && packet[0] == 224
+                        && packet[1] as usize == 32 + channel
+                })
+                .map_or(0, |(_, packet)| packet[3]);
+            reply.extend(model(channel, speed).to_be_bytes());
+        }
+        return Ok(reply);
+    }
+    replies
+        .get(&request)
+        .cloned()
+        .ok_or_else(|| anyhow!("No reply from {}", device_id).into())
+}
+
+/// Writes and reads for one mock device from its I/O thread. Denied writes
+/// are left to `send`.
+struct MockWriter {
+    device_id: DeviceId,
+    packets: PacketLog,
+    failing: FailingDevices,
+    hangs: Hangs,
+    replies: HashMap<Vec<u8>, Vec<u8>>,
+    rpm_model: Option<fn(usize, u8) -> u16>,
+}
+
+impl DeviceWriter for MockWriter {
+    fn send(&mut self, command: &Command) -> Result<()> {
+        self.hangs.wait(&self.device_id);
+        if self.failing.lock().unwrap().contains(&self.device_id) {
+            return Err(anyhow!("Write to {} timed out", self.device_id));
+        }
+        let packet = encode_packet(self.device_id.1, command)?;
+        self.packets
+            .lock()
+            .unwrap()
+            .push((self.device_id.clone(), packet));
+        Ok(())
+    }
+
+    fn read_report(&mut self, report: Report) -> Result<Vec<u8>, HardwareError> {
+        self.hangs.wait(&self.device_id);
+        answer_report(
+            &self.device_id,
+            report,
+            &self.packets,
+            &self.replies,
+            self.rpm_model,
+        )
+    }
+}
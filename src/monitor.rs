@@ -0,0 +1,96 @@
+use anyhow::{bail, Result};
+use std::time::Duration;
+
+use crate::backend::{Command, DeviceMetadata, FanBackend, HardwareError, Report};
+use crate::config::DeviceId;
+
+/// Lets the wrapped backend discover devices and answer requests, such as
+/// RPM reports, but refuses every command that would change a device. The
+/// daemon's monitor mode runs on these, so a bug elsewhere can't write.
+pub struct ReadOnlyBackend {
+    inner: Box<dyn FanBackend>,
+}
+
+impl ReadOnlyBackend {
+    pub fn new(inner: Box<dyn FanBackend>) -> Self {
+        Self { inner }
+    }
+}
+
+impl FanBackend for ReadOnlyBackend {
+    fn devices(&self) -> Vec<DeviceId> {
+        self.inner.devices()
+    }
+
+    fn metadata(&self, device_id: &DeviceId) -> Option<DeviceMetadata> {
+        self.inner.metadata(device_id)
+    }
+
+    fn send(&mut self, device_id: &DeviceId, command: &Command) -> Result<()> {
+        bail!(
+            "Monitor mode: not sending {:?} to device {}",
+            command,
+            device_id
+        )
+    }
+
+    fn rescan(&mut self) -> Result<()> {
+        self.inner.rescan()
+    }
+
+    fn reopen(&mut self, device_id: &DeviceId) -> Result<()> {
+        self.inner.reopen(device_id)
+    }
+
+    fn settle_time(&self, command: &Command) -> Duration {
+        self.inner.settle_time(command)
+    }
+
+    fn read_report(
+        &mut self,
+        device_id: &DeviceId,
+        report: Report,
+    ) -> Result<Vec<u8>, HardwareError> {
+        self.inner.read_report(device_id, report)
+    }
+
+    // Nothing was taken over, so there is nothing to hand back
+    fn release(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SpeedPercent;
+    use crate::mock::MockBackend;
+
+    #[test]
+    fn test_read_only() {
+        let device_id = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let backend = MockBackend::new(vec![device_id.clone()])
+            .with_reply(vec![224, 82, 0, 0], vec![224, 82, 4, 76, 0, 0, 0, 0, 0, 0]);
+        let packets = backend.packets();
+        let mut read_only = ReadOnlyBackend::new(Box::new(backend));
+
+        assert_eq!(read_only.devices(), vec![device_id.clone()]);
+        assert!(read_only
+            .send(
+                &device_id,
+                &Command::SetSpeed {
+                    channel: 0,
+                    speed_percent: SpeedPercent::new(50).unwrap(),
+                },
+            )
+            .is_err());
+        assert_eq!(
+            read_only.read_report(&device_id, Report::Rpms).unwrap(),
+            vec![224, 82, 4, 76, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            *packets.lock().unwrap(),
+            vec![(device_id.clone(), vec![224, 82, 0, 0])]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use crate::config::{CurveConfig, DeviceId, FanCurve, TemperatureSource};
+use crate::output::{ChannelName, NamesReport, SourceName};
+
+/// `text` as lowercase ASCII letters, digits and single dashes, e.g.
+/// "Front Top" as "front-top".
+pub fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
+}
+
+/// The identifier of a channel without a named curve, e.g.
+/// "0cf2-a103-abc-2".
+pub fn generated_channel_id(device_id: &DeviceId, channel: usize) -> String {
+    slug(&format!(
+        "{:04x}-{:04x}-{}-{}",
+        device_id.0, device_id.1, device_id.2, channel
+    ))
+}
+
+/// The identifier of a temperature source, from its key, e.g. "gpu-0-edge".
+/// Readings are kept by key, so this is what to call with one.
+pub fn source_id_from_key(key: &str) -> String {
+    slug(key)
+}
+
+pub fn source_id(source: &TemperatureSource) -> String {
+    source_id_from_key(&source.key())
+}
+
+/// The one identifier of each driven channel, used wherever a channel is
+/// named for machines: the status file, the journal and debug logs. It is
+/// the curve's name as a slug, or generated from the device and channel for
+/// unnamed curves. Curves sharing a name, such as the channels of a
+/// broadcast curve, get their channel number appended.
+#[derive(Clone, Debug, Default)]
+pub struct ChannelNames {
+    ids: HashMap<(DeviceId, usize), String>,
+}
+
+impl ChannelNames {
+    pub fn new(fan_curves: &[FanCurve]) -> Self {
+        let base = |fan_curve: &FanCurve| match fan_curve.name.as_deref().map(slug) {
+            Some(name) if !name.is_empty() => name,
+            _ => generated_channel_id(&fan_curve.device_id, fan_curve.channel),
+        };
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for fan_curve in fan_curves {
+            *counts.entry(base(fan_curve)).or_default() += 1;
+        }
+        let ids = fan_curves
+            .iter()
+            .map(|fan_curve| {
+                let id = base(fan_curve);
+                let id = if counts[&id] > 1 {
+                    format!("{}-{}", id, fan_curve.channel)
+                } else {
+                    id
+                };
+                ((fan_curve.device_id.clone(), fan_curve.channel), id)
+            })
+            .collect();
+        Self { ids }
+    }
+
+    /// The channel's identifier; a channel without a curve gets the
+    /// generated one.
+    pub fn get(&self, device_id: &DeviceId, channel: usize) -> String {
+        self.ids
+            .get(&(device_id.clone(), channel))
+            .cloned()
+            .unwrap_or_else(|| generated_channel_id(device_id, channel))
+    }
+}
+
+/// Every channel and source identifier of `config`, for `uni-sync-curve
+/// names`. The config should be resolved, so broadcast curves show a line
+/// per channel.
+pub fn names_report(config: &CurveConfig) -> NamesReport {
+    let names = ChannelNames::new(&config.fan_curves);
+    NamesReport {
+        channels: config
+            .fan_curves
+            .iter()
+            .map(|fan_curve| ChannelName {
+                id: names.get(&fan_curve.device_id, fan_curve.channel),
+                device_id: fan_curve.device_id.clone(),
+                channel: fan_curve.channel,
+                curve: fan_curve.label(),
+            })
+            .collect(),
+        sources: config
+            .source_chain()
+            .iter()
+            .map(|source| SourceName {
+                id: source_id(source),
+                source: source.name().to_string(),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_names() {
+        assert_eq!(slug("Front Top"), "front-top");
+        assert_eq!(slug("  CPU / rad (push)  "), "cpu-rad-push");
+        assert_eq!(slug("gpu 0000:01:00.0 edge"), "gpu-0000-01-00-0-edge");
+
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let curve = |name: Option<&str>, channel: usize| FanCurve {
+            name: name.map(str::to_string),
+            device_id: hub.clone(),
+            channel,
+            ..FanCurve::default()
+        };
+        // A broadcast curve's channels share its name
+        let names = ChannelNames::new(&[
+            curve(Some("Pump"), 0),
+            curve(None, 1),
+            curve(Some("rad"), 2),
+            curve(Some("rad"), 3),
+            curve(Some("!!"), 4),
+        ]);
+        let ids: Vec<String> = (0..6).map(|channel| names.get(&hub, channel)).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "pump",
+                "0cf2-a103-abc-1",
+                "rad-2",
+                "rad-3",
+                "0cf2-a103-abc-4",
+                "0cf2-a103-abc-5"
+            ]
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use notify_rust::Notification;
+
+use crate::config::{NotifySettings, Urgency};
+
+/// Shows a desktop notification over the session bus's
+/// org.freedesktop.Notifications service.
+///
+/// Runs in the background; failures are only reported with --debug so a
+/// missing notification daemon never gets in the way of fan control.
+pub fn send(settings: &NotifySettings, urgency: Urgency, summary: &str, body: &str, debug: bool) {
+    // The D-Bus client only connects to the bus in DBUS_SESSION_BUS_ADDRESS
+    if let Some(bus) = &settings.user_bus {
+        if std::env::var("DBUS_SESSION_BUS_ADDRESS").ok().as_ref() != Some(bus) {
+            std::env::set_var("DBUS_SESSION_BUS_ADDRESS", bus);
+        }
+    }
+    if std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() {
+        if debug {
+            println!(
+                "No session bus available, skipping notification: {}",
+                summary
+            );
+        }
+        return;
+    }
+
+    let urgency = match urgency {
+        Urgency::Low => notify_rust::Urgency::Low,
+        Urgency::Normal => notify_rust::Urgency::Normal,
+        Urgency::Critical => notify_rust::Urgency::Critical,
+    };
+    let mut notification = Notification::new();
+    notification
+        .appname("uni-sync-curve")
+        .summary(summary)
+        .body(body)
+        .urgency(urgency);
+
+    tokio::spawn(async move {
+        if let Err(e) = notification.show_async().await {
+            if debug {
+                println!("Could not show notification: {}", e);
+            }
+        }
+    });
+}
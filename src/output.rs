@@ -0,0 +1,555 @@
+//! What the subcommands that scripts read print. Each builds one of the
+//! reports here; `--output json` prints it with a schema version and field
+//! names that only change along with that version, while the text is for
+//! people and may change in any release.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::fmt::Write;
+
+use crate::backend::DeviceMetadata;
+use crate::bench::{self, BenchReport};
+use crate::config::DeviceId;
+use crate::eval::{self, Evaluation};
+use crate::explain;
+use crate::gpu::GpuSensorKind;
+use crate::journal::{self, JournalDump};
+use crate::status::{self, Status};
+use crate::style::Style;
+use crate::units::DisplayUnits;
+
+/// Goes up with any change to the JSON a script could trip over: a field
+/// renamed, removed or changing type. New fields don't change it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// A subcommand's output, serialized as is for JSON.
+pub trait Report: Serialize {
+    fn text(&self) -> String;
+}
+
+#[derive(Serialize)]
+struct Versioned<'a, T> {
+    schema_version: u32,
+    #[serde(flatten)]
+    report: &'a T,
+}
+
+pub fn render<T: Report>(format: OutputFormat, report: &T) -> Result<String> {
+    Ok(match format {
+        OutputFormat::Text => report.text(),
+        OutputFormat::Json => {
+            let versioned = Versioned {
+                schema_version: SCHEMA_VERSION,
+                report,
+            };
+            format!("{}\n", serde_json::to_string_pretty(&versioned)?)
+        }
+    })
+}
+
+pub fn print<T: Report>(format: OutputFormat, report: &T) -> Result<()> {
+    print!("{}", render(format, report)?);
+    Ok(())
+}
+
+/// `list-devices`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct DevicesReport {
+    pub devices: Vec<DeviceEntry>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct DeviceEntry {
+    pub device_id: DeviceId,
+    pub model: String,
+    pub metadata: Option<DeviceMetadata>,
+    /// For hubs that report it.
+    pub fans_per_channel: Option<Vec<u8>>,
+}
+
+impl Report for DevicesReport {
+    fn text(&self) -> String {
+        let mut text = String::new();
+        for device in &self.devices {
+            let _ = write!(text, "{} {}", device.device_id, device.model);
+            if let Some(metadata) = &device.metadata {
+                let _ = write!(text, " ({})", metadata);
+            }
+            if let Some(counts) = &device.fans_per_channel {
+                let _ = write!(text, ", fans per channel: {:?}", counts);
+            }
+            let _ = writeln!(text);
+        }
+        text
+    }
+}
+
+/// `list-sensors`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SensorsReport {
+    pub cpu_celsius: Option<f64>,
+    pub soc_celsius: Option<f64>,
+    pub gpus: Vec<GpuEntry>,
+    pub sensors: Vec<SensorEntry>,
+    #[serde(skip)]
+    pub units: DisplayUnits,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct GpuEntry {
+    pub index: usize,
+    pub pci_bus_id: String,
+    pub driver: String,
+    pub temperatures: Vec<GpuTemperature>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct GpuTemperature {
+    pub sensor: GpuSensorKind,
+    pub celsius: f64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SensorEntry {
+    pub label: String,
+    pub celsius: f64,
+    /// The built-in source that reads it, if any.
+    pub source: Option<String>,
+}
+
+impl Report for SensorsReport {
+    fn text(&self) -> String {
+        let mut text = String::new();
+        for (name, reading) in [("cpu", self.cpu_celsius), ("soc", self.soc_celsius)] {
+            match reading {
+                Some(celsius) => {
+                    let _ = writeln!(text, "{}  {}", name, self.units.temperature(celsius));
+                }
+                None => {
+                    let _ = writeln!(text, "{}  unavailable", name);
+                }
+            }
+        }
+        for gpu in &self.gpus {
+            let _ = write!(
+                text,
+                "gpu {}  {}  {}",
+                gpu.index, gpu.pci_bus_id, gpu.driver
+            );
+            for temperature in &gpu.temperatures {
+                let _ = write!(
+                    text,
+                    "  {:?} {}",
+                    temperature.sensor,
+                    self.units.temperature(temperature.celsius)
+                );
+            }
+            let _ = writeln!(text);
+        }
+        for sensor in &self.sensors {
+            let source = match &sensor.source {
+                Some(source) => format!("read by the {} source", source),
+                None => "no source reads it".to_string(),
+            };
+            let _ = writeln!(
+                text,
+                "sensor {:?}  {}  ({})",
+                sensor.label,
+                self.units.temperature(sensor.celsius),
+                source
+            );
+        }
+        text
+    }
+}
+
+/// `status`: the daemon's status file as it is.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct StatusReport {
+    pub status: Status,
+    #[serde(skip)]
+    pub now: u64,
+    #[serde(skip)]
+    pub units: DisplayUnits,
+    #[serde(skip)]
+    pub style: Style,
+    /// Also show duty statistics and memory use.
+    #[serde(skip)]
+    pub stats: bool,
+}
+
+impl Report for StatusReport {
+    fn text(&self) -> String {
+        let mut text = status::format_status(&self.status, self.now, self.units, self.style);
+        if self.stats {
+            text.push('\n');
+            text.push_str(&status::format_duty(&self.status));
+            if let Some(memory) = &self.status.memory {
+                text.push_str(&status::format_memory(memory));
+            }
+        }
+        text
+    }
+}
+
+/// `eval`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct EvalReport {
+    pub curve: String,
+    pub evaluations: Vec<Evaluation>,
+    #[serde(skip)]
+    pub full_pipeline: bool,
+    #[serde(skip)]
+    pub units: DisplayUnits,
+}
+
+impl Report for EvalReport {
+    fn text(&self) -> String {
+        eval::format_evaluations(&self.evaluations, self.full_pipeline, self.units)
+    }
+}
+
+/// `convert`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ConvertReport {
+    pub model: String,
+    /// The RPM given, when converting from RPM.
+    pub requested_rpm: Option<u16>,
+    pub speed_percent: u8,
+    /// What the hub aims for at `speed_percent`.
+    pub rpm: u16,
+    pub min_rpm: u16,
+    pub max_rpm: u16,
+}
+
+impl Report for ConvertReport {
+    fn text(&self) -> String {
+        let mut text = match self.requested_rpm {
+            Some(requested) => format!(
+                "{} RPM is {}% on a {} ({} RPM)\n",
+                requested, self.speed_percent, self.model, self.rpm
+            ),
+            None => format!(
+                "{}% is {} RPM on a {}\n",
+                self.speed_percent, self.rpm, self.model
+            ),
+        };
+        let _ = writeln!(text, "Range: {}-{} RPM", self.min_rpm, self.max_rpm);
+        text
+    }
+}
+
+/// `explain`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ExplainReport {
+    #[serde(flatten)]
+    pub report: explain::Report,
+    #[serde(skip)]
+    pub units: DisplayUnits,
+}
+
+impl Report for ExplainReport {
+    fn text(&self) -> String {
+        explain::format_report(&self.report, self.units)
+    }
+}
+
+/// `journal`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct JournalReport {
+    #[serde(flatten)]
+    pub dump: JournalDump,
+    #[serde(skip)]
+    pub units: DisplayUnits,
+}
+
+impl Report for JournalReport {
+    fn text(&self) -> String {
+        journal::format_dump(&self.dump, self.units)
+    }
+}
+
+/// `bench`
+impl Report for BenchReport {
+    fn text(&self) -> String {
+        bench::format_report(self)
+    }
+}
+
+/// `names`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct NamesReport {
+    pub channels: Vec<ChannelName>,
+    pub sources: Vec<SourceName>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ChannelName {
+    pub id: String,
+    pub device_id: DeviceId,
+    pub channel: usize,
+    pub curve: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SourceName {
+    pub id: String,
+    /// The source's type, e.g. "gpu".
+    pub source: String,
+}
+
+impl Report for NamesReport {
+    fn text(&self) -> String {
+        let mut text = String::new();
+        for channel in &self.channels {
+            let _ = writeln!(
+                text,
+                "channel {}  {} channel {}  (curve {})",
+                channel.id, channel.device_id, channel.channel, channel.curve
+            );
+        }
+        for source in &self.sources {
+            let _ = writeln!(text, "source {}  ({} source)", source.id, source.source);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json<T: Report>(report: &T) -> String {
+        render(OutputFormat::Json, report).unwrap()
+    }
+
+    #[test]
+    fn test_devices_json() {
+        let report = DevicesReport {
+            devices: vec![DeviceEntry {
+                device_id: DeviceId(0x0cf2, 0xa103, "ABC".to_string()),
+                model: "SL v2".to_string(),
+                metadata: None,
+                fans_per_channel: Some(vec![3, 2, 0, 0]),
+            }],
+        };
+        assert_eq!(
+            json(&report),
+            r#"{
+  "schema_version": 1,
+  "devices": [
+    {
+      "device_id": [
+        3314,
+        41219,
+        "ABC"
+      ],
+      "model": "SL v2",
+      "metadata": null,
+      "fans_per_channel": [
+        3,
+        2,
+        0,
+        0
+      ]
+    }
+  ]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_sensors_json() {
+        let report = SensorsReport {
+            cpu_celsius: Some(61.5),
+            soc_celsius: None,
+            gpus: vec![],
+            sensors: vec![
+                SensorEntry {
+                    label: "coretemp Core 0".to_string(),
+                    celsius: 61.5,
+                    source: Some("cpu".to_string()),
+                },
+                SensorEntry {
+                    label: "nvme Composite".to_string(),
+                    celsius: 40.0,
+                    source: None,
+                },
+            ],
+            units: DisplayUnits::default(),
+        };
+        assert_eq!(
+            json(&report),
+            r#"{
+  "schema_version": 1,
+  "cpu_celsius": 61.5,
+  "soc_celsius": null,
+  "gpus": [],
+  "sensors": [
+    {
+      "label": "coretemp Core 0",
+      "celsius": 61.5,
+      "source": "cpu"
+    },
+    {
+      "label": "nvme Composite",
+      "celsius": 40.0,
+      "source": null
+    }
+  ]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_eval_json() {
+        let report = EvalReport {
+            curve: "quiet".to_string(),
+            evaluations: vec![Evaluation {
+                temperature: 55.0,
+                curve_speed: 40,
+                adjusted_speed: 45,
+            }],
+            full_pipeline: true,
+            units: DisplayUnits::default(),
+        };
+        assert_eq!(
+            json(&report),
+            r#"{
+  "schema_version": 1,
+  "curve": "quiet",
+  "evaluations": [
+    {
+      "temperature": 55.0,
+      "curve_speed": 40,
+      "adjusted_speed": 45
+    }
+  ]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_journal_and_explain_json() {
+        let journal = JournalReport {
+            dump: JournalDump {
+                dumped_at: 1700000000.5,
+                trigger: journal::DumpTrigger::Request,
+                entries: Vec::new(),
+            },
+            units: DisplayUnits::Fahrenheit,
+        };
+        assert_eq!(
+            json(&journal),
+            r#"{
+  "schema_version": 1,
+  "dumped_at": 1700000000.5,
+  "trigger": "request",
+  "entries": []
+}
+"#
+        );
+
+        let explain = ExplainReport {
+            report: explain::Report {
+                temperature_celsius: 50.0,
+                temperature_from: "--temp".to_string(),
+                override_percent: 0,
+                curves: Vec::new(),
+                notes: Vec::new(),
+            },
+            units: DisplayUnits::default(),
+        };
+        assert_eq!(
+            json(&explain),
+            r#"{
+  "schema_version": 1,
+  "temperature_celsius": 50.0,
+  "temperature_from": "--temp",
+  "override_percent": 0,
+  "curves": []
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        let report = ConvertReport {
+            model: "SL v2".to_string(),
+            requested_rpm: Some(1000),
+            speed_percent: 40,
+            rpm: 950,
+            min_rpm: 250,
+            max_rpm: 2000,
+        };
+        assert_eq!(
+            json(&report),
+            r#"{
+  "schema_version": 1,
+  "model": "SL v2",
+  "requested_rpm": 1000,
+  "speed_percent": 40,
+  "rpm": 950,
+  "min_rpm": 250,
+  "max_rpm": 2000
+}
+"#
+        );
+        assert_eq!(
+            render(OutputFormat::Text, &report).unwrap(),
+            "1000 RPM is 40% on a SL v2 (950 RPM)\nRange: 250-2000 RPM\n"
+        );
+    }
+
+    #[test]
+    fn test_names_json() {
+        let report = NamesReport {
+            channels: vec![ChannelName {
+                id: "ABC/0".to_string(),
+                device_id: DeviceId(0x0cf2, 0xa103, "ABC".to_string()),
+                channel: 0,
+                curve: "quiet".to_string(),
+            }],
+            sources: vec![SourceName {
+                id: "cpu".to_string(),
+                source: "cpu".to_string(),
+            }],
+        };
+        assert_eq!(
+            json(&report),
+            r#"{
+  "schema_version": 1,
+  "channels": [
+    {
+      "id": "ABC/0",
+      "device_id": [
+        3314,
+        41219,
+        "ABC"
+      ],
+      "channel": 0,
+      "curve": "quiet"
+    }
+  ],
+  "sources": [
+    {
+      "id": "cpu",
+      "source": "cpu"
+    }
+  ]
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt::Write;
+
+use crate::config::CurveConfig;
+use crate::engine::PIPELINE;
+use crate::names::ChannelNames;
+
+/// The stages every speed goes through, in order, and which of them the
+/// config makes act on each curve, for `uni-sync-curve pipeline`.
+pub fn format_pipeline(config: &CurveConfig) -> String {
+    let mut text = String::from(
+        "Stages, in the order they apply; the last to change a speed gives its reason:\n",
+    );
+    for (number, stage) in PIPELINE.iter().enumerate() {
+        let reason = match stage.reason() {
+            Some(reason) => format!("{:?}", reason),
+            None => "Curve".to_string(),
+        };
+        let _ = writeln!(
+            text,
+            "  {}. {:<13} {:<13} {}",
+            number + 1,
+            stage.name(),
+            reason,
+            stage.description()
+        );
+    }
+    if config.fan_curves.is_empty() {
+        return text;
+    }
+    text.push_str("\nActive stages by channel; the override can act on any of them at runtime:\n");
+    let names = ChannelNames::new(&config.fan_curves);
+    for fan_curve in &config.fan_curves {
+        let active: Vec<&str> = PIPELINE
+            .iter()
+            .filter(|stage| stage.is_active(config, fan_curve))
+            .map(|stage| stage.name())
+            .collect();
+        let _ = writeln!(
+            text,
+            "  {}: {}",
+            names.get(&fan_curve.device_id, fan_curve.channel),
+            active.join(", ")
+        );
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Anticipation, DeviceId, FanCurve};
+
+    #[test]
+    fn test_format_pipeline() {
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let config = CurveConfig {
+            startup_ramp_seconds: Some(30),
+            fan_curves: vec![
+                FanCurve {
+                    name: Some("Front".to_string()),
+                    device_id: hub.clone(),
+                    group: Some("intake".to_string()),
+                    never_stop: true,
+                    ..Default::default()
+                },
+                FanCurve {
+                    device_id: hub.clone(),
+                    channel: 1,
+                    anticipation: Some(Anticipation {
+                        degrees_per_second_gain: 5.0,
+                        max_boost_percent: 10,
+                    }),
+                    speed_offset_percent: 5,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let text = format_pipeline(&config);
+        assert!(text.contains("  1. curve "), "{}", text);
+        assert!(text.contains("  8. never stop    NeverStop "), "{}", text);
+        assert!(text.contains("  9. group         Group "), "{}", text);
+        assert!(text.contains("  5. trim          Curve "), "{}", text);
+        assert!(
+            text.contains("  front: curve, startup ramp, never stop, group\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("  0cf2-a103-abc-1: curve, anticipation, trim, startup ramp\n"),
+            "{}",
+            text
+        );
+    }
+}
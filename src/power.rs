@@ -0,0 +1,129 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{PowerProfile, PowerState};
+
+pub const POWER_SUPPLY_ROOT: &str = "/sys/class/power_supply";
+
+fn read_attribute(supply: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(supply.join(name))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+/// The power state as the kernel's power supplies tell it: AC while any
+/// line-powered supply (mains, USB, a UPS on line power) is online, battery
+/// while none is or a battery discharges without one. None when there are
+/// no supplies to tell.
+pub fn read_power_state(root: &Path) -> Option<PowerState> {
+    let mut line_powered = Vec::new();
+    let mut discharging = false;
+    for entry in fs::read_dir(root).ok()?.flatten() {
+        let supply = entry.path();
+        let kind = read_attribute(&supply, "type").unwrap_or_default();
+        if kind == "Battery" {
+            discharging |= read_attribute(&supply, "status").as_deref() == Some("Discharging");
+        } else if let Some(online) = read_attribute(&supply, "online") {
+            line_powered.push(online == "1");
+        }
+    }
+    if line_powered.iter().any(|&online| online) {
+        Some(PowerState::Ac)
+    } else if !line_powered.is_empty() || discharging {
+        Some(PowerState::Battery)
+    } else {
+        None
+    }
+}
+
+/// Follows the power state from tick to tick. Machines without power
+/// supplies count as on AC; that is logged once, not every tick.
+pub struct PowerMonitor {
+    root: PathBuf,
+    state: Option<PowerState>,
+    unknown_logged: bool,
+}
+
+impl PowerMonitor {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            state: None,
+            unknown_logged: false,
+        }
+    }
+
+    /// Reads the power state, returning it and whether it changed.
+    pub fn poll(&mut self) -> (PowerState, bool) {
+        let state = read_power_state(&self.root).unwrap_or_else(|| {
+            if !self.unknown_logged {
+                eprintln!("No power supplies in {:?}; assuming AC power", self.root);
+                self.unknown_logged = true;
+            }
+            PowerState::Ac
+        });
+        let changed = self.state.is_some_and(|last| last != state);
+        self.state = Some(state);
+        (state, changed)
+    }
+}
+
+/// What every speed gets added: the interactive override while it is set,
+/// the power state's profile otherwise.
+pub fn effective_override(override_percent: i8, profile: Option<&PowerProfile>) -> i8 {
+    if override_percent != 0 {
+        override_percent
+    } else {
+        profile.map(|profile| profile.offset_percent).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supply(root: &Path, name: &str, attributes: &[(&str, &str)]) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (attribute, value) in attributes {
+            fs::write(dir.join(attribute), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_power_state() {
+        let root = std::env::temp_dir().join(format!("uni-sync-power-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let mut monitor = PowerMonitor::new(&root);
+
+        // No supplies: AC
+        assert_eq!(read_power_state(&root), None);
+        assert_eq!(monitor.poll(), (PowerState::Ac, false));
+
+        supply(
+            &root,
+            "BAT0",
+            &[("type", "Battery"), ("status", "Discharging")],
+        );
+        assert_eq!(read_power_state(&root), Some(PowerState::Battery));
+        assert_eq!(monitor.poll(), (PowerState::Battery, true));
+
+        supply(&root, "AC", &[("type", "Mains"), ("online", "1")]);
+        assert_eq!(monitor.poll(), (PowerState::Ac, true));
+        assert_eq!(monitor.poll(), (PowerState::Ac, false));
+
+        // A UPS off line power
+        fs::remove_dir_all(&root).unwrap();
+        supply(&root, "ups", &[("type", "UPS"), ("online", "0")]);
+        assert_eq!(read_power_state(&root), Some(PowerState::Battery));
+        fs::remove_dir_all(&root).unwrap();
+
+        let quiet = PowerProfile {
+            offset_percent: -15,
+        };
+        assert_eq!(effective_override(0, Some(&quiet)), -15);
+        assert_eq!(effective_override(10, Some(&quiet)), 10);
+        assert_eq!(effective_override(0, None), 0);
+    }
+}
@@ -0,0 +1,127 @@
+/// A built-in temperature source that reads the hottest of the sensors
+/// whose label contains one of its keywords.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SensorPreset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub keywords: &'static [&'static str],
+}
+
+/// Every preset. A sensor belongs to the first whose keywords match its
+/// label, so no sensor is read by two presets.
+pub const PRESETS: &[SensorPreset] = &[
+    SensorPreset {
+        name: "cpu",
+        description: "CPU package and cores",
+        keywords: &["cpu", "core", "processor", "tctl"],
+    },
+    SensorPreset {
+        name: "soc",
+        description: "SoC, iGPU package and chipset, as on mini PCs",
+        keywords: &["acpitz", "soc", "pch", "dts"],
+    },
+];
+
+pub const CPU: &SensorPreset = &PRESETS[0];
+pub const SOC: &SensorPreset = &PRESETS[1];
+
+impl SensorPreset {
+    /// Whether `label` contains one of the keywords, in any case.
+    pub fn matches(&self, label: &str) -> bool {
+        let label = label.to_lowercase();
+        self.keywords.iter().any(|keyword| label.contains(keyword))
+    }
+}
+
+/// The preset a sensor labelled `label` is read by, if any.
+pub fn preset_for(label: &str) -> Option<&'static SensorPreset> {
+    PRESETS.iter().find(|preset| preset.matches(label))
+}
+
+/// The hottest of `sensors`, as (label, °C), that `preset` reads.
+pub fn max_temperature(preset: &SensorPreset, sensors: &[(String, f64)]) -> Option<f64> {
+    sensors
+        .iter()
+        .filter(|(label, _)| preset_for(label) == Some(preset))
+        .map(|&(_, temperature)| temperature)
+        .reduce(f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each label with the preset that should read it.
+    type Labels = &'static [(&'static str, Option<&'static str>)];
+
+    /// Sensor labels as sysinfo reports them on a few machines: hwmon name,
+    /// then the sensor's own label.
+    const MACHINES: &[(&str, Labels)] = &[
+        (
+            "Intel N100 mini PC",
+            &[
+                ("acpitz temp1", Some("soc")),
+                ("coretemp Package id 0", Some("cpu")),
+                ("coretemp Core 0", Some("cpu")),
+                ("nvme Composite", None),
+                ("iwlwifi_1 temp1", None),
+            ],
+        ),
+        (
+            "Intel Celeron J4125 mini PC",
+            &[
+                ("soc_dts0 temp1", Some("soc")),
+                ("soc_dts1 temp1", Some("soc")),
+                ("acpitz temp1", Some("soc")),
+                ("coretemp Core 0", Some("cpu")),
+            ],
+        ),
+        (
+            "Intel desktop with a Cannon Lake PCH",
+            &[
+                ("pch_cannonlake temp1", Some("soc")),
+                ("coretemp Package id 0", Some("cpu")),
+                ("nouveau temp1", None),
+            ],
+        ),
+        (
+            "AMD Ryzen 7 5800U mini PC",
+            &[
+                ("k10temp Tctl", Some("cpu")),
+                ("amdgpu edge", None),
+                ("acpitz temp1", Some("soc")),
+                ("nvme Composite", None),
+            ],
+        ),
+    ];
+
+    #[test]
+    fn test_presets_match_real_labels() {
+        for (machine, labels) in MACHINES {
+            for (label, expected) in *labels {
+                assert_eq!(
+                    preset_for(label).map(|preset| preset.name),
+                    *expected,
+                    "{}: {}",
+                    machine,
+                    label
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_temperature() {
+        let sensors: Vec<(String, f64)> = [
+            ("acpitz temp1", 52.0),
+            ("soc_dts0 temp1", 61.0),
+            ("coretemp Core 0", 70.0),
+            ("nvme Composite", 80.0),
+        ]
+        .map(|(label, temperature)| (label.to_string(), temperature))
+        .into();
+        assert_eq!(max_temperature(SOC, &sensors), Some(61.0));
+        assert_eq!(max_temperature(CPU, &sensors), Some(70.0));
+        assert_eq!(max_temperature(SOC, &sensors[2..]), None);
+    }
+}
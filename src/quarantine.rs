@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config::DeviceId;
+
+/// Consecutive failed write cycles after which a device is quarantined.
+pub const QUARANTINE_AFTER_FAILURES: u32 = 3;
+
+/// Waits between probes of a quarantined device; the last one repeats.
+pub const PROBE_BACKOFF: [Duration; 3] = [
+    Duration::from_secs(10),
+    Duration::from_secs(30),
+    Duration::from_secs(60),
+];
+
+/// Failed probes after which a quarantined device is reset, once per
+/// quarantine.
+pub const RESET_AFTER_PROBES: usize = 3;
+
+#[derive(Clone, Copy, Debug)]
+struct Probe {
+    next_at: Instant,
+    attempt: usize,
+    reset: bool,
+}
+
+/// Tracks write failures per device, and keeps devices that keep failing
+/// out of the write path until a probe gets through.
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    failures: HashMap<DeviceId, u32>,
+    quarantined: HashMap<DeviceId, Probe>,
+}
+
+impl Quarantine {
+    pub fn is_quarantined(&self, device_id: &DeviceId) -> bool {
+        self.quarantined.contains_key(device_id)
+    }
+
+    /// Whether a quarantined device is due for a probe.
+    pub fn probe_due(&self, device_id: &DeviceId, now: Instant) -> bool {
+        self.quarantined
+            .get(device_id)
+            .is_some_and(|probe| now >= probe.next_at)
+    }
+
+    /// Quarantines a device right away, without waiting for repeated failures.
+    /// Returns false if it already was.
+    pub fn quarantine_now(&mut self, device_id: &DeviceId, now: Instant) -> bool {
+        if self.is_quarantined(device_id) {
+            return false;
+        }
+        self.failures.remove(device_id);
+        self.quarantined.insert(
+            device_id.clone(),
+            Probe {
+                next_at: now + PROBE_BACKOFF[0],
+                attempt: 0,
+                reset: false,
+            },
+        );
+        true
+    }
+
+    /// Whether a quarantined device failed enough probes to be reset, and
+    /// wasn't reset yet this quarantine. Counts the reset as done.
+    pub fn take_reset(&mut self, device_id: &DeviceId) -> bool {
+        match self.quarantined.get_mut(device_id) {
+            Some(probe) if probe.attempt >= RESET_AFTER_PROBES && !probe.reset => {
+                probe.reset = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Records the outcome of one write cycle (or probe) for a device.
+    /// Returns true when this puts the device into quarantine.
+    pub fn record(&mut self, device_id: &DeviceId, ok: bool, now: Instant) -> bool {
+        if ok {
+            self.failures.remove(device_id);
+            self.quarantined.remove(device_id);
+            return false;
+        }
+
+        if let Some(probe) = self.quarantined.get_mut(device_id) {
+            probe.attempt += 1;
+            probe.next_at = now + PROBE_BACKOFF[probe.attempt.min(PROBE_BACKOFF.len() - 1)];
+            return false;
+        }
+
+        let failures = self.failures.entry(device_id.clone()).or_default();
+        *failures += 1;
+        *failures >= QUARANTINE_AFTER_FAILURES && self.quarantine_now(device_id, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quarantine_backoff() {
+        let device_id = DeviceId(0x0cf2, 0xa103, "FLAKY".to_string());
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let mut quarantine = Quarantine::default();
+
+        // A success in between resets the count
+        assert!(!quarantine.record(&device_id, false, at(0)));
+        assert!(!quarantine.record(&device_id, false, at(1)));
+        assert!(!quarantine.record(&device_id, true, at(2)));
+        assert!(!quarantine.record(&device_id, false, at(3)));
+        assert!(!quarantine.record(&device_id, false, at(4)));
+        assert!(quarantine.record(&device_id, false, at(5)));
+        assert!(quarantine.is_quarantined(&device_id));
+
+        // Probes back off 10s, 30s, then every 60s
+        assert!(!quarantine.probe_due(&device_id, at(14)));
+        assert!(quarantine.probe_due(&device_id, at(15)));
+        assert!(!quarantine.record(&device_id, false, at(15)));
+        assert!(!quarantine.take_reset(&device_id));
+        assert!(!quarantine.probe_due(&device_id, at(44)));
+        assert!(quarantine.probe_due(&device_id, at(45)));
+        quarantine.record(&device_id, false, at(45));
+        assert!(!quarantine.probe_due(&device_id, at(104)));
+        assert!(quarantine.probe_due(&device_id, at(105)));
+        quarantine.record(&device_id, false, at(105));
+        assert!(quarantine.probe_due(&device_id, at(165)));
+
+        // The third failed probe calls for a reset, once
+        assert!(quarantine.take_reset(&device_id));
+        assert!(!quarantine.take_reset(&device_id));
+
+        // A successful probe reinstates the device
+        quarantine.record(&device_id, true, at(165));
+        assert!(!quarantine.is_quarantined(&device_id));
+        assert!(!quarantine.probe_due(&device_id, at(1000)));
+    }
+}
@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// A queue that never holds more than its capacity: pushing onto a full one
+/// drops the oldest item. Every buffer the daemon keeps across ticks is one
+/// of these, so a long-running daemon can't slowly grow.
+#[derive(Clone, Debug)]
+pub struct RingBuffer<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+}
+
+/// How full a buffer is, as the status file reports it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BufferUsage {
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl BufferUsage {
+    /// Adds up several buffers of the same kind, such as one per channel.
+    pub fn sum(usages: impl IntoIterator<Item = BufferUsage>) -> BufferUsage {
+        usages
+            .into_iter()
+            .fold(BufferUsage::default(), |total, usage| BufferUsage {
+                len: total.len + usage.len,
+                capacity: total.capacity + usage.capacity,
+            })
+    }
+}
+
+impl<T> RingBuffer<T> {
+    /// A capacity of 0 is taken as 1, so the latest item is always kept.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            items: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Adds `item` as the newest, returning the oldest if it had to go.
+    pub fn push(&mut self, item: T) -> Option<T> {
+        let dropped = if self.items.len() >= self.capacity {
+            self.items.pop_front()
+        } else {
+            None
+        };
+        self.items.push_back(item);
+        dropped
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.items.pop_front()
+    }
+
+    /// Changes the capacity, dropping the oldest items that no longer fit.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        while self.items.len() > self.capacity {
+            self.items.pop_front();
+        }
+    }
+
+    /// Removes every item, oldest first.
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.items.drain(..)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn usage(&self) -> BufferUsage {
+        BufferUsage {
+            len: self.len(),
+            capacity: self.capacity,
+        }
+    }
+
+    /// The item `index` places from the oldest.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.items.get(index)
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        self.items.front()
+    }
+
+    pub fn back(&self) -> Option<&T> {
+        self.items.back()
+    }
+
+    /// Oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + ExactSizeIterator {
+        self.items.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.items.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut ring = RingBuffer::new(3);
+        assert_eq!(ring.len(), 0);
+        for item in 0..3 {
+            assert_eq!(ring.push(item), None);
+        }
+        assert_eq!(ring.push(3), Some(0));
+        assert_eq!(ring.iter().copied().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(
+            (ring.front(), ring.back(), ring.get(1)),
+            (Some(&1), Some(&3), Some(&2))
+        );
+
+        for item in 4..10_000 {
+            ring.push(item);
+            assert!(ring.len() <= 3);
+        }
+        ring.set_capacity(2);
+        assert_eq!(ring.iter().copied().collect::<Vec<_>>(), vec![9998, 9999]);
+        assert_eq!(
+            ring.usage(),
+            BufferUsage {
+                len: 2,
+                capacity: 2
+            }
+        );
+        assert_eq!(RingBuffer::<u8>::new(0).usage().capacity, 1);
+        assert_eq!(ring.drain().count(), 2);
+        assert_eq!(ring.len(), 0);
+    }
+}
@@ -0,0 +1,231 @@
+use std::time::Duration;
+
+use crate::config::{RpmControl, RpmPoint};
+
+/// Below this a fan counts as not spinning.
+pub const STALL_RPM: u16 = 100;
+
+/// The RPM `points` give for `temperature`: interpolated between them and
+/// held beyond the first and last.
+pub fn target_rpm(points: &[RpmPoint], temperature: f64) -> f64 {
+    let mut sorted_points = points.to_vec();
+    sorted_points.sort_by(|a, b| a.temperature_celsius.total_cmp(&b.temperature_celsius));
+    let (Some(first), Some(last)) = (sorted_points.first(), sorted_points.last()) else {
+        return 0.0;
+    };
+    if temperature <= first.temperature_celsius {
+        return first.fan_rpm as f64;
+    }
+    if temperature >= last.temperature_celsius {
+        return last.fan_rpm as f64;
+    }
+    sorted_points
+        .windows(2)
+        .find(|pair| temperature <= pair[1].temperature_celsius)
+        .map(|pair| {
+            let (low, high) = (pair[0], pair[1]);
+            let span = high.temperature_celsius - low.temperature_celsius;
+            if span == 0.0 {
+                return high.fan_rpm as f64;
+            }
+            let progress = (temperature - low.temperature_celsius) / span;
+            low.fan_rpm as f64 + progress * (high.fan_rpm as f64 - low.fan_rpm as f64)
+        })
+        // Only a NaN temperature gets here
+        .unwrap_or(first.fan_rpm as f64)
+}
+
+/// The duty a curve with `rpm_control` asks for, carried from tick to tick.
+/// Each tick moves it in proportion to how far the RPM measured since the
+/// last one is off target; the duty never winds up past the cap or while
+/// the fans are stalled.
+#[derive(Clone, Debug, Default)]
+pub struct RpmLoop {
+    duty: Option<f64>,
+    measured: Option<u16>,
+    stalled_for: Duration,
+    last_elapsed: Option<Duration>,
+    // Whether the loop's duty was what the channel ran at last tick; when a
+    // later stage set it, the RPM says nothing about the loop's duty
+    followed: bool,
+}
+
+impl RpmLoop {
+    /// The channel's RPM as read since the last tick; None without a
+    /// reading.
+    pub fn measure(&mut self, rpm: Option<u16>) {
+        self.measured = rpm;
+    }
+
+    /// Tells the loop whether its duty was what the channel was set to.
+    pub fn set_followed(&mut self, followed: bool) {
+        self.followed = followed;
+    }
+
+    /// This tick's duty, and how it was arrived at. `curve_duty` is what the
+    /// curve's own points give, `elapsed` the time since the daemon started.
+    pub fn update(
+        &mut self,
+        control: &RpmControl,
+        temperature: f64,
+        curve_duty: f64,
+        elapsed: Duration,
+    ) -> (f64, String) {
+        let since_last = self
+            .last_elapsed
+            .map_or(Duration::ZERO, |last| elapsed.saturating_sub(last));
+        self.last_elapsed = Some(elapsed);
+        let cap = control.max_speed_percent.get() as f64;
+        let target = target_rpm(&control.points, temperature);
+        let Some(measured) = self.measured else {
+            self.duty = None;
+            self.stalled_for = Duration::ZERO;
+            return (
+                curve_duty.min(cap),
+                format!(
+                    "target {:.0} RPM, no RPM reading, following the curve",
+                    target
+                ),
+            );
+        };
+
+        let duty = self.duty.unwrap_or(curve_duty.min(cap));
+        if target >= STALL_RPM as f64 && measured < STALL_RPM && duty > 0.0 {
+            self.stalled_for += since_last;
+            if self.stalled_for >= Duration::from_secs(control.stall_seconds) {
+                self.duty = None;
+                return (
+                    curve_duty.min(cap),
+                    format!(
+                        "target {:.0} RPM, stalled for {:.0}s, following the curve",
+                        target,
+                        self.stalled_for.as_secs_f64()
+                    ),
+                );
+            }
+            self.duty = Some(duty);
+            return (
+                duty,
+                format!("target {:.0} RPM, no RPM at {:.0}%, holding", target, duty),
+            );
+        }
+        self.stalled_for = Duration::ZERO;
+
+        let error = target - measured as f64;
+        let adjusted = if !self.followed || error.abs() <= control.deadband_rpm as f64 {
+            duty
+        } else {
+            (duty + control.gain_percent_per_100_rpm * error / 100.0).clamp(0.0, cap)
+        };
+        self.duty = Some(adjusted);
+        (
+            adjusted,
+            format!("target {:.0} RPM, measured {} RPM", target, measured),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An aged fan: no spin below 12% duty, then 18 RPM per percent up to
+    /// 1600 RPM.
+    fn fan(duty: f64) -> u16 {
+        ((duty.round() - 12.0) * 18.0).clamp(0.0, 1600.0) as u16
+    }
+
+    fn control() -> RpmControl {
+        RpmControl {
+            points: vec![
+                RpmPoint {
+                    temperature_celsius: 30.0,
+                    fan_rpm: 600,
+                },
+                RpmPoint {
+                    temperature_celsius: 60.0,
+                    fan_rpm: 1200,
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// Runs the loop against `fan` for `ticks` ticks a second apart, from
+    /// where it left off, returning the duty of each.
+    fn run(
+        rpm_loop: &mut RpmLoop,
+        control: &RpmControl,
+        temperature: f64,
+        fan: impl Fn(f64) -> Option<u16>,
+        ticks: u64,
+    ) -> Vec<f64> {
+        let mut duty = rpm_loop.duty.unwrap_or(30.0);
+        (0..ticks)
+            .map(|tick| {
+                rpm_loop.measure(fan(duty));
+                rpm_loop.set_followed(true);
+                duty = rpm_loop
+                    .update(control, temperature, 30.0, Duration::from_secs(tick))
+                    .0;
+                duty
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_target_rpm() {
+        let points = control().points;
+        assert_eq!(target_rpm(&points, 20.0), 600.0);
+        assert_eq!(target_rpm(&points, 45.0), 900.0);
+        assert_eq!(target_rpm(&points, 90.0), 1200.0);
+        assert_eq!(target_rpm(&[], 45.0), 0.0);
+    }
+
+    #[test]
+    fn test_converges_without_oscillating() {
+        let control = control();
+        let mut rpm_loop = RpmLoop::default();
+        let duties = run(&mut rpm_loop, &control, 45.0, |duty| Some(fan(duty)), 40);
+
+        // Rises to the duty that holds 900 RPM, never past it
+        let settled = *duties.last().unwrap();
+        assert!((fan(settled) as f64 - 900.0).abs() <= 50.0, "{:?}", duties);
+        assert!(
+            duties.windows(2).all(|pair| pair[1] >= pair[0]),
+            "{:?}",
+            duties
+        );
+        assert_eq!(duties[30..].iter().filter(|&&d| d != settled).count(), 0);
+
+        // Then comes back down for a cooler target
+        let duties = run(&mut rpm_loop, &control, 30.0, |duty| Some(fan(duty)), 40);
+        let settled = *duties.last().unwrap();
+        assert!((fan(settled) as f64 - 600.0).abs() <= 50.0, "{:?}", duties);
+    }
+
+    #[test]
+    fn test_stalled_fan_falls_back_to_the_curve() {
+        let control = RpmControl {
+            max_speed_percent: crate::config::SpeedPercent::new(80).unwrap(),
+            stall_seconds: 10,
+            ..control()
+        };
+        let mut rpm_loop = RpmLoop::default();
+        let duties = run(&mut rpm_loop, &control, 60.0, |_| Some(0), 30);
+        // Held rather than wound up, then the curve's duty
+        assert!(duties.iter().all(|&duty| duty == 30.0), "{:?}", duties);
+        let (_, detail) = rpm_loop.update(&control, 60.0, 30.0, Duration::from_secs(30));
+        assert!(detail.contains("stalled"), "{}", detail);
+
+        // Never past the cap, however far off the RPM is
+        let mut rpm_loop = RpmLoop::default();
+        let duties = run(&mut rpm_loop, &control, 60.0, |_| Some(200), 60);
+        assert_eq!(*duties.last().unwrap(), 80.0);
+
+        // Without readings the curve's duty is used
+        let mut rpm_loop = RpmLoop::default();
+        let duties = run(&mut rpm_loop, &control, 60.0, |_| None, 3);
+        assert_eq!(duties, vec![30.0; 3]);
+    }
+}
@@ -0,0 +1,553 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::config::{SourceHealthSettings, TemperatureSource};
+
+/// How long a tick waits for a sensor read before using the previous reading.
+pub const SENSOR_READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The oldest previous reading that may stand in for a slow read.
+pub const STALE_READING_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// Readings outside this range count as failed reads, such as the 0 or 255
+/// some sensors report while dropping off the bus.
+pub const PLAUSIBLE_CELSIUS: RangeInclusive<f64> = -40.0..=150.0;
+
+type ReadFn = Arc<dyn Fn() -> Option<f64> + Send + Sync>;
+
+/// Reads a temperature on tokio's blocking pool, so a slow or hung sensor
+/// never holds up the fan loop. A read that outlives the timeout keeps
+/// running in the background and no second read is started until it
+/// finishes; meanwhile the last reading is used while it is recent.
+pub struct TemperatureReader {
+    read: ReadFn,
+    timeout: Duration,
+    max_age: Duration,
+    pending: Option<JoinHandle<Option<f64>>>,
+    last: Option<(Instant, f64)>,
+}
+
+impl TemperatureReader {
+    pub fn new(
+        read: impl Fn() -> Option<f64> + Send + Sync + 'static,
+        timeout: Duration,
+        max_age: Duration,
+    ) -> Self {
+        Self {
+            read: Arc::new(read),
+            timeout,
+            max_age,
+            pending: None,
+            last: None,
+        }
+    }
+
+    pub async fn read(&mut self) -> Option<f64> {
+        let read = self.read.clone();
+        let pending = self
+            .pending
+            .get_or_insert_with(|| tokio::task::spawn_blocking(move || read()));
+
+        match tokio::time::timeout(self.timeout, pending).await {
+            Ok(result) => {
+                self.pending = None;
+                let temperature = result.ok().flatten()?;
+                self.last = Some((Instant::now(), temperature));
+                Some(temperature)
+            }
+            Err(_) => {
+                let stale = self
+                    .last
+                    .filter(|(read_at, _)| read_at.elapsed() <= self.max_age);
+                match stale {
+                    Some((read_at, _)) => eprintln!(
+                        "Reading the temperature took longer than {:?}, using the reading from {:.1}s ago",
+                        self.timeout,
+                        read_at.elapsed().as_secs_f64()
+                    ),
+                    None => eprintln!(
+                        "Reading the temperature took longer than {:?}, and there is no recent reading",
+                        self.timeout
+                    ),
+                }
+                stale.map(|(_, temperature)| temperature)
+            }
+        }
+    }
+}
+
+/// One source's reading in a tick's snapshot.
+#[derive(Clone, Debug)]
+pub struct SourceReading {
+    /// The source's TemperatureSource::key.
+    pub key: String,
+    pub temperature: Option<f64>,
+    pub read_at: Instant,
+    pub read_time: Duration,
+}
+
+/// Every distinct source's reading for one tick. Each is read once, however
+/// many times the config names it.
+#[derive(Clone, Debug, Default)]
+pub struct SensorReadings {
+    readings: Vec<SourceReading>,
+}
+
+impl SensorReadings {
+    /// The source's value: its sensor's reading, transformed.
+    pub fn get(&self, source: &TemperatureSource) -> Option<f64> {
+        let raw = self.reading(source)?.temperature?;
+        Some(source.apply(raw))
+    }
+
+    pub fn reading(&self, source: &TemperatureSource) -> Option<&SourceReading> {
+        let key = source.key();
+        self.readings.iter().find(|reading| reading.key == key)
+    }
+
+    pub fn readings(&self) -> &[SourceReading] {
+        &self.readings
+    }
+
+    /// The first source of `chain` with a plausible reading: its index in
+    /// the chain and the temperature.
+    pub fn first_plausible(&self, chain: &[TemperatureSource]) -> Option<(usize, f64)> {
+        chain.iter().enumerate().find_map(|(index, source)| {
+            self.get(source)
+                .filter(|temperature| PLAUSIBLE_CELSIUS.contains(temperature))
+                .map(|temperature| (index, temperature))
+        })
+    }
+}
+
+/// Which source of the fallback chain the curves follow, so each switch is
+/// logged once rather than every tick.
+#[derive(Clone, Debug)]
+pub struct SourceChain {
+    sources: Vec<TemperatureSource>,
+    active: usize,
+}
+
+impl SourceChain {
+    pub fn new(sources: Vec<TemperatureSource>) -> Self {
+        Self { sources, active: 0 }
+    }
+
+    pub fn sources(&self) -> &[TemperatureSource] {
+        &self.sources
+    }
+
+    /// The source the last reading came from.
+    pub fn active(&self) -> &TemperatureSource {
+        &self.sources[self.active]
+    }
+
+    /// Records which source this tick's reading came from, returning a line
+    /// to log when it isn't the one before. A tick without any reading
+    /// changes nothing; the source's health covers that.
+    pub fn observe(&mut self, used: Option<usize>) -> Option<String> {
+        let used = used.filter(|&used| used != self.active)?;
+        let (from, to) = (self.sources[self.active].key(), self.sources[used].key());
+        self.active = used;
+        Some(if used == 0 {
+            format!("Temperature source {} is back; switching from {}", to, from)
+        } else {
+            format!(
+                "Temperature source {} has no plausible reading; falling back to {}",
+                from, to
+            )
+        })
+    }
+}
+
+type NewReaderFn = Box<dyn Fn(&TemperatureSource) -> TemperatureReader + Send>;
+
+/// A TemperatureReader per distinct source, kept across ticks so a hung read
+/// is never started twice. Sources no longer in use are dropped.
+pub struct SourceReaders {
+    new_reader: NewReaderFn,
+    readers: HashMap<String, TemperatureReader>,
+}
+
+impl SourceReaders {
+    pub fn new(
+        new_reader: impl Fn(&TemperatureSource) -> TemperatureReader + Send + 'static,
+    ) -> Self {
+        Self {
+            new_reader: Box::new(new_reader),
+            readers: HashMap::new(),
+        }
+    }
+
+    /// Reads each distinct source among `sources` once.
+    pub async fn snapshot(&mut self, sources: &[TemperatureSource]) -> SensorReadings {
+        let mut distinct: Vec<(String, &TemperatureSource)> = Vec::new();
+        for source in sources {
+            let key = source.key();
+            if !distinct.iter().any(|(seen, _)| *seen == key) {
+                distinct.push((key, source));
+            }
+        }
+        self.readers
+            .retain(|key, _| distinct.iter().any(|(wanted, _)| wanted == key));
+
+        let mut readings = Vec::with_capacity(distinct.len());
+        for (key, source) in distinct {
+            let reader = self
+                .readers
+                .entry(key.clone())
+                .or_insert_with(|| (self.new_reader)(source));
+            let started = Instant::now();
+            let temperature = reader.read().await;
+            readings.push(SourceReading {
+                key,
+                temperature,
+                read_at: started,
+                read_time: started.elapsed(),
+            });
+        }
+        SensorReadings { readings }
+    }
+}
+
+/// Whether a temperature source is giving readings.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceState {
+    #[default]
+    Available,
+    /// Some reads failed in a row; curves keep their last speeds.
+    Degraded,
+    /// Enough reads failed in a row that the daemon stops trusting it.
+    Lost,
+}
+
+/// A change of a source's state, and the run of reads that caused it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceTransition {
+    pub from: SourceState,
+    pub to: SourceState,
+    /// Consecutive failed reads, or successful ones when recovering.
+    pub reads: u32,
+}
+
+/// The state of a source, as the status file shows it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SourceStatus {
+    pub name: String,
+    pub state: SourceState,
+    /// Failed reads since the last successful one.
+    pub failures: u32,
+    /// State changes since the daemon started.
+    pub transitions: u32,
+}
+
+/// Debounces a source's reads into states: a single failed read or a single
+/// good one in a run of failures changes nothing, so a flapping sensor gives
+/// one transition each way rather than one per tick.
+#[derive(Clone, Debug)]
+pub struct SourceHealth {
+    settings: SourceHealthSettings,
+    state: SourceState,
+    failures: u32,
+    successes: u32,
+    transitions: u32,
+}
+
+impl SourceHealth {
+    pub fn new(settings: SourceHealthSettings) -> Self {
+        Self {
+            settings,
+            state: SourceState::Available,
+            failures: 0,
+            successes: 0,
+            transitions: 0,
+        }
+    }
+
+    /// Applies new thresholds from the next read on, keeping the state.
+    pub fn set_settings(&mut self, settings: SourceHealthSettings) {
+        self.settings = settings;
+    }
+
+    pub fn state(&self) -> SourceState {
+        self.state
+    }
+
+    /// Records one read, returning the transition it causes if any.
+    pub fn observe(&mut self, ok: bool) -> Option<SourceTransition> {
+        let (to, reads) = if ok {
+            self.failures = 0;
+            self.successes += 1;
+            let recovered = self.successes >= self.settings.recovered_after_successes;
+            (
+                (self.state != SourceState::Available && recovered)
+                    .then_some(SourceState::Available),
+                self.successes,
+            )
+        } else {
+            self.successes = 0;
+            self.failures += 1;
+            let to = if self.failures >= self.settings.lost_after_failures {
+                Some(SourceState::Lost)
+            } else if self.failures >= self.settings.degraded_after_failures
+                && self.state == SourceState::Available
+            {
+                Some(SourceState::Degraded)
+            } else {
+                None
+            };
+            (to.filter(|&to| to != self.state), self.failures)
+        };
+
+        let to = to?;
+        let transition = SourceTransition {
+            from: self.state,
+            to,
+            reads,
+        };
+        self.state = to;
+        self.transitions += 1;
+        Some(transition)
+    }
+
+    pub fn status(&self, name: &str) -> SourceStatus {
+        SourceStatus {
+            name: name.to_string(),
+            state: self.state,
+            failures: self.failures,
+            transitions: self.transitions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_slow_read_uses_previous_reading() {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let counter = reads.clone();
+        let mut reader = TemperatureReader::new(
+            move || match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => Some(50.0),
+                _ => {
+                    std::thread::sleep(Duration::from_millis(300));
+                    Some(60.0)
+                }
+            },
+            Duration::from_millis(20),
+            Duration::from_secs(30),
+        );
+
+        assert_eq!(reader.read().await, Some(50.0));
+        // The second read hangs; the tick goes on with the previous reading
+        assert_eq!(reader.read().await, Some(50.0));
+        // and no other read piles up behind the hung one
+        assert_eq!(reader.read().await, Some(50.0));
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(reader.read().await, Some(60.0));
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_stale_reading_expires() {
+        let mut reader = TemperatureReader::new(
+            || {
+                std::thread::sleep(Duration::from_millis(200));
+                Some(70.0)
+            },
+            Duration::from_millis(10),
+            Duration::ZERO,
+        );
+        assert_eq!(reader.read().await, None);
+    }
+
+    fn states(health: &mut SourceHealth, reads: &str) -> Vec<SourceState> {
+        reads
+            .chars()
+            .filter_map(|read| health.observe(read == '+'))
+            .map(|transition| transition.to)
+            .collect()
+    }
+
+    #[test]
+    fn test_source_health_debounces() {
+        use SourceState::*;
+        let mut health = SourceHealth::new(SourceHealthSettings::default());
+
+        // Lone failures, and lone successes while degraded, change nothing
+        assert_eq!(states(&mut health, "+-+-+-+"), vec![]);
+        assert_eq!(states(&mut health, "--"), vec![Degraded]);
+        assert_eq!(states(&mut health, "+-+-+--+-"), vec![]);
+        assert_eq!(health.state(), Degraded);
+        assert_eq!(states(&mut health, "+++"), vec![Available]);
+
+        // A driver reload: one lost, one recovered, however long it lasts
+        assert_eq!(
+            states(&mut health, "----------+-+++"),
+            vec![Degraded, Lost, Available]
+        );
+        let status = health.status("gpu");
+        assert_eq!(status.state, Available);
+        assert_eq!(status.failures, 0);
+        assert_eq!(status.transitions, 5);
+
+        let transition = (0..5).filter_map(|_| health.observe(false)).last();
+        assert_eq!(
+            transition,
+            Some(SourceTransition {
+                from: Degraded,
+                to: Lost,
+                reads: 5
+            })
+        );
+    }
+
+    #[test]
+    fn test_source_health_lost_at_once() {
+        let mut health = SourceHealth::new(SourceHealthSettings {
+            degraded_after_failures: 1,
+            lost_after_failures: 1,
+            recovered_after_successes: 1,
+        });
+        assert_eq!(
+            states(&mut health, "-+"),
+            vec![SourceState::Lost, SourceState::Available]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_reads_each_source_once() {
+        use crate::gpu::{GpuSelector, GpuSensorKind};
+        let reads = Arc::new(AtomicUsize::new(0));
+        let readers_made = Arc::new(AtomicUsize::new(0));
+        let (counter, made) = (reads.clone(), readers_made.clone());
+        let mut readers = SourceReaders::new(move |source| {
+            made.fetch_add(1, Ordering::SeqCst);
+            let counter = counter.clone();
+            let temperature = match source {
+                TemperatureSource::Cpu { .. } => 50.0,
+                TemperatureSource::Soc { .. } => 55.0,
+                TemperatureSource::Gpu { .. } => 65.0,
+            };
+            TemperatureReader::new(
+                move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Some(temperature)
+                },
+                Duration::from_secs(1),
+                Duration::from_secs(30),
+            )
+        });
+        let gpu = |bus_id: &str| TemperatureSource::Gpu {
+            gpu: Some(GpuSelector::BusId(bus_id.to_string())),
+            sensor: GpuSensorKind::Junction,
+            transform: None,
+            reading_latency_seconds: None,
+        };
+        // The same GPU, written two ways, and the CPU three times
+        let sources = vec![
+            TemperatureSource::default(),
+            gpu("0000:03:00.0"),
+            TemperatureSource::default(),
+            gpu(" 0000:03:00.0 "),
+            TemperatureSource::default(),
+        ];
+
+        let readings = readers.snapshot(&sources).await;
+        assert_eq!(readings.readings().len(), 2);
+        assert_eq!(readings.get(&TemperatureSource::default()), Some(50.0));
+        assert_eq!(readings.get(&gpu("0000:03:00.0")), Some(65.0));
+        assert_eq!(readings.get(&gpu("0000:04:00.0")), None);
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+
+        readers.snapshot(&sources).await;
+        assert_eq!(reads.load(Ordering::SeqCst), 4);
+        assert_eq!(readers_made.load(Ordering::SeqCst), 2);
+
+        // A source dropped from the config loses its reader
+        readers.snapshot(&sources[..1]).await;
+        readers.snapshot(&sources).await;
+        assert_eq!(readers_made.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_chain() {
+        use crate::gpu::GpuSensorKind;
+        // The CPU's and the GPU's next readings
+        let next = Arc::new(std::sync::Mutex::new((None, None)));
+        let shared = next.clone();
+        let mut readers = SourceReaders::new(move |source| {
+            let next = shared.clone();
+            let is_cpu = matches!(source, TemperatureSource::Cpu { .. });
+            TemperatureReader::new(
+                move || {
+                    let (cpu, gpu) = *next.lock().unwrap();
+                    if is_cpu {
+                        cpu
+                    } else {
+                        gpu
+                    }
+                },
+                Duration::from_secs(1),
+                Duration::from_secs(30),
+            )
+        });
+        let mut chain = SourceChain::new(vec![
+            TemperatureSource::default(),
+            TemperatureSource::Gpu {
+                gpu: None,
+                sensor: GpuSensorKind::Edge,
+                transform: None,
+                reading_latency_seconds: None,
+            },
+        ]);
+        let mut health = SourceHealth::new(SourceHealthSettings::default());
+        let mut tick = async |cpu: Option<f64>, gpu: Option<f64>| {
+            *next.lock().unwrap() = (cpu, gpu);
+            let readings = readers.snapshot(chain.sources()).await;
+            let chosen = readings.first_plausible(chain.sources());
+            health.observe(chosen.is_some());
+            let line = chain.observe(chosen.map(|(index, _)| index));
+            (chosen.map(|(_, temperature)| temperature), line)
+        };
+
+        assert_eq!(tick(Some(45.0), Some(60.0)).await, (Some(45.0), None));
+        let (temperature, line) = tick(None, Some(60.0)).await;
+        assert_eq!(temperature, Some(60.0));
+        assert_eq!(
+            line.unwrap(),
+            "Temperature source cpu has no plausible reading; falling back to gpu hottest edge"
+        );
+        // An implausible reading is no reading, and staying on the fallback
+        // logs nothing more
+        assert_eq!(tick(Some(255.0), Some(60.0)).await, (Some(60.0), None));
+        assert_eq!(tick(None, Some(61.0)).await, (Some(61.0), None));
+        // Only a tick where every source fails counts as a failed read
+        assert_eq!(tick(None, None).await, (None, None));
+        let (temperature, line) = tick(Some(47.0), None).await;
+        assert_eq!(temperature, Some(47.0));
+        assert_eq!(
+            line.unwrap(),
+            "Temperature source cpu is back; switching from gpu hottest edge"
+        );
+        let lost_after = SourceHealthSettings::default().lost_after_failures;
+        for _ in 0..lost_after {
+            tick(None, Some(999.0)).await;
+        }
+        assert_eq!(health.state(), SourceState::Lost);
+        // The failed tick before the primary came back was reset by it
+        assert_eq!(health.status("cpu").failures, lost_after);
+        assert_eq!(chain.active(), &TemperatureSource::default());
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How long a shutdown may take before the steps left are abandoned.
+pub const DEFAULT_SHUTDOWN_TIMEOUT_SECONDS: u64 = 10;
+
+/// Tells background tasks the daemon is stopping. Clones share one token.
+#[derive(Clone, Debug)]
+pub struct ShutdownToken {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for ShutdownToken {
+    fn default() -> Self {
+        Self {
+            sender: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl ShutdownToken {
+    pub fn cancel(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Resolves once the token is cancelled, at once if it already is.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.sender.subscribe();
+        let _ = receiver.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+/// The steps of a shutdown, in the order they run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Cancel the token: keyboard commands stop and no tick starts.
+    StopControls,
+    /// Write the commands still queued, so no device is left half-updated.
+    FinishWrites,
+    /// Put channels back as `restore_on_exit` asks.
+    ExitPolicy,
+    /// Write the status file a last time.
+    FlushState,
+}
+
+pub const STEPS: [Step; 4] = [
+    Step::StopControls,
+    Step::FinishWrites,
+    Step::ExitPolicy,
+    Step::FlushState,
+];
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Step::StopControls => "stopping controls",
+            Step::FinishWrites => "finishing writes",
+            Step::ExitPolicy => "restoring channels",
+            Step::FlushState => "writing the status",
+        })
+    }
+}
+
+/// What a shutdown got through before its deadline.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub completed: Vec<Step>,
+    /// The step running when the deadline passed; it and the ones after it
+    /// were abandoned.
+    pub abandoned: Option<Step>,
+}
+
+impl Report {
+    pub fn warning(&self, timeout: Duration) -> Option<String> {
+        let step = self.abandoned?;
+        Some(format!(
+            "Warning: shutdown took longer than {:?} while {}; abandoning the rest",
+            timeout, step
+        ))
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM where there is one.
+pub async fn signalled() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown_token() {
+        let token = ShutdownToken::default();
+        let waiter = tokio::spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+        assert!(!token.is_cancelled());
+        token.cancel();
+        waiter.await.unwrap();
+        assert!(token.is_cancelled());
+        // Already cancelled
+        token.cancelled().await;
+
+        let report = Report {
+            completed: vec![Step::StopControls],
+            abandoned: Some(Step::FinishWrites),
+        };
+        assert_eq!(
+            report.warning(Duration::from_secs(10)).unwrap(),
+            "Warning: shutdown took longer than 10s while finishing writes; abandoning the rest"
+        );
+        assert_eq!(Report::default().warning(Duration::from_secs(10)), None);
+    }
+}
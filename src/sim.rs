@@ -0,0 +1,567 @@
+//! End-to-end scenarios for the daemon loop. Each file in tests/scenarios
+//! scripts the temperature and hardware faults over simulated time, runs a
+//! Daemon on mock hubs with tokio's clock paused, and compares what it did
+//! against the transcript the file expects.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+use crate::config::{ChannelMode, CurveConfig, DeviceId, SpeedPercent};
+use crate::daemon::Daemon;
+use crate::engine::SpeedReason;
+use crate::hardware::{FanController, SpeedCommand};
+use crate::mock::MockBackend;
+use crate::sensors::{self, SourceReaders, TemperatureReader};
+use crate::shutdown;
+
+const SCENARIO_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scenarios");
+
+#[derive(Deserialize)]
+struct Scenario {
+    duration_seconds: u64,
+    devices: Vec<DeviceId>,
+    config: CurveConfig,
+    /// (seconds, temperature) steps; a null temperature is a failed read.
+    temperatures: Vec<(u64, Option<f64>)>,
+    #[serde(default)]
+    faults: Vec<Fault>,
+    transcript: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Fault {
+    at_seconds: u64,
+    action: FaultAction,
+    device_id: DeviceId,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+enum FaultAction {
+    /// Every write to the device fails until it is replugged.
+    Unplug,
+    Replug,
+}
+
+/// A Daemon driving `fan_controller`, as run_daemon sets one up, whose
+/// temperature source reads `temperature`.
+fn new_daemon(
+    config: &CurveConfig,
+    mut fan_controller: FanController,
+    temperature: Arc<Mutex<Option<f64>>>,
+    status_path: &Path,
+) -> Daemon {
+    crate::configure_controller(config, &mut fan_controller);
+    let (resolved, _) = crate::resolve_config(config, &fan_controller);
+    let source_readers = SourceReaders::new(move |_| {
+        let temperature = temperature.clone();
+        TemperatureReader::new(
+            move || *temperature.lock().unwrap(),
+            sensors::SENSOR_READ_TIMEOUT,
+            sensors::STALE_READING_MAX_AGE,
+        )
+    });
+    Daemon::new(
+        config.clone(),
+        fan_controller,
+        resolved.fan_curves,
+        source_readers,
+        status_path,
+        false,
+    )
+}
+
+/// The lines a run produced: faults, packets, and each channel's speed and
+/// reason or the source's state when they change. The daemon writes every
+/// channel on every tick, so a packet is left out when it repeats the last
+/// one of its kind to the same device.
+async fn run(scenario: &Scenario, status_path: &Path) -> Vec<String> {
+    let backend = MockBackend::new(scenario.devices.clone());
+    let packets = backend.packets();
+    let failing = backend.failing();
+    let fan_controller = FanController::with_backends(vec![Box::new(backend)]);
+    let temperature = Arc::new(Mutex::new(None));
+    let mut daemon = new_daemon(
+        &scenario.config,
+        fan_controller,
+        temperature.clone(),
+        status_path,
+    );
+
+    let started = Instant::now();
+    let mut transcript = Vec::new();
+    let mut faults = scenario.faults.iter().peekable();
+    let mut packets_seen = 0;
+    let mut last_packets: HashMap<(DeviceId, Vec<u8>), Vec<u8>> = HashMap::new();
+    let mut last_lines: Vec<String> = Vec::new();
+    loop {
+        time::sleep_until(daemon.next_deadline()).await;
+        let seconds = started.elapsed().as_secs();
+        if seconds >= scenario.duration_seconds {
+            break;
+        }
+
+        while let Some(fault) = faults.next_if(|fault| fault.at_seconds <= seconds) {
+            transcript.push(format!(
+                "{}s {:?} {}",
+                seconds, fault.action, fault.device_id
+            ));
+            let mut failing = failing.lock().unwrap();
+            match fault.action {
+                FaultAction::Unplug => failing.insert(fault.device_id.clone()),
+                FaultAction::Replug => failing.remove(&fault.device_id),
+            };
+        }
+        *temperature.lock().unwrap() = scenario
+            .temperatures
+            .iter()
+            .rev()
+            .find(|(at, _)| *at <= seconds)
+            .and_then(|(_, temperature)| *temperature);
+
+        assert_eq!(daemon.tick().await, ControlFlow::Continue(()));
+
+        let packets = packets.lock().unwrap();
+        for (device_id, packet) in &packets[packets_seen..] {
+            let kind = (device_id.clone(), packet[..packet.len().min(3)].to_vec());
+            if last_packets.insert(kind, packet.clone()).as_ref() == Some(packet) {
+                continue;
+            }
+            let bytes: Vec<String> = packet.iter().map(|b| format!("{:02x}", b)).collect();
+            transcript.push(format!("{}s {} {}", seconds, device_id, bytes.join(" ")));
+        }
+        packets_seen = packets.len();
+
+        let Some(status) = &daemon.last_status else {
+            continue;
+        };
+        for (name, usage) in status.memory.unwrap().buffers() {
+            assert!(usage.len <= usage.capacity, "Too many {}", name);
+        }
+        let mut lines: Vec<String> = status
+            .channels
+            .iter()
+            .map(|channel| {
+                format!(
+                    "{} {}% {:?}{}",
+                    channel.curve,
+                    channel.speed_percent,
+                    channel.reason,
+                    if channel.quarantined {
+                        " quarantined"
+                    } else {
+                        ""
+                    }
+                )
+            })
+            .collect();
+        if let Some(source) = &status.source {
+            lines.push(format!("source {:?}", source.state));
+        }
+        if let Some(seconds) = status.stretched_interval_seconds {
+            lines.push(format!("interval stretched to {}s", seconds));
+        }
+        for line in &lines {
+            if !last_lines.contains(line) {
+                transcript.push(format!("{}s {}", seconds, line));
+            }
+        }
+        last_lines = lines;
+    }
+    transcript
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scenarios() {
+    let mut paths: Vec<_> = std::fs::read_dir(SCENARIO_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let dir = std::env::temp_dir().join(format!("uni-sync-sim-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for path in paths {
+        let content = std::fs::read_to_string(&path).unwrap();
+        let scenario: Scenario = serde_json::from_str(&content)
+            .unwrap_or_else(|e| panic!("Invalid scenario {:?}: {}", path, e));
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        let transcript = run(&scenario, &dir.join(format!("{}.json", name))).await;
+        assert!(
+            transcript == scenario.transcript,
+            "Scenario {} produced a different transcript:\n{}",
+            name,
+            serde_json::to_string_pretty(&transcript).unwrap()
+        );
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn shutdown_config(restore_on_exit: bool, io_timeout_ms: Option<u64>) -> CurveConfig {
+    serde_json::from_value(serde_json::json!({
+        "interval_seconds": 5,
+        "restore_on_exit": restore_on_exit,
+        "io_timeout_ms": io_timeout_ms,
+        "fan_curves": [{
+            "name": "front",
+            "device_id": [3314, 41219, "SIM"],
+            "channel": 0,
+            "mode": "Manual",
+            "curve_points": [{ "temperature_celsius": 40.0, "fan_speed_percent": 60 }]
+        }]
+    }))
+    .unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_shutdown_order() {
+    let hub = DeviceId(0x0cf2, 0xa103, "SIM".to_string());
+    // The hub reports channel 0 at 30% before startup
+    let backend = MockBackend::new(vec![hub.clone()]).with_reply(
+        vec![224, 81, 0, 0],
+        vec![224, 81, 0, 1, 0, 0, 30, 0, 45, 100],
+    );
+    let packets = backend.packets();
+    let mut fan_controller = FanController::with_backends(vec![Box::new(backend)]);
+    fan_controller.set_hub_reports(true);
+    fan_controller.read_previous_states().await;
+    let status_path =
+        std::env::temp_dir().join(format!("uni-sync-shutdown-{}.json", std::process::id()));
+    let temperature = Arc::new(Mutex::new(Some(50.0)));
+    let mut daemon = new_daemon(
+        &shutdown_config(true, None),
+        fan_controller,
+        temperature,
+        &status_path,
+    );
+    assert_eq!(daemon.tick().await, ControlFlow::Continue(()));
+
+    // A write still queued when the signal comes
+    daemon.fan_controller.enqueue(SpeedCommand {
+        device_id: hub.clone(),
+        channel: 0,
+        mode: ChannelMode::Manual,
+        speed_percent: SpeedPercent::saturating(80),
+        reason: SpeedReason::Curve,
+    });
+    packets.lock().unwrap().clear();
+    let report = daemon.shutdown(Duration::from_secs(10)).await;
+    assert_eq!(report.completed, shutdown::STEPS.to_vec());
+    assert_eq!(report.abandoned, None);
+    // No tick runs once shutdown started
+    assert_eq!(daemon.tick().await, ControlFlow::Continue(()));
+
+    // The queued write lands, and nothing after it: the hub's reported
+    // state isn't confirmed, so restore_on_exit writes nothing back
+    let sent: Vec<Vec<u8>> = packets.lock().unwrap().drain(..).map(|(_, p)| p).collect();
+    assert_eq!(sent, vec![vec![224, 32, 0, 82]]);
+    assert!(status_path.exists());
+    std::fs::remove_file(&status_path).unwrap();
+    std::fs::remove_file(crate::journal::journal_path(&status_path)).unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_shutdown_deadline() {
+    let hub = DeviceId(0x0cf2, 0xa103, "SIM".to_string());
+    let backend = MockBackend::new(vec![hub.clone()]);
+    let hangs = backend.hangs();
+    let fan_controller = FanController::with_backends(vec![Box::new(backend)]);
+    let status_path =
+        std::env::temp_dir().join(format!("uni-sync-deadline-{}.json", std::process::id()));
+    let temperature = Arc::new(Mutex::new(Some(50.0)));
+    let mut daemon = new_daemon(
+        &shutdown_config(false, Some(60_000)),
+        fan_controller,
+        temperature,
+        &status_path,
+    );
+    assert_eq!(daemon.tick().await, ControlFlow::Continue(()));
+
+    // The hub wedges with a write in flight
+    hangs.hang(&hub);
+    daemon.fan_controller.enqueue(SpeedCommand {
+        device_id: hub.clone(),
+        channel: 0,
+        mode: ChannelMode::Manual,
+        speed_percent: SpeedPercent::saturating(80),
+        reason: SpeedReason::Curve,
+    });
+    let started = Instant::now();
+    let report = daemon.shutdown(Duration::from_secs(1)).await;
+    assert_eq!(started.elapsed(), Duration::from_secs(1));
+    assert_eq!(
+        report,
+        shutdown::Report {
+            completed: vec![shutdown::Step::StopControls],
+            abandoned: Some(shutdown::Step::FinishWrites),
+        }
+    );
+    hangs.release(&hub);
+    let _ = std::fs::remove_file(&status_path);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_names_match_in_every_sink() {
+    let config: CurveConfig = serde_json::from_value(serde_json::json!({
+        "interval_seconds": 5,
+        "fallback_sources": [{ "type": "gpu", "gpu": 0, "sensor": "junction" }],
+        "fan_curves": [
+            {
+                "name": "Front Top",
+                "device_id": [3314, 41219, "SIM"],
+                "channel": 0,
+                "mode": "Manual",
+                "curve_points": [{ "temperature_celsius": 40.0, "fan_speed_percent": 60 }]
+            },
+            {
+                "device_id": [3314, 41219, "SIM"],
+                "channel": 1,
+                "mode": "Manual",
+                "curve_points": [{ "temperature_celsius": 40.0, "fan_speed_percent": 60 }]
+            }
+        ]
+    }))
+    .unwrap();
+    let hub = DeviceId(0x0cf2, 0xa103, "SIM".to_string());
+    let fan_controller =
+        FanController::with_backends(vec![Box::new(MockBackend::new(vec![hub.clone()]))]);
+    let dir = std::env::temp_dir().join(format!("uni-sync-names-{}", std::process::id()));
+    let status_path = dir.join("status.json");
+    let mut daemon = new_daemon(
+        &config,
+        fan_controller,
+        Arc::new(Mutex::new(Some(50.0))),
+        &status_path,
+    );
+    assert_eq!(daemon.tick().await, ControlFlow::Continue(()));
+
+    let channels = vec!["front-top".to_string(), "0cf2-a103-sim-1".to_string()];
+    let sources = vec!["cpu".to_string(), "gpu-0-junction".to_string()];
+    let names = crate::output::Report::text(&crate::names::names_report(&config));
+    for id in channels.iter().chain(&sources) {
+        assert!(
+            names.contains(&format!(" {}  ", id)),
+            "{} not in {}",
+            id,
+            names
+        );
+    }
+
+    let status = crate::status::read_status(&status_path).unwrap();
+    let status_ids: Vec<String> = status.channels.iter().map(|c| c.id.clone()).collect();
+    assert_eq!(status_ids, channels);
+    let tick_sources: Vec<String> = status
+        .tick
+        .unwrap()
+        .source_ms
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+    assert_eq!(tick_sources, sources);
+
+    daemon.dump_journal(crate::journal::DumpTrigger::Request);
+    let dump = crate::journal::read_dump(&crate::journal::journal_path(&status_path)).unwrap();
+    let journal_ids: Vec<String> = dump.entries.iter().map(|e| e.channel_id.clone()).collect();
+    assert_eq!(journal_ids, channels);
+    assert!(dump.entries.iter().all(|e| e.source == sources[0]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_override_mid_tick() {
+    let hub = DeviceId(0x0cf2, 0xa103, "SIM".to_string());
+    let fan_curves: Vec<_> = [0, 1]
+        .map(|channel| {
+            serde_json::json!({
+                "device_id": [3314, 41219, "SIM"],
+                "channel": channel,
+                "mode": "Manual",
+                "curve_points": [{ "temperature_celsius": 40.0, "fan_speed_percent": 50 }]
+            })
+        })
+        .into();
+    let config: CurveConfig = serde_json::from_value(serde_json::json!({
+        "interval_seconds": 5,
+        "command_delay_ms": 100,
+        "fan_curves": fan_curves
+    }))
+    .unwrap();
+    let backend = MockBackend::new(vec![hub.clone()]);
+    let packets = backend.packets();
+    let fan_controller = FanController::with_backends(vec![Box::new(backend)]);
+    let dir = std::env::temp_dir().join(format!("uni-sync-nudge-{}", std::process::id()));
+    let mut daemon = new_daemon(
+        &config,
+        fan_controller,
+        Arc::new(Mutex::new(Some(50.0))),
+        &dir.join("status.json"),
+    );
+
+    // The override comes in while the tick is still writing
+    let (control_tx, mut control_rx) =
+        tokio::sync::mpsc::channel(crate::control::CONTROL_QUEUE_DEPTH);
+    let started = Instant::now();
+    tokio::spawn(async move {
+        time::sleep(Duration::from_millis(150)).await;
+        control_tx
+            .send(crate::control::ControlCommand::Nudge(10))
+            .await
+            .unwrap();
+    });
+    assert_eq!(daemon.tick().await, ControlFlow::Continue(()));
+    assert!(started.elapsed() > Duration::from_millis(150));
+    let tick_packets = packets.lock().unwrap().len();
+
+    let Ok(crate::control::ControlCommand::Nudge(step)) = control_rx.try_recv() else {
+        panic!("the override should be waiting for the loop");
+    };
+    assert_eq!(daemon.nudge(step).await, ControlFlow::Continue(()));
+
+    // The tick's whole sequence went out first, then the override's speeds
+    let packets = packets.lock().unwrap();
+    let speeds = |packets: &[(DeviceId, Vec<u8>)]| -> Vec<Vec<u8>> {
+        packets
+            .iter()
+            .filter(|(_, packet)| packet[..2] == [224, 32] || packet[..2] == [224, 33])
+            .map(|(_, packet)| packet.clone())
+            .collect()
+    };
+    let before = speeds(&packets[..tick_packets]);
+    let after = speeds(&packets[tick_packets..]);
+    assert_eq!(before.len(), 2);
+    assert_eq!(after.len(), packets.len() - tick_packets);
+    for (before, after) in before.iter().zip(&after) {
+        assert_eq!(before[..3], after[..3]);
+        assert!(after[3] > before[3], "{:?} then {:?}", before, after);
+    }
+    assert_eq!(daemon.last_status.as_ref().unwrap().override_percent, 10);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_rpm_control_holds_the_target() {
+    let hub = DeviceId(0x0cf2, 0xa103, "SIM".to_string());
+    let config: CurveConfig = serde_json::from_value(serde_json::json!({
+        "interval_seconds": 2,
+        "experimental_hub_reports": true,
+        "fan_curves": [{
+            "device_id": [3314, 41219, "SIM"],
+            "channel": 0,
+            "mode": "Manual",
+            "curve_points": [{ "temperature_celsius": 40.0, "fan_speed_percent": 40 }],
+            "rpm_control": {
+                "points": [{ "temperature_celsius": 40.0, "fan_rpm": 900 }]
+            }
+        }]
+    }))
+    .unwrap();
+    // Fans that need well over the curve's 40% for 900 RPM: 30 RPM per
+    // speed byte past 30, which an SL v2 sends for about 23%
+    let backend = MockBackend::new(vec![hub.clone()])
+        .with_rpm_model(|_, speed| (speed.saturating_sub(30) as u16) * 30);
+    let packets = backend.packets();
+    let fan_controller = FanController::with_backends(vec![Box::new(backend)]);
+    let dir = std::env::temp_dir().join(format!("uni-sync-rpm-{}", std::process::id()));
+    let mut daemon = new_daemon(
+        &config,
+        fan_controller,
+        Arc::new(Mutex::new(Some(50.0))),
+        &dir.join("status.json"),
+    );
+    for _ in 0..30 {
+        time::sleep_until(daemon.next_deadline()).await;
+        assert_eq!(daemon.tick().await, ControlFlow::Continue(()));
+    }
+
+    let speeds: Vec<u8> = packets
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, packet)| packet[..2] == [224, 32])
+        .map(|(_, packet)| packet[3])
+        .collect();
+    // Up from the curve's speed to one that holds 900 RPM, never past it
+    // and steady once there
+    let settled = *speeds.last().unwrap();
+    assert_eq!(speeds[0], 47);
+    assert!(
+        ((settled as i32 - 30) * 30 - 900).abs() <= 50,
+        "{:?}",
+        speeds
+    );
+    assert!(
+        speeds.windows(2).all(|pair| pair[1] >= pair[0]),
+        "{:?}",
+        speeds
+    );
+    assert!(speeds[speeds.len() - 10..].iter().all(|&s| s == settled));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_slew_substeps() {
+    let hub = DeviceId(0x0cf2, 0xa103, "SIM".to_string());
+    let config: CurveConfig = serde_json::from_value(serde_json::json!({
+        "interval_seconds": 4,
+        "fan_curves": [{
+            "device_id": [3314, 41219, "SIM"],
+            "channel": 0,
+            "mode": "Manual",
+            "slew_substeps": 3,
+            "curve_points": [
+                { "temperature_celsius": 20.0, "fan_speed_percent": 20 },
+                { "temperature_celsius": 100.0, "fan_speed_percent": 100 }
+            ]
+        }]
+    }))
+    .unwrap();
+    let backend = MockBackend::new(vec![hub.clone()]);
+    let packets = backend.packets();
+    let fan_controller = FanController::with_backends(vec![Box::new(backend)]);
+    let temperature = Arc::new(Mutex::new(Some(40.0)));
+    let dir = std::env::temp_dir().join(format!("uni-sync-slew-{}", std::process::id()));
+    let mut daemon = new_daemon(
+        &config,
+        fan_controller,
+        temperature.clone(),
+        &dir.join("status.json"),
+    );
+    let speeds = || -> Vec<u8> {
+        packets
+            .lock()
+            .unwrap()
+            .drain(..)
+            .filter(|(_, packet)| packet[..2] == [224, 32])
+            .map(|(_, packet)| packet[3])
+            .collect()
+    };
+
+    // Nothing to ease in from on the first write
+    assert_eq!(daemon.tick().await, ControlFlow::Continue(()));
+    assert_eq!(speeds(), vec![47]);
+
+    // 40% to 55% by way of 45% and 50%, a third of the 1s window apart
+    *temperature.lock().unwrap() = Some(55.0);
+    time::sleep_until(daemon.next_deadline()).await;
+    let started = Instant::now();
+    assert_eq!(daemon.tick().await, ControlFlow::Continue(()));
+    // The paused clock rounds timers up to the next millisecond
+    let took = started.elapsed();
+    assert!(took >= Duration::from_millis(666) && took <= Duration::from_millis(670));
+    assert_eq!(speeds(), vec![51, 56, 60]);
+    assert_eq!(daemon.fan_controller.queue_stats().substeps, 2);
+
+    // The override goes out at once
+    let started = Instant::now();
+    assert_eq!(daemon.nudge(10).await, ControlFlow::Continue(()));
+    assert_eq!(started.elapsed(), Duration::ZERO);
+    assert_eq!(speeds(), vec![69]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
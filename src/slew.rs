@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use crate::engine::SpeedReason;
+use crate::hardware::SpeedCommand;
+
+/// Share of the interval a tick's substeps are spread over, so the tick's
+/// writes still finish well within it.
+pub const WINDOW_FRACTION: f64 = 0.25;
+/// Longest a tick's substeps take, however long the interval.
+pub const MAX_WINDOW: Duration = Duration::from_secs(2);
+
+/// How long a tick with `interval` may spread its substeps over.
+pub fn window(interval: Duration) -> Duration {
+    interval.mul_f64(WINDOW_FRACTION).min(MAX_WINDOW)
+}
+
+/// Whether a change the engine made for `reason` is eased in. Changes the
+/// override, the never-stop floor or throttling force, and the fallback
+/// speed, go out at once.
+pub fn is_slewed(reason: SpeedReason) -> bool {
+    matches!(
+        reason,
+        SpeedReason::Curve
+            | SpeedReason::Anticipation
+            | SpeedReason::Group
+            | SpeedReason::StartupRamp
+    )
+}
+
+/// The speeds written on the way from `from` to `to` when the change is
+/// split into `substeps` writes: evenly spaced whole percents, without
+/// repeats or either end.
+pub fn intermediate_speeds(from: u8, to: u8, substeps: u8) -> Vec<u8> {
+    let mut speeds: Vec<u8> = (1..substeps)
+        .map(|step| {
+            let progress = step as f64 / substeps as f64;
+            (from as f64 + (to as f64 - from as f64) * progress).round() as u8
+        })
+        .filter(|&speed| speed != from && speed != to)
+        .collect();
+    speeds.dedup();
+    speeds
+}
+
+/// The substeps of one tick's slewed changes, and when after the first of
+/// them the final speeds are due.
+#[derive(Debug, Default, PartialEq)]
+pub struct Plan {
+    /// Commands written together, by their offset from the first, in order.
+    pub steps: Vec<(Duration, Vec<SpeedCommand>)>,
+    pub final_at: Duration,
+}
+
+/// Spreads each change across `window`: a change with n intermediate
+/// speeds writes one every `window / (n + 1)`, starting right away. The
+/// final speeds go out together, once the change with the longest spacing
+/// has had its last. `changes` are the final commands with the speed each
+/// channel is at and its `slew_substeps`.
+pub fn plan(changes: &[(SpeedCommand, u8, u8)], window: Duration) -> Plan {
+    let mut plan = Plan::default();
+    for (command, from, substeps) in changes {
+        let speeds = intermediate_speeds(*from, command.speed_percent.get(), *substeps);
+        let spacing = window / (speeds.len() as u32 + 1);
+        for (step, speed) in speeds.iter().enumerate() {
+            let offset = spacing * step as u32;
+            let command = SpeedCommand {
+                speed_percent: crate::config::SpeedPercent::saturating(*speed),
+                ..command.clone()
+            };
+            match plan.steps.iter_mut().find(|(at, _)| *at == offset) {
+                Some((_, commands)) => commands.push(command),
+                None => plan.steps.push((offset, vec![command])),
+            }
+        }
+        plan.final_at = plan.final_at.max(spacing * speeds.len() as u32);
+    }
+    plan.steps.sort_by_key(|(offset, _)| *offset);
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ChannelMode, DeviceId, SpeedPercent};
+
+    #[test]
+    fn test_intermediate_speeds() {
+        assert_eq!(intermediate_speeds(40, 55, 3), vec![45, 50]);
+        assert_eq!(intermediate_speeds(55, 40, 3), vec![50, 45]);
+        assert_eq!(intermediate_speeds(40, 60, 4), vec![45, 50, 55]);
+        // Too small a change to split into that many
+        assert_eq!(intermediate_speeds(40, 42, 4), vec![41]);
+        assert_eq!(intermediate_speeds(40, 41, 10), Vec::<u8>::new());
+        assert_eq!(intermediate_speeds(40, 40, 3), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_plan() {
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let command = |channel, speed| SpeedCommand {
+            device_id: hub.clone(),
+            channel,
+            mode: ChannelMode::Manual,
+            speed_percent: SpeedPercent::new(speed).unwrap(),
+            reason: SpeedReason::Curve,
+        };
+        let plan = plan(
+            &[(command(0, 55), 40, 3), (command(1, 60), 40, 4)],
+            Duration::from_millis(1200),
+        );
+        let speeds: Vec<(u64, Vec<(usize, u8)>)> = plan
+            .steps
+            .iter()
+            .map(|(offset, commands)| {
+                (
+                    offset.as_millis() as u64,
+                    commands
+                        .iter()
+                        .map(|c| (c.channel, c.speed_percent.get()))
+                        .collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            speeds,
+            vec![
+                (0, vec![(0, 45), (1, 45)]),
+                (300, vec![(1, 50)]),
+                (400, vec![(0, 50)]),
+                (600, vec![(1, 55)]),
+            ]
+        );
+        assert_eq!(plan.final_at, Duration::from_millis(900));
+    }
+}
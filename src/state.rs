@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use crate::config::{CurveConfig, FanCurve};
+
+/// Runtime state worth carrying over a reboot, as `state export` prints it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RuntimeState {
+    /// Unix time of the export, in seconds.
+    pub exported_at: u64,
+    /// Curves disabled with `uni-sync-curve curve disable`, by label.
+    #[serde(default)]
+    pub disabled_curves: BTreeSet<String>,
+    /// The interactive override the daemon last ran with.
+    #[serde(default)]
+    pub override_percent: i8,
+}
+
+/// What importing a state changes, and what it had to leave out and why.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Import {
+    pub disabled_curves: BTreeSet<String>,
+    pub skipped: Vec<String>,
+}
+
+/// Applies what it can of `state` to a daemon running `config`.
+pub fn import(state: &RuntimeState, config: &CurveConfig) -> Import {
+    let labels: BTreeSet<String> = config.fan_curves.iter().map(FanCurve::label).collect();
+    let mut import = Import::default();
+    for label in &state.disabled_curves {
+        if labels.contains(label) {
+            import.disabled_curves.insert(label.clone());
+        } else {
+            import
+                .skipped
+                .push(format!("disabled curve {}: no such curve", label));
+        }
+    }
+    if state.override_percent != 0 {
+        import.skipped.push(format!(
+            "override {:+}%: only --interactive can set it; press +/- there",
+            state.override_percent
+        ));
+    }
+    import
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import() {
+        let config = CurveConfig {
+            fan_curves: vec![FanCurve {
+                name: Some("front".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let state: RuntimeState = serde_json::from_str(
+            r#"{"exported_at": 1700000000, "disabled_curves": ["front", "pump"], "override_percent": -10}"#,
+        )
+        .unwrap();
+
+        let import = import(&state, &config);
+        assert_eq!(
+            import.disabled_curves,
+            BTreeSet::from(["front".to_string()])
+        );
+        assert_eq!(import.skipped.len(), 2);
+        assert!(import.skipped[0].contains("pump"));
+        assert!(import.skipped[1].contains("-10%"));
+
+        let empty: RuntimeState = serde_json::from_str(r#"{"exported_at": 0}"#).unwrap();
+        assert_eq!(super::import(&empty, &config), Import::default());
+    }
+}
@@ -0,0 +1,538 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::backend::DeviceMetadata;
+use crate::config::{ChannelMode, DeviceId, Manage, PowerState};
+use crate::duty::DutyStats;
+use crate::engine::SpeedReason;
+use crate::hardware::ChannelState;
+use crate::history::Extrapolation;
+use crate::ring::BufferUsage;
+use crate::sensors::{SourceState, SourceStatus};
+use crate::style::{speed_bar, Color, Column, Style, Table, SPEED_BAR_WIDTH};
+use crate::timing::TickStats;
+use crate::units::DisplayUnits;
+
+pub const STATUS_FILE: &str = "/run/uni-sync-curve/status.json";
+
+/// Snapshot of the daemon's last tick, written for `uni-sync-curve status`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Status {
+    /// Unix time of the tick, in seconds.
+    pub updated_at: u64,
+    pub temperature_celsius: Option<f64>,
+    pub channels: Vec<ChannelStatus>,
+    /// Curves that are configured but not driven, by label.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled_curves: Vec<String>,
+    /// How long the tick took.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tick: Option<TickStats>,
+    /// Why the config couldn't be loaded, while the daemon runs its built-in
+    /// safe mode curves instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safe_mode: Option<String>,
+    /// What each managed device reported about itself at startup.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<(DeviceId, DeviceMetadata)>,
+    /// The interactive override added to every channel's speed.
+    #[serde(default)]
+    pub override_percent: i8,
+    /// Whether the temperature source is giving readings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceStatus>,
+    /// The power state, when `power_profiles` are configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_state: Option<PowerState>,
+    /// How full the daemon's buffers are.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<MemoryStatus>,
+    /// The interval the daemon ticks at, when `auto_stretch_interval` made
+    /// it longer than the configured one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stretched_interval_seconds: Option<u64>,
+    /// The temperature the curves followed, when the source's reading
+    /// latency moved it from `temperature_celsius`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extrapolation: Option<Extrapolation>,
+    /// Percentage of CPUs throttled for heat over the last tick, when a
+    /// curve has `throttle_points` and the CPUs count throttling.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub throttle_pressure_percent: Option<f64>,
+}
+
+/// The buffers the daemon keeps across ticks, each with a fixed capacity.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStatus {
+    /// Temperature samples kept for rates of change and alerts.
+    pub temperature_samples: BufferUsage,
+    /// Speed changes kept for duty statistics, over every channel.
+    pub duty_transitions: BufferUsage,
+    /// Commands waiting to be written, over every device.
+    pub write_queues: BufferUsage,
+    /// Speed commands kept for `uni-sync-curve journal`.
+    #[serde(default)]
+    pub speed_journal: BufferUsage,
+}
+
+impl MemoryStatus {
+    /// Each buffer, by the name the status shows.
+    pub fn buffers(&self) -> [(&'static str, BufferUsage); 4] {
+        [
+            ("temperature samples", self.temperature_samples),
+            ("duty transitions", self.duty_transitions),
+            ("write queues", self.write_queues),
+            ("speed journal", self.speed_journal),
+        ]
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChannelStatus {
+    pub curve: String,
+    /// The channel's identifier, as `uni-sync-curve names` lists it.
+    #[serde(default)]
+    pub id: String,
+    pub device_id: DeviceId,
+    pub channel: usize,
+    pub mode: ChannelMode,
+    pub speed_percent: u8,
+    /// Fans connected to the channel, when the hub reports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fan_count: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// The group member whose speed every member is running at.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_winner: Option<String>,
+    /// Why the channel runs at this speed.
+    pub reason: SpeedReason,
+    /// What the channel was doing before the daemon started; None when the
+    /// hub can't report it.
+    #[serde(default)]
+    pub previous: Option<ChannelState>,
+    /// The device kept failing writes; its curves are skipped until it responds.
+    #[serde(default)]
+    pub quarantined: bool,
+    #[serde(default)]
+    pub manage: Manage,
+    /// The curve is `never_stop`: the channel is never driven below its floor.
+    #[serde(default)]
+    pub never_stop: bool,
+    /// What the channel did over the last day, since the daemon started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duty: Option<DutyStats>,
+    /// Speed writes left before changes wait for budget; None without a cap.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_budget: Option<u32>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn write_status(path: &Path, status: &Status) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Write then rename, so readers never see a half-written file
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, serde_json::to_string_pretty(status)?)?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+pub fn read_status(path: &Path) -> Result<Status> {
+    let content = std::fs::read_to_string(path).with_context(|| {
+        format!(
+            "Could not read {:?}; is the uni-sync-curve daemon running?",
+            path
+        )
+    })?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// The status as aligned text; `style` adds colors and speed bars on a terminal.
+pub fn format_status(status: &Status, now: u64, units: DisplayUnits, style: Style) -> String {
+    let mut text = String::new();
+
+    if let Some(error) = &status.safe_mode {
+        let _ = writeln!(
+            text,
+            "{}",
+            style.paint(
+                Color::Red,
+                &format!(
+                    "SAFE MODE: the configuration could not be loaded ({}); every channel runs the built-in curve",
+                    error
+                )
+            )
+        );
+    }
+
+    let _ = write!(
+        text,
+        "Updated {}s ago",
+        now.saturating_sub(status.updated_at)
+    );
+    match status.temperature_celsius {
+        Some(temperature) => {
+            let _ = writeln!(
+                text,
+                ", CPU {}",
+                style.temperature(temperature, &units.temperature(temperature))
+            );
+        }
+        None => {
+            let _ = writeln!(text, ", CPU temperature unavailable");
+        }
+    }
+    if let Some(source) = &status.source {
+        let _ = writeln!(text, "{}", format_source(source, style));
+    }
+    if let Some(power_state) = status.power_state {
+        let _ = writeln!(text, "On {} power", power_state);
+    }
+    if let Some(seconds) = status.stretched_interval_seconds {
+        let _ = writeln!(
+            text,
+            "Ticking every {}s: the configured interval is too short for the devices",
+            seconds
+        );
+    }
+
+    let mut columns = vec![
+        Column::left(24, 0),
+        Column::left(0, 1),
+        Column::left(0, 1),
+        Column::left(6, 1),
+        Column::right(4, 1),
+    ];
+    if style.is_colored() {
+        columns.push(Column::left(SPEED_BAR_WIDTH, 1));
+    }
+    columns.push(Column::left(0, 0));
+    let mut table = Table::new(columns);
+    for channel in &status.channels {
+        let mut cells = vec![
+            channel.curve.clone(),
+            channel.device_id.to_string(),
+            format!("ch{}", channel.channel),
+            format!("{:?}", channel.mode),
+            format!("{}%", channel.speed_percent),
+        ];
+        if style.is_colored() {
+            cells.push(speed_bar(channel.speed_percent, SPEED_BAR_WIDTH));
+        }
+        cells.push(channel_notes(channel));
+        // Quarantined devices report stale speeds; monitor-only ones aren't driven
+        let inactive = channel.quarantined || channel.manage == Manage::MonitorOnly;
+        table.row(cells, inactive.then_some(Color::Dim));
+    }
+    for line in table.lines(style) {
+        let _ = writeln!(text, "{}", line);
+    }
+    for (device_id, metadata) in &status.devices {
+        let _ = writeln!(text, "Device {}: {}", device_id, metadata);
+    }
+    if let Some(tick) = &status.tick {
+        let _ = writeln!(text, "{}", tick.summary());
+    }
+    if !status.disabled_curves.is_empty() {
+        let _ = writeln!(text, "Disabled: {}", status.disabled_curves.join(", "));
+    }
+
+    text
+}
+
+fn format_source(source: &SourceStatus, style: Style) -> String {
+    let (state, color) = match source.state {
+        SourceState::Available => ("available".to_string(), None),
+        SourceState::Degraded => (
+            format!("DEGRADED, {} failed reads in a row", source.failures),
+            Some(Color::Yellow),
+        ),
+        SourceState::Lost => (
+            format!(
+                "LOST, {} failed reads in a row; channels run at the fallback speed",
+                source.failures
+            ),
+            Some(Color::Red),
+        ),
+    };
+    let mut line = format!("Temperature source {}: {}", source.name, state);
+    if source.transitions > 0 {
+        let _ = write!(line, " ({} changes since startup)", source.transitions);
+    }
+    match color {
+        Some(color) => style.paint(color, &line),
+        None => line,
+    }
+}
+
+/// Each channel's duty statistics, for `status --stats`.
+pub fn format_duty(status: &Status) -> String {
+    let mut table = Table::new(vec![
+        Column::left(24, 0),
+        Column::right(8, 1),
+        Column::right(8, 1),
+        Column::right(8, 1),
+        Column::right(0, 1),
+    ]);
+    table.row(
+        ["CURVE", "STOPPED", "AVERAGE", "MAX", "OVER"]
+            .map(String::from)
+            .to_vec(),
+        None,
+    );
+    for channel in &status.channels {
+        let Some(duty) = &channel.duty else {
+            continue;
+        };
+        table.row(
+            vec![
+                channel.curve.clone(),
+                format!("{:.1}%", duty.stopped_percent),
+                format!("{:.1}%", duty.average_percent),
+                format!("{}%", duty.max_percent),
+                format_span(duty.window_seconds),
+            ],
+            None,
+        );
+    }
+    let mut text = String::new();
+    for line in table.lines(Style::plain()) {
+        let _ = writeln!(text, "{}", line);
+    }
+    text
+}
+
+/// How full each buffer is, for `status --stats`.
+pub fn format_memory(memory: &MemoryStatus) -> String {
+    let buffers: Vec<String> = memory
+        .buffers()
+        .iter()
+        .map(|(name, usage)| format!("{} {}/{}", name, usage.len, usage.capacity))
+        .collect();
+    format!("Memory: {}\n", buffers.join(", "))
+}
+
+/// A duration as its largest unit, e.g. "5m" or "23h".
+fn format_span(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m", seconds / 60),
+        _ => format!("{}h", seconds / 3600),
+    }
+}
+
+/// Everything about a channel besides its speed, each note led by two spaces.
+fn channel_notes(channel: &ChannelStatus) -> String {
+    let mut text = String::new();
+    if channel.reason != SpeedReason::Curve {
+        let _ = write!(text, "  ({:?})", channel.reason);
+    }
+    if let Some(fan_count) = channel.fan_count {
+        let _ = write!(text, "  {} fans", fan_count);
+    }
+    if let Some(group) = &channel.group {
+        let _ = write!(text, "  group {}", group);
+        if let Some(winner) = &channel.group_winner {
+            let _ = write!(text, " (set by {})", winner);
+        }
+    }
+    if channel.never_stop {
+        let _ = write!(text, "  never stops");
+    }
+    if channel.quarantined {
+        let _ = write!(text, "  QUARANTINED");
+    }
+    if channel.write_budget == Some(0) {
+        let _ = write!(text, "  write budget spent, changes deferred");
+    }
+    match channel.manage {
+        Manage::Full => {}
+        Manage::SpeedOnly => {
+            let _ = write!(text, "  speed only");
+        }
+        Manage::MonitorOnly => {
+            let _ = write!(text, "  monitor only, not written");
+        }
+    }
+    match &channel.previous {
+        Some(previous) => {
+            let _ = write!(
+                text,
+                "  was {:?} {}%",
+                previous.mode, previous.speed_percent
+            );
+        }
+        None => {
+            let _ = write!(text, "  previous state unknown");
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SpeedPercent;
+    use crate::style::TemperatureThresholds;
+
+    #[test]
+    fn test_format_status() {
+        let status = Status {
+            updated_at: 100,
+            temperature_celsius: Some(54.25),
+            channels: vec![
+                ChannelStatus {
+                    curve: "front-1".to_string(),
+                    id: "front-1".to_string(),
+                    device_id: DeviceId(0x0cf2, 0xa102, "ABC".to_string()),
+                    channel: 0,
+                    mode: ChannelMode::Manual,
+                    speed_percent: 70,
+                    fan_count: Some(3),
+                    group: Some("front".to_string()),
+                    group_winner: Some("front-2".to_string()),
+                    reason: SpeedReason::Group,
+                    previous: Some(ChannelState {
+                        mode: ChannelMode::PWM,
+                        speed_percent: SpeedPercent::new(40).unwrap(),
+                    }),
+                    quarantined: false,
+                    manage: Manage::Full,
+                    never_stop: false,
+                    duty: None,
+                    write_budget: Some(12),
+                },
+                ChannelStatus {
+                    curve: "top".to_string(),
+                    id: "top".to_string(),
+                    device_id: DeviceId(0x0cf2, 0xa102, "ABC".to_string()),
+                    channel: 3,
+                    mode: ChannelMode::PWM,
+                    speed_percent: 100,
+                    fan_count: None,
+                    group: None,
+                    group_winner: None,
+                    reason: SpeedReason::Curve,
+                    previous: None,
+                    quarantined: true,
+                    manage: Manage::MonitorOnly,
+                    never_stop: true,
+                    duty: Some(DutyStats {
+                        window_seconds: 7300.0,
+                        stopped_percent: 12.5,
+                        average_percent: 41.25,
+                        max_percent: 100,
+                    }),
+                    write_budget: Some(0),
+                },
+            ],
+            disabled_curves: vec!["rear".to_string()],
+            tick: Some(TickStats {
+                sensor_ms: 2.0,
+                engine_ms: 0.1,
+                write_ms: 30.0,
+                device_write_ms: Vec::new(),
+                total_ms: 32.1,
+                overruns: 2,
+                ..TickStats::default()
+            }),
+            safe_mode: None,
+            devices: vec![(
+                DeviceId(0x0cf2, 0xa102, "ABC".to_string()),
+                DeviceMetadata {
+                    manufacturer: Some("ENE".to_string()),
+                    product: None,
+                    release_number: 0x0130,
+                    port_path: None,
+                },
+            )],
+            override_percent: 0,
+            source: Some(SourceStatus {
+                name: "gpu".to_string(),
+                state: SourceState::Degraded,
+                failures: 2,
+                transitions: 3,
+            }),
+            power_state: Some(PowerState::Battery),
+            memory: Some(MemoryStatus {
+                temperature_samples: BufferUsage {
+                    len: 14,
+                    capacity: 14,
+                },
+                duty_transitions: BufferUsage {
+                    len: 9,
+                    capacity: 2 * 4096,
+                },
+                write_queues: BufferUsage::default(),
+                speed_journal: BufferUsage {
+                    len: 3,
+                    capacity: 1000,
+                },
+            }),
+            stretched_interval_seconds: Some(3),
+            extrapolation: None,
+            throttle_pressure_percent: None,
+        };
+
+        assert_eq!(
+            format_status(&status, 103, DisplayUnits::Celsius, Style::plain()),
+            "Updated 3s ago, CPU 54.2°C\n\
+             Temperature source gpu: DEGRADED, 2 failed reads in a row (3 changes since startup)\n\
+             On battery power\n\
+             Ticking every 3s: the configured interval is too short for the devices\n\
+             front-1                  (0cf2, a102, ABC) ch0 Manual  70%  (Group)  3 fans  group front (set by front-2)  was PWM 40%\n\
+             top                      (0cf2, a102, ABC) ch3 PWM    100%  never stops  QUARANTINED  write budget spent, changes deferred  monitor only, not written  previous state unknown\n\
+             Device (0cf2, a102, ABC): ENE unknown product, firmware 1.30\n\
+             Tick took 32.1ms: sensor 2.0ms, curves 0.1ms, writes 30.0ms; 2 ticks skipped so far\n\
+             Disabled: rear\n"
+        );
+
+        let colored = format_status(
+            &status,
+            103,
+            DisplayUnits::Celsius,
+            Style::colored(Some(TemperatureThresholds {
+                warm: 50.0,
+                hot: 60.0,
+            })),
+        );
+        let lines: Vec<&str> = colored.lines().collect();
+        assert_eq!(lines[0], "Updated 3s ago, CPU \x1b[33m54.2°C\x1b[0m");
+        assert!(lines[1].starts_with("\x1b[33mTemperature source gpu: DEGRADED"));
+        assert_eq!(lines[2], "On battery power");
+        assert!(lines[4].contains("ch0 Manual  70% ███████     (Group)"));
+        // The quarantined, monitor-only channel is dimmed as a whole
+        assert!(lines[5].starts_with("\x1b[2mtop "));
+        assert!(lines[5].ends_with("previous state unknown\x1b[0m"));
+
+        assert_eq!(
+            format_duty(&status),
+            "CURVE                     STOPPED  AVERAGE      MAX OVER\n\
+             top                         12.5%    41.2%     100%   2h\n"
+        );
+        assert_eq!(
+            format_memory(&status.memory.unwrap()),
+            "Memory: temperature samples 14/14, duty transitions 9/8192, write queues 0/0, speed journal 3/1000\n"
+        );
+
+        let safe = Status {
+            safe_mode: Some("expected value at line 3 column 5".to_string()),
+            ..status
+        };
+        assert!(format_status(&safe, 103, DisplayUnits::Fahrenheit, Style::plain()).starts_with(
+            "SAFE MODE: the configuration could not be loaded (expected value at line 3 column 5)"
+        ));
+    }
+}
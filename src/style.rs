@@ -0,0 +1,314 @@
+use std::io::IsTerminal;
+
+use crate::config::{AlertCondition, AlertRule};
+
+const RESET: &str = "\x1b[0m";
+/// How far below a lone overheat alert temperatures turn yellow.
+const WARM_MARGIN_CELSIUS: f64 = 10.0;
+/// Width of a speed bar, in characters.
+pub const SPEED_BAR_WIDTH: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    Green,
+    Yellow,
+    Red,
+    /// For stale or inactive rows.
+    Dim,
+    /// For table headers.
+    Bold,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Color::Green => "\x1b[32m",
+            Color::Yellow => "\x1b[33m",
+            Color::Red => "\x1b[31m",
+            Color::Dim => "\x1b[2m",
+            Color::Bold => "\x1b[1m",
+        }
+    }
+}
+
+/// CPU temperatures from which output turns yellow and red, in °C. They
+/// come from the overheat alerts, so a color always means an alert is near.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureThresholds {
+    pub warm: f64,
+    pub hot: f64,
+}
+
+impl TemperatureThresholds {
+    /// Red from the highest overheat alert, yellow from the lowest, or
+    /// `WARM_MARGIN_CELSIUS` below it when there is only one. None without
+    /// overheat alerts.
+    pub fn from_alerts(rules: &[AlertRule]) -> Option<Self> {
+        let thresholds: Vec<f64> = rules
+            .iter()
+            .filter_map(|rule| match rule.condition {
+                AlertCondition::Overheat { above_celsius } => Some(above_celsius),
+                _ => None,
+            })
+            .collect();
+        let hot = thresholds.iter().copied().reduce(f64::max)?;
+        let lowest = thresholds.iter().copied().reduce(f64::min)?;
+        let warm = if lowest < hot {
+            lowest
+        } else {
+            hot - WARM_MARGIN_CELSIUS
+        };
+        Some(Self { warm, hot })
+    }
+
+    pub fn color(&self, celsius: f64) -> Color {
+        if celsius >= self.hot {
+            Color::Red
+        } else if celsius >= self.warm {
+            Color::Yellow
+        } else {
+            Color::Green
+        }
+    }
+}
+
+/// How text for a terminal is dressed up. Plain style adds nothing, so
+/// plain output stays aligned text that scripts can read.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Style {
+    color: bool,
+    thresholds: Option<TemperatureThresholds>,
+}
+
+impl Style {
+    pub fn plain() -> Self {
+        Self::default()
+    }
+
+    pub fn colored(thresholds: Option<TemperatureThresholds>) -> Self {
+        Self {
+            color: true,
+            thresholds,
+        }
+    }
+
+    /// Colored when stdout is a terminal and NO_COLOR is unset or empty.
+    pub fn for_stdout(thresholds: Option<TemperatureThresholds>) -> Self {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        if no_color || !std::io::stdout().is_terminal() {
+            return Self::plain();
+        }
+        Self::colored(thresholds)
+    }
+
+    pub fn is_colored(self) -> bool {
+        self.color
+    }
+
+    pub fn paint(self, color: Color, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color.code(), text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// `text` showing a temperature of `celsius`, colored by the thresholds.
+    pub fn temperature(self, celsius: f64, text: &str) -> String {
+        match self.thresholds {
+            Some(thresholds) => self.paint(thresholds.color(celsius), text),
+            None => text.to_string(),
+        }
+    }
+}
+
+/// `percent` as a bar of block characters `width` wide, in eighths.
+pub fn speed_bar(percent: u8, width: usize) -> String {
+    const PARTIAL: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+    let eighths = (percent.min(100) as usize * width * 8 + 50) / 100;
+    let mut bar = "█".repeat(eighths / 8);
+    if !eighths.is_multiple_of(8) {
+        bar.push(PARTIAL[eighths % 8]);
+    }
+    let padding = width - bar.chars().count();
+    bar.extend(std::iter::repeat_n(' ', padding));
+    bar
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// A table column: alignment, minimum width and the spaces before it.
+#[derive(Clone, Copy, Debug)]
+pub struct Column {
+    pub align: Align,
+    pub min_width: usize,
+    pub gap: usize,
+}
+
+impl Column {
+    pub fn left(min_width: usize, gap: usize) -> Self {
+        Self {
+            align: Align::Left,
+            min_width,
+            gap,
+        }
+    }
+
+    pub fn right(min_width: usize, gap: usize) -> Self {
+        Self {
+            align: Align::Right,
+            min_width,
+            gap,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Row {
+    cells: Vec<String>,
+    color: Option<Color>,
+}
+
+/// Rows of cells padded to the widest cell in each column. Colors are
+/// applied after padding, so escape codes never throw the columns off.
+#[derive(Clone, Debug)]
+pub struct Table {
+    columns: Vec<Column>,
+    rows: Vec<Row>,
+}
+
+impl Table {
+    pub fn new(columns: Vec<Column>) -> Self {
+        Self {
+            columns,
+            rows: Vec::new(),
+        }
+    }
+
+    /// A row, all of it in `color` if there is one.
+    pub fn row(&mut self, cells: Vec<String>, color: Option<Color>) {
+        self.rows.push(Row { cells, color });
+    }
+
+    /// One line per row, without trailing newlines. A left-aligned last
+    /// column isn't padded.
+    pub fn lines(&self, style: Style) -> Vec<String> {
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                self.rows
+                    .iter()
+                    .filter_map(|row| row.cells.get(index))
+                    .map(|text| text.chars().count())
+                    .fold(column.min_width, usize::max)
+            })
+            .collect();
+
+        self.rows
+            .iter()
+            .map(|row| {
+                let mut line = String::new();
+                for (index, text) in row.cells.iter().enumerate() {
+                    let column = self.columns[index];
+                    let last = index + 1 == row.cells.len();
+                    if last && text.is_empty() {
+                        break;
+                    }
+                    let padding = widths[index].saturating_sub(text.chars().count());
+                    let padded = match column.align {
+                        Align::Left if last => text.clone(),
+                        Align::Left => format!("{}{}", text, " ".repeat(padding)),
+                        Align::Right => format!("{}{}", " ".repeat(padding), text),
+                    };
+                    line.push_str(&" ".repeat(column.gap));
+                    line.push_str(&padded);
+                }
+                match row.color {
+                    Some(color) => style.paint(color, &line),
+                    None => line,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overheat(above_celsius: f64) -> AlertRule {
+        AlertRule {
+            condition: AlertCondition::Overheat { above_celsius },
+            actions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_thresholds_from_alerts() {
+        assert_eq!(TemperatureThresholds::from_alerts(&[]), None);
+        let single = TemperatureThresholds::from_alerts(&[overheat(85.0)]).unwrap();
+        assert_eq!(
+            single,
+            TemperatureThresholds {
+                warm: 75.0,
+                hot: 85.0
+            }
+        );
+        let pair = TemperatureThresholds::from_alerts(&[overheat(90.0), overheat(70.0)]).unwrap();
+        assert_eq!(
+            pair,
+            TemperatureThresholds {
+                warm: 70.0,
+                hot: 90.0
+            }
+        );
+
+        assert_eq!(pair.color(69.9), Color::Green);
+        assert_eq!(pair.color(70.0), Color::Yellow);
+        assert_eq!(pair.color(95.0), Color::Red);
+    }
+
+    #[test]
+    fn test_speed_bar() {
+        assert_eq!(speed_bar(0, 4), "    ");
+        assert_eq!(speed_bar(50, 4), "██  ");
+        assert_eq!(speed_bar(55, 4), "██▎ ");
+        assert_eq!(speed_bar(100, 4), "████");
+        assert_eq!(speed_bar(100, SPEED_BAR_WIDTH).chars().count(), 10);
+    }
+
+    #[test]
+    fn test_table() {
+        let mut table = Table::new(vec![
+            Column::left(4, 0),
+            Column::right(0, 1),
+            Column::left(0, 2),
+        ]);
+        table.row(
+            vec!["NAME".into(), "SPEED".into(), "NOTE".into()],
+            Some(Color::Bold),
+        );
+        table.row(
+            vec!["front-1".into(), "70%".into(), "".into()],
+            Some(Color::Dim),
+        );
+
+        assert_eq!(
+            table.lines(Style::plain()),
+            vec!["NAME    SPEED  NOTE", "front-1   70%"]
+        );
+        assert_eq!(
+            table.lines(Style::colored(None)),
+            vec![
+                "\x1b[1mNAME    SPEED  NOTE\x1b[0m",
+                "\x1b[2mfront-1   70%\x1b[0m"
+            ]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backend::{Command, FanBackend};
+use crate::config::{ChannelMode, DeviceId};
+
+pub const HWMON_ROOT: &str = "/sys/class/hwmon";
+
+// pwmN_enable values from the hwmon sysfs ABI
+const PWM_ENABLE_MANUAL: &str = "1";
+const PWM_ENABLE_AUTOMATIC: &str = "2";
+
+/// Drives motherboard fan headers exposed as /sys/class/hwmon/hwmonX/pwmN.
+/// Each pwm file is a pseudo-device with a single channel 0 and the id
+/// (0, 0, "hwmonX/pwmN").
+pub struct SysfsPwmBackend {
+    pwm_paths: HashMap<DeviceId, PathBuf>,
+    // pwmN_enable contents from before we took the header over
+    original_enable: HashMap<DeviceId, String>,
+}
+
+impl SysfsPwmBackend {
+    pub fn discover(root: &Path) -> Result<Self> {
+        let mut pwm_paths = HashMap::new();
+
+        if root.exists() {
+            for hwmon in fs::read_dir(root)? {
+                let hwmon = hwmon?.path();
+                let Some(hwmon_name) = hwmon.file_name().and_then(|n| n.to_str()) else {
+                    continue;
+                };
+                let Ok(entries) = fs::read_dir(&hwmon) else {
+                    continue;
+                };
+
+                for entry in entries.flatten() {
+                    let file_name = entry.file_name();
+                    let Some(file_name) = file_name.to_str() else {
+                        continue;
+                    };
+                    if is_pwm_file(file_name) {
+                        pwm_paths.insert(
+                            DeviceId(0, 0, format!("{}/{}", hwmon_name, file_name)),
+                            entry.path(),
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            pwm_paths,
+            original_enable: HashMap::new(),
+        })
+    }
+
+    fn pwm_path(&self, device_id: &DeviceId) -> Result<&PathBuf> {
+        self.pwm_paths
+            .get(device_id)
+            .ok_or_else(|| anyhow!("Device with given device id {} not available", device_id))
+    }
+
+    fn enable_path(pwm_path: &Path) -> PathBuf {
+        let mut path = pwm_path.as_os_str().to_owned();
+        path.push("_enable");
+        PathBuf::from(path)
+    }
+
+    fn take_manual_control(&mut self, device_id: &DeviceId) -> Result<()> {
+        let enable_path = Self::enable_path(self.pwm_path(device_id)?);
+        let current = fs::read_to_string(&enable_path)
+            .with_context(|| format!("reading {:?}", enable_path))?
+            .trim()
+            .to_string();
+
+        if !self.original_enable.contains_key(device_id) {
+            self.original_enable
+                .insert(device_id.clone(), current.clone());
+        } else if current != PWM_ENABLE_MANUAL {
+            // Some drivers (e.g. nct6775 after suspend) silently switch the
+            // header back to automatic; take it back before writing a duty.
+            eprintln!(
+                "{} was switched to pwm_enable={} by the driver, re-enabling manual control",
+                device_id, current
+            );
+        }
+
+        if current != PWM_ENABLE_MANUAL {
+            fs::write(&enable_path, PWM_ENABLE_MANUAL)
+                .with_context(|| format!("writing {:?}", enable_path))?;
+        }
+        Ok(())
+    }
+}
+
+fn is_pwm_file(name: &str) -> bool {
+    name.strip_prefix("pwm")
+        .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn percent_to_pwm(speed_percent: u8) -> u8 {
+    (speed_percent.min(100) as f64 * 255.0 / 100.0).round() as u8
+}
+
+impl FanBackend for SysfsPwmBackend {
+    fn devices(&self) -> Vec<DeviceId> {
+        self.pwm_paths.keys().cloned().collect()
+    }
+
+    fn send(&mut self, device_id: &DeviceId, command: &Command) -> Result<()> {
+        match command {
+            Command::SyncRgb(_) => Ok(()),
+            Command::SetMode { channel, .. } | Command::SetSpeed { channel, .. }
+                if *channel != 0 =>
+            {
+                bail!("{} only has channel 0, not channel {}", device_id, channel)
+            }
+            Command::SetMode {
+                mode: ChannelMode::Manual,
+                ..
+            } => self.take_manual_control(device_id),
+            Command::SetMode {
+                mode: ChannelMode::PWM,
+                ..
+            } => {
+                // PWM mode hands the header back to the chip's own curve
+                let enable_path = Self::enable_path(self.pwm_path(device_id)?);
+                fs::write(&enable_path, PWM_ENABLE_AUTOMATIC)
+                    .with_context(|| format!("writing {:?}", enable_path))
+            }
+            Command::SetSpeed { speed_percent, .. } => {
+                self.take_manual_control(device_id)?;
+                let pwm_path = self.pwm_path(device_id)?;
+                fs::write(pwm_path, percent_to_pwm(speed_percent.get()).to_string())
+                    .with_context(|| format!("writing {:?}", pwm_path))
+            }
+        }
+    }
+
+    fn release(&mut self) -> Result<()> {
+        for (device_id, original) in self.original_enable.drain() {
+            let Some(pwm_path) = self.pwm_paths.get(&device_id) else {
+                continue;
+            };
+            // Never leave a header in manual mode behind us
+            let restore = if original == PWM_ENABLE_MANUAL {
+                PWM_ENABLE_AUTOMATIC
+            } else {
+                original.as_str()
+            };
+            let enable_path = Self::enable_path(pwm_path);
+            if let Err(e) = fs::write(&enable_path, restore) {
+                eprintln!("Error restoring {:?}: {}", enable_path, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SpeedPercent;
+
+    fn fake_hwmon(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "uni-sync-curve-sysfs-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        let hwmon = root.join("hwmon3");
+        fs::create_dir_all(&hwmon).unwrap();
+        fs::write(hwmon.join("name"), "nct6775").unwrap();
+        fs::write(hwmon.join("pwm2"), "80").unwrap();
+        fs::write(hwmon.join("pwm2_enable"), "5").unwrap();
+        fs::write(hwmon.join("pwm2_mode"), "1").unwrap();
+        root
+    }
+
+    fn read(root: &Path, file: &str) -> String {
+        fs::read_to_string(root.join("hwmon3").join(file)).unwrap()
+    }
+
+    #[test]
+    fn test_sysfs_pwm_backend() {
+        let root = fake_hwmon("control");
+        let device_id = DeviceId(0, 0, "hwmon3/pwm2".to_string());
+
+        let mut backend = SysfsPwmBackend::discover(&root).unwrap();
+        assert_eq!(backend.devices(), vec![device_id.clone()]);
+
+        let set_speed = |speed_percent| Command::SetSpeed {
+            channel: 0,
+            speed_percent: SpeedPercent::new(speed_percent).unwrap(),
+        };
+        backend.send(&device_id, &set_speed(50)).unwrap();
+        assert_eq!(read(&root, "pwm2"), "128");
+        assert_eq!(read(&root, "pwm2_enable"), "1");
+
+        // Driver resets the header to automatic behind our back
+        fs::write(root.join("hwmon3/pwm2_enable"), "2").unwrap();
+        backend.send(&device_id, &set_speed(100)).unwrap();
+        assert_eq!(read(&root, "pwm2"), "255");
+        assert_eq!(read(&root, "pwm2_enable"), "1");
+
+        assert!(backend
+            .send(
+                &device_id,
+                &Command::SetSpeed {
+                    channel: 1,
+                    speed_percent: SpeedPercent::ZERO
+                }
+            )
+            .is_err());
+
+        backend.release().unwrap();
+        assert_eq!(read(&root, "pwm2_enable"), "5");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CPU_ROOT: &str = "/sys/devices/system/cpu";
+
+/// Counters of the times a CPU was throttled for heat, under
+/// cpuN/thermal_throttle. Only Intel CPUs have them.
+const COUNTER_FILES: [&str; 2] = ["core_throttle_count", "package_throttle_count"];
+
+/// How far `current` moved on from `previous`. A counter that went
+/// backwards was reset, e.g. by taking the CPU offline, or wrapped; it is
+/// counted from zero.
+pub fn counter_delta(previous: u64, current: u64) -> u64 {
+    if current >= previous {
+        current - previous
+    } else {
+        current
+    }
+}
+
+/// Turns the CPUs' thermal throttle counters into a throttle pressure: the
+/// percentage of CPUs throttled since the last poll, 0 to 100.
+pub struct ThrottleMonitor {
+    /// Each CPU's counter files.
+    cpus: Vec<Vec<PathBuf>>,
+    last: HashMap<PathBuf, u64>,
+}
+
+impl ThrottleMonitor {
+    /// None when no CPU under `root` has throttle counters.
+    pub fn discover(root: &Path) -> Option<Self> {
+        let mut cpus = Vec::new();
+        for entry in fs::read_dir(root).ok()?.flatten() {
+            let name = entry.file_name();
+            let is_cpu = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("cpu"))
+                .is_some_and(|index| {
+                    !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit())
+                });
+            if !is_cpu {
+                continue;
+            }
+            let counters: Vec<PathBuf> = COUNTER_FILES
+                .iter()
+                .map(|file| entry.path().join("thermal_throttle").join(file))
+                .filter(|path| path.exists())
+                .collect();
+            if !counters.is_empty() {
+                cpus.push(counters);
+            }
+        }
+        if cpus.is_empty() {
+            return None;
+        }
+        cpus.sort();
+        Some(Self {
+            cpus,
+            last: HashMap::new(),
+        })
+    }
+
+    /// The throttle pressure since the last poll. The first poll only reads
+    /// where the counters start, so it is 0. Counters that can't be read
+    /// count as not throttled.
+    pub fn poll(&mut self) -> f64 {
+        let mut throttled = 0;
+        for counters in &self.cpus {
+            let mut cpu_throttled = false;
+            for path in counters {
+                let Some(current) = fs::read_to_string(path)
+                    .ok()
+                    .and_then(|content| content.trim().parse::<u64>().ok())
+                else {
+                    continue;
+                };
+                if let Some(previous) = self.last.insert(path.clone(), current) {
+                    cpu_throttled |= counter_delta(previous, current) > 0;
+                }
+            }
+            if cpu_throttled {
+                throttled += 1;
+            }
+        }
+        100.0 * throttled as f64 / self.cpus.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_pressure() {
+        assert_eq!(counter_delta(10, 15), 5);
+        assert_eq!(counter_delta(u64::MAX - 1, 3), 3);
+        assert_eq!(counter_delta(7, 7), 0);
+
+        let root =
+            std::env::temp_dir().join(format!("uni-sync-curve-throttle-{}", std::process::id()));
+        let counter = |cpu: usize, file: &str| {
+            root.join(format!("cpu{}", cpu))
+                .join("thermal_throttle")
+                .join(file)
+        };
+        let set = |cpu: usize, file: &str, value: u64| {
+            let path = counter(cpu, file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, format!("{}\n", value)).unwrap();
+        };
+        fs::create_dir_all(root.join("cpufreq")).unwrap();
+        assert!(ThrottleMonitor::discover(&root).is_none());
+
+        for cpu in 0..4 {
+            set(cpu, "core_throttle_count", 100);
+            set(cpu, "package_throttle_count", 20);
+        }
+        let mut monitor = ThrottleMonitor::discover(&root).unwrap();
+        assert_eq!(monitor.poll(), 0.0);
+        assert_eq!(monitor.poll(), 0.0);
+
+        set(1, "core_throttle_count", 101);
+        assert_eq!(monitor.poll(), 25.0);
+        assert_eq!(monitor.poll(), 0.0);
+
+        // A package counter counts for its CPU, and a reset counter counts
+        // as throttled once it moved on from zero
+        set(0, "package_throttle_count", 21);
+        set(2, "core_throttle_count", 4);
+        set(3, "core_throttle_count", 0);
+        assert_eq!(monitor.poll(), 50.0);
+
+        // A CPU whose counters vanished no longer counts as throttled
+        fs::remove_dir_all(root.join("cpu3")).unwrap();
+        set(0, "core_throttle_count", 200);
+        assert_eq!(monitor.poll(), 25.0);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
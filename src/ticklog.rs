@@ -0,0 +1,116 @@
+use crate::units::DisplayUnits;
+
+/// Temperature change since the last full breakdown that prints a new one.
+pub const TEMPERATURE_THRESHOLD_CELSIUS: f64 = 1.0;
+
+/// Quiet ticks between summary lines.
+pub const SUMMARY_EVERY_TICKS: u32 = 30;
+
+/// What `--debug` prints for one tick.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TickLog {
+    /// The per-curve breakdown: something changed.
+    Full,
+    /// One line standing in for the quiet ticks since the last output.
+    Summary(String),
+    Quiet,
+}
+
+/// Keeps per-tick debug output down to the ticks where something happened:
+/// a speed changed, the temperature moved by more than the threshold since
+/// the last breakdown, or a write failed. Otherwise a summary line is
+/// printed every `summary_every` ticks.
+#[derive(Debug)]
+pub struct TickSummarizer {
+    threshold: f64,
+    summary_every: u32,
+    last_temperature: Option<f64>,
+    last_speeds: Vec<u8>,
+    quiet_ticks: u32,
+    units: DisplayUnits,
+}
+
+impl Default for TickSummarizer {
+    fn default() -> Self {
+        Self::new(TEMPERATURE_THRESHOLD_CELSIUS, SUMMARY_EVERY_TICKS)
+    }
+}
+
+impl TickSummarizer {
+    pub fn new(threshold: f64, summary_every: u32) -> Self {
+        Self {
+            threshold,
+            summary_every: summary_every.max(1),
+            last_temperature: None,
+            last_speeds: Vec::new(),
+            quiet_ticks: 0,
+            units: DisplayUnits::default(),
+        }
+    }
+
+    pub fn set_units(&mut self, units: DisplayUnits) {
+        self.units = units;
+    }
+
+    pub fn observe(&mut self, temperature: f64, speeds: &[u8], error: bool) -> TickLog {
+        let moved = self
+            .last_temperature
+            .is_none_or(|last| (temperature - last).abs() > self.threshold);
+        if error || moved || speeds != self.last_speeds.as_slice() {
+            self.last_temperature = Some(temperature);
+            self.last_speeds = speeds.to_vec();
+            self.quiet_ticks = 0;
+            return TickLog::Full;
+        }
+
+        self.quiet_ticks += 1;
+        if self.quiet_ticks.is_multiple_of(self.summary_every) {
+            TickLog::Summary(format!(
+                "steady: {}, all channels unchanged for {} ticks",
+                self.units.temperature(temperature),
+                self.quiet_ticks
+            ))
+        } else {
+            TickLog::Quiet
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_and_summary_lines() {
+        let mut summarizer = TickSummarizer::new(1.0, 3);
+
+        assert_eq!(summarizer.observe(42.3, &[40, 50], false), TickLog::Full);
+        // Small wobbles stay quiet, and every third quiet tick is summarized
+        assert_eq!(summarizer.observe(42.8, &[40, 50], false), TickLog::Quiet);
+        assert_eq!(summarizer.observe(41.9, &[40, 50], false), TickLog::Quiet);
+        assert_eq!(
+            summarizer.observe(42.3, &[40, 50], false),
+            TickLog::Summary("steady: 42.3°C, all channels unchanged for 3 ticks".to_string())
+        );
+        assert_eq!(summarizer.observe(42.3, &[40, 50], false), TickLog::Quiet);
+
+        // A speed change, an error, or a temperature move breaks the streak
+        assert_eq!(summarizer.observe(42.3, &[40, 55], false), TickLog::Full);
+        assert_eq!(summarizer.observe(42.3, &[40, 55], true), TickLog::Full);
+        assert_eq!(summarizer.observe(42.3, &[40, 55], false), TickLog::Quiet);
+        assert_eq!(summarizer.observe(43.4, &[40, 55], false), TickLog::Full);
+    }
+
+    #[test]
+    fn test_slow_drift_prints_breakdown() {
+        let mut summarizer = TickSummarizer::new(1.0, 100);
+
+        summarizer.observe(40.0, &[30], false);
+        let logs: Vec<_> = [40.4, 40.8, 41.2]
+            .iter()
+            .map(|&temperature| summarizer.observe(temperature, &[30], false))
+            .collect();
+        // Measured against the last breakdown, not the previous tick
+        assert_eq!(logs, vec![TickLog::Quiet, TickLog::Quiet, TickLog::Full]);
+    }
+}
@@ -0,0 +1,426 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+use crate::config::DeviceId;
+
+/// How long one tick took, phase by phase, in milliseconds.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct TickStats {
+    pub sensor_ms: f64,
+    pub engine_ms: f64,
+    pub write_ms: f64,
+    /// Time spent writing to each device, in the order they were written.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub device_write_ms: Vec<(DeviceId, f64)>,
+    /// Time each device waited for the devices written before it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub device_queue_ms: Vec<(DeviceId, f64)>,
+    /// Time spent reading each distinct temperature source, by its key.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_ms: Vec<(String, f64)>,
+    pub total_ms: f64,
+    /// Unix time the tick was due, and when it actually started, in seconds.
+    #[serde(default)]
+    pub scheduled_at: f64,
+    #[serde(default)]
+    pub started_at: f64,
+    /// Deadlines skipped since startup because a tick ran past them.
+    #[serde(default)]
+    pub overruns: u64,
+}
+
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Unix time of `instant`, in seconds.
+pub fn unix_seconds(instant: Instant) -> f64 {
+    let now = Instant::now();
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let time = if instant <= now {
+        since_epoch.saturating_sub(now - instant)
+    } else {
+        since_epoch + (instant - now)
+    };
+    time.as_secs_f64()
+}
+
+/// Tick deadlines on a fixed grid of `period` from the start, so the time a
+/// tick's work takes doesn't push back the ticks after it. A tick that runs
+/// past the next deadline skips ahead to the first deadline still to come.
+#[derive(Debug)]
+pub struct TickSchedule {
+    period: Duration,
+    next: Instant,
+    overruns: u64,
+    // A tick started since the last deadline check
+    ticked: bool,
+}
+
+impl TickSchedule {
+    /// The first tick is due at `start`.
+    pub fn new(start: Instant, period: Duration) -> Self {
+        Self {
+            period: period.max(Duration::from_millis(1)),
+            next: start,
+            overruns: 0,
+            ticked: false,
+        }
+    }
+
+    /// Keeps the current deadline and spaces the ones after it by `period`.
+    pub fn set_period(&mut self, period: Duration) {
+        self.period = period.max(Duration::from_millis(1));
+    }
+
+    /// When the next tick is due. If the last tick ran past it, skips to the
+    /// first deadline after `now` and counts the ones skipped as overruns.
+    pub fn next_deadline(&mut self, now: Instant) -> Instant {
+        if std::mem::take(&mut self.ticked) && now > self.next {
+            let missed = ((now - self.next).as_nanos() / self.period.as_nanos()) as u32 + 1;
+            self.next += self.period * missed;
+            self.overruns += missed as u64;
+        }
+        self.next
+    }
+
+    /// Starts the tick that is due, returning when it was scheduled.
+    pub fn start_tick(&mut self) -> Instant {
+        let scheduled = self.next;
+        self.next += self.period;
+        self.ticked = true;
+        scheduled
+    }
+
+    pub fn overruns(&self) -> u64 {
+        self.overruns
+    }
+}
+
+/// Ticks measured before deciding whether the interval is long enough.
+pub const INTERVAL_CHECK_TICKS: usize = 5;
+
+/// Whether the hardware keeps up with the configured interval, judged from
+/// the first few ticks after startup or a reload.
+#[derive(Debug)]
+pub struct IntervalCheck {
+    interval: Duration,
+    durations: Vec<Duration>,
+}
+
+impl IntervalCheck {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            durations: Vec::with_capacity(INTERVAL_CHECK_TICKS),
+        }
+    }
+
+    /// Records how long a tick took. Once `INTERVAL_CHECK_TICKS` ticks have
+    /// all taken longer than the interval, returns the shortest whole-second
+    /// interval that fits their median, just once. The median leaves out
+    /// one-off work such as the first tick's mode writes.
+    pub fn observe(&mut self, took: Duration) -> Option<u64> {
+        if self.durations.len() >= INTERVAL_CHECK_TICKS {
+            return None;
+        }
+        self.durations.push(took);
+        if self.durations.len() < INTERVAL_CHECK_TICKS
+            || self.durations.iter().any(|took| *took <= self.interval)
+        {
+            return None;
+        }
+        let mut sorted = self.durations.clone();
+        sorted.sort();
+        Some(sorted[sorted.len() / 2].as_secs_f64().ceil() as u64)
+    }
+}
+
+pub fn interval_warning(interval_seconds: u64, feasible_seconds: u64, stretch: bool) -> String {
+    let advice = if stretch {
+        format!("stretching the interval to {}s", feasible_seconds)
+    } else {
+        "set interval_seconds to at least that, or auto_stretch_interval to do so automatically"
+            .to_string()
+    };
+    format!(
+        "Warning: the last {} ticks each took longer than the {}s interval; these devices need at least {}s, {}",
+        INTERVAL_CHECK_TICKS, interval_seconds, feasible_seconds, advice
+    )
+}
+
+/// Every `period`, a pass that re-sends the full state of every device,
+/// staggered one device per tick so a pass never lands on a single tick.
+#[derive(Debug)]
+pub struct ReconcileSchedule {
+    // A zero period disables reconciliation
+    interval: JitteredInterval,
+    next_pass: Instant,
+    // Devices of the running pass not reconciled yet
+    pending: VecDeque<DeviceId>,
+}
+
+impl ReconcileSchedule {
+    /// The first pass is due one period after `start`.
+    pub fn new(start: Instant, mut interval: JitteredInterval) -> Self {
+        Self {
+            next_pass: start + interval.next_period(),
+            interval,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Takes effect from the next pass; a running pass finishes.
+    pub fn set_interval(&mut self, now: Instant, interval: JitteredInterval) {
+        if interval.period != self.interval.period
+            || interval.jitter_percent != self.interval.jitter_percent
+        {
+            self.interval = interval;
+            self.next_pass = now + self.interval.next_period();
+        }
+    }
+
+    /// Starts a pass over `devices` when one is due, returning whether it did.
+    pub fn start_pass_if_due(&mut self, now: Instant, devices: &[DeviceId]) -> bool {
+        if self.interval.period.is_zero() || now < self.next_pass || !self.pending.is_empty() {
+            return false;
+        }
+        self.next_pass = now + self.interval.next_period();
+        self.pending = devices.iter().cloned().collect();
+        !self.pending.is_empty()
+    }
+
+    /// The device to reconcile on this tick, while a pass is running.
+    pub fn next_device(&mut self) -> Option<DeviceId> {
+        self.pending.pop_front()
+    }
+}
+
+/// A timer period varied at random by up to `jitter_percent` of it either
+/// way, so daemons started together on several machines drift apart instead
+/// of reconciling in step. Each period is drawn evenly from the range, so
+/// on average it is the configured one. Without jitter it is exact.
+#[derive(Clone, Debug)]
+pub struct JitteredInterval {
+    period: Duration,
+    jitter_percent: u8,
+    // xorshift64* state; never zero
+    state: u64,
+}
+
+impl JitteredInterval {
+    pub fn new(period: Duration, jitter_percent: u8) -> Self {
+        // Machines differ in clock and pid, which is all the seed needs
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_nanos() as u64);
+        Self::with_seed(
+            period,
+            jitter_percent,
+            nanos ^ u64::from(std::process::id()),
+        )
+    }
+
+    fn with_seed(period: Duration, jitter_percent: u8, seed: u64) -> Self {
+        Self {
+            period,
+            jitter_percent: jitter_percent.min(100),
+            state: seed | 1,
+        }
+    }
+
+    /// The length of the next period.
+    pub fn next_period(&mut self) -> Duration {
+        if self.jitter_percent == 0 {
+            return self.period;
+        }
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let random = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        // Evenly in [-1, 1)
+        let unit = (random >> 11) as f64 / (1u64 << 52) as f64 - 1.0;
+        self.period
+            .mul_f64(1.0 + unit * f64::from(self.jitter_percent) / 100.0)
+    }
+}
+
+impl TickStats {
+    /// Whether the tick took longer than the interval, delaying the next one.
+    pub fn over_budget(&self, interval: Duration) -> bool {
+        self.total_ms > millis(interval)
+    }
+
+    pub fn summary(&self) -> String {
+        let mut text = format!(
+            "Tick took {:.1}ms: sensor {:.1}ms",
+            self.total_ms, self.sensor_ms
+        );
+        // One source is the whole sensor time already
+        if self.source_ms.len() > 1 {
+            let sources: Vec<String> = self
+                .source_ms
+                .iter()
+                .map(|(key, ms)| format!("{} {:.1}ms", key, ms))
+                .collect();
+            let _ = write!(text, " ({})", sources.join(", "));
+        }
+        let _ = write!(
+            text,
+            ", curves {:.1}ms, writes {:.1}ms",
+            self.engine_ms, self.write_ms
+        );
+        for (device_id, ms) in &self.device_write_ms {
+            let _ = write!(text, " ({} {:.1}ms", device_id, ms);
+            let queued = self.device_queue_ms.iter().find(|(d, _)| d == device_id);
+            if let Some((_, queued_ms)) = queued.filter(|(_, queued_ms)| *queued_ms > 0.0) {
+                let _ = write!(text, " after {:.1}ms queued", queued_ms);
+            }
+            text.push(')');
+        }
+        if self.overruns > 0 {
+            let _ = write!(text, "; {} ticks skipped so far", self.overruns);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_stats() {
+        let stats = TickStats {
+            sensor_ms: 1.25,
+            engine_ms: 0.05,
+            write_ms: 2400.0,
+            device_write_ms: vec![
+                (DeviceId(0x0cf2, 0xa103, "ABC".to_string()), 2400.0),
+                (DeviceId(0x0cf2, 0xa102, "DEF".to_string()), 30.0),
+            ],
+            device_queue_ms: vec![
+                (DeviceId(0x0cf2, 0xa103, "ABC".to_string()), 0.0),
+                (DeviceId(0x0cf2, 0xa102, "DEF".to_string()), 2400.0),
+            ],
+            source_ms: vec![("cpu".to_string(), 0.25), ("gpu 0 edge".to_string(), 1.0)],
+            total_ms: 2401.3,
+            ..TickStats::default()
+        };
+
+        assert!(stats.over_budget(Duration::from_secs(2)));
+        assert!(!stats.over_budget(Duration::from_secs(5)));
+        assert_eq!(
+            stats.summary(),
+            "Tick took 2401.3ms: sensor 1.2ms (cpu 0.2ms, gpu 0 edge 1.0ms), curves 0.1ms, writes 2400.0ms ((0cf2, a103, ABC) 2400.0ms) ((0cf2, a102, DEF) 30.0ms after 2400.0ms queued)"
+        );
+    }
+
+    #[test]
+    fn test_tick_schedule() {
+        let start = Instant::now();
+        let period = Duration::from_secs(2);
+        let mut schedule = TickSchedule::new(start, period);
+
+        // The first tick is due at once, even when checked a little later
+        assert_eq!(
+            schedule.next_deadline(start + Duration::from_millis(5)),
+            start
+        );
+        assert_eq!(schedule.start_tick(), start);
+        // Work within the period doesn't move the next deadline
+        let done = start + Duration::from_millis(1500);
+        assert_eq!(schedule.next_deadline(done), start + period);
+        assert_eq!(schedule.start_tick(), start + period);
+
+        // Running past the next deadline skips it
+        let done = start + Duration::from_millis(4500);
+        assert_eq!(schedule.next_deadline(done), start + period * 3);
+        assert_eq!(schedule.overruns(), 1);
+        assert_eq!(schedule.start_tick(), start + period * 3);
+
+        let done = start + Duration::from_millis(12100);
+        assert_eq!(schedule.next_deadline(done), start + period * 7);
+        assert_eq!(schedule.overruns(), 4);
+        // Checking again without a tick in between skips nothing
+        assert_eq!(schedule.next_deadline(done), start + period * 7);
+        assert_eq!(schedule.overruns(), 4);
+    }
+
+    #[test]
+    fn test_interval_check() {
+        let mut check = IntervalCheck::new(Duration::from_secs(1));
+        assert_eq!(check.observe(Duration::from_millis(3500)), None);
+        for _ in 2..INTERVAL_CHECK_TICKS {
+            assert_eq!(check.observe(Duration::from_millis(2300)), None);
+        }
+        assert_eq!(check.observe(Duration::from_millis(1800)), Some(3));
+        // Decided once
+        assert_eq!(check.observe(Duration::from_millis(2300)), None);
+        assert_eq!(
+            interval_warning(1, 3, true),
+            "Warning: the last 5 ticks each took longer than the 1s interval; these devices need at least 3s, stretching the interval to 3s"
+        );
+
+        // A single tick that fits means the interval is sustainable
+        let mut check = IntervalCheck::new(Duration::from_secs(1));
+        assert_eq!(check.observe(Duration::from_millis(400)), None);
+        for _ in 1..INTERVAL_CHECK_TICKS * 2 {
+            assert_eq!(check.observe(Duration::from_millis(1800)), None);
+        }
+    }
+
+    #[test]
+    fn test_jittered_interval() {
+        let period = Duration::from_secs(300);
+        let mut exact = JitteredInterval::new(period, 0);
+        assert!((0..10).all(|_| exact.next_period() == period));
+
+        let mut jittered = JitteredInterval::with_seed(period, 10, 42);
+        let periods: Vec<Duration> = (0..10_000).map(|_| jittered.next_period()).collect();
+        assert!(periods
+            .iter()
+            .all(|p| (Duration::from_secs(270)..=Duration::from_secs(330)).contains(p)));
+        // Spread over the range, with the mean period kept
+        assert!(periods.iter().any(|p| *p < Duration::from_secs(275)));
+        assert!(periods.iter().any(|p| *p > Duration::from_secs(325)));
+        let mean = periods.iter().sum::<Duration>() / periods.len() as u32;
+        assert!(mean.abs_diff(period) < Duration::from_secs(1), "{:?}", mean);
+
+        // Seeds differ, so do the periods
+        let mut other = JitteredInterval::with_seed(period, 10, 44);
+        assert_ne!(periods[..3], [(); 3].map(|_| other.next_period()));
+    }
+
+    #[test]
+    fn test_reconcile_schedule() {
+        let start = Instant::now();
+        let period = Duration::from_secs(300);
+        let hub = DeviceId(0x0cf2, 0xa103, "ABC".to_string());
+        let other = DeviceId(0x0cf2, 0xa102, "DEF".to_string());
+        let devices = [hub.clone(), other.clone()];
+        let mut schedule = ReconcileSchedule::new(start, JitteredInterval::new(period, 0));
+
+        assert!(!schedule.start_pass_if_due(start, &devices));
+        assert_eq!(schedule.next_device(), None);
+
+        // One device per tick
+        let due = start + period;
+        assert!(schedule.start_pass_if_due(due, &devices));
+        assert_eq!(schedule.next_device(), Some(hub));
+        // A pass still running isn't restarted
+        assert!(!schedule.start_pass_if_due(due + period, &devices));
+        assert_eq!(schedule.next_device(), Some(other));
+        assert_eq!(schedule.next_device(), None);
+        assert!(schedule.start_pass_if_due(due + period, &devices));
+
+        let mut disabled = ReconcileSchedule::new(start, JitteredInterval::new(Duration::ZERO, 20));
+        assert!(!disabled.start_pass_if_due(start + period, &devices));
+        assert_eq!(disabled.next_device(), None);
+    }
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+/// The unit temperatures are shown in. Configuration and every calculation
+/// stay in Celsius; only text meant for people is converted, and only by the
+/// functions here.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DisplayUnits {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+/// The unit a curve's point temperatures are written in, when the curve
+/// says with `temperature_unit`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+pub fn celsius_from_fahrenheit(fahrenheit: f64) -> f64 {
+    (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+pub fn fahrenheit_from_celsius(celsius: f64) -> f64 {
+    celsius * 9.0 / 5.0 + 32.0
+}
+
+impl DisplayUnits {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            DisplayUnits::Celsius => "°C",
+            DisplayUnits::Fahrenheit => "°F",
+        }
+    }
+
+    /// The number shown for a temperature of `celsius`.
+    pub fn value(self, celsius: f64) -> f64 {
+        match self {
+            DisplayUnits::Celsius => celsius,
+            DisplayUnits::Fahrenheit => fahrenheit_from_celsius(celsius),
+        }
+    }
+
+    /// A temperature, e.g. "72.5°C" or "162.5°F".
+    pub fn temperature(self, celsius: f64) -> String {
+        format!("{:.1}{}", self.value(celsius), self.symbol())
+    }
+
+    /// A temperature difference, signed: scaled but without the Fahrenheit
+    /// offset, e.g. "-1.8°F" for a 1°C drop.
+    pub fn delta(self, celsius: f64) -> String {
+        let scaled = match self {
+            DisplayUnits::Celsius => celsius,
+            DisplayUnits::Fahrenheit => celsius * 9.0 / 5.0,
+        };
+        format!("{:+.1}{}", scaled, self.symbol())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temperatures() {
+        let f = DisplayUnits::Fahrenheit;
+        assert_eq!(f.temperature(0.0), "32.0°F");
+        assert_eq!(f.temperature(100.0), "212.0°F");
+        assert_eq!(f.temperature(-40.0), "-40.0°F");
+        assert_eq!(f.temperature(72.5), "162.5°F");
+        assert_eq!(DisplayUnits::Celsius.temperature(-12.34), "-12.3°C");
+        assert_eq!(celsius_from_fahrenheit(212.0), 100.0);
+        assert!((celsius_from_fahrenheit(f.value(63.7)) - 63.7).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_deltas() {
+        assert_eq!(DisplayUnits::Fahrenheit.delta(-1.0), "-1.8°F");
+        assert_eq!(DisplayUnits::Fahrenheit.delta(0.5), "+0.9°F");
+        assert_eq!(DisplayUnits::Celsius.delta(-2.25), "-2.2°C");
+        assert_eq!(DisplayUnits::Celsius.delta(0.0), "+0.0°C");
+    }
+}
@@ -0,0 +1,189 @@
+//! Protocol conformance vectors. Each file in tests/vectors gives the
+//! packets one hub family is expected to be sent or asked, as hex bytes the
+//! way captures show them, so a new family or firmware can be described from
+//! a capture without writing Rust. `uni-sync-curve vectors check` and the
+//! tests run every vector against the protocol table.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::backend::Command;
+use crate::capture::hex;
+use crate::config::{ChannelMode, SpeedPercent};
+use crate::hardware::{self, FAMILIES};
+
+pub const VECTOR_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vectors");
+
+/// A file may also say where its packets come from in a "source" string,
+/// e.g. the capture and firmware; it is for people and not read.
+#[derive(Deserialize, Debug)]
+pub struct VectorFile {
+    /// A short family name from `FAMILIES`, e.g. "slv2".
+    pub family: String,
+    pub vectors: Vec<Vector>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Vector {
+    #[serde(flatten)]
+    pub operation: Operation,
+    /// The packet as hex bytes, e.g. "e0 20 00 38"; null when the family has
+    /// no such packet.
+    pub packet: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum Operation {
+    SetSpeed { channel: usize, speed_percent: u8 },
+    SetMode { channel: usize, mode: ChannelMode },
+    SyncRgb { enabled: bool },
+    FanCountRequest,
+    ChannelStateRequest,
+    RpmRequest,
+}
+
+impl Operation {
+    /// The packet the protocol table has for this operation on `product_id`.
+    fn packet(&self, product_id: u16) -> Result<Option<Vec<u8>>> {
+        let command = match self {
+            Operation::SetSpeed {
+                channel,
+                speed_percent,
+            } => Command::SetSpeed {
+                channel: *channel,
+                speed_percent: SpeedPercent::new(*speed_percent)?,
+            },
+            Operation::SetMode { channel, mode } => Command::SetMode {
+                channel: *channel,
+                mode: mode.clone(),
+            },
+            Operation::SyncRgb { enabled } => Command::SyncRgb(*enabled),
+            Operation::FanCountRequest => return Ok(hardware::fan_count_request(product_id)),
+            Operation::ChannelStateRequest => {
+                return Ok(hardware::channel_state_request(product_id))
+            }
+            Operation::RpmRequest => return Ok(hardware::rpm_request(product_id)),
+        };
+        hardware::encode_packet(product_id, &command).map(Some)
+    }
+}
+
+/// Bytes written as "e0 20 00 38", with any whitespace between them.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    text.split_whitespace()
+        .map(|byte| {
+            u8::from_str_radix(byte, 16).with_context(|| format!("Invalid hex byte {:?}", byte))
+        })
+        .collect()
+}
+
+/// The vectors of `file` the protocol table disagrees with, described.
+pub fn check(file: &VectorFile) -> Result<Vec<String>> {
+    let Some(&(_, product_id)) = FAMILIES
+        .iter()
+        .find(|(family, _)| family.eq_ignore_ascii_case(&file.family))
+    else {
+        bail!("Unknown family {:?}", file.family);
+    };
+    let mut mismatches = Vec::new();
+    for vector in &file.vectors {
+        let expected = vector.packet.as_deref().map(parse_hex).transpose()?;
+        let actual = vector.operation.packet(product_id)?;
+        if actual != expected {
+            let show = |packet: &Option<Vec<u8>>| match packet {
+                Some(packet) => hex(packet),
+                None => "no packet".to_string(),
+            };
+            mismatches.push(format!(
+                "{} {:?}: expected {}, got {}",
+                file.family,
+                vector.operation,
+                show(&expected),
+                show(&actual)
+            ));
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Every vector file in `dir` checked: how many vectors there were, and
+/// each mismatch.
+pub fn check_dir(dir: &Path) -> Result<(usize, Vec<String>)> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir)
+        .with_context(|| format!("Could not read {:?}", dir))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    paths.retain(|path| path.extension().is_some_and(|e| e == "json"));
+    paths.sort();
+    if paths.is_empty() {
+        bail!("No vector files in {:?}", dir);
+    }
+
+    let mut count = 0;
+    let mut mismatches = Vec::new();
+    for path in paths {
+        let content = std::fs::read_to_string(&path)?;
+        let file: VectorFile = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid vector file {:?}", path))?;
+        count += file.vectors.len();
+        mismatches.extend(check(&file).with_context(|| format!("In {:?}", path))?);
+    }
+    Ok((count, mismatches))
+}
+
+pub fn format_check(count: usize, mismatches: &[String]) -> String {
+    let mut text = String::new();
+    for mismatch in mismatches {
+        let _ = writeln!(text, "MISMATCH {}", mismatch);
+    }
+    let _ = writeln!(text, "{} vectors, {} mismatched", count, mismatches.len());
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vectors() {
+        let (count, mismatches) = check_dir(Path::new(VECTOR_DIR)).unwrap();
+        assert!(count > 0);
+        assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+    }
+
+    #[test]
+    fn test_mismatch_is_reported() {
+        let file: VectorFile = serde_json::from_str(
+            r#"{
+                "family": "slv2",
+                "vectors": [
+                    { "operation": "set_speed", "channel": 1, "speed_percent": 50, "packet": "e0 21 00 38" },
+                    { "operation": "set_speed", "channel": 1, "speed_percent": 50, "packet": "e0 21 00 39" },
+                    { "operation": "rpm_request", "packet": null },
+                    { "operation": "set_mode", "channel": 2, "mode": "PWM", "packet": "E0 10 62 44" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            check(&file).unwrap(),
+            vec![
+                "slv2 SetSpeed { channel: 1, speed_percent: 50 }: expected e0 21 00 39, got e0 21 00 38",
+                "slv2 RpmRequest: expected no packet, got e0 52 00 00",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_vectors() {
+        assert!(parse_hex("e0 2g").is_err());
+        let file = VectorFile {
+            family: "xl".to_string(),
+            vectors: vec![],
+        };
+        assert!(check(&file).is_err());
+    }
+}
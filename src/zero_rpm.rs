@@ -0,0 +1,210 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time;
+
+use crate::backend::HardwareError;
+use crate::config::{BelowMin, ChannelMode, DeviceId, FanCurve, Manage, ZeroRpmCheckSettings};
+use crate::hardware::FanController;
+
+/// What a channel did when run at the test speed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum CheckResult {
+    /// Fans spin at a fan's speed, so the channel may stop.
+    Passed {
+        rpm: u16,
+    },
+    Failed {
+        rpm: u16,
+        reason: String,
+    },
+    /// The hub can't report RPMs, so nothing is known about the channel.
+    Unsupported,
+}
+
+/// Check results by channel, as kept in the results file.
+pub type CheckResults = BTreeMap<String, CheckResult>;
+
+/// Results are kept next to the config; deleting the file checks every
+/// channel again on the next start.
+pub fn results_path(config_path: &Path) -> PathBuf {
+    config_path.with_extension("zero-rpm.json")
+}
+
+pub fn channel_key(device_id: &DeviceId, channel: usize) -> String {
+    format!("{}/{}", device_id, channel)
+}
+
+/// The kept results. No file means no channel was checked yet.
+pub fn read_results(path: &Path) -> Result<CheckResults> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(CheckResults::new()),
+        Err(e) => Err(e).with_context(|| format!("Could not read {:?}", path)),
+    }
+}
+
+pub fn write_results(path: &Path, results: &CheckResults) -> Result<()> {
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, serde_json::to_string_pretty(results)?)?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Whether the curve would stop its channel, and so needs it checked.
+pub fn needs_check(fan_curve: &FanCurve) -> bool {
+    fan_curve.below_min == BelowMin::Zero
+        && fan_curve.manage != Manage::MonitorOnly
+        && matches!(fan_curve.mode, ChannelMode::Manual)
+}
+
+pub fn classify(rpm: u16, settings: &ZeroRpmCheckSettings) -> CheckResult {
+    if rpm < settings.min_fan_rpm {
+        CheckResult::Failed {
+            rpm,
+            reason: format!("below {} RPM, no fan spinning", settings.min_fan_rpm),
+        }
+    } else if rpm > settings.max_fan_rpm {
+        CheckResult::Failed {
+            rpm,
+            reason: format!("above {} RPM, looks like a pump", settings.max_fan_rpm),
+        }
+    } else {
+        CheckResult::Passed { rpm }
+    }
+}
+
+/// Keeps curves whose channel didn't pass from stopping it, by clamping
+/// them at their first point instead. Returns a warning for each.
+pub fn apply_results(fan_curves: &mut [FanCurve], results: &CheckResults) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for fan_curve in fan_curves.iter_mut().filter(|curve| needs_check(curve)) {
+        let why = match results.get(&channel_key(&fan_curve.device_id, fan_curve.channel)) {
+            Some(CheckResult::Passed { .. }) => continue,
+            Some(CheckResult::Failed { rpm, reason }) => format!("ran at {} RPM, {}", rpm, reason),
+            Some(CheckResult::Unsupported) => "the hub can't report RPMs".to_string(),
+            None => "not checked".to_string(),
+        };
+        warnings.push(format!(
+            "Curve {} won't stop its fans: {}",
+            fan_curve.label(),
+            why
+        ));
+        fan_curve.below_min = BelowMin::Clamp;
+    }
+    warnings
+}
+
+/// Runs the channel at the test speed, waits for it to settle and reads
+/// its RPM.
+pub async fn run_check(
+    fan_controller: &mut FanController,
+    device_id: &DeviceId,
+    channel: usize,
+    settings: &ZeroRpmCheckSettings,
+) -> Result<CheckResult> {
+    fan_controller
+        .set_fan_speed(
+            device_id,
+            channel,
+            &ChannelMode::Manual,
+            settings.test_speed_percent,
+        )
+        .await?;
+    time::sleep(Duration::from_secs(settings.settle_seconds)).await;
+    let rpms = match fan_controller.query_rpms(device_id).await {
+        Ok(rpms) => rpms,
+        Err(HardwareError::Unsupported { .. }) => return Ok(CheckResult::Unsupported),
+        Err(e) => return Err(e.into()),
+    };
+    let rpm = rpms.get(channel).copied().with_context(|| {
+        format!(
+            "Device {} reported no RPM for channel {}",
+            device_id, channel
+        )
+    })?;
+    Ok(classify(rpm, settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockBackend;
+
+    fn settings() -> ZeroRpmCheckSettings {
+        ZeroRpmCheckSettings {
+            settle_seconds: 0,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_check() {
+        let slv2 = DeviceId(0x0cf2, 0xa103, "V2".to_string());
+        let sl = DeviceId(0x0cf2, 0xa100, "V1".to_string());
+        let backend = MockBackend::new(vec![slv2.clone(), sl.clone()]).with_reply(
+            vec![224, 82, 0, 0],
+            vec![224, 82, 0x04, 0x4c, 0x0a, 0x28, 0, 20, 0, 0],
+        );
+        let mut controller = FanController::with_backends(vec![Box::new(backend)]);
+        controller.set_hub_reports(true);
+
+        let mut results = CheckResults::new();
+        for channel in 0..3 {
+            let result = run_check(&mut controller, &slv2, channel, &settings())
+                .await
+                .unwrap();
+            results.insert(channel_key(&slv2, channel), result);
+        }
+        let result = run_check(&mut controller, &sl, 0, &settings())
+            .await
+            .unwrap();
+        results.insert(channel_key(&sl, 0), result);
+
+        assert_eq!(
+            results[&channel_key(&slv2, 0)],
+            CheckResult::Passed { rpm: 1100 }
+        );
+        assert!(matches!(
+            &results[&channel_key(&slv2, 1)],
+            CheckResult::Failed { rpm: 2600, reason } if reason.contains("pump")
+        ));
+        assert!(matches!(
+            &results[&channel_key(&slv2, 2)],
+            CheckResult::Failed { rpm: 20, reason } if reason.contains("no fan")
+        ));
+        assert_eq!(results[&channel_key(&sl, 0)], CheckResult::Unsupported);
+
+        let curve = |device_id: &DeviceId, channel| FanCurve {
+            device_id: device_id.clone(),
+            channel,
+            below_min: BelowMin::Zero,
+            ..Default::default()
+        };
+        let mut fan_curves = vec![
+            curve(&slv2, 0),
+            curve(&slv2, 1),
+            curve(&slv2, 3),
+            curve(&sl, 0),
+        ];
+        let warnings = apply_results(&mut fan_curves, &results);
+
+        assert_eq!(fan_curves[0].below_min, BelowMin::Zero);
+        assert!(fan_curves[1..]
+            .iter()
+            .all(|fan_curve| fan_curve.below_min == BelowMin::Clamp));
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[1].ends_with("not checked"));
+
+        let dir = std::env::temp_dir().join(format!("uni-sync-zero-rpm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = results_path(&dir.join("uni-sync-curve.json"));
+        assert_eq!(read_results(&path).unwrap(), CheckResults::new());
+        write_results(&path, &results).unwrap();
+        assert_eq!(read_results(&path).unwrap(), results);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}